@@ -0,0 +1,66 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Prints a serialized `TaskDefinition` (like the ones dumped for failed tasks)
+//! or `PhysicalPlanNode` in human-readable form.
+//!
+//! Usage: `inspect_plan [--plan] <file>`, where `--plan` reads the file as a
+//! `PhysicalPlanNode` instead of a `TaskDefinition`.
+
+use std::error::Error;
+use std::process::exit;
+
+use datafusion::physical_plan::displayable;
+use plan_serde::from_proto::try_parse_physical_plan;
+use plan_serde::protobuf::{PhysicalPlanNode, TaskDefinition};
+use plan_serde::validate::validate_physical_plan;
+use prost::Message;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let (plan_only, path) = match args.as_slice() {
+        [flag, path] if flag == "--plan" => (true, path),
+        [path] => (false, path),
+        _ => {
+            eprintln!("Usage: inspect_plan [--plan] <file>");
+            exit(2);
+        }
+    };
+    let raw = std::fs::read(path)?;
+
+    let plan = if plan_only {
+        let plan = PhysicalPlanNode::decode(raw.as_slice())?;
+        println!("{}", serde_json::to_string_pretty(&plan)?);
+        plan
+    } else {
+        let task_definition = TaskDefinition::decode(raw.as_slice())?;
+        println!("{}", serde_json::to_string_pretty(&task_definition)?);
+        task_definition.plan.ok_or("plan is empty")?
+    };
+
+    let errors = validate_physical_plan(&plan).errors;
+    if !errors.is_empty() {
+        eprintln!("Converting plan failed with {} error(s):", errors.len());
+        for error in errors {
+            eprintln!("  {}: {}", error.path, error.message);
+        }
+        exit(1);
+    }
+    let execution_plan = try_parse_physical_plan(&plan)?;
+    println!("{}", displayable(execution_plan.as_ref()).indent());
+    Ok(())
+}
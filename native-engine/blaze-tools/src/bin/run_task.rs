@@ -0,0 +1,353 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Executes a serialized `TaskDefinition` (like the ones dumped for failed
+//! tasks) against local files without the JVM, to replay or benchmark a task.
+//!
+//! Usage: `run_task [options] <file>`, options are:
+//!
+//! * `--plan`: read the file as a `PhysicalPlanNode` instead of a `TaskDefinition`
+//! * `--partition <n>`: partition to execute, defaults to the partition of the task
+//! * `--shuffle <native_shuffle_id>=<file>[:<offset>:<length>],...`: read blocks
+//!   of a shuffle reader from ranges of map output data files. Without a range,
+//!   the block of the reduce partition is located with the `.index` file next to
+//!   the data file, or the whole file is read if there is no index file
+//! * `--reduce-partition <n>`: reduce partition read from map output data files,
+//!   defaults to the executed partition
+//! * `--rewrite-path <from>=<to>`: replace prefix `from` of scanned file paths
+//! * `--shuffle-output-dir <dir>`: write output files of a shuffle writer into `dir`
+//! * `--output <file>`: write result batches into an arrow ipc file instead of
+//!   printing them
+//! * `--batch-size <n>`: batch size of the session, defaults to 16384
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::process::exit;
+use std::sync::Arc;
+use std::time::Instant;
+
+use datafusion::arrow::ipc::writer::FileWriter;
+use datafusion::arrow::util::pretty::print_batches;
+use datafusion::physical_plan::display::DisplayableExecutionPlan;
+use datafusion::physical_plan::{displayable, ExecutionPlan};
+use datafusion::prelude::{SessionConfig, SessionContext};
+use datafusion_ext::shuffle_reader_exec::{
+    register_local_shuffle_blocks, LocalShuffleBlock,
+};
+use futures::StreamExt;
+use plan_serde::from_proto::try_parse_physical_plan;
+use plan_serde::protobuf::physical_plan_node::PhysicalPlanType;
+use plan_serde::protobuf::{FileScanExecConf, PhysicalPlanNode, TaskDefinition};
+use prost::Message;
+
+const USAGE: &str = "Usage: run_task [--plan] [--partition <n>] \
+    [--shuffle <native_shuffle_id>=<file>[:<offset>:<length>],...] \
+    [--reduce-partition <n>] \
+    [--rewrite-path <from>=<to>] [--shuffle-output-dir <dir>] \
+    [--output <file>] [--batch-size <n>] <file>";
+
+#[derive(Default)]
+struct Args {
+    path: String,
+    plan_only: bool,
+    partition: Option<usize>,
+    shuffles: Vec<(String, Vec<ShuffleFile>)>,
+    reduce_partition: Option<usize>,
+    path_rewrites: Vec<(String, String)>,
+    shuffle_output_dir: Option<String>,
+    output: Option<String>,
+    batch_size: Option<usize>,
+}
+
+fn parse_args() -> Result<Args, Box<dyn Error>> {
+    let mut args = Args::default();
+    let mut iter = std::env::args().skip(1);
+    let mut path = None;
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or(format!("missing value of {}", arg));
+        match arg.as_str() {
+            "--plan" => args.plan_only = true,
+            "--partition" => args.partition = Some(value()?.parse()?),
+            "--shuffle" => args.shuffles.push(parse_shuffle(&value()?)?),
+            "--reduce-partition" => args.reduce_partition = Some(value()?.parse()?),
+            "--rewrite-path" => {
+                let value = value()?;
+                let (from, to) = value
+                    .split_once('=')
+                    .ok_or(format!("invalid path rewrite: {}", value))?;
+                args.path_rewrites.push((from.to_owned(), to.to_owned()));
+            }
+            "--shuffle-output-dir" => args.shuffle_output_dir = Some(value()?),
+            "--output" => args.output = Some(value()?),
+            "--batch-size" => args.batch_size = Some(value()?.parse()?),
+            _ if arg.starts_with("--") || path.is_some() => {
+                return Err(format!("unexpected argument: {}", arg).into());
+            }
+            _ => path = Some(arg),
+        }
+    }
+    args.path = path.ok_or("missing input file")?;
+    Ok(args)
+}
+
+/// A map output data file given with `--shuffle`, with an optional byte range
+struct ShuffleFile {
+    path: String,
+    range: Option<(u64, u64)>,
+}
+
+/// parses `<native_shuffle_id>=<file>[:<offset>:<length>],...`
+fn parse_shuffle(value: &str) -> Result<(String, Vec<ShuffleFile>), Box<dyn Error>> {
+    let (native_shuffle_id, files) = value
+        .split_once('=')
+        .ok_or(format!("invalid shuffle: {}", value))?;
+    let files = files
+        .split(',')
+        .map(|file| {
+            let parts = file.rsplitn(3, ':').collect::<Vec<_>>();
+            if let [length, offset, path] = parts.as_slice() {
+                if let (Ok(offset), Ok(length)) = (offset.parse(), length.parse()) {
+                    return ShuffleFile {
+                        path: path.to_string(),
+                        range: Some((offset, length)),
+                    };
+                }
+            }
+            ShuffleFile {
+                path: file.to_owned(),
+                range: None,
+            }
+        })
+        .collect();
+    Ok((native_shuffle_id.to_owned(), files))
+}
+
+/// locates the block of `reduce_partition` in a map output data file. the range
+/// is read from the `.index` file next to the data file, which contains offsets
+/// of all reduce partitions as big-endian u64s. files without an index file are
+/// read as a whole
+fn locate_shuffle_block(
+    file: &ShuffleFile,
+    reduce_partition: usize,
+) -> Result<LocalShuffleBlock, Box<dyn Error>> {
+    let (offset, length) = match file.range {
+        Some(range) => range,
+        None => {
+            let index_path = Path::new(&file.path).with_extension("index");
+            if index_path.exists() {
+                let mut index_file = File::open(&index_path)?;
+                let mut offsets = [0u8; 16];
+                index_file.seek(SeekFrom::Start(reduce_partition as u64 * 8))?;
+                index_file.read_exact(&mut offsets).map_err(|err| {
+                    format!(
+                        "reduce partition {} not found in {}: {}",
+                        reduce_partition,
+                        index_path.display(),
+                        err
+                    )
+                })?;
+                let start = u64::from_be_bytes(offsets[0..8].try_into()?);
+                let end = u64::from_be_bytes(offsets[8..16].try_into()?);
+                (start, end - start)
+            } else {
+                (0, std::fs::metadata(&file.path)?.len())
+            }
+        }
+    };
+    Ok(LocalShuffleBlock {
+        path: file.path.clone(),
+        offset,
+        length,
+    })
+}
+
+/// replaces scanned file paths and output files of shuffle writers, so that the
+/// plan reads and writes local files
+fn localize_plan(plan: &mut PhysicalPlanNode, args: &Args) {
+    let rewrite_paths = |conf: Option<&mut FileScanExecConf>| {
+        let files = conf
+            .into_iter()
+            .flat_map(|conf| conf.file_groups.iter_mut())
+            .flat_map(|file_group| file_group.files.iter_mut());
+        for file in files {
+            for (from, to) in &args.path_rewrites {
+                if let Some(suffix) = file.path.strip_prefix(from.as_str()) {
+                    file.path = format!("{}{}", to, suffix);
+                    break;
+                }
+            }
+        }
+    };
+    let plan_type = match &mut plan.physical_plan_type {
+        Some(plan_type) => plan_type,
+        None => return,
+    };
+    let children = match plan_type {
+        PhysicalPlanType::ParquetScan(node) => {
+            rewrite_paths(node.base_conf.as_mut());
+            vec![]
+        }
+        PhysicalPlanType::CsvScan(node) => {
+            rewrite_paths(node.base_conf.as_mut());
+            vec![]
+        }
+        PhysicalPlanType::AvroScan(node) => {
+            rewrite_paths(node.base_conf.as_mut());
+            vec![]
+        }
+        PhysicalPlanType::ShuffleWriter(node) => {
+            if let Some(dir) = &args.shuffle_output_dir {
+                let localize = |file: &str| {
+                    let file_name = Path::new(file).file_name().unwrap_or_default();
+                    Path::new(dir)
+                        .join(file_name)
+                        .to_string_lossy()
+                        .into_owned()
+                };
+                node.output_data_file = localize(&node.output_data_file);
+                node.output_index_file = localize(&node.output_index_file);
+            }
+            node.input.as_deref_mut().into_iter().collect()
+        }
+        PhysicalPlanType::Projection(node) => {
+            node.input.as_deref_mut().into_iter().collect()
+        }
+        PhysicalPlanType::Filter(node) => node.input.as_deref_mut().into_iter().collect(),
+        PhysicalPlanType::CoalesceBatches(node) => {
+            node.input.as_deref_mut().into_iter().collect()
+        }
+        PhysicalPlanType::Merge(node) => node.input.as_deref_mut().into_iter().collect(),
+        PhysicalPlanType::Repartition(node) => {
+            node.input.as_deref_mut().into_iter().collect()
+        }
+        PhysicalPlanType::GlobalLimit(node) => {
+            node.input.as_deref_mut().into_iter().collect()
+        }
+        PhysicalPlanType::LocalLimit(node) => {
+            node.input.as_deref_mut().into_iter().collect()
+        }
+        PhysicalPlanType::Window(node) => node.input.as_deref_mut().into_iter().collect(),
+        PhysicalPlanType::HashAggregate(node) => {
+            node.input.as_deref_mut().into_iter().collect()
+        }
+        PhysicalPlanType::Sort(node) => node.input.as_deref_mut().into_iter().collect(),
+        PhysicalPlanType::RenameColumns(node) => {
+            node.input.as_deref_mut().into_iter().collect()
+        }
+        PhysicalPlanType::HashJoin(node) => node
+            .left
+            .as_deref_mut()
+            .into_iter()
+            .chain(node.right.as_deref_mut())
+            .collect(),
+        PhysicalPlanType::SortMergeJoin(node) => node
+            .left
+            .as_deref_mut()
+            .into_iter()
+            .chain(node.right.as_deref_mut())
+            .collect(),
+        PhysicalPlanType::CrossJoin(node) => node
+            .left
+            .as_deref_mut()
+            .into_iter()
+            .chain(node.right.as_deref_mut())
+            .collect(),
+        PhysicalPlanType::Union(node) => node.children.iter_mut().collect(),
+        PhysicalPlanType::Empty(_)
+        | PhysicalPlanType::EmptyPartitions(_)
+        | PhysicalPlanType::ShuffleReader(_)
+        | PhysicalPlanType::Unresolved(_) => vec![],
+    };
+    for child in children {
+        localize_plan(child, args);
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = parse_args().unwrap_or_else(|err| {
+        eprintln!("{}\n{}", err, USAGE);
+        exit(2);
+    });
+    let raw = std::fs::read(&args.path)?;
+    let (mut plan, partition) = if args.plan_only {
+        (PhysicalPlanNode::decode(raw.as_slice())?, 0)
+    } else {
+        let task_definition = TaskDefinition::decode(raw.as_slice())?;
+        let partition = task_definition
+            .task_id
+            .map(|task_id| task_id.partition_id as usize)
+            .unwrap_or_default();
+        (task_definition.plan.ok_or("plan is empty")?, partition)
+    };
+    let partition = args.partition.unwrap_or(partition);
+
+    localize_plan(&mut plan, &args);
+    let reduce_partition = args.reduce_partition.unwrap_or(partition);
+    for (native_shuffle_id, files) in &args.shuffles {
+        let blocks = files
+            .iter()
+            .map(|file| locate_shuffle_block(file, reduce_partition))
+            .collect::<Result<Vec<_>, _>>()?;
+        register_local_shuffle_blocks(native_shuffle_id, blocks);
+    }
+
+    let execution_plan: Arc<dyn ExecutionPlan> = try_parse_physical_plan(&plan)?;
+    eprintln!("Executing partition {} of plan:", partition);
+    eprintln!("{}", displayable(execution_plan.as_ref()).indent());
+
+    let config = SessionConfig::new().with_batch_size(args.batch_size.unwrap_or(16384));
+    let task_ctx = SessionContext::with_config(config).task_ctx();
+    let start_time = Instant::now();
+    let mut stream = execution_plan.execute(partition, task_ctx)?;
+
+    let mut writer = match &args.output {
+        Some(output) => Some(FileWriter::try_new(
+            File::create(output)?,
+            &stream.schema(),
+        )?),
+        None => None,
+    };
+    let (mut num_batches, mut num_rows) = (0, 0);
+    while let Some(batch) = stream.next().await {
+        let batch = batch?;
+        num_batches += 1;
+        num_rows += batch.num_rows();
+        match &mut writer {
+            Some(writer) => writer.write(&batch)?,
+            None => print_batches(&[batch])?,
+        }
+    }
+    if let Some(writer) = &mut writer {
+        writer.finish()?;
+    }
+
+    eprintln!(
+        "Finished in {:?}, {} rows in {} batches",
+        start_time.elapsed(),
+        num_rows,
+        num_batches
+    );
+    eprintln!("Metrics:");
+    eprintln!(
+        "{}",
+        DisplayableExecutionPlan::with_metrics(execution_plan.as_ref()).indent()
+    );
+    Ok(())
+}
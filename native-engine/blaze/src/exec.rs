@@ -1,36 +1,62 @@
 use std::any::Any;
+use std::collections::HashMap;
 use std::error::Error;
-
+use std::future::Future;
 use std::panic::AssertUnwindSafe;
 use std::path::PathBuf;
-use std::sync::Arc;
-use std::time::Duration;
-
-use datafusion::arrow::array::{export_array_into_raw, StructArray};
-use datafusion::arrow::ffi::{FFI_ArrowArray, FFI_ArrowSchema};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::error::{ArrowError, Result as ArrowResult};
+use datafusion::arrow::ffi_stream::{export_reader_into_raw, FFI_ArrowArrayStream};
+use datafusion::arrow::record_batch::{RecordBatch, RecordBatchReader};
+use datafusion::error::DataFusionError;
 use datafusion::execution::disk_manager::DiskManagerConfig;
 use datafusion::execution::memory_manager::MemoryManagerConfig;
 use datafusion::execution::runtime_env::{RuntimeConfig, RuntimeEnv};
-use datafusion::physical_plan::{displayable, ExecutionPlan};
+use datafusion::physical_plan::{displayable, ExecutionPlan, SendableRecordBatchStream};
 use datafusion::prelude::{SessionConfig, SessionContext};
 use datafusion_ext::jni_bridge::JavaClasses;
+use datafusion_ext::native_error::{with_operator_errors, NativeError};
+use datafusion_ext::shuffle_writer_exec::ShuffleWriterExec;
+use datafusion_ext::spark_task_context::{with_task_context, CancelHandle};
 use datafusion_ext::*;
+use futures::future::{AbortHandle, Abortable};
 use futures::{FutureExt, StreamExt};
-use jni::objects::{JClass, JString};
+use jni::objects::{GlobalRef, JClass, JString};
 use jni::objects::{JObject, JThrowable};
-use jni::sys::{jboolean, jlong, JNI_FALSE, JNI_TRUE};
+use jni::sys::{jbyteArray, jlong};
 use jni::JNIEnv;
-use log::LevelFilter;
 use once_cell::sync::OnceCell;
+use plan_serde::from_proto::try_parse_physical_plan;
+use plan_serde::parallelize::parallelize_task_plan;
+use plan_serde::protobuf;
+use plan_serde::protobuf::PhysicalPlanNode;
+use plan_serde::protobuf::ShuffleWriteResult;
 use plan_serde::protobuf::TaskDefinition;
+use plan_serde::validate::validate_physical_plan;
 use prost::Message;
-use simplelog::{ColorChoice, ConfigBuilder, TermLogger, TerminalMode, ThreadLogMode};
-use tokio::runtime::Runtime;
+use tokio::runtime::{Handle, Runtime};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::Instant;
 
-use crate::metrics::update_spark_metric_node;
+use crate::logging::init_logging;
+use crate::metrics::SparkMetricReporter;
 
-static LOGGING_INIT: OnceCell<()> = OnceCell::new();
 static SESSIONCTX: OnceCell<SessionContext> = OnceCell::new();
+static TASK_DUMP_DIR: OnceCell<Option<PathBuf>> = OnceCell::new();
+static TASK_PARALLELISM: OnceCell<usize> = OnceCell::new();
+static METRICS_UPDATE_INTERVAL: OnceCell<Option<Duration>> = OnceCell::new();
+
+// executor-wide runtime shared by all native tasks, taken out on shutdown
+static TOKIO_RUNTIME: OnceCell<Mutex<Option<Runtime>>> = OnceCell::new();
+static TOKIO_RUNTIME_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+static NEXT_NATIVE_TASK_ID: AtomicU64 = AtomicU64::new(1);
+static RUNNING_NATIVE_TASKS: OnceCell<Mutex<HashMap<u64, CancelHandle>>> =
+    OnceCell::new();
 
 #[allow(non_snake_case)]
 #[allow(clippy::single_match)]
@@ -42,24 +68,18 @@ pub extern "system" fn Java_org_apache_spark_sql_blaze_JniBridge_initNative(
     native_memory: i64,
     memory_fraction: f64,
     tmp_dirs: JString,
+    task_dump_dir: JString,
+    num_worker_threads: i64,
+    task_parallelism: i64,
+    metrics_update_interval_ms: i64,
 ) {
     match std::panic::catch_unwind(|| {
-        // init logging
-        LOGGING_INIT.get_or_init(|| {
-            TermLogger::init(
-                LevelFilter::Info,
-                ConfigBuilder::new()
-                    .set_thread_mode(ThreadLogMode::Both)
-                    .build(),
-                TerminalMode::Stderr,
-                ColorChoice::Never,
-            )
-            .unwrap();
-        });
-
         // init jni java classes
         JavaClasses::init(&env);
 
+        // init logging, forwarded to the jvm
+        init_logging();
+
         // init datafusion session context
         SESSIONCTX.get_or_init(|| {
             let dirs = jni_get_string!(tmp_dirs)
@@ -79,6 +99,35 @@ pub extern "system" fn Java_org_apache_spark_sql_blaze_JniBridge_initNative(
             let config = SessionConfig::new().with_batch_size(batch_size);
             SessionContext::with_config_rt(config, runtime)
         });
+
+        // init tokio runtime shared by all tasks of the executor
+        TOKIO_RUNTIME.get_or_init(|| {
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .worker_threads((num_worker_threads as usize).max(1))
+                .thread_name("blaze-native-worker")
+                .thread_keep_alive(Duration::MAX) // keep jvm-attached threads alive
+                .enable_all()
+                .build()
+                .unwrap();
+            Mutex::new(Some(runtime))
+        });
+
+        // number of sub-partitions a task is split into, 1 means disabled
+        TASK_PARALLELISM.get_or_init(|| (task_parallelism as usize).max(1));
+
+        // interval of reporting metrics of running tasks, 0 means disabled
+        METRICS_UPDATE_INTERVAL.get_or_init(|| {
+            Some(metrics_update_interval_ms)
+                .filter(|&interval_ms| interval_ms > 0)
+                .map(|interval_ms| Duration::from_millis(interval_ms as u64))
+        });
+
+        // init dir for dumping task definitions of failed tasks
+        TASK_DUMP_DIR.get_or_init(|| {
+            Some(jni_get_string!(task_dump_dir).unwrap())
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+        });
     }) {
         Err(err) => {
             handle_unwinded(err);
@@ -93,19 +142,12 @@ pub extern "system" fn Java_org_apache_spark_sql_blaze_JniBridge_callNative(
     _: JNIEnv,
     _: JClass,
     wrapper: JObject,
-) {
-    if let Err(err) = std::panic::catch_unwind(|| {
+    stream_ptr: jlong,
+) -> jlong {
+    match std::panic::catch_unwind(|| {
         log::info!("Entering blaze callNative()");
 
-        let wrapper = Arc::new(jni_new_global_ref!(wrapper).unwrap());
-        let wrapper_clone = wrapper.clone();
-
-        let obj_true =
-            jni_new_global_ref!(jni_new_object!(JavaBoolean, JNI_TRUE).unwrap()).unwrap();
-
-        let obj_false =
-            jni_new_global_ref!(jni_new_object!(JavaBoolean, JNI_FALSE).unwrap())
-                .unwrap();
+        let wrapper = jni_new_global_ref!(wrapper).unwrap();
 
         // decode plan
         let raw_task_definition: JObject = jni_call!(
@@ -113,194 +155,483 @@ pub extern "system" fn Java_org_apache_spark_sql_blaze_JniBridge_callNative(
         )
         .unwrap();
 
-        let task_definition = TaskDefinition::decode(
-            jni_convert_byte_array!(raw_task_definition.into_inner())
-                .unwrap()
-                .as_slice(),
-        )
-        .unwrap();
-
-        let task_id = &task_definition.task_id.expect("task_id is empty");
-        let plan = &task_definition.plan.expect("plan is empty");
-
-        // get execution plan
-        let execution_plan: Arc<dyn ExecutionPlan> = plan.try_into().unwrap();
-        let execution_plan_displayable =
-            displayable(execution_plan.as_ref()).indent().to_string();
-        log::info!("Creating native execution plan succeeded");
-        log::info!("  task_id={:?}", task_id);
-        log::info!("  execution plan:\n{}", execution_plan_displayable);
+        let raw_task_definition =
+            jni_convert_byte_array!(raw_task_definition.into_inner()).unwrap();
+
+        // dump the task definition if the task fails before executing
+        let (execution_plan, stream) = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            let task_definition =
+                TaskDefinition::decode(raw_task_definition.as_slice()).unwrap();
+
+            let task_id = &task_definition.task_id.expect("task_id is empty");
+            let mut plan = task_definition.plan.expect("plan is empty");
+            let mut partition = task_id.partition_id as usize;
+
+            // split the partition into sub-partitions executed in parallel
+            let task_parallelism = *TASK_PARALLELISM.get().unwrap();
+            if let Some(parallelized_plan) =
+                parallelize_task_plan(&plan, partition, task_parallelism)
+            {
+                log::info!("Executing task with parallelism {}", task_parallelism);
+                plan = parallelized_plan;
+                partition = 0;
+            }
 
-        // execute
-        let session_ctx = SESSIONCTX.get().unwrap();
-        let task_ctx = session_ctx.task_ctx();
-        let mut stream = execution_plan
-            .execute(task_id.partition_id as usize, task_ctx)
-            .unwrap();
+            // get execution plan, with errors attributed to their operators
+            let execution_plan: Arc<dyn ExecutionPlan> = try_parse_physical_plan(&plan)
+                .map_err(|err| DataFusionError::Plan(err.to_string()))
+                .and_then(with_operator_errors)
+                .unwrap_or_else(|err| {
+                    let err = report_native_error(&wrapper, err);
+                    panic!("Error decoding native execution plan: {}", err)
+                });
+            log::info!(
+                "Creating native execution plan succeeded, task_id={:?}",
+                task_id
+            );
+            log::debug!(
+                "  execution plan:\n{}",
+                displayable(execution_plan.as_ref()).indent()
+            );
+
+            // execute, operators may spawn tasks on the shared runtime
+            let session_ctx = SESSIONCTX.get().unwrap();
+            let task_ctx = session_ctx.task_ctx();
+            let _runtime_guard = runtime_handle().enter();
+            let stream =
+                execution_plan
+                    .execute(partition, task_ctx)
+                    .unwrap_or_else(|err| {
+                        let err = report_native_error(&wrapper, err);
+                        panic!("Error executing native execution plan: {}", err)
+                    });
+            (execution_plan, stream)
+        }))
+        .unwrap_or_else(|err| {
+            // errors not reported yet are reported as panics, the jvm keeps the
+            // first reported error
+            report_native_error(
+                &wrapper,
+                DataFusionError::Execution(format!(
+                    "native execution panics: {}",
+                    panic_message::panic_message(&err)
+                )),
+            );
+            dump_failed_task_definition(&raw_task_definition);
+            std::panic::resume_unwind(err)
+        });
 
         let task_context = jni_new_global_ref!(
             jni_call_static!(JniBridge.getTaskContext() -> JObject).unwrap()
         )
         .unwrap();
 
-        // a runtime wrapper that calls shutdown_background on dropping
-        struct RuntimeWrapper {
-            runtime: Option<Runtime>,
+        // export the output as an arrow stream pulled by the jvm, batches are
+        // passed from the native task through a bounded channel
+        let (sender, receiver) = tokio::sync::mpsc::channel(1);
+        let reader = NativeBatchReader {
+            schema: stream.schema(),
+            receiver,
+        };
+        unsafe {
+            export_reader_into_raw(
+                Box::new(reader),
+                stream_ptr as *mut FFI_ArrowArrayStream,
+            );
         }
-        impl Drop for RuntimeWrapper {
-            fn drop(&mut self) {
-                if let Some(rt) = self.runtime.take() {
-                    rt.shutdown_background();
+
+        // spawn a task producing batches on the shared runtime, which can be
+        // cancelled by the jvm with the returned id
+        let native_task_id = NEXT_NATIVE_TASK_ID.fetch_add(1, Ordering::Relaxed);
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        let cancel_handle = CancelHandle::default();
+        cancel_handle.register(abort_handle);
+        running_native_tasks()
+            .lock()
+            .unwrap()
+            .insert(native_task_id, cancel_handle.clone());
+
+        runtime_handle().spawn(async move {
+            let error_wrapper = wrapper.clone();
+            let error_sender = sender.clone();
+            let error_execution_plan = execution_plan.clone();
+            let mut metric_reporter = SparkMetricReporter::default();
+            let task_cancel_handle = cancel_handle.clone();
+            let result = AssertUnwindSafe(Abortable::new(
+                with_task_context(
+                    native_task_id,
+                    Some(task_context),
+                    task_cancel_handle,
+                    produce_batches(
+                        wrapper,
+                        execution_plan,
+                        stream,
+                        sender,
+                        &mut metric_reporter,
+                    ),
+                ),
+                abort_registration,
+            ))
+            .catch_unwind()
+            .await;
+            running_native_tasks()
+                .lock()
+                .unwrap()
+                .remove(&native_task_id);
+
+            // stop tasks spawned by the finished task, cancelNative called
+            // later by the jvm cannot find the task any more
+            cancel_handle.cancel();
+
+            let err = match result {
+                Ok(Ok(Ok(()))) => return,
+                Ok(Err(_aborted)) => {
+                    // the stream is already dropped here, releasing memory
+                    // reservations and spill files
+                    log::info!("Blaze native executing cancelled.");
+                    let err =
+                        DataFusionError::External(Box::new(NativeError::cancelled()));
+                    let err = report_native_error(&error_wrapper, err);
+                    let _ = error_sender
+                        .send(Err(ArrowError::ExternalError(err.into())))
+                        .await;
+                    return;
                 }
+                Ok(Ok(Err(err))) => err,
+                Err(err) => DataFusionError::Execution(format!(
+                    "native execution panics: {}",
+                    panic_message::panic_message(&err)
+                )),
+            };
+            if jni_exception_check!().unwrap_or(false) {
+                let _ = jni_exception_describe!();
+                let _ = jni_exception_clear!();
             }
+            let err = report_native_error(&error_wrapper, err);
+            log::error!("native execution failed: {}", err);
+
+            // report progress of the failed task, like spills before the error
+            let updated = update_metrics(
+                &error_wrapper,
+                error_execution_plan,
+                &mut metric_reporter,
+            );
+            if let Err(err) = updated {
+                log::warn!("Error updating metrics of failed task: {}", err);
+                let _ = jni_exception_clear!();
+            }
+            dump_failed_task_definition(&raw_task_definition);
+
+            // the error is taken from get_last_error() of the stream on the jvm
+            // side, the receiver is already dropped if the stream was closed
+            let _ = error_sender
+                .send(Err(ArrowError::ExternalError(err.into())))
+                .await;
+            log::info!("Blaze native executing exited with error.");
+        });
+
+        log::info!("Blaze native task spawned");
+        native_task_id as jlong
+    }) {
+        Err(err) => {
+            handle_unwinded(err);
+            0
         }
+        Ok(native_task_id) => native_task_id,
+    }
+}
 
-        // spawn a thread to poll batches
-        let runtime = Arc::new(RuntimeWrapper {
-            runtime: Some(
-                tokio::runtime::Builder::new_multi_thread()
-                    .worker_threads(1)
-                    .thread_keep_alive(Duration::MAX) // always use same thread
-                    .build()
-                    .unwrap(),
-            ),
-        });
-        let runtime_clone = runtime.clone();
-
-        runtime.clone().runtime.as_ref().unwrap().spawn(async move {
-            AssertUnwindSafe(async move {
-                let mut total_batches = 0;
-                let mut total_rows = 0;
-
-                // propagate task context to spawned children threads
-                jni_call_static!(JniBridge.setTaskContext(task_context.as_obj()) -> ()).unwrap();
-
-                // load batches
-                while let Some(r) = stream.next().await {
-                    match r {
-                        Ok(batch) => {
-                            let num_rows = batch.num_rows();
-                            if num_rows == 0 {
-                                continue;
-                            }
-                            total_batches += 1;
-                            total_rows += num_rows;
-
-                            // value_queue -> (schema_ptr, array_ptr)
-                            let mut input = JObject::null();
-                            while jni_call!(BlazeCallNativeWrapper(wrapper.as_obj()).isFinished() -> jboolean).unwrap() != JNI_TRUE {
-                                input = jni_call!(BlazeCallNativeWrapper(wrapper.as_obj()).dequeueWithTimeout() -> JObject).unwrap();
-
-                                if !input.is_null() {
-                                    break;
-                                }
-                            }
-                            if input.is_null() { // wrapper.isFinished = true
-                                break;
-                            }
-
-                            let schema_ptr = jni_call!(ScalaTuple2(input)._1() -> JObject).unwrap();
-                            let schema_ptr = jni_call!(JavaLong(schema_ptr).longValue() -> jlong).unwrap();
-                            let array_ptr = jni_call!(ScalaTuple2(input)._2() -> JObject).unwrap();
-                            let array_ptr = jni_call!(JavaLong(array_ptr).longValue() -> jlong).unwrap();
-
-                            let out_schema = schema_ptr as *mut FFI_ArrowSchema;
-                            let out_array = array_ptr as *mut FFI_ArrowArray;
-                            let batch: Arc<StructArray> = Arc::new(batch.into());
-                            unsafe {
-                                export_array_into_raw(
-                                    batch,
-                                    out_array,
-                                    out_schema,
-                                )
-                                .expect("export_array_into_raw error");
-                            }
-
-                            // value_queue <- hasNext=true
-                            while {
-                                jni_call!(BlazeCallNativeWrapper(wrapper.as_obj()).isFinished() -> jboolean).unwrap() != JNI_TRUE &&
-                                jni_call!(BlazeCallNativeWrapper(wrapper.as_obj()).enqueueWithTimeout(obj_true.as_obj()) -> jboolean).unwrap() != JNI_TRUE
-                            } {}
-                        }
-                        Err(e) => {
-                            panic!("stream.next() error: {:?}", e);
-                        }
-                    }
-                }
+/// Cancels a native task started by callNative together with the tasks spawned
+/// by it, its plan is dropped the next time the task yields. Finished tasks are
+/// ignored.
+#[allow(non_snake_case)]
+#[no_mangle]
+pub extern "system" fn Java_org_apache_spark_sql_blaze_JniBridge_cancelNative(
+    _: JNIEnv,
+    _: JClass,
+    native_task_id: jlong,
+) {
+    if let Err(err) = std::panic::catch_unwind(|| {
+        let native_task_id = native_task_id as u64;
+        let cancel_handle = running_native_tasks()
+            .lock()
+            .unwrap()
+            .remove(&native_task_id);
+        if let Some(cancel_handle) = cancel_handle {
+            log::info!("Cancelling blaze native task {}", native_task_id);
+            cancel_handle.cancel();
+        }
+    }) {
+        handle_unwinded(err);
+    }
+}
 
-                // value_queue -> (discard)
-                while jni_call!(BlazeCallNativeWrapper(wrapper.as_obj()).isFinished() -> jboolean).unwrap() != JNI_TRUE {
-                    let input = jni_call!(BlazeCallNativeWrapper(wrapper.as_obj()).dequeueWithTimeout() -> JObject).unwrap();
-                    if !input.is_null() {
-                        break;
-                    }
-                }
+/// Sends all output batches into the channel of the exported stream, then
+/// reports shuffle statistics and metrics before the stream ends. Metrics are
+/// also reported periodically while running.
+async fn produce_batches(
+    wrapper: GlobalRef,
+    execution_plan: Arc<dyn ExecutionPlan>,
+    mut stream: SendableRecordBatchStream,
+    sender: Sender<ArrowResult<RecordBatch>>,
+    metric_reporter: &mut SparkMetricReporter,
+) -> datafusion::error::Result<()> {
+    let mut total_batches = 0;
+    let mut total_rows = 0;
+    let mut metrics_update_interval = METRICS_UPDATE_INTERVAL
+        .get()
+        .copied()
+        .flatten()
+        .map(|interval| tokio::time::interval_at(Instant::now() + interval, interval));
+
+    // load batches
+    loop {
+        let batch = update_metrics_until(
+            stream.next(),
+            &mut metrics_update_interval,
+            &wrapper,
+            &execution_plan,
+            metric_reporter,
+        )
+        .await;
+        let batch = match batch {
+            Some(batch) => batch?,
+            None => break,
+        };
+        let num_rows = batch.num_rows();
+        if num_rows == 0 {
+            continue;
+        }
+        total_batches += 1;
+        total_rows += num_rows;
+
+        // the jvm may not consume output for a long time, keep reporting metrics
+        let sent = update_metrics_until(
+            sender.send(Ok(batch)),
+            &mut metrics_update_interval,
+            &wrapper,
+            &execution_plan,
+            metric_reporter,
+        )
+        .await;
+        if sent.is_err() {
+            log::info!("Native output stream closed by the JVM");
+            break;
+        }
+    }
 
-                // value_queue <- hasNext=false
-                while {
-                    jni_call!(BlazeCallNativeWrapper(wrapper.as_obj()).isFinished() -> jboolean).unwrap() != JNI_TRUE &&
-                    jni_call!(BlazeCallNativeWrapper(wrapper.as_obj()).enqueueWithTimeout(obj_false.as_obj()) -> jboolean).unwrap() != JNI_TRUE
-                } {}
-
-                log::info!("Updating blaze exec metrics ...");
-                let metrics = jni_call!(
-                    BlazeCallNativeWrapper(wrapper.as_obj()).getMetrics() -> JObject
-                ).unwrap();
-
-                update_spark_metric_node(
-                    metrics,
-                    execution_plan.clone(),
-                ).unwrap();
-
-                log::info!("Blaze native executing finished.");
-                log::info!("  total loaded batches: {}", total_batches);
-                log::info!("  total loaded rows: {}", total_rows);
-                std::mem::drop(runtime);
-            })
-            .catch_unwind()
-            .await
-            .map_err(|err| {
-                let panic_message = panic_message::panic_message(&err);
-
-                let e = if jni_exception_check!()? {
-                    log::error!("native execution panics with an java exception");
-                    log::error!("panic message: {}", panic_message);
-                    jni_exception_occurred!()?.into()
-                } else {
-                    log::error!("native execution panics");
-                    log::error!("panic message: {}", panic_message);
-                    jni_new_object!(
-                        JavaRuntimeException,
-                        jni_new_string!("blaze native panics")?,
-                        JObject::null()
-                    )?
-                };
-
-                // error_queue <- exception
-
-                while jni_call!(
-                    BlazeCallNativeWrapper(wrapper_clone.as_obj()).isFinished() -> jboolean
-                ).unwrap() != JNI_TRUE {
-                    let enqueued = jni_call!(
-                        BlazeCallNativeWrapper(wrapper_clone.as_obj()).enqueueError(e) -> jboolean
-                    )?;
-                    if enqueued == JNI_TRUE {
-                        break;
-                    }
+    // report statistics of shuffle output partitions before finishing
+    if let Some(shuffle_writer) = execution_plan
+        .as_any()
+        .downcast_ref::<ShuffleWriterExec>()
+        .filter(|exec| exec.partition_stats().is_some())
+    {
+        let result = ShuffleWriteResult::try_from(shuffle_writer)
+            .map_err(|err| DataFusionError::Execution(err.to_string()))?;
+        let raw_result = jni_byte_array_from_slice!(&result.encode_to_vec())?;
+        jni_call!(
+            BlazeCallNativeWrapper(wrapper.as_obj())
+                .setRawShuffleWriteResult(JObject::from(raw_result)) -> ()
+        )?;
+    }
+
+    log::info!("Updating blaze exec metrics ...");
+    update_metrics(&wrapper, execution_plan, metric_reporter)?;
+
+    log::info!("Blaze native executing finished.");
+    log::info!("  total loaded batches: {}", total_batches);
+    log::info!("  total loaded rows: {}", total_rows);
+    Ok(())
+}
+
+/// Waits for `future`, updating metrics of the running task at every tick of
+/// the interval meanwhile
+async fn update_metrics_until<F: Future>(
+    future: F,
+    interval: &mut Option<tokio::time::Interval>,
+    wrapper: &GlobalRef,
+    execution_plan: &Arc<dyn ExecutionPlan>,
+    metric_reporter: &mut SparkMetricReporter,
+) -> F::Output {
+    tokio::pin!(future);
+    loop {
+        tokio::select! {
+            output = &mut future => return output,
+            _ = tick(interval) => {
+                let plan = execution_plan.clone();
+                if let Err(err) = update_metrics(wrapper, plan, metric_reporter) {
+                    log::warn!("Error updating metrics of running task: {}", err);
                 }
-                log::info!("Blaze native executing exited with error.");
-                std::mem::drop(runtime_clone);
-                datafusion::error::Result::Ok(())
-            })
-            .unwrap();
+            }
+        }
+    }
+}
+
+/// Next tick of the interval, never completes if there is no interval
+async fn tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => futures::future::pending().await,
+    }
+}
+
+fn update_metrics(
+    wrapper: &GlobalRef,
+    execution_plan: Arc<dyn ExecutionPlan>,
+    metric_reporter: &mut SparkMetricReporter,
+) -> datafusion::error::Result<()> {
+    let metrics = jni_call!(
+        BlazeCallNativeWrapper(wrapper.as_obj()).getMetrics() -> JObject
+    )?;
+    metric_reporter.update_spark_metric_node(metrics, execution_plan)?;
+    jni_delete_local_ref!(metrics)?;
+    Ok(())
+}
+
+/// Reports the error of a failed task to the jvm as a serialized NativeError,
+/// returns its message. Errors are not reported while a jvm exception is pending,
+/// which is then the cause of the failure seen by the jvm.
+fn report_native_error(wrapper: &GlobalRef, err: DataFusionError) -> String {
+    let err = NativeError::wrap(err);
+    let native_error = NativeError::find(&err).expect("missing native error");
+    let message = native_error.to_string();
+
+    if !jni_exception_check!().unwrap_or(true) {
+        let raw_error = protobuf::NativeError::from(native_error).encode_to_vec();
+        let reported = jni_byte_array_from_slice!(&raw_error).and_then(|raw_error| {
+            jni_call!(
+                BlazeCallNativeWrapper(wrapper.as_obj())
+                    .setRawNativeError(JObject::from(raw_error)) -> ()
+            )
         });
+        if let Err(err) = reported {
+            log::warn!("Error reporting native error to the JVM: {}", err);
+        }
+    }
+    message
+}
+
+/// Output of a native task exported to the jvm as an arrow stream. Releasing the
+/// stream drops the receiver, which stops the producing task.
+struct NativeBatchReader {
+    schema: SchemaRef,
+    receiver: Receiver<ArrowResult<RecordBatch>>,
+}
 
-        log::info!("Blaze native thread created");
+impl Iterator for NativeBatchReader {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // called from the jvm thread, outside of the runtime
+        self.receiver.blocking_recv()
+    }
+}
+
+impl RecordBatchReader for NativeBatchReader {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+/// Shuts down the shared runtime when the executor exits, waiting a bounded
+/// time for running native tasks.
+#[allow(non_snake_case)]
+#[no_mangle]
+pub extern "system" fn Java_org_apache_spark_sql_blaze_JniBridge_finalizeNative(
+    _: JNIEnv,
+    _: JClass,
+) {
+    if let Err(err) = std::panic::catch_unwind(|| {
+        let runtime = TOKIO_RUNTIME
+            .get()
+            .and_then(|runtime| runtime.lock().unwrap().take());
+        if let Some(runtime) = runtime {
+            log::info!("Shutting down blaze native runtime");
+            runtime.shutdown_timeout(TOKIO_RUNTIME_SHUTDOWN_TIMEOUT);
+        }
     }) {
         handle_unwinded(err);
     }
 }
 
+fn running_native_tasks() -> &'static Mutex<HashMap<u64, CancelHandle>> {
+    RUNNING_NATIVE_TASKS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn runtime_handle() -> Handle {
+    TOKIO_RUNTIME
+        .get()
+        .expect("native runtime is not initialized")
+        .lock()
+        .unwrap()
+        .as_ref()
+        .expect("native runtime is shut down")
+        .handle()
+        .clone()
+}
+
+/// Validates a serialized PhysicalPlanNode without executing it, returns a
+/// serialized PlanValidationResult listing all unsupported nodes and expressions.
+#[allow(non_snake_case)]
+#[no_mangle]
+pub extern "system" fn Java_org_apache_spark_sql_blaze_JniBridge_validatePlan(
+    env: JNIEnv,
+    _: JClass,
+    raw_plan: jbyteArray,
+) -> jbyteArray {
+    match std::panic::catch_unwind(|| {
+        // init jni java classes, may be called on the driver before initNative
+        JavaClasses::init(&env);
+        init_logging();
+
+        let plan = PhysicalPlanNode::decode(
+            jni_convert_byte_array!(raw_plan).unwrap().as_slice(),
+        )
+        .unwrap();
+        let result = validate_physical_plan(&plan);
+        jni_byte_array_from_slice!(&result.encode_to_vec()).unwrap()
+    }) {
+        Err(err) => {
+            handle_unwinded(err);
+            JObject::null().into_inner()
+        }
+        Ok(raw_result) => raw_result,
+    }
+}
+
+/// Writes the raw task definition of a failed task into the dir configured by
+/// `spark.blaze.dumpFailedTasksDir`, which can be inspected or replayed later
+fn dump_failed_task_definition(raw_task_definition: &[u8]) {
+    let dir = match TASK_DUMP_DIR.get() {
+        Some(Some(dir)) => dir,
+        _ => return,
+    };
+    let task_name = TaskDefinition::decode(raw_task_definition)
+        .ok()
+        .and_then(|task_definition| task_definition.task_id)
+        .map(|task_id| {
+            format!(
+                "{}-{}-{}",
+                task_id.job_id, task_id.stage_id, task_id.partition_id
+            )
+        })
+        .unwrap_or_else(|| "unknown".to_owned());
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default();
+    let path = dir.join(format!("task-{}-{}.pb", task_name, timestamp));
+
+    match std::fs::create_dir_all(dir)
+        .and_then(|_| std::fs::write(&path, raw_task_definition))
+    {
+        Ok(()) => log::info!("Dumped task definition of failed task to {:?}", path),
+        Err(err) => log::warn!("Error dumping task definition to {:?}: {}", path, err),
+    }
+}
+
 fn is_jvm_interrupted() -> datafusion::error::Result<bool> {
     let interrupted_exception_class = "java.lang.InterruptedException";
     if jni_exception_check!()? {
@@ -1,4 +1,5 @@
 mod exec;
+mod logging;
 mod metrics;
 
 #[cfg(feature = "mm")]
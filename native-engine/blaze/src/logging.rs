@@ -0,0 +1,138 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SyncSender};
+use std::time::{Duration, Instant};
+
+use datafusion_ext::{
+    jni_call_static, jni_delete_local_ref, jni_exception_check, jni_exception_clear,
+    jni_new_string,
+};
+use jni::objects::JObject;
+use jni::sys::jint;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use once_cell::sync::OnceCell;
+
+// records queued for the jvm, later records are dropped when the queue is full
+const LOG_QUEUE_CAPACITY: usize = 10000;
+const LOG_LEVEL_SYNC_INTERVAL: Duration = Duration::from_secs(10);
+
+static LOGGER: OnceCell<JniLogger> = OnceCell::new();
+static NUM_DROPPED_RECORDS: AtomicU64 = AtomicU64::new(0);
+thread_local! {
+    // records of the forwarding thread are ignored, since forwarding them
+    // would produce more records
+    static IS_LOGGER_THREAD: Cell<bool> = Cell::new(false);
+}
+
+/// Installs the logger forwarding native logs to the SLF4J loggers of the JVM,
+/// with the most verbose level of the `blaze.native` logger and its configured
+/// child loggers. JavaClasses must be initialized first.
+pub fn init_logging() {
+    LOGGER.get_or_init(|| {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(LOG_QUEUE_CAPACITY);
+        std::thread::Builder::new()
+            .name("blaze-native-logger".to_owned())
+            .spawn(move || forward_records(receiver))
+            .expect("error spawning native logger thread");
+        sync_log_level();
+        JniLogger { sender }
+    });
+    let _ = log::set_logger(LOGGER.get().unwrap());
+}
+
+struct LogRecord {
+    level: Level,
+    target: String,
+    message: String,
+}
+
+/// Logger queuing records for the forwarding thread, which calls into the jvm.
+/// Logging never blocks the calling thread.
+struct JniLogger {
+    sender: SyncSender<LogRecord>,
+}
+
+impl Log for JniLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level() && !IS_LOGGER_THREAD.with(Cell::get)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let thread = std::thread::current();
+        let record = LogRecord {
+            level: record.level(),
+            target: record.target().to_owned(),
+            message: format!("[{}] {}", thread.name().unwrap_or("-"), record.args()),
+        };
+        if self.sender.try_send(record).is_err() {
+            NUM_DROPPED_RECORDS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn forward_records(receiver: Receiver<LogRecord>) {
+    IS_LOGGER_THREAD.with(|is_logger_thread| is_logger_thread.set(true));
+    let mut last_synced = Instant::now();
+    loop {
+        match receiver.recv_timeout(LOG_LEVEL_SYNC_INTERVAL) {
+            Ok(record) => forward_record(record),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        let num_dropped_records = NUM_DROPPED_RECORDS.swap(0, Ordering::Relaxed);
+        if num_dropped_records > 0 {
+            forward_record(LogRecord {
+                level: Level::Warn,
+                target: module_path!().to_owned(),
+                message: format!("{} native log records dropped", num_dropped_records),
+            });
+        }
+        if last_synced.elapsed() >= LOG_LEVEL_SYNC_INTERVAL {
+            sync_log_level();
+            last_synced = Instant::now();
+        }
+    }
+}
+
+fn forward_record(record: LogRecord) {
+    let forwarded = || -> datafusion::error::Result<()> {
+        let target = JObject::from(jni_new_string!(&record.target)?);
+        let message = JObject::from(jni_new_string!(&record.message)?);
+        let result = jni_call_static!(
+            JniBridge.nativeLog(record.level as jint, target, message) -> ()
+        );
+        // the thread is never detached, so local refs must be released
+        jni_delete_local_ref!(target)?;
+        jni_delete_local_ref!(message)?;
+        result
+    };
+    if forwarded().is_err() && jni_exception_check!().unwrap_or(false) {
+        let _ = jni_exception_clear!();
+    }
+}
+
+/// Updates the max level of native logs from the levels of the jvm loggers, so
+/// that records disabled by every logger are not even formatted.
+fn sync_log_level() {
+    let level_filter = match jni_call_static!(JniBridge.getNativeLogLevel() -> jint) {
+        Ok(0) => LevelFilter::Off,
+        Ok(1) => LevelFilter::Error,
+        Ok(2) => LevelFilter::Warn,
+        Ok(3) => LevelFilter::Info,
+        Ok(4) => LevelFilter::Debug,
+        Ok(_) => LevelFilter::Trace,
+        Err(_) => {
+            if jni_exception_check!().unwrap_or(false) {
+                let _ = jni_exception_clear!();
+            }
+            return;
+        }
+    };
+    log::set_max_level(level_filter);
+}
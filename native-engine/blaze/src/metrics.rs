@@ -1,55 +1,151 @@
+use std::any::Any;
+use std::collections::HashMap;
 use std::sync::Arc;
 
+use datafusion::physical_plan::coalesce_batches::CoalesceBatchesExec;
+use datafusion::physical_plan::metrics::MetricValue;
 use datafusion::physical_plan::ExecutionPlan;
 use jni::objects::JObject;
 
 use datafusion_ext::jni_call;
+use datafusion_ext::jni_delete_local_ref;
 use datafusion_ext::jni_new_string;
+use datafusion_ext::merge_exec::MergeExec;
 
-const REPORTED_METRICS: &[&str] = &[
-    "input_rows",
-    "input_batches",
-    "output_rows",
-    "output_batches",
-    "elapsed_compute",
-    "join_time",
+/// How a native operator is mapped to the metric nodes built by the spark plans
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MetricMapping {
+    /// the operator has a metric node, its inputs are mapped to the children
+    /// of the node
+    Node,
+    /// the operator is added by the native side without a metric node, its
+    /// metrics are dropped and its input is mapped to the node of the operator
+    Transparent,
+}
+
+/// Operators added by the native side without a spark plan, all other
+/// operators are mapped with `MetricMapping::Node`
+const METRIC_MAPPINGS: &[(fn(&dyn Any) -> bool, MetricMapping)] = &[
+    // added by intra-task parallelism and plan rewrites
+    (is::<MergeExec>, MetricMapping::Transparent),
+    (is::<CoalesceBatchesExec>, MetricMapping::Transparent),
 ];
 
-pub fn update_spark_metric_node(
-    metric_node: JObject,
-    execution_plan: Arc<dyn ExecutionPlan>,
-) -> datafusion::error::Result<()> {
-    // update current node
-    update_metrics(
-        metric_node,
-        &execution_plan
+fn is<T: 'static>(any: &dyn Any) -> bool {
+    any.is::<T>()
+}
+
+fn metric_mapping(plan: &dyn ExecutionPlan) -> MetricMapping {
+    METRIC_MAPPINGS
+        .iter()
+        .find(|(matches, _)| matches(plan.as_any()))
+        .map(|&(_, mapping)| mapping)
+        .unwrap_or(MetricMapping::Node)
+}
+
+/// Reports metrics of a running plan into the spark metric tree, metrics and
+/// nodes not declared on the spark side are ignored. Spark metrics are
+/// accumulated, so only the increases since the last report are added.
+#[derive(Default)]
+pub struct SparkMetricReporter {
+    // last reported values by pre-order index of the operator and metric name
+    reported: HashMap<(usize, String), i64>,
+}
+
+impl SparkMetricReporter {
+    pub fn update_spark_metric_node(
+        &mut self,
+        metric_node: JObject,
+        execution_plan: Arc<dyn ExecutionPlan>,
+    ) -> datafusion::error::Result<()> {
+        let mut operator_index = 0;
+        self.update_node(metric_node, execution_plan, &mut operator_index)
+    }
+
+    fn update_node(
+        &mut self,
+        metric_node: JObject,
+        execution_plan: Arc<dyn ExecutionPlan>,
+        operator_index: &mut usize,
+    ) -> datafusion::error::Result<()> {
+        *operator_index += 1;
+        if metric_mapping(execution_plan.as_ref()) == MetricMapping::Transparent {
+            for child_plan in execution_plan.children() {
+                self.update_node(metric_node, child_plan, operator_index)?;
+            }
+            return Ok(());
+        }
+
+        // update current node
+        self.update_metrics(metric_node, execution_plan.as_ref(), *operator_index)?;
+
+        // update children nodes
+        for (i, child_plan) in execution_plan.children().into_iter().enumerate() {
+            let child_metric_node = jni_call!(
+                SparkMetricNode(metric_node).getChild(i as i32) -> JObject
+            )?;
+            if child_metric_node.is_null() {
+                log::debug!("No metric node for input {} of operator, ignored", i);
+                continue;
+            }
+            self.update_node(child_metric_node, child_plan, operator_index)?;
+        }
+        Ok(())
+    }
+
+    fn update_metrics(
+        &mut self,
+        metric_node: JObject,
+        execution_plan: &dyn ExecutionPlan,
+        operator_index: usize,
+    ) -> datafusion::error::Result<()> {
+        // metrics of all partitions and labels (like scanned files) are summed
+        let metrics = execution_plan
             .metrics()
             .unwrap_or_default()
-            .iter()
-            .map(|m| m.value())
-            .map(|m| (m.name(), m.as_usize() as i64))
-            .collect::<Vec<_>>(),
-    )?;
-
-    // update children nodes
-    for (i, child_plan) in execution_plan.children().iter().enumerate() {
-        let child_metric_node = jni_call!(
-            SparkMetricNode(metric_node).getChild(i as i32) -> JObject
-        )?;
-        update_spark_metric_node(child_metric_node, child_plan.clone())?;
+            .aggregate_by_name();
+
+        for metric in metrics.iter() {
+            let name = metric.value().name();
+            let value = match metric_value(metric.value()) {
+                Some(value) => value,
+                None => continue,
+            };
+            let reported = self
+                .reported
+                .entry((operator_index, name.to_owned()))
+                .or_default();
+            // forwarded values only grow, so deltas are never negative
+            if value <= *reported {
+                continue;
+            }
+            let delta = value - *reported;
+            let jname = jni_new_string!(name)?;
+            jni_call!(SparkMetricNode(metric_node).add(jname, delta) -> ())?;
+            jni_delete_local_ref!(jname.into())?;
+            *reported = value;
+        }
+        Ok(())
     }
-    Ok(())
 }
 
-fn update_metrics(
-    metric_node: JObject,
-    metric_values: &[(&str, i64)],
-) -> datafusion::error::Result<()> {
-    for &(name, value) in metric_values {
-        if REPORTED_METRICS.contains(&name) {
-            let jname = jni_new_string!(&name)?;
-            jni_call!(SparkMetricNode(metric_node).add(jname, value) -> ())?;
+/// Value of a native metric reported to its spark metric: times in nanoseconds,
+/// bytes and counts as they are. Gauges of current values (like `mem_used`) go
+/// up and down and cannot be accumulated, so only peak gauges are forwarded.
+/// Timestamps are not forwarded.
+fn metric_value(value: &MetricValue) -> Option<i64> {
+    match value {
+        MetricValue::OutputRows(count)
+        | MetricValue::SpillCount(count)
+        | MetricValue::SpilledBytes(count)
+        | MetricValue::Count { count, .. } => Some(count.value() as i64),
+        MetricValue::Gauge { name, gauge } if name.starts_with("peak_") => {
+            Some(gauge.value() as i64)
+        }
+        MetricValue::CurrentMemoryUsage(_) | MetricValue::Gauge { .. } => None,
+        MetricValue::ElapsedCompute(time) | MetricValue::Time { time, .. } => {
+            Some(time.value() as i64)
         }
+        MetricValue::StartTimestamp(_) | MetricValue::EndTimestamp(_) => None,
     }
-    Ok(())
 }
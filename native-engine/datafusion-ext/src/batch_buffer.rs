@@ -1,8 +1,12 @@
+use std::any::Any;
 use std::sync::Arc;
 
 use datafusion::arrow::array::make_builder;
 use datafusion::arrow::array::*;
+use datafusion::arrow::compute::concat;
+use datafusion::arrow::datatypes::DataType;
 use datafusion::arrow::datatypes::Schema;
+use datafusion::arrow::datatypes::TimeUnit;
 use datafusion::arrow::error::Result as ArrowResult;
 use datafusion::arrow::record_batch::RecordBatch;
 
@@ -10,6 +14,8 @@ pub struct MutableRecordBatch {
     pub(crate) arrays: Vec<Box<dyn ArrayBuilder>>,
     target_batch_size: usize,
     current_size: usize,
+    /// size of variable-length data and concatenated arrays appended so far
+    data_size: usize,
     schema: Arc<Schema>,
 }
 
@@ -20,6 +26,7 @@ impl MutableRecordBatch {
             arrays,
             target_batch_size,
             current_size: 0,
+            data_size: 0,
             schema,
         }
     }
@@ -34,6 +41,7 @@ impl MutableRecordBatch {
     pub fn output(&mut self) -> ArrowResult<RecordBatch> {
         let result = make_batch(self.schema.clone(), self.arrays.drain(..).collect());
         self.current_size = 0;
+        self.data_size = 0;
         result
     }
 
@@ -41,6 +49,29 @@ impl MutableRecordBatch {
         self.current_size += size;
     }
 
+    pub fn append_data_size(&mut self, size: usize) {
+        self.data_size += size;
+    }
+
+    /// Estimated memory allocated by the builders. Fixed-width slots and validity
+    /// bitmaps are preallocated for `target_batch_size` rows and grow with more
+    /// rows, variable-length data is recorded with `append_data_size`. Nothing is
+    /// allocated after `output` moved the builders out.
+    pub fn mem_size(&self) -> usize {
+        if self.arrays.is_empty() {
+            return 0;
+        }
+        let num_rows = self.current_size.max(self.target_batch_size);
+        let slot_size = self
+            .schema
+            .fields()
+            .iter()
+            .map(|field| builder_slot_size(field.data_type()))
+            .sum::<usize>();
+        let bitmap_size = (num_rows + 7) / 8 * self.schema.fields().len();
+        num_rows * slot_size + bitmap_size + self.data_size
+    }
+
     #[inline]
     pub fn is_full(&self) -> bool {
         self.current_size > self.target_batch_size
@@ -53,11 +84,128 @@ pub fn new_arrays(schema: &Arc<Schema>, batch_size: usize) -> Vec<Box<dyn ArrayB
         .iter()
         .map(|field| {
             let dt = field.data_type();
-            make_builder(dt, batch_size)
+            new_array_builder(dt, batch_size)
         })
         .collect::<Vec<_>>()
 }
 
+/// Create a typed builder for flat types, other types (nested, dictionary,
+/// timestamp with timezone, etc.) are buffered with [`ConcatArrayBuilder`]
+fn new_array_builder(dt: &DataType, batch_size: usize) -> Box<dyn ArrayBuilder> {
+    match dt {
+        DataType::Boolean
+        | DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64
+        | DataType::Float32
+        | DataType::Float64
+        | DataType::Date32
+        | DataType::Date64
+        | DataType::Time32(TimeUnit::Second)
+        | DataType::Time32(TimeUnit::Millisecond)
+        | DataType::Time64(TimeUnit::Microsecond)
+        | DataType::Time64(TimeUnit::Nanosecond)
+        | DataType::Timestamp(_, None)
+        | DataType::Utf8
+        | DataType::LargeUtf8
+        | DataType::Binary
+        | DataType::LargeBinary
+        | DataType::Decimal(_, _) => make_builder(dt, batch_size),
+        _ => Box::new(ConcatArrayBuilder::new(dt.clone())),
+    }
+}
+
+/// Bytes preallocated for each row by the builder created with `new_array_builder`
+fn builder_slot_size(dt: &DataType) -> usize {
+    match dt {
+        DataType::Boolean | DataType::Int8 | DataType::UInt8 => 1,
+        DataType::Int16 | DataType::UInt16 => 2,
+        DataType::Int32
+        | DataType::UInt32
+        | DataType::Float32
+        | DataType::Date32
+        | DataType::Time32(TimeUnit::Second)
+        | DataType::Time32(TimeUnit::Millisecond) => 4,
+        DataType::Int64
+        | DataType::UInt64
+        | DataType::Float64
+        | DataType::Date64
+        | DataType::Time64(TimeUnit::Microsecond)
+        | DataType::Time64(TimeUnit::Nanosecond)
+        | DataType::Timestamp(_, None) => 8,
+        DataType::Decimal(_, _) => 16,
+        // offset and one byte of initial value capacity
+        DataType::Utf8 | DataType::Binary => 5,
+        DataType::LargeUtf8 | DataType::LargeBinary => 9,
+        // concatenated arrays are counted as data size
+        _ => 0,
+    }
+}
+
+/// A builder collecting whole arrays and concatenating them on finish, used
+/// for types without a builder that can be appended from another array.
+pub struct ConcatArrayBuilder {
+    data_type: DataType,
+    arrays: Vec<ArrayRef>,
+    len: usize,
+}
+
+impl ConcatArrayBuilder {
+    pub fn new(data_type: DataType) -> Self {
+        Self {
+            data_type,
+            arrays: vec![],
+            len: 0,
+        }
+    }
+
+    pub fn append_array(&mut self, array: ArrayRef) {
+        self.len += array.len();
+        self.arrays.push(array);
+    }
+}
+
+impl ArrayBuilder for ConcatArrayBuilder {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        let arrays = std::mem::take(&mut self.arrays);
+        self.len = 0;
+        match arrays.len() {
+            0 => new_empty_array(&self.data_type),
+            1 => arrays.into_iter().next().unwrap(),
+            _ => {
+                let arrays = arrays.iter().map(|a| a.as_ref()).collect::<Vec<_>>();
+                // arrays are all of the same type, so concatenating never fails
+                concat(&arrays).unwrap()
+            }
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_box_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
 pub fn make_batch(
     schema: Arc<Schema>,
     mut arrays: Vec<Box<dyn ArrayBuilder>>,
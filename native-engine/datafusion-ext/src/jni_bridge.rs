@@ -120,6 +120,21 @@ macro_rules! jni_get_object_class {
     }};
 }
 
+#[macro_export]
+macro_rules! jni_is_instance_of {
+    ($obj:expr, $clsname:ident) => {{
+        $crate::jni_bridge::THREAD_JNIENV.with(|env| {
+            $crate::jni_map_error_with_env!(
+                env,
+                env.is_instance_of(
+                    $obj,
+                    paste::paste! {$crate::jni_bridge::JavaClasses::get().[<c $clsname>].class}
+                )
+            )
+        })
+    }};
+}
+
 #[macro_export]
 macro_rules! jni_call {
     ($clsname:ident($obj:expr).$method:ident($($args:expr),* $(,)?) -> $ret:ty) => {{
@@ -172,6 +187,15 @@ macro_rules! jni_convert_byte_array {
     }};
 }
 
+#[macro_export]
+macro_rules! jni_byte_array_from_slice {
+    ($value:expr) => {{
+        $crate::jni_bridge::THREAD_JNIENV.with(|env| {
+            $crate::jni_map_error_with_env!(env, env.byte_array_from_slice($value))
+        })
+    }};
+}
+
 #[macro_export]
 macro_rules! jni_new_global_ref {
     ($obj:expr) => {{
@@ -272,6 +296,7 @@ pub struct JavaClasses<'a> {
     pub cSparkMetricNode: SparkMetricNode<'a>,
 
     pub cBlazeCallNativeWrapper: BlazeCallNativeWrapper<'a>,
+    pub cBlazeFileSegmentSeekableByteChannel: BlazeFileSegmentSeekableByteChannel<'a>,
 }
 
 #[allow(clippy::non_send_fields_in_send_ty)]
@@ -325,6 +350,8 @@ impl JavaClasses<'static> {
                 cSparkMetricNode: SparkMetricNode::new(env).unwrap(),
 
                 cBlazeCallNativeWrapper: BlazeCallNativeWrapper::new(env).unwrap(),
+                cBlazeFileSegmentSeekableByteChannel:
+                    BlazeFileSegmentSeekableByteChannel::new(env).unwrap(),
             };
             log::info!("Initializing JavaClasses finished");
             java_classes
@@ -358,6 +385,10 @@ pub struct JniBridge<'a> {
     pub method_readFSDataInputStream_ret: JavaType,
     pub method_seekByteChannel: JStaticMethodID<'a>,
     pub method_seekByteChannel_ret: JavaType,
+    pub method_getNativeLogLevel: JStaticMethodID<'a>,
+    pub method_getNativeLogLevel_ret: JavaType,
+    pub method_nativeLog: JStaticMethodID<'a>,
+    pub method_nativeLog_ret: JavaType,
 }
 impl<'a> JniBridge<'a> {
     pub const SIG_TYPE: &'static str = "org/apache/spark/sql/blaze/JniBridge";
@@ -422,6 +453,18 @@ impl<'a> JniBridge<'a> {
                 "(Ljava/nio/channels/SeekableByteChannel;J)J",
             )?,
             method_seekByteChannel_ret: JavaType::Primitive(Primitive::Long),
+            method_getNativeLogLevel: env.get_static_method_id(
+                class,
+                "getNativeLogLevel",
+                "()I",
+            )?,
+            method_getNativeLogLevel_ret: JavaType::Primitive(Primitive::Int),
+            method_nativeLog: env.get_static_method_id(
+                class,
+                "nativeLog",
+                "(ILjava/lang/String;Ljava/lang/String;)V",
+            )?,
+            method_nativeLog_ret: JavaType::Primitive(Primitive::Void),
         })
     }
 }
@@ -832,18 +875,14 @@ impl<'a> SparkMetricNode<'a> {
 #[allow(non_snake_case)]
 pub struct BlazeCallNativeWrapper<'a> {
     pub class: JClass<'a>,
-    pub method_isFinished: JMethodID<'a>,
-    pub method_isFinished_ret: JavaType,
     pub method_getRawTaskDefinition: JMethodID<'a>,
     pub method_getRawTaskDefinition_ret: JavaType,
     pub method_getMetrics: JMethodID<'a>,
     pub method_getMetrics_ret: JavaType,
-    pub method_enqueueWithTimeout: JMethodID<'a>,
-    pub method_enqueueWithTimeout_ret: JavaType,
-    pub method_enqueueError: JMethodID<'a>,
-    pub method_enqueueError_ret: JavaType,
-    pub method_dequeueWithTimeout: JMethodID<'a>,
-    pub method_dequeueWithTimeout_ret: JavaType,
+    pub method_setRawShuffleWriteResult: JMethodID<'a>,
+    pub method_setRawShuffleWriteResult_ret: JavaType,
+    pub method_setRawNativeError: JMethodID<'a>,
+    pub method_setRawNativeError_ret: JavaType,
 }
 impl<'a> BlazeCallNativeWrapper<'a> {
     pub const SIG_TYPE: &'static str =
@@ -853,8 +892,6 @@ impl<'a> BlazeCallNativeWrapper<'a> {
         let class = get_global_jclass(env, Self::SIG_TYPE)?;
         Ok(BlazeCallNativeWrapper {
             class,
-            method_isFinished: env.get_method_id(class, "isFinished", "()Z").unwrap(),
-            method_isFinished_ret: JavaType::Primitive(Primitive::Boolean),
             method_getRawTaskDefinition: env
                 .get_method_id(class, "getRawTaskDefinition", "()[B")
                 .unwrap(),
@@ -869,20 +906,41 @@ impl<'a> BlazeCallNativeWrapper<'a> {
                 )
                 .unwrap(),
             method_getMetrics_ret: JavaType::Object(SparkMetricNode::SIG_TYPE.to_owned()),
-            method_enqueueWithTimeout: env
-                .get_method_id(class, "enqueueWithTimeout", "(Ljava/lang/Object;)Z")
+            method_setRawShuffleWriteResult: env
+                .get_method_id(class, "setRawShuffleWriteResult", "([B)V")
                 .unwrap(),
-            method_enqueueWithTimeout_ret: JavaType::Primitive(Primitive::Boolean),
-            method_enqueueError: env
-                .get_method_id(class, "enqueueError", "(Ljava/lang/Object;)Z")
+            method_setRawShuffleWriteResult_ret: JavaType::Primitive(Primitive::Void),
+            method_setRawNativeError: env
+                .get_method_id(class, "setRawNativeError", "([B)V")
                 .unwrap(),
-            method_enqueueError_ret: JavaType::Primitive(Primitive::Boolean),
-            method_dequeueWithTimeout: env
-                .get_method_id(class, "dequeueWithTimeout", "()Ljava/lang/Object;")
-                .unwrap(),
-            method_dequeueWithTimeout_ret: JavaType::Object(
-                "java/lang/Object".to_owned(),
-            ),
+            method_setRawNativeError_ret: JavaType::Primitive(Primitive::Void),
+        })
+    }
+}
+
+#[allow(non_snake_case)]
+pub struct BlazeFileSegmentSeekableByteChannel<'a> {
+    pub class: JClass<'a>,
+    pub method_getFile: JMethodID<'a>,
+    pub method_getFile_ret: JavaType,
+    pub method_getOffset: JMethodID<'a>,
+    pub method_getOffset_ret: JavaType,
+    pub method_getLength: JMethodID<'a>,
+    pub method_getLength_ret: JavaType,
+}
+impl<'a> BlazeFileSegmentSeekableByteChannel<'a> {
+    pub const SIG_TYPE: &'static str = "org/blaze/FileSegmentSeekableByteChannel";
+
+    pub fn new(env: &JNIEnv<'a>) -> JniResult<BlazeFileSegmentSeekableByteChannel<'a>> {
+        let class = get_global_jclass(env, Self::SIG_TYPE)?;
+        Ok(BlazeFileSegmentSeekableByteChannel {
+            class,
+            method_getFile: env.get_method_id(class, "getFile", "()Ljava/io/File;")?,
+            method_getFile_ret: JavaType::Object(JavaFile::SIG_TYPE.to_owned()),
+            method_getOffset: env.get_method_id(class, "getOffset", "()J")?,
+            method_getOffset_ret: JavaType::Primitive(Primitive::Long),
+            method_getLength: env.get_method_id(class, "getLength", "()J")?,
+            method_getLength_ret: JavaType::Primitive(Primitive::Long),
         })
     }
 }
@@ -7,9 +7,16 @@ use std::sync::Arc;
 pub mod empty_partitions_exec;
 pub mod hdfs_object_store; // note: can be changed to priv once plan transforming is removed
 pub mod jni_bridge;
+pub mod merge_exec;
+pub mod native_error;
 pub mod rename_columns_exec;
+pub mod shuffle_checksum;
+pub mod shuffle_codec;
 pub mod shuffle_reader_exec;
+pub mod shuffle_schema;
+pub mod shuffle_stats;
 pub mod shuffle_writer_exec;
+pub mod spark_task_context;
 
 mod batch_buffer;
 mod spark_hash;
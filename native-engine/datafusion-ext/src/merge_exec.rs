@@ -0,0 +1,416 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::any::Any;
+use std::fmt::Formatter;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use async_trait::async_trait;
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::error::{ArrowError, Result as ArrowResult};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::DataFusionError;
+use datafusion::error::Result;
+use datafusion::execution::context::TaskContext;
+use datafusion::physical_expr::PhysicalSortExpr;
+use datafusion::physical_plan::metrics::{
+    BaselineMetrics, ExecutionPlanMetricsSet, MetricsSet,
+};
+use datafusion::physical_plan::{
+    DisplayFormatType, ExecutionPlan, Partitioning, RecordBatchStream,
+    SendableRecordBatchStream, Statistics,
+};
+use futures::future::Aborted;
+use futures::{Stream, StreamExt};
+use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+
+use crate::spark_task_context;
+
+/// Merges all input partitions into a single partition like DataFusion's
+/// CoalescePartitionsExec. Input partitions are executed in tasks spawned on
+/// the first poll, which inherit the spark task context of the native task and
+/// are aborted when the output stream is dropped.
+#[derive(Debug)]
+pub struct MergeExec {
+    input: Arc<dyn ExecutionPlan>,
+    metrics: ExecutionPlanMetricsSet,
+}
+
+impl MergeExec {
+    pub fn new(input: Arc<dyn ExecutionPlan>) -> Self {
+        Self {
+            input,
+            metrics: ExecutionPlanMetricsSet::new(),
+        }
+    }
+
+    pub fn input(&self) -> &Arc<dyn ExecutionPlan> {
+        &self.input
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for MergeExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(1)
+    }
+
+    fn output_ordering(&self) -> Option<&[PhysicalSortExpr]> {
+        None
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![self.input.clone()]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        if children.len() != 1 {
+            return Err(DataFusionError::Plan(
+                "MergeExec expects one child".to_string(),
+            ));
+        }
+        Ok(Arc::new(MergeExec::new(children[0].clone())))
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> Result<SendableRecordBatchStream> {
+        if partition != 0 {
+            return Err(DataFusionError::Internal(format!(
+                "MergeExec invalid partition {}",
+                partition
+            )));
+        }
+        let baseline_metrics = BaselineMetrics::new(&self.metrics, partition);
+        let num_input_partitions = self.input.output_partitioning().partition_count();
+        if num_input_partitions == 1 {
+            return self.input.execute(0, context);
+        }
+        Ok(Box::pin(MergeStream {
+            schema: self.schema(),
+            input: Some((self.input.clone(), context)),
+            receiver: None,
+            input_tasks: vec![],
+            baseline_metrics,
+        }))
+    }
+
+    fn metrics(&self) -> Option<MetricsSet> {
+        Some(self.metrics.clone_inner())
+    }
+
+    fn fmt_as(&self, t: DisplayFormatType, f: &mut Formatter) -> std::fmt::Result {
+        match t {
+            DisplayFormatType::Default => write!(f, "MergeExec"),
+        }
+    }
+
+    fn statistics(&self) -> Statistics {
+        self.input.statistics()
+    }
+}
+
+struct MergeStream {
+    schema: SchemaRef,
+    /// input to be executed on first polling
+    input: Option<(Arc<dyn ExecutionPlan>, Arc<TaskContext>)>,
+    receiver: Option<Receiver<ArrowResult<RecordBatch>>>,
+    input_tasks: Vec<JoinHandle<Result<(), Aborted>>>,
+    baseline_metrics: BaselineMetrics,
+}
+
+impl MergeStream {
+    fn start_input_tasks(&mut self) -> Receiver<ArrowResult<RecordBatch>> {
+        let (input, context) = self.input.take().unwrap();
+        let num_input_partitions = input.output_partitioning().partition_count();
+        let (sender, receiver) = tokio::sync::mpsc::channel(num_input_partitions.max(1));
+        for input_partition in 0..num_input_partitions {
+            let input = input.clone();
+            let context = context.clone();
+            let sender = sender.clone();
+            let input_task = spark_task_context::spawn(async move {
+                let mut stream = match input.execute(input_partition, context) {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        let e = ArrowError::ExternalError(Box::new(e));
+                        let _ = sender.send(Err(e)).await;
+                        return;
+                    }
+                };
+                while let Some(batch) = stream.next().await {
+                    let failed = batch.is_err();
+                    // stop if the receiver is dropped or after the first error
+                    if sender.send(batch).await.is_err() || failed {
+                        return;
+                    }
+                }
+            });
+            self.input_tasks.push(input_task);
+        }
+        receiver
+    }
+}
+
+impl Drop for MergeStream {
+    fn drop(&mut self) {
+        // input tasks would otherwise run until their next send
+        for input_task in &self.input_tasks {
+            input_task.abort();
+        }
+    }
+}
+
+impl RecordBatchStream for MergeStream {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+impl Stream for MergeStream {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        if self.receiver.is_none() {
+            self.receiver = Some(self.start_input_tasks());
+        }
+        let poll = self.receiver.as_mut().unwrap().poll_recv(cx);
+        self.baseline_metrics.record_poll(poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use async_trait::async_trait;
+    use datafusion::arrow::array::{as_primitive_array, Int32Array};
+    use datafusion::arrow::datatypes::{DataType, Field, Int32Type, Schema, SchemaRef};
+    use datafusion::arrow::error::ArrowError;
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::error::Result;
+    use datafusion::execution::context::TaskContext;
+    use datafusion::physical_expr::PhysicalSortExpr;
+    use datafusion::physical_plan::common::collect;
+    use datafusion::physical_plan::memory::MemoryExec;
+    use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+    use datafusion::physical_plan::{
+        ExecutionPlan, Partitioning, SendableRecordBatchStream, Statistics,
+    };
+    use datafusion::prelude::SessionContext;
+    use futures::StreamExt;
+
+    use crate::merge_exec::MergeExec;
+
+    fn test_batch(values: Vec<i32>) -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        RecordBatch::try_new(schema, vec![Arc::new(Int32Array::from(values))]).unwrap()
+    }
+
+    fn values(batches: &[RecordBatch]) -> Vec<i32> {
+        let mut values = batches
+            .iter()
+            .flat_map(|batch| {
+                as_primitive_array::<Int32Type>(batch.column(0))
+                    .values()
+                    .to_vec()
+            })
+            .collect::<Vec<_>>();
+        values.sort_unstable();
+        values
+    }
+
+    /// Input whose partitions output one batch and then never finish, except
+    /// the failing partition which outputs an error
+    #[derive(Debug)]
+    struct PendingExec {
+        num_partitions: usize,
+        failing_partition: Option<usize>,
+        num_dropped: Arc<AtomicUsize>,
+    }
+
+    struct DropCounter(Arc<AtomicUsize>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[async_trait]
+    impl ExecutionPlan for PendingExec {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn schema(&self) -> SchemaRef {
+            test_batch(vec![]).schema()
+        }
+
+        fn output_partitioning(&self) -> Partitioning {
+            Partitioning::UnknownPartitioning(self.num_partitions)
+        }
+
+        fn output_ordering(&self) -> Option<&[PhysicalSortExpr]> {
+            None
+        }
+
+        fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+            vec![]
+        }
+
+        fn with_new_children(
+            self: Arc<Self>,
+            _children: Vec<Arc<dyn ExecutionPlan>>,
+        ) -> Result<Arc<dyn ExecutionPlan>> {
+            Ok(self)
+        }
+
+        fn execute(
+            &self,
+            partition: usize,
+            _context: Arc<TaskContext>,
+        ) -> Result<SendableRecordBatchStream> {
+            let counter = DropCounter(self.num_dropped.clone());
+            let first = if self.failing_partition == Some(partition) {
+                Err(ArrowError::ComputeError("test error".to_owned()))
+            } else {
+                Ok(test_batch(vec![partition as i32]))
+            };
+            let stream = futures::stream::once(async move { first })
+                .chain(futures::stream::pending())
+                .map(move |batch| {
+                    let _counter = &counter;
+                    batch
+                });
+            Ok(Box::pin(RecordBatchStreamAdapter::new(
+                self.schema(),
+                stream,
+            )))
+        }
+
+        fn statistics(&self) -> Statistics {
+            Statistics::default()
+        }
+    }
+
+    fn wait_dropped(num_dropped: &AtomicUsize, expected: usize) {
+        let start = Instant::now();
+        while num_dropped.load(Ordering::SeqCst) < expected {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "inputs not dropped"
+            );
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_merge_partitions() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let partitions = vec![
+            vec![test_batch(vec![1, 2]), test_batch(vec![3])],
+            vec![],
+            vec![test_batch(vec![4, 5, 6])],
+        ];
+        let schema = partitions[0][0].schema();
+        let input = MemoryExec::try_new(&partitions, schema, None).unwrap();
+        let merge = MergeExec::new(Arc::new(input));
+        assert_eq!(merge.output_partitioning().partition_count(), 1);
+
+        let task_ctx = SessionContext::new().task_ctx();
+        let batches = runtime
+            .block_on(async { collect(merge.execute(0, task_ctx)?).await })
+            .unwrap();
+        assert_eq!(values(&batches), vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_single_partition_passthrough() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let partitions = vec![vec![test_batch(vec![3, 1]), test_batch(vec![2])]];
+        let schema = partitions[0][0].schema();
+        let input = MemoryExec::try_new(&partitions, schema, None).unwrap();
+        let merge = MergeExec::new(Arc::new(input));
+
+        // batches are output in their original order
+        let task_ctx = SessionContext::new().task_ctx();
+        let batches = runtime
+            .block_on(async { collect(merge.execute(0, task_ctx)?).await })
+            .unwrap();
+        assert_eq!(batches, partitions[0]);
+    }
+
+    #[test]
+    fn test_error_propagation() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let num_dropped = Arc::new(AtomicUsize::new(0));
+        let merge = MergeExec::new(Arc::new(PendingExec {
+            num_partitions: 3,
+            failing_partition: Some(1),
+            num_dropped: num_dropped.clone(),
+        }));
+
+        // the error is output although the other inputs never finish
+        let task_ctx = SessionContext::new().task_ctx();
+        let err = runtime
+            .block_on(async { collect(merge.execute(0, task_ctx)?).await })
+            .unwrap_err();
+        assert!(err.to_string().contains("test error"));
+        wait_dropped(&num_dropped, 3);
+    }
+
+    #[test]
+    fn test_drop_aborts_input_tasks() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let num_dropped = Arc::new(AtomicUsize::new(0));
+        let merge = MergeExec::new(Arc::new(PendingExec {
+            num_partitions: 3,
+            failing_partition: None,
+            num_dropped: num_dropped.clone(),
+        }));
+
+        let task_ctx = SessionContext::new().task_ctx();
+        runtime.block_on(async {
+            let mut stream = merge.execute(0, task_ctx).unwrap();
+            assert!(stream.next().await.unwrap().is_ok());
+            assert_eq!(num_dropped.load(Ordering::SeqCst), 0);
+        });
+        wait_dropped(&num_dropped, 3);
+    }
+}
@@ -0,0 +1,348 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Structured errors of native execution reported to the JVM.
+//!
+//! Every operator of an executed plan is wrapped into an [`OperatorErrorExec`],
+//! which attaches the name of the operator to the first error it outputs, so
+//! the failing operator is known even if the error is passed up through its
+//! parents.
+
+use std::any::Any;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::error::ArrowError;
+use datafusion::error::{DataFusionError, Result};
+use datafusion::execution::context::TaskContext;
+use datafusion::physical_expr::PhysicalSortExpr;
+use datafusion::physical_plan::metrics::MetricsSet;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::{
+    DisplayFormatType, ExecutionPlan, Partitioning, SendableRecordBatchStream, Statistics,
+};
+use futures::StreamExt;
+
+use crate::shuffle_reader_exec::ShuffleCorruptedError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NativeErrorKind {
+    Execution,
+    Io,
+    ResourcesExhausted,
+    Plan,
+    ArithmeticOverflow,
+    Cancelled,
+    ShuffleCorrupted,
+}
+
+#[derive(Debug)]
+pub struct NativeError {
+    pub kind: NativeErrorKind,
+    /// name of the failing operator, if known
+    pub operator: Option<String>,
+    pub cause: DataFusionError,
+}
+
+impl NativeError {
+    pub fn new(cause: DataFusionError, operator: Option<String>) -> Self {
+        let kind = error_chain(&cause)
+            .find_map(error_kind)
+            .unwrap_or(NativeErrorKind::Execution);
+        Self {
+            kind,
+            operator,
+            cause,
+        }
+    }
+
+    pub fn cancelled() -> Self {
+        Self {
+            kind: NativeErrorKind::Cancelled,
+            operator: None,
+            cause: DataFusionError::Execution("native execution cancelled".to_owned()),
+        }
+    }
+
+    /// Finds the native error in the chain of `err`
+    pub fn find(err: &DataFusionError) -> Option<&NativeError> {
+        error_chain(err).find_map(|err| err.downcast_ref::<NativeError>())
+    }
+
+    /// Wraps `err` into a native error of an unknown operator, unless its chain
+    /// already contains one
+    pub fn wrap(err: DataFusionError) -> DataFusionError {
+        if Self::find(&err).is_some() {
+            return err;
+        }
+        DataFusionError::External(Box::new(Self::new(err, None)))
+    }
+
+    /// Native shuffle id and block index of the corrupted shuffle block which
+    /// caused the error, if known
+    pub fn corrupted_block(&self) -> Option<(&str, usize)> {
+        error_chain(&self.cause)
+            .filter_map(|err| err.downcast_ref::<ShuffleCorruptedError>())
+            .find_map(|err| err.block.as_ref())
+            .map(|(native_shuffle_id, block_id)| (native_shuffle_id.as_str(), *block_id))
+    }
+
+    /// Messages of the cause and its nested causes, from outer to inner
+    pub fn causes(&self) -> Vec<String> {
+        error_chain(&self.cause)
+            .map(|err| err.to_string())
+            .collect()
+    }
+}
+
+impl Display for NativeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.operator {
+            Some(operator) => {
+                write!(f, "{:?} error in {}: {}", self.kind, operator, self.cause)
+            }
+            None => write!(f, "{:?} error: {}", self.kind, self.cause),
+        }
+    }
+}
+
+impl Error for NativeError {}
+
+/// `err` and its causes, DataFusionError and ArrowError do not expose their
+/// causes through `Error::source`
+fn error_chain<'a>(
+    err: &'a (dyn Error + 'static),
+) -> impl Iterator<Item = &'a (dyn Error + 'static)> {
+    std::iter::successors(Some(err), |&err| -> Option<&'a (dyn Error + 'static)> {
+        if let Some(err) = err.downcast_ref::<DataFusionError>() {
+            return match err {
+                DataFusionError::ArrowError(err) => Some(err),
+                DataFusionError::External(err) => Some(err.as_ref()),
+                _ => None,
+            };
+        }
+        if let Some(err) = err.downcast_ref::<ArrowError>() {
+            return match err {
+                ArrowError::ExternalError(err) => Some(err.as_ref()),
+                _ => None,
+            };
+        }
+        if let Some(err) = err.downcast_ref::<NativeError>() {
+            return Some(&err.cause);
+        }
+        err.source()
+    })
+}
+
+/// prefix of the errors of arrow's checked arithmetic kernels
+const ARROW_OVERFLOW_MESSAGE_PREFIX: &str = "Overflow happened on: ";
+
+fn error_kind(err: &(dyn Error + 'static)) -> Option<NativeErrorKind> {
+    if let Some(err) = err.downcast_ref::<NativeError>() {
+        return Some(err.kind);
+    }
+    if err.is::<ShuffleCorruptedError>() {
+        return Some(NativeErrorKind::ShuffleCorrupted);
+    }
+    if err.is::<std::io::Error>() {
+        return Some(NativeErrorKind::Io);
+    }
+    if let Some(err) = err.downcast_ref::<DataFusionError>() {
+        return match err {
+            DataFusionError::IoError(_) => Some(NativeErrorKind::Io),
+            DataFusionError::ResourcesExhausted(_) => {
+                Some(NativeErrorKind::ResourcesExhausted)
+            }
+            DataFusionError::Plan(_)
+            | DataFusionError::NotImplemented(_)
+            | DataFusionError::SchemaError(_) => Some(NativeErrorKind::Plan),
+            _ => None,
+        };
+    }
+    if let Some(err) = err.downcast_ref::<ArrowError>() {
+        return match err {
+            ArrowError::IoError(_) => Some(NativeErrorKind::Io),
+            ArrowError::DivideByZero => Some(NativeErrorKind::ArithmeticOverflow),
+            ArrowError::ComputeError(message)
+                if message.starts_with(ARROW_OVERFLOW_MESSAGE_PREFIX) =>
+            {
+                Some(NativeErrorKind::ArithmeticOverflow)
+            }
+            _ => None,
+        };
+    }
+    None
+}
+
+/// Wraps every operator of the plan into an [`OperatorErrorExec`]
+pub fn with_operator_errors(
+    plan: Arc<dyn ExecutionPlan>,
+) -> Result<Arc<dyn ExecutionPlan>> {
+    let children = plan.children();
+    let plan = if children.is_empty() {
+        plan
+    } else {
+        let children = children
+            .into_iter()
+            .map(with_operator_errors)
+            .collect::<Result<Vec<_>>>()?;
+        plan.with_new_children(children)?
+    };
+    Ok(Arc::new(OperatorErrorExec::new(plan)))
+}
+
+/// Transparent wrapper of an operator, which attaches its name to errors not
+/// attributed to an operator yet. It is seen as the wrapped operator by
+/// downcasting, displaying and metrics.
+#[derive(Debug)]
+pub struct OperatorErrorExec {
+    input: Arc<dyn ExecutionPlan>,
+    operator: String,
+}
+
+impl OperatorErrorExec {
+    pub fn new(input: Arc<dyn ExecutionPlan>) -> Self {
+        struct DisplayOperator<'a>(&'a dyn ExecutionPlan);
+        impl Display for DisplayOperator<'_> {
+            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                self.0.fmt_as(DisplayFormatType::Default, f)
+            }
+        }
+        let display = DisplayOperator(input.as_ref()).to_string();
+        let operator = display.split(':').next().unwrap_or_default().to_owned();
+        Self { input, operator }
+    }
+}
+
+fn attach_operator(err: DataFusionError, operator: &str) -> DataFusionError {
+    if NativeError::find(&err).is_some() {
+        return err;
+    }
+    DataFusionError::External(Box::new(NativeError::new(err, Some(operator.to_owned()))))
+}
+
+#[async_trait]
+impl ExecutionPlan for OperatorErrorExec {
+    fn as_any(&self) -> &dyn Any {
+        self.input.as_any()
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        self.input.output_partitioning()
+    }
+
+    fn output_ordering(&self) -> Option<&[PhysicalSortExpr]> {
+        self.input.output_ordering()
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        self.input.children()
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        Ok(Arc::new(OperatorErrorExec::new(
+            self.input.clone().with_new_children(children)?,
+        )))
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> Result<SendableRecordBatchStream> {
+        let input = self
+            .input
+            .execute(partition, context)
+            .map_err(|err| attach_operator(err, &self.operator))?;
+        let operator = self.operator.clone();
+        Ok(Box::pin(RecordBatchStreamAdapter::new(
+            input.schema(),
+            input.map(move |batch| {
+                batch.map_err(|err| {
+                    let err =
+                        attach_operator(DataFusionError::ArrowError(err), &operator);
+                    ArrowError::ExternalError(Box::new(err))
+                })
+            }),
+        )))
+    }
+
+    fn metrics(&self) -> Option<MetricsSet> {
+        self.input.metrics()
+    }
+
+    fn fmt_as(&self, t: DisplayFormatType, f: &mut Formatter) -> std::fmt::Result {
+        self.input.fmt_as(t, f)
+    }
+
+    fn statistics(&self) -> Statistics {
+        self.input.statistics()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::arrow::error::ArrowError;
+    use datafusion::error::DataFusionError;
+
+    use crate::native_error::{NativeError, NativeErrorKind};
+
+    #[test]
+    fn test_native_error_kind() {
+        let io_error = std::io::Error::new(std::io::ErrorKind::Other, "disk failure");
+        let err = DataFusionError::ArrowError(ArrowError::ExternalError(Box::new(
+            DataFusionError::IoError(io_error),
+        )));
+        let native_error = NativeError::new(err, Some("ShuffleWriterExec".to_owned()));
+        assert_eq!(native_error.kind, NativeErrorKind::Io);
+        assert_eq!(native_error.causes().len(), 3);
+
+        let err = DataFusionError::ArrowError(ArrowError::ComputeError(
+            "Overflow happened on: 9223372036854775807 + 1".to_owned(),
+        ));
+        let err = NativeError::wrap(DataFusionError::ArrowError(
+            ArrowError::ExternalError(Box::new(err)),
+        ));
+        let native_error = NativeError::find(&err).unwrap();
+        assert_eq!(native_error.kind, NativeErrorKind::ArithmeticOverflow);
+        assert_eq!(native_error.operator, None);
+
+        // other compute errors mentioning overflow are not overflows
+        let cast_error = DataFusionError::ArrowError(ArrowError::ComputeError(
+            "Cannot cast string 'overflow' to value of Int64 type".to_owned(),
+        ));
+        let native_error = NativeError::new(cast_error, None);
+        assert_eq!(native_error.kind, NativeErrorKind::Execution);
+
+        // errors already attributed are not wrapped again
+        let wrapped = NativeError::wrap(err);
+        assert!(matches!(
+            NativeError::find(&wrapped).unwrap().cause,
+            DataFusionError::ArrowError(_)
+        ));
+    }
+}
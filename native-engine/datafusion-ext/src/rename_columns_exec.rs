@@ -74,6 +74,14 @@ impl RenameColumnsExec {
             metrics: ExecutionPlanMetricsSet::new(),
         })
     }
+
+    pub fn input(&self) -> &Arc<dyn ExecutionPlan> {
+        &self.input
+    }
+
+    pub fn renamed_column_names(&self) -> &[String] {
+        &self.renamed_column_names
+    }
 }
 
 #[async_trait]
@@ -0,0 +1,242 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Checksums of shuffle partitions.
+//!
+//! Checksums are computed over all ipc parts of a partition, with values
+//! identical to `java.util.zip.Adler32` and `java.util.zip.CRC32C` used by
+//! spark's shuffle checksums. They are appended to each non-empty partition
+//! in the data file as a trailer, so that readers can verify fetched blocks:
+//!
+//! ```text
+//! [part] ... [part][checksum: u64][-(algorithm id): i64]
+//! ```
+//!
+//! The negative algorithm id distinguishes the trailer from lengths of legacy
+//! ipc parts, and checksums never start with the magic of stream parts (see
+//! [`crate::shuffle_codec`]) since they are 32-bit values.
+
+use std::io::Read;
+use std::io::Write;
+
+use adler32::RollingAdler32;
+
+pub const CHECKSUM_TRAILER_LENGTH: u64 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShuffleChecksumAlgorithm {
+    None,
+    Adler32,
+    Crc32c,
+}
+
+impl Default for ShuffleChecksumAlgorithm {
+    fn default() -> Self {
+        ShuffleChecksumAlgorithm::None
+    }
+}
+
+impl ShuffleChecksumAlgorithm {
+    pub fn id(&self) -> i64 {
+        match self {
+            ShuffleChecksumAlgorithm::None => 0,
+            ShuffleChecksumAlgorithm::Adler32 => 1,
+            ShuffleChecksumAlgorithm::Crc32c => 2,
+        }
+    }
+
+    pub fn from_id(id: i64) -> Option<Self> {
+        [
+            ShuffleChecksumAlgorithm::None,
+            ShuffleChecksumAlgorithm::Adler32,
+            ShuffleChecksumAlgorithm::Crc32c,
+        ]
+        .into_iter()
+        .find(|algorithm| algorithm.id() == id)
+    }
+
+    /// the last 8 bytes of checksum trailer
+    pub fn trailer_marker(&self) -> i64 {
+        -self.id()
+    }
+
+    pub fn from_trailer_marker(marker: i64) -> Option<Self> {
+        [
+            ShuffleChecksumAlgorithm::Adler32,
+            ShuffleChecksumAlgorithm::Crc32c,
+        ]
+        .into_iter()
+        .find(|algorithm| algorithm.trailer_marker() == marker)
+    }
+}
+
+pub enum ShuffleChecksum {
+    None,
+    Adler32(RollingAdler32),
+    Crc32c(u32),
+}
+
+impl ShuffleChecksum {
+    pub fn new(algorithm: ShuffleChecksumAlgorithm) -> Self {
+        match algorithm {
+            ShuffleChecksumAlgorithm::None => ShuffleChecksum::None,
+            ShuffleChecksumAlgorithm::Adler32 => {
+                ShuffleChecksum::Adler32(RollingAdler32::new())
+            }
+            ShuffleChecksumAlgorithm::Crc32c => ShuffleChecksum::Crc32c(0),
+        }
+    }
+
+    pub fn algorithm(&self) -> ShuffleChecksumAlgorithm {
+        match self {
+            ShuffleChecksum::None => ShuffleChecksumAlgorithm::None,
+            ShuffleChecksum::Adler32(_) => ShuffleChecksumAlgorithm::Adler32,
+            ShuffleChecksum::Crc32c(_) => ShuffleChecksumAlgorithm::Crc32c,
+        }
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        match self {
+            ShuffleChecksum::None => {}
+            ShuffleChecksum::Adler32(adler32) => adler32.update_buffer(buf),
+            ShuffleChecksum::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, buf),
+        }
+    }
+
+    pub fn value(&self) -> u64 {
+        match self {
+            ShuffleChecksum::None => 0,
+            ShuffleChecksum::Adler32(adler32) => adler32.hash() as u64,
+            ShuffleChecksum::Crc32c(crc) => *crc as u64,
+        }
+    }
+}
+
+/// A writer computing checksum and position of all written bytes
+pub struct ChecksumWriter<W: Write> {
+    inner: W,
+    checksum: ShuffleChecksum,
+    position: u64,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    pub fn new(inner: W, algorithm: ShuffleChecksumAlgorithm) -> Self {
+        Self {
+            inner,
+            checksum: ShuffleChecksum::new(algorithm),
+            position: 0,
+        }
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn checksum(&self) -> u64 {
+        self.checksum.value()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// start computing checksum of next partition
+    pub fn reset_checksum(&mut self) {
+        self.checksum = ShuffleChecksum::new(self.checksum.algorithm());
+    }
+
+    /// write trailer with checksum of current partition
+    pub fn write_checksum_trailer(&mut self) -> std::io::Result<()> {
+        let checksum = self.checksum();
+        let marker = self.checksum.algorithm().trailer_marker();
+        self.write_all(&checksum.to_le_bytes())?;
+        self.write_all(&marker.to_le_bytes())?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.checksum.update(&buf[..n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// compute checksum of all bytes from `input`
+pub fn compute_checksum<R: Read>(
+    mut input: R,
+    algorithm: ShuffleChecksumAlgorithm,
+) -> std::io::Result<u64> {
+    let mut checksum = ShuffleChecksum::new(algorithm);
+    let mut buf = vec![0u8; 65536];
+    loop {
+        match input.read(&mut buf)? {
+            0 => break,
+            n => checksum.update(&buf[..n]),
+        }
+    }
+    Ok(checksum.value())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crate::shuffle_checksum::{
+        compute_checksum, ChecksumWriter, ShuffleChecksumAlgorithm,
+    };
+
+    #[test]
+    fn test_checksum_values() {
+        // expected values are computed with java.util.zip.Adler32 / CRC32C
+        let data = b"123456789";
+        let adler32 = compute_checksum(&data[..], ShuffleChecksumAlgorithm::Adler32);
+        let crc32c = compute_checksum(&data[..], ShuffleChecksumAlgorithm::Crc32c);
+        assert_eq!(adler32.unwrap(), 0x091e01de);
+        assert_eq!(crc32c.unwrap(), 0xe3069283);
+
+        let empty = compute_checksum(&b""[..], ShuffleChecksumAlgorithm::Adler32);
+        assert_eq!(empty.unwrap(), 1);
+    }
+
+    #[test]
+    fn test_checksum_writer() {
+        let algorithm = ShuffleChecksumAlgorithm::Crc32c;
+        let mut writer = ChecksumWriter::new(vec![], algorithm);
+        writer.write_all(b"abc").unwrap();
+        writer.reset_checksum();
+        writer.write_all(b"123456789").unwrap();
+        assert_eq!(writer.position(), 12);
+        assert_eq!(writer.checksum(), 0xe3069283);
+
+        writer.write_checksum_trailer().unwrap();
+        let output = writer.into_inner();
+        assert_eq!(output.len(), 28);
+        assert_eq!(&output[12..20], &0xe3069283u64.to_le_bytes());
+        let marker = i64::from_le_bytes(output[20..28].try_into().unwrap());
+        assert_eq!(
+            ShuffleChecksumAlgorithm::from_trailer_marker(marker),
+            Some(algorithm)
+        );
+    }
+}
@@ -0,0 +1,293 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Format and compression of shuffle ipc parts.
+//!
+//! Each shuffle part is a complete arrow ipc file or stream, optionally wrapped
+//! into a lz4 or zstd frame. Compressed frames are recognized by their magic number,
+//! so readers do not need to know the codec used by the writer.
+//!
+//! Two formats of parts are supported:
+//!
+//! ```text
+//! legacy (version 1): [ipc file][part length: u64]
+//! stream (version 2): [magic][version: u8][checksum algorithm: u8][chunk]...[0: u32]
+//! chunk:              [chunk length: u32][chunk of ipc stream]
+//! ```
+//!
+//! Legacy parts are located from the end of block and need random access, while
+//! stream parts can be decoded sequentially from any reader. The ipc stream of a
+//! stream part is split into length-prefixed chunks, so that it can be written and
+//! read without buffering the whole part. All parts of a block use the same
+//! format, which is recognized from the leading bytes.
+
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::ipc::writer::FileWriter;
+use datafusion::arrow::ipc::writer::StreamWriter;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::{DataFusionError, Result};
+
+use crate::shuffle_checksum::ShuffleChecksumAlgorithm;
+
+const LZ4_FRAME_MAGIC: [u8; 4] = [0x04, 0x22, 0x4d, 0x18];
+const ZSTD_FRAME_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const ZSTD_LEVEL: i32 = 1;
+
+pub const STREAM_PART_MAGIC: [u8; 6] = *b"BLZIPC";
+pub const STREAM_PART_VERSION: u8 = 2;
+pub const STREAM_PART_HEADER_LENGTH: u64 = 8;
+const STREAM_PART_CHUNK_SIZE: usize = 65536;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShuffleCompressionCodec {
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl Default for ShuffleCompressionCodec {
+    fn default() -> Self {
+        ShuffleCompressionCodec::None
+    }
+}
+
+impl ShuffleCompressionCodec {
+    /// detect codec of an ipc part from its leading bytes
+    fn detect(header: &[u8]) -> Self {
+        if header.starts_with(&LZ4_FRAME_MAGIC) {
+            ShuffleCompressionCodec::Lz4
+        } else if header.starts_with(&ZSTD_FRAME_MAGIC) {
+            ShuffleCompressionCodec::Zstd
+        } else {
+            ShuffleCompressionCodec::None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IpcFormat {
+    File,
+    Stream,
+}
+
+/// write batches as a single (possibly compressed) legacy ipc file part into
+/// `output`, without the trailing part length
+pub fn write_ipc_part<W: Write>(
+    batches: &[RecordBatch],
+    schema: &SchemaRef,
+    codec: ShuffleCompressionCodec,
+    output: W,
+) -> Result<W> {
+    write_compressed_ipc(batches, schema, codec, IpcFormat::File, output)
+}
+
+/// write batches as a single (possibly compressed) stream part into `output`,
+/// `checksum_algorithm` is the algorithm of checksum trailer of the block
+pub fn write_ipc_stream_part<W: Write>(
+    batches: &[RecordBatch],
+    schema: &SchemaRef,
+    codec: ShuffleCompressionCodec,
+    checksum_algorithm: ShuffleChecksumAlgorithm,
+    mut output: W,
+) -> Result<W> {
+    output.write_all(&STREAM_PART_MAGIC)?;
+    output.write_all(&[STREAM_PART_VERSION, checksum_algorithm.id() as u8])?;
+    let chunked = ChunkedWriter::new(output);
+    let chunked =
+        write_compressed_ipc(batches, schema, codec, IpcFormat::Stream, chunked)?;
+    Ok(chunked.finish()?)
+}
+
+/// Writer splitting written data into length-prefixed chunks
+struct ChunkedWriter<W: Write> {
+    inner: W,
+    chunk: Vec<u8>,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            chunk: Vec::with_capacity(STREAM_PART_CHUNK_SIZE),
+        }
+    }
+
+    fn write_chunk(&mut self) -> std::io::Result<()> {
+        if !self.chunk.is_empty() {
+            self.inner
+                .write_all(&(self.chunk.len() as u32).to_le_bytes())?;
+            self.inner.write_all(&self.chunk)?;
+            self.chunk.clear();
+        }
+        Ok(())
+    }
+
+    /// write the last chunk and the terminating empty chunk
+    fn finish(mut self) -> std::io::Result<W> {
+        self.write_chunk()?;
+        self.inner.write_all(&0u32.to_le_bytes())?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(STREAM_PART_CHUNK_SIZE - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..len]);
+        if self.chunk.len() == STREAM_PART_CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reader of a (possibly compressed) ipc stream, decompressing it while reading
+pub fn ipc_stream_reader<R: Read + Send + 'static>(
+    mut input: R,
+) -> Result<Box<dyn Read + Send>> {
+    let mut header = [0u8; 4];
+    let mut header_len = 0;
+    while header_len < header.len() {
+        match input.read(&mut header[header_len..])? {
+            0 => break,
+            n => header_len += n,
+        }
+    }
+    let codec = ShuffleCompressionCodec::detect(&header[..header_len]);
+    let input = Cursor::new(header).take(header_len as u64).chain(input);
+    Ok(match codec {
+        ShuffleCompressionCodec::None => Box::new(input),
+        ShuffleCompressionCodec::Lz4 => {
+            Box::new(lz4_flex::frame::FrameDecoder::new(input))
+        }
+        ShuffleCompressionCodec::Zstd => Box::new(zstd::Decoder::new(input)?),
+    })
+}
+
+fn write_compressed_ipc<W: Write>(
+    batches: &[RecordBatch],
+    schema: &SchemaRef,
+    codec: ShuffleCompressionCodec,
+    format: IpcFormat,
+    output: W,
+) -> Result<W> {
+    match codec {
+        ShuffleCompressionCodec::None => write_ipc(batches, schema, format, output),
+        ShuffleCompressionCodec::Lz4 => {
+            let encoder = lz4_flex::frame::FrameEncoder::new(output);
+            let encoder = write_ipc(batches, schema, format, encoder)?;
+            encoder.finish().map_err(|e| {
+                DataFusionError::Execution(format!("lz4 compression error: {}", e))
+            })
+        }
+        ShuffleCompressionCodec::Zstd => {
+            let encoder = zstd::Encoder::new(output, ZSTD_LEVEL)?;
+            let encoder = write_ipc(batches, schema, format, encoder)?;
+            Ok(encoder.finish()?)
+        }
+    }
+}
+
+fn write_ipc<W: Write>(
+    batches: &[RecordBatch],
+    schema: &SchemaRef,
+    format: IpcFormat,
+    output: W,
+) -> Result<W> {
+    match format {
+        IpcFormat::File => {
+            let mut file_writer = FileWriter::try_new(output, schema.as_ref())?;
+            for batch in batches {
+                file_writer.write(batch)?;
+            }
+            file_writer.finish()?;
+            Ok(file_writer.into_inner()?)
+        }
+        IpcFormat::Stream => {
+            let mut stream_writer = StreamWriter::try_new(output, schema.as_ref())?;
+            for batch in batches {
+                stream_writer.write(batch)?;
+            }
+            stream_writer.finish()?;
+            Ok(stream_writer.into_inner()?)
+        }
+    }
+}
+
+/// Reader of an ipc part, decompressing it into memory if it was written
+/// with a compression codec.
+pub enum IpcPartReader<R: Read + Seek> {
+    Uncompressed(R),
+    Decompressed(Cursor<Vec<u8>>),
+}
+
+impl<R: Read + Seek> IpcPartReader<R> {
+    pub fn try_new(mut input: R) -> Result<Self> {
+        let mut header = [0u8; 4];
+        let mut header_len = 0;
+        while header_len < header.len() {
+            match input.read(&mut header[header_len..])? {
+                0 => break,
+                n => header_len += n,
+            }
+        }
+        input.seek(SeekFrom::Start(0))?;
+
+        let mut decompressed = vec![];
+        match ShuffleCompressionCodec::detect(&header[..header_len]) {
+            ShuffleCompressionCodec::None => {
+                return Ok(IpcPartReader::Uncompressed(input));
+            }
+            ShuffleCompressionCodec::Lz4 => {
+                lz4_flex::frame::FrameDecoder::new(input)
+                    .read_to_end(&mut decompressed)?;
+            }
+            ShuffleCompressionCodec::Zstd => {
+                zstd::Decoder::new(input)?.read_to_end(&mut decompressed)?;
+            }
+        }
+        Ok(IpcPartReader::Decompressed(Cursor::new(decompressed)))
+    }
+}
+
+impl<R: Read + Seek> Read for IpcPartReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            IpcPartReader::Uncompressed(r) => r.read(buf),
+            IpcPartReader::Decompressed(r) => r.read(buf),
+        }
+    }
+}
+
+impl<R: Read + Seek> Seek for IpcPartReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            IpcPartReader::Uncompressed(r) => r.seek(pos),
+            IpcPartReader::Decompressed(r) => r.seek(pos),
+        }
+    }
+}
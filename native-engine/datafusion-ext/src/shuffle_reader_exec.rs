@@ -16,63 +16,101 @@
 // under the License.
 
 use std::any::Any;
+use std::collections::VecDeque;
 use std::fmt::Debug;
 use std::fmt::Formatter;
+use std::fs::File;
+use std::io::Cursor;
 use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
+use std::os::unix::fs::FileExt;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::task::Context;
 use std::task::Poll;
 
 use async_trait::async_trait;
+use dashmap::DashMap;
+use datafusion::arrow::datatypes::Schema;
 use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::error::ArrowError;
 use datafusion::arrow::error::Result as ArrowResult;
 use datafusion::arrow::ipc::reader::FileReader;
+use datafusion::arrow::ipc::reader::StreamReader;
 use datafusion::arrow::record_batch::RecordBatch;
 use datafusion::error::{DataFusionError, Result};
 use datafusion::execution::context::TaskContext;
+use datafusion::execution::memory_manager::ConsumerType;
+use datafusion::execution::memory_manager::MemoryConsumer;
+use datafusion::execution::memory_manager::MemoryConsumerId;
+use datafusion::execution::memory_manager::MemoryManager;
+use datafusion::execution::runtime_env::RuntimeEnv;
+use datafusion::physical_plan::common::batch_byte_size;
 use datafusion::physical_plan::expressions::PhysicalSortExpr;
 use datafusion::physical_plan::metrics::BaselineMetrics;
 use datafusion::physical_plan::metrics::ExecutionPlanMetricsSet;
+use datafusion::physical_plan::metrics::Gauge;
 use datafusion::physical_plan::metrics::MetricsSet;
+use datafusion::physical_plan::metrics::Time;
 use datafusion::physical_plan::DisplayFormatType;
 use datafusion::physical_plan::ExecutionPlan;
 use datafusion::physical_plan::Partitioning;
-use datafusion::physical_plan::Partitioning::UnknownPartitioning;
 use datafusion::physical_plan::RecordBatchStream;
 use datafusion::physical_plan::SendableRecordBatchStream;
 use datafusion::physical_plan::Statistics;
 use futures::Stream;
 use jni::objects::{GlobalRef, JObject};
 use jni::sys::{jboolean, jint, jlong, JNI_TRUE};
+use once_cell::sync::OnceCell;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::Notify;
 
 use crate::jni_call;
 use crate::jni_call_static;
 use crate::jni_delete_local_ref;
+use crate::jni_get_string;
+use crate::jni_is_instance_of;
 use crate::jni_new_direct_byte_buffer;
 use crate::jni_new_global_ref;
 use crate::jni_new_string;
+use crate::shuffle_checksum::compute_checksum;
+use crate::shuffle_checksum::ShuffleChecksum;
+use crate::shuffle_checksum::ShuffleChecksumAlgorithm;
+use crate::shuffle_checksum::CHECKSUM_TRAILER_LENGTH;
+use crate::shuffle_codec::ipc_stream_reader;
+use crate::shuffle_codec::IpcPartReader;
+use crate::shuffle_codec::STREAM_PART_HEADER_LENGTH;
+use crate::shuffle_codec::STREAM_PART_MAGIC;
+use crate::shuffle_codec::STREAM_PART_VERSION;
+use crate::shuffle_schema::SchemaAdapter;
+use crate::spark_task_context;
 use crate::ResultExt;
 
 #[derive(Debug, Clone)]
 pub struct ShuffleReaderExec {
-    pub num_partitions: usize,
+    /// Partitioning of shuffle data, which is `Partitioning::Hash` if the data
+    /// was written with spark's hash partitioning of the given expressions
+    pub partitioning: Partitioning,
     pub native_shuffle_id: String,
     pub schema: SchemaRef,
+    /// Number of segments decoded ahead in background, 0 to decode on polling
+    pub prefetch_depth: usize,
     pub metrics: ExecutionPlanMetricsSet,
 }
 impl ShuffleReaderExec {
     pub fn new(
-        num_partitions: usize,
+        partitioning: Partitioning,
         native_shuffle_id: String,
         schema: SchemaRef,
+        prefetch_depth: usize,
     ) -> ShuffleReaderExec {
         ShuffleReaderExec {
-            num_partitions,
+            partitioning,
             native_shuffle_id,
             schema,
+            prefetch_depth,
             metrics: ExecutionPlanMetricsSet::new(),
         }
     }
@@ -89,7 +127,7 @@ impl ExecutionPlan for ShuffleReaderExec {
     }
 
     fn output_partitioning(&self) -> Partitioning {
-        UnknownPartitioning(self.num_partitions)
+        self.partitioning.clone()
     }
 
     fn output_ordering(&self) -> Option<&[PhysicalSortExpr]> {
@@ -111,27 +149,64 @@ impl ExecutionPlan for ShuffleReaderExec {
 
     fn execute(
         &self,
-        _partition: usize,
-        _context: Arc<TaskContext>,
+        partition: usize,
+        context: Arc<TaskContext>,
     ) -> Result<SendableRecordBatchStream> {
         let baseline_metrics = BaselineMetrics::new(&self.metrics, 0);
         let elapsed_compute = baseline_metrics.elapsed_compute().clone();
         let _timer = elapsed_compute.timer();
 
-        let segments_provider = jni_call_static!(
-            JniBridge.getResource(
-                jni_new_string!(&self.native_shuffle_id)?
-            ) -> JObject
-        )?;
-        let segments = jni_new_global_ref!(
-            jni_call!(ScalaFunction0(segments_provider).apply() -> JObject)?
-        )?;
+        let local_blocks = local_shuffle_blocks()
+            .get(&self.native_shuffle_id)
+            .map(|blocks| blocks.clone());
+        let blocks = match local_blocks {
+            Some(blocks) => ShuffleBlocks::Local(blocks.into()),
+            None => {
+                let blocks_provider = jni_call_static!(
+                    JniBridge.getResource(
+                        jni_new_string!(&self.native_shuffle_id)?
+                    ) -> JObject
+                )?;
+                ShuffleBlocks::Jvm(jni_new_global_ref!(
+                    jni_call!(ScalaFunction0(blocks_provider).apply() -> JObject)?
+                )?)
+            }
+        };
 
         let schema = self.schema.clone();
-        Ok(Box::pin(ShuffleReaderStream::new(
+        let batch_size = context.session_config().batch_size;
+        let elapsed_compute = baseline_metrics.elapsed_compute().clone();
+        let segments = ShuffleSegmentReader::new(
+            self.native_shuffle_id.clone(),
+            schema.clone(),
+            blocks,
+        );
+        if self.prefetch_depth == 0 {
+            let stream = ShuffleReaderStream::new(schema, segments, baseline_metrics);
+            return Ok(Box::pin(CoalesceStream::new(
+                Box::pin(stream),
+                batch_size,
+                elapsed_compute,
+            )));
+        }
+
+        let consumer = Arc::new(PrefetchMemoryConsumer::new(
+            partition,
+            context.runtime_env(),
+            baseline_metrics.mem_used().clone(),
+        ));
+        context.runtime_env().register_requester(consumer.id());
+        let stream = PrefetchShuffleReaderStream::new(
             schema,
-            segments,
+            Box::new(segments),
+            self.prefetch_depth,
+            consumer,
             baseline_metrics,
+        );
+        Ok(Box::pin(CoalesceStream::new(
+            Box::pin(stream),
+            batch_size,
+            elapsed_compute,
         )))
     }
 
@@ -148,52 +223,254 @@ impl ExecutionPlan for ShuffleReaderExec {
     }
 }
 
+/// A shuffle block stored in a local file, which can be read without the JVM
+#[derive(Debug, Clone)]
+pub struct LocalShuffleBlock {
+    pub path: String,
+    pub offset: u64,
+    pub length: u64,
+}
+
+fn local_shuffle_blocks() -> &'static DashMap<String, Vec<LocalShuffleBlock>> {
+    static LOCAL_SHUFFLE_BLOCKS: OnceCell<DashMap<String, Vec<LocalShuffleBlock>>> =
+        OnceCell::new();
+    LOCAL_SHUFFLE_BLOCKS.get_or_init(DashMap::new)
+}
+
+/// Registers local blocks to be read by shuffle readers of `native_shuffle_id`
+/// instead of blocks provided by the JVM, used to replay tasks without the JVM
+pub fn register_local_shuffle_blocks(
+    native_shuffle_id: impl Into<String>,
+    blocks: Vec<LocalShuffleBlock>,
+) {
+    local_shuffle_blocks().insert(native_shuffle_id.into(), blocks);
+}
+
+/// Source of the blocks read by a shuffle reader
+enum ShuffleBlocks {
+    /// Scala iterator of blocks provided by the JVM
+    Jvm(GlobalRef),
+    /// Blocks registered with [`register_local_shuffle_blocks`]
+    Local(VecDeque<LocalShuffleBlock>),
+}
+
+/// Reader of segments (ipc parts) from shuffle blocks. Each block provided by
+/// the JVM is composed of one or more ipc parts (see [`crate::shuffle_codec`]),
+/// and an optional checksum trailer (see [`crate::shuffle_checksum`]).
+///
+/// Blocks in local shuffle files are read directly from the files, other blocks
+/// are read from the JVM channels. Batches of each segment are converted into
+/// the expected schema (see [`crate::shuffle_schema`]).
+struct ShuffleSegmentReader {
+    native_shuffle_id: String,
+    schema: SchemaRef,
+    blocks: ShuffleBlocks,
+    num_blocks_read: usize,
+    num_block_parts_read: usize,
+    block_parts: Option<BlockParts<ShuffleBlockReader>>,
+}
+unsafe impl Sync for ShuffleSegmentReader {} // safety: blocks is safe to be shared
+#[allow(clippy::non_send_fields_in_send_ty)]
+unsafe impl Send for ShuffleSegmentReader {}
+
+impl ShuffleSegmentReader {
+    fn new(native_shuffle_id: String, schema: SchemaRef, blocks: ShuffleBlocks) -> Self {
+        Self {
+            native_shuffle_id,
+            schema,
+            blocks,
+            num_blocks_read: 0,
+            num_block_parts_read: 0,
+            block_parts: None,
+        }
+    }
+
+    /// batches of the next segment, or None if all blocks are read
+    fn next_segment(&mut self) -> Result<Option<PartBatches>> {
+        loop {
+            if let Some(block_parts) = &mut self.block_parts {
+                match block_parts.next_part() {
+                    Ok(Some((part_schema, part_batches))) => {
+                        self.num_block_parts_read += 1;
+                        let part_batches = self.with_block_errors(part_batches);
+                        return self.adapt_part(&part_schema, part_batches).map(Some);
+                    }
+                    Ok(None) => {}
+                    Err(e) => return Err(self.block_error(self.num_blocks_read - 1, e)),
+                }
+            }
+            if !self.next_block()? {
+                self.block_parts = None;
+                return Ok(None);
+            }
+        }
+    }
+
+    fn block_error(&self, block_id: usize, e: DataFusionError) -> DataFusionError {
+        // corruption errors are kept to be reported as fetch failures
+        if let Some(e) = block_corrupted(&e, &self.native_shuffle_id, block_id) {
+            return e;
+        }
+        DataFusionError::Execution(format!(
+            "error reading block #{} of shuffle {}: {}",
+            block_id, self.native_shuffle_id, e,
+        ))
+    }
+
+    /// attach the current block to corruption errors found while decoding the
+    /// batches of its part, like [`Self::block_error`]
+    fn with_block_errors(&self, part_batches: PartBatches) -> PartBatches {
+        let native_shuffle_id = self.native_shuffle_id.clone();
+        let block_id = self.num_blocks_read - 1;
+        Box::new(part_batches.map(move |batch| {
+            batch.map_err(|e| {
+                let corrupted = match &e {
+                    ArrowError::ExternalError(e) => e
+                        .downcast_ref::<DataFusionError>()
+                        .and_then(|e| block_corrupted(e, &native_shuffle_id, block_id)),
+                    _ => None,
+                };
+                match corrupted {
+                    Some(corrupted) => ArrowError::ExternalError(Box::new(corrupted)),
+                    None => e,
+                }
+            })
+        }))
+    }
+
+    /// validate schema of the current part, and convert its batches into the
+    /// expected schema if they differ
+    fn adapt_part(
+        &self,
+        part_schema: &Schema,
+        part_batches: PartBatches,
+    ) -> Result<PartBatches> {
+        let segment = format!(
+            "part #{} of block #{} of shuffle {}",
+            self.num_block_parts_read - 1,
+            self.num_blocks_read - 1,
+            self.native_shuffle_id,
+        );
+        let adapter = SchemaAdapter::try_new(part_schema, &self.schema).map_err(|e| {
+            DataFusionError::Execution(format!("error reading {}: {}", segment, e))
+        })?;
+        if adapter.is_identity() {
+            return Ok(part_batches);
+        }
+        // errors of the part are passed as they are, keeping corruption errors
+        Ok(Box::new(part_batches.map(move |batch| {
+            batch.and_then(|batch| {
+                adapter.adapt(batch).map_err(|e| {
+                    ArrowError::InvalidArgumentError(format!(
+                        "error reading {}: {}",
+                        segment, e
+                    ))
+                })
+            })
+        })))
+    }
+
+    fn next_block(&mut self) -> Result<bool> {
+        let next_block = match &mut self.blocks {
+            ShuffleBlocks::Jvm(blocks) => next_jvm_block(blocks.as_obj())?,
+            ShuffleBlocks::Local(blocks) => match blocks.pop_front() {
+                Some(block) => {
+                    let reader = LocalFileSegmentReader::try_new(
+                        &block.path,
+                        block.offset,
+                        block.length,
+                    )?;
+                    Some((ShuffleBlockReader::Local(reader), block.length))
+                }
+                None => None,
+            },
+        };
+        let (block, block_len) = match next_block {
+            Some(next_block) => next_block,
+            None => return Ok(false),
+        };
+
+        let block_id = self.num_blocks_read;
+        self.num_blocks_read += 1;
+        self.num_block_parts_read = 0;
+        let block_parts = BlockParts::try_new(block, block_len)
+            .map_err(|e| self.block_error(block_id, e))?;
+        self.block_parts = Some(block_parts);
+        Ok(true)
+    }
+}
+
+/// next block of the Scala iterator of blocks, or None if all blocks are read
+fn next_jvm_block(blocks: JObject) -> Result<Option<(ShuffleBlockReader, u64)>> {
+    if jni_call!(ScalaIterator(blocks).hasNext() -> jboolean)? != JNI_TRUE {
+        return Ok(None);
+    }
+
+    let channel = jni_call!(ScalaIterator(blocks).next() -> JObject)?;
+    let (block, block_len) =
+        if jni_is_instance_of!(channel, BlazeFileSegmentSeekableByteChannel)? {
+            let file = jni_call!(
+                BlazeFileSegmentSeekableByteChannel(channel).getFile() -> JObject
+            )?;
+            let path_obj = jni_call!(JavaFile(file).getPath() -> JObject)?;
+            let path = jni_get_string!(path_obj.into())?;
+            let offset = jni_call!(
+                BlazeFileSegmentSeekableByteChannel(channel).getOffset() -> jlong
+            )? as u64;
+            let len = jni_call!(
+                BlazeFileSegmentSeekableByteChannel(channel).getLength() -> jlong
+            )? as u64;
+            jni_delete_local_ref!(path_obj)?;
+            jni_delete_local_ref!(file)?;
+
+            let reader = LocalFileSegmentReader::try_new(&path, offset, len)?;
+            (ShuffleBlockReader::Local(reader), len)
+        } else {
+            let len = jni_call!(
+                JavaNioSeekableByteChannel(channel).size() -> jlong
+            )? as u64;
+            let reader =
+                SeekableByteChannelReader::new(jni_new_global_ref!(channel)?, len);
+            (ShuffleBlockReader::Channel(reader), len)
+        };
+
+    // channel ref must be explicitly deleted to avoid OOM
+    jni_delete_local_ref!(channel)?;
+    Ok(Some((block, block_len)))
+}
+
+impl Iterator for ShuffleSegmentReader {
+    type Item = Result<PartBatches>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_segment().transpose()
+    }
+}
+
+type PartBatches = Box<dyn Iterator<Item = ArrowResult<RecordBatch>> + Send>;
+type SegmentIterator = Box<dyn Iterator<Item = Result<PartBatches>> + Send>;
+
+/// Stream of batches from shuffle segments, which are decoded on polling
 struct ShuffleReaderStream {
     schema: SchemaRef,
-    segments: GlobalRef,
-    arrow_file_reader: Option<FileReader<SeekableByteChannelReader>>,
+    segments: ShuffleSegmentReader,
+    part_batches: Option<PartBatches>,
     baseline_metrics: BaselineMetrics,
 }
-unsafe impl Sync for ShuffleReaderStream {} // safety: segments is safe to be shared
-#[allow(clippy::non_send_fields_in_send_ty)]
-unsafe impl Send for ShuffleReaderStream {}
 
 impl ShuffleReaderStream {
-    pub fn new(
+    fn new(
         schema: SchemaRef,
-        segments: GlobalRef,
+        segments: ShuffleSegmentReader,
         baseline_metrics: BaselineMetrics,
     ) -> ShuffleReaderStream {
         ShuffleReaderStream {
             schema,
             segments,
-            arrow_file_reader: None,
+            part_batches: None,
             baseline_metrics,
         }
     }
-
-    fn next_segment(&mut self) -> Result<bool> {
-        if jni_call!(
-            ScalaIterator(self.segments.as_obj()).hasNext() -> jboolean
-        )? != JNI_TRUE
-        {
-            self.arrow_file_reader = None;
-            return Ok(false);
-        }
-
-        let channel = jni_call!(
-            ScalaIterator(self.segments.as_obj()).next() -> JObject
-        )?;
-
-        self.arrow_file_reader = Some(FileReader::try_new(
-            SeekableByteChannelReader(jni_new_global_ref!(channel)?),
-            None,
-        )?);
-
-        // channel ref must be explicitly deleted to avoid OOM
-        jni_delete_local_ref!(channel)?;
-        Ok(true)
-    }
 }
 
 impl Stream for ShuffleReaderStream {
@@ -206,16 +483,17 @@ impl Stream for ShuffleReaderStream {
         let elapsed_compute = self.baseline_metrics.elapsed_compute().clone();
         let _timer = elapsed_compute.timer();
 
-        if let Some(arrow_file_reader) = &mut self.arrow_file_reader {
-            if let Some(record_batch) = arrow_file_reader.next() {
+        if let Some(part_batches) = &mut self.part_batches {
+            if let Some(record_batch) = part_batches.next() {
                 return self
                     .baseline_metrics
                     .record_poll(Poll::Ready(Some(record_batch)));
             }
         }
 
-        // current arrow file reader reaches EOF, try next ipc
-        if self.next_segment()? {
+        // current part reaches EOF, try next part
+        self.part_batches = self.segments.next_segment()?;
+        if self.part_batches.is_some() {
             return self.poll_next(cx);
         }
         Poll::Ready(None)
@@ -227,35 +505,1353 @@ impl RecordBatchStream for ShuffleReaderStream {
     }
 }
 
-struct SeekableByteChannelReader(GlobalRef);
+/// A segment decoded by the prefetching thread, with its memory size charged
+/// to the memory manager
+struct PrefetchedSegment {
+    batches: VecDeque<RecordBatch>,
+    mem_size: usize,
+}
+
+/// Stream of batches from shuffle segments, which are fetched and decoded in a
+/// blocking thread ahead of polling. At most `prefetch_depth` decoded segments
+/// are buffered in the channel.
+struct PrefetchShuffleReaderStream {
+    schema: SchemaRef,
+    /// segments to be moved into the prefetching thread on first polling
+    segments: Option<SegmentIterator>,
+    prefetch_depth: usize,
+    receiver: Option<Receiver<Result<PrefetchedSegment>>>,
+    current: Option<PrefetchedSegment>,
+    consumer: Arc<PrefetchMemoryConsumer>,
+    baseline_metrics: BaselineMetrics,
+}
+
+impl PrefetchShuffleReaderStream {
+    fn new(
+        schema: SchemaRef,
+        segments: SegmentIterator,
+        prefetch_depth: usize,
+        consumer: Arc<PrefetchMemoryConsumer>,
+        baseline_metrics: BaselineMetrics,
+    ) -> Self {
+        Self {
+            schema,
+            segments: Some(segments),
+            prefetch_depth,
+            receiver: None,
+            current: None,
+            consumer,
+            baseline_metrics,
+        }
+    }
+
+    fn start_prefetching(&mut self) -> Receiver<Result<PrefetchedSegment>> {
+        let (sender, receiver) = tokio::sync::mpsc::channel(self.prefetch_depth);
+        let segments = self.segments.take().unwrap();
+        let consumer = self.consumer.clone();
+        let elapsed_compute = self.baseline_metrics.elapsed_compute().clone();
+
+        // prefetching thread needs task context of the JVM to fetch blocks
+        spark_task_context::spawn_blocking(move || {
+            prefetch_segments(segments, sender, consumer, elapsed_compute)
+        });
+        receiver
+    }
+}
+
+/// Decode segments and send them into the channel, until all segments are read,
+/// an error occurs or the receiver is dropped
+fn prefetch_segments(
+    segments: SegmentIterator,
+    sender: Sender<Result<PrefetchedSegment>>,
+    consumer: Arc<PrefetchMemoryConsumer>,
+    elapsed_compute: Time,
+) {
+    let handle = tokio::runtime::Handle::current();
+    for part_batches in segments {
+        // blocking threads cannot be aborted, stop when the task is cancelled
+        if spark_task_context::is_cancelled() {
+            return;
+        }
+        let batches = {
+            let _timer = elapsed_compute.timer();
+            part_batches.and_then(|part_batches| {
+                Ok(part_batches.collect::<ArrowResult<VecDeque<_>>>()?)
+            })
+        };
+        let segment = batches.map(|batches| {
+            let mem_size = batches.iter().map(batch_byte_size).sum::<usize>();
+            handle.block_on(consumer.acquire(mem_size));
+            PrefetchedSegment { batches, mem_size }
+        });
+        let failed = segment.is_err();
+
+        if let Err(tokio::sync::mpsc::error::SendError(Ok(segment))) =
+            sender.blocking_send(segment)
+        {
+            // receiver is dropped
+            consumer.release(segment.mem_size);
+            return;
+        }
+        if failed {
+            return;
+        }
+    }
+}
+
+impl Stream for PrefetchShuffleReaderStream {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(current) = &mut self.current {
+                if let Some(batch) = current.batches.pop_front() {
+                    return self
+                        .baseline_metrics
+                        .record_poll(Poll::Ready(Some(Ok(batch))));
+                }
+                // current segment is consumed, release its memory before receiving
+                // the next one so that a paused prefetching thread can continue
+                let mem_size = current.mem_size;
+                self.current = None;
+                self.consumer.release(mem_size);
+            }
+
+            if self.receiver.is_none() {
+                self.receiver = Some(self.start_prefetching());
+            }
+            match self.receiver.as_mut().unwrap().poll_recv(cx) {
+                Poll::Ready(Some(Ok(segment))) => self.current = Some(segment),
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e.into()))),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+impl RecordBatchStream for PrefetchShuffleReaderStream {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+/// Stream concatenating small batches from shuffle segments into batches of at
+/// least `batch_size` rows, batches larger than that are passed through
+struct CoalesceStream {
+    input: SendableRecordBatchStream,
+    batch_size: usize,
+    staging: Vec<RecordBatch>,
+    staging_rows: usize,
+    elapsed_compute: Time,
+}
+
+impl CoalesceStream {
+    fn new(
+        input: SendableRecordBatchStream,
+        batch_size: usize,
+        elapsed_compute: Time,
+    ) -> Self {
+        Self {
+            input,
+            batch_size,
+            staging: vec![],
+            staging_rows: 0,
+            elapsed_compute,
+        }
+    }
+
+    fn flush_staging(&mut self) -> ArrowResult<RecordBatch> {
+        let _timer = self.elapsed_compute.timer();
+        let schema = self.input.schema();
+        let batch = RecordBatch::concat(&schema, &self.staging)?;
+        self.staging.clear();
+        self.staging_rows = 0;
+        Ok(batch)
+    }
+}
+
+impl Stream for CoalesceStream {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        loop {
+            match self.input.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(batch))) => {
+                    if batch.num_rows() >= self.batch_size && self.staging.is_empty() {
+                        return Poll::Ready(Some(Ok(batch)));
+                    }
+                    self.staging_rows += batch.num_rows();
+                    self.staging.push(batch);
+                    if self.staging_rows >= self.batch_size {
+                        return Poll::Ready(Some(self.flush_staging()));
+                    }
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) if !self.staging.is_empty() => {
+                    return Poll::Ready(Some(self.flush_staging()));
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+impl RecordBatchStream for CoalesceStream {
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+}
+
+/// Memory consumer of prefetched segments. Decoded segments cannot be spilled,
+/// so prefetching is paused until the buffered segments are consumed when the
+/// memory manager requires spilling.
+struct PrefetchMemoryConsumer {
+    id: MemoryConsumerId,
+    runtime: Arc<RuntimeEnv>,
+    mem_used: Gauge,
+    released: Notify,
+    /// memory of buffered segments, shared by prefetching thread and the stream
+    used: AtomicUsize,
+}
+
+impl PrefetchMemoryConsumer {
+    fn new(partition_id: usize, runtime: Arc<RuntimeEnv>, mem_used: Gauge) -> Self {
+        Self {
+            id: MemoryConsumerId::new(partition_id),
+            runtime,
+            mem_used,
+            released: Notify::new(),
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// charge memory of a decoded segment, waiting for consumed segments to be
+    /// released if memory is insufficient
+    async fn acquire(&self, mem_size: usize) {
+        // the segment is already decoded, so its memory is recorded even if
+        // it cannot be granted
+        if self.try_grow(mem_size).await.is_err() {
+            self.grow(mem_size);
+        }
+        let used = self.used.fetch_add(mem_size, Ordering::SeqCst) + mem_size;
+        self.mem_used.set(used);
+    }
+
+    fn release(&self, mem_size: usize) {
+        let used = self.used.fetch_sub(mem_size, Ordering::SeqCst) - mem_size;
+        self.mem_used.set(used);
+        self.shrink(mem_size);
+        self.released.notify_waiters();
+    }
+}
+
+impl Debug for PrefetchMemoryConsumer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PrefetchMemoryConsumer")
+            .field("id", &self.id())
+            .field("memory_used", &self.used())
+            .finish()
+    }
+}
+
+#[async_trait]
+impl MemoryConsumer for PrefetchMemoryConsumer {
+    fn name(&self) -> String {
+        "ShuffleReaderPrefetch".to_owned()
+    }
+
+    fn id(&self) -> &MemoryConsumerId {
+        &self.id
+    }
+
+    fn memory_manager(&self) -> Arc<MemoryManager> {
+        self.runtime.memory_manager.clone()
+    }
+
+    fn type_(&self) -> &ConsumerType {
+        &ConsumerType::Requesting
+    }
+
+    async fn spill(&self) -> Result<usize> {
+        // wait until all buffered segments are consumed, their memory is already
+        // freed by the stream
+        loop {
+            let released = self.released.notified();
+            if self.mem_used() == 0 {
+                return Ok(0);
+            }
+            released.await;
+        }
+    }
+
+    fn mem_used(&self) -> usize {
+        self.used.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for PrefetchMemoryConsumer {
+    fn drop(&mut self) {
+        self.runtime.drop_consumer(self.id(), self.used());
+    }
+}
+
+/// Input of a block of stream parts, starting with the magic read to detect the
+/// format of the block
+type StreamBlockInputReader<R> = std::io::Chain<Cursor<[u8; 6]>, R>;
+
+/// Parts of a shuffle block in either format
+enum BlockParts<R: Read + Seek + Clone> {
+    /// Legacy ipc file parts, which are split from the end of block
+    File(VecDeque<BlockPartReader<R>>),
+    /// Stream parts, which are decoded sequentially
+    Stream(StreamBlockReader<StreamBlockInputReader<R>>),
+}
+
+impl<R: Read + Seek + Clone + Send + 'static> BlockParts<R> {
+    fn try_new(mut block: R, block_len: u64) -> Result<Self> {
+        let mut header = [0u8; STREAM_PART_MAGIC.len()];
+        let is_stream = block_len >= header.len() as u64 && {
+            block.read_exact(&mut header)?;
+            header == STREAM_PART_MAGIC
+        };
+
+        if is_stream {
+            // the magic is read again by the stream reader, which never seeks
+            let input = Cursor::new(header).chain(block);
+            return Ok(BlockParts::Stream(StreamBlockReader::new(input, block_len)));
+        }
+        Ok(BlockParts::File(read_block_parts(block, block_len)?.into()))
+    }
+
+    /// schema and batches of the next part, or None if all parts are read
+    fn next_part(&mut self) -> Result<Option<(SchemaRef, PartBatches)>> {
+        match self {
+            BlockParts::File(parts) => match parts.pop_front() {
+                Some(part) => {
+                    let reader =
+                        FileReader::try_new(IpcPartReader::try_new(part)?, None)?;
+                    Ok(Some((reader.schema(), Box::new(reader))))
+                }
+                None => Ok(None),
+            },
+            BlockParts::Stream(stream_block) => match stream_block.next_part()? {
+                Some(part) => Ok(Some((part.schema(), Box::new(part)))),
+                None => Ok(None),
+            },
+        }
+    }
+}
+
+/// Sequential reader of a shuffle block of stream parts, which reads the block
+/// once from start to end without seeking. Checksums are computed while reading,
+/// and each checksum trailer is verified against the bytes since the previous
+/// trailer (a fetched block may be composed of several partitions) before the
+/// last batch of the part preceding it is returned.
+struct StreamBlockReader<R: Read> {
+    input: Arc<Mutex<StreamBlockInput<R>>>,
+}
+
+impl<R: Read + Send + 'static> StreamBlockReader<R> {
+    fn new(input: R, block_len: u64) -> Self {
+        Self {
+            input: Arc::new(Mutex::new(StreamBlockInput {
+                input,
+                block_len,
+                position: 0,
+                checksum: None,
+                next_header: None,
+                chunk_remaining: 0,
+                part_finished: true,
+                num_parts: 0,
+                error: None,
+            })),
+        }
+    }
+
+    /// read the next part, returns a reader of its batches or None at end of block
+    fn next_part(&mut self) -> Result<Option<StreamPartBatches<R>>> {
+        {
+            let mut input = self.input.lock().unwrap();
+            // skip unread batches of the previous part
+            input.finish_part()?;
+            if !input.start_part()? {
+                return Ok(None);
+            }
+        }
+        let part_input = StreamPartInput(self.input.clone());
+        let reader = StreamReader::try_new(ipc_stream_reader(part_input)?)?;
+        Ok(Some(StreamPartBatches {
+            reader,
+            input: self.input.clone(),
+            pending: None,
+            finished: false,
+        }))
+    }
+}
+
+/// Input of a stream block shared by the block reader and the reader of the
+/// current part
+struct StreamBlockInput<R: Read> {
+    input: R,
+    block_len: u64,
+    position: u64,
+    /// checksum of the bytes since the previous trailer, None if there are no
+    /// parts since the previous trailer
+    checksum: Option<ShuffleChecksum>,
+    /// header of the next part, read while looking for checksum trailers
+    next_header: Option<[u8; STREAM_PART_HEADER_LENGTH as usize]>,
+    /// remaining length of the current chunk of the current part
+    chunk_remaining: usize,
+    part_finished: bool,
+    num_parts: usize,
+    /// error found while decoding the current part, which is only seen by the
+    /// ipc reader as an io error
+    error: Option<DataFusionError>,
+}
+
+impl<R: Read> StreamBlockInput<R> {
+    /// read exactly `buf.len()` bytes, which are not added to the checksum
+    fn read_raw(&mut self, buf: &mut [u8]) -> Result<()> {
+        if self.position + buf.len() as u64 > self.block_len {
+            return Err(shuffle_corrupted(format!(
+                "truncated after part #{}",
+                self.num_parts,
+            )));
+        }
+        self.input.read_exact(buf)?;
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+
+    /// read exactly `buf.len()` bytes, which are added to the checksum
+    fn read_checksummed(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.read_raw(buf)?;
+        if let Some(checksum) = &mut self.checksum {
+            checksum.update(buf);
+        }
+        Ok(())
+    }
+
+    /// read the ipc stream of the current part, returns 0 after its last chunk
+    fn read_part(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.part_finished {
+            return Ok(0);
+        }
+        if self.chunk_remaining == 0 {
+            let mut chunk_len_buf = [0u8; 4];
+            self.read_checksummed(&mut chunk_len_buf)?;
+            self.chunk_remaining = u32::from_le_bytes(chunk_len_buf) as usize;
+            if self.chunk_remaining == 0 {
+                self.part_finished = true;
+                return Ok(0);
+            }
+        }
+        let len = buf.len().min(self.chunk_remaining);
+        self.read_checksummed(&mut buf[..len])?;
+        self.chunk_remaining -= len;
+        Ok(len)
+    }
+
+    /// skip the rest of the current part, then verify the checksum trailers
+    /// following it and read the header of the next part if any
+    fn finish_part(&mut self) -> Result<()> {
+        if !self.part_finished {
+            let mut buf = vec![0u8; 65536];
+            while self.read_part(&mut buf)? > 0 {}
+        }
+
+        let mut header = [0u8; STREAM_PART_HEADER_LENGTH as usize];
+        while self.next_header.is_none() && self.position < self.block_len {
+            self.read_raw(&mut header)?;
+            if header[..STREAM_PART_MAGIC.len()] == STREAM_PART_MAGIC {
+                self.next_header = Some(header);
+            } else {
+                self.verify_checksum_trailer(header)?;
+            }
+        }
+        match &self.checksum {
+            Some(checksum)
+                if self.next_header.is_none()
+                    && checksum.algorithm() != ShuffleChecksumAlgorithm::None =>
+            {
+                Err(shuffle_corrupted("missing checksum trailer".to_owned()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// start reading the next part from its header, returns false at end of block
+    fn start_part(&mut self) -> Result<bool> {
+        let header = match self.next_header.take() {
+            Some(header) => header,
+            None => return Ok(false),
+        };
+
+        // part header: [magic][version][checksum algorithm]
+        let version = header[STREAM_PART_MAGIC.len()];
+        if version != STREAM_PART_VERSION {
+            return Err(DataFusionError::Execution(format!(
+                "unsupported shuffle part version: {}",
+                version,
+            )));
+        }
+        let algorithm_id = header[STREAM_PART_MAGIC.len() + 1] as i64;
+        let algorithm =
+            ShuffleChecksumAlgorithm::from_id(algorithm_id).ok_or_else(|| {
+                shuffle_corrupted(format!("invalid checksum algorithm {}", algorithm_id))
+            })?;
+        self.checksum
+            .get_or_insert_with(|| ShuffleChecksum::new(algorithm))
+            .update(&header);
+        self.chunk_remaining = 0;
+        self.part_finished = false;
+        self.num_parts += 1;
+        Ok(true)
+    }
+
+    /// verify the trailer of parts since the previous trailer
+    fn verify_checksum_trailer(&mut self, checksum_buf: [u8; 8]) -> Result<()> {
+        if self.position + 8 > self.block_len {
+            return Err(shuffle_corrupted(format!(
+                "truncated checksum trailer after part #{}",
+                self.num_parts,
+            )));
+        }
+        let mut marker_buf = [0u8; 8];
+        self.read_raw(&mut marker_buf)?;
+        let marker = i64::from_le_bytes(marker_buf);
+        let algorithm = ShuffleChecksumAlgorithm::from_trailer_marker(marker)
+            .ok_or_else(|| {
+                shuffle_corrupted(format!(
+                    "invalid part header after part #{}",
+                    self.num_parts,
+                ))
+            })?;
+        let checksum = self.checksum.take();
+        let actual = match &checksum {
+            Some(checksum) if checksum.algorithm() == algorithm => checksum.value(),
+            _ => {
+                return Err(shuffle_corrupted(format!(
+                    "unexpected {:?} checksum trailer after part #{}",
+                    algorithm, self.num_parts,
+                )));
+            }
+        };
+        let expected = u64::from_le_bytes(checksum_buf);
+        if actual != expected {
+            return Err(shuffle_corrupted(format!(
+                "{:?} checksum mismatch (expected {:#x}, actual {:#x}, parts {})",
+                algorithm, expected, actual, self.num_parts,
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Reader of the ipc stream of the current part of a stream block
+struct StreamPartInput<R: Read>(Arc<Mutex<StreamBlockInput<R>>>);
+
+impl<R: Read> Read for StreamPartInput<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut input = self.0.lock().unwrap();
+        match input.read_part(buf) {
+            Ok(n) => Ok(n),
+            Err(e) => {
+                let message = e.to_string();
+                input.error = Some(e);
+                Err(std::io::Error::new(std::io::ErrorKind::Other, message))
+            }
+        }
+    }
+}
+
+/// Batches of a part of a stream block. The last batch is returned only after
+/// the checksum trailer following the part is verified.
+struct StreamPartBatches<R: Read> {
+    reader: StreamReader<Box<dyn Read + Send>>,
+    input: Arc<Mutex<StreamBlockInput<R>>>,
+    /// batch read ahead of the returned batches
+    pending: Option<RecordBatch>,
+    finished: bool,
+}
+
+impl<R: Read> StreamPartBatches<R> {
+    fn schema(&self) -> SchemaRef {
+        self.reader.schema()
+    }
+}
+
+impl<R: Read> Iterator for StreamPartBatches<R> {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.finished {
+            match self.reader.next() {
+                Some(Ok(batch)) => {
+                    if let Some(batch) = self.pending.replace(batch) {
+                        return Some(Ok(batch));
+                    }
+                }
+                Some(Err(e)) => {
+                    self.finished = true;
+                    self.pending = None;
+                    // prefer the error found by the input, which keeps its kind
+                    let input_error = self.input.lock().unwrap().error.take();
+                    return Some(Err(match input_error {
+                        Some(input_error) => {
+                            ArrowError::ExternalError(Box::new(input_error))
+                        }
+                        None => e,
+                    }));
+                }
+                None => {
+                    self.finished = true;
+                    if let Err(e) = self.input.lock().unwrap().finish_part() {
+                        self.pending = None;
+                        return Some(Err(ArrowError::ExternalError(Box::new(e))));
+                    }
+                }
+            }
+        }
+        self.pending.take().map(Ok)
+    }
+}
+
+/// Split a shuffle block into its ipc parts, verifying the checksums if the block
+/// has checksum trailers. A fetched block may be composed of several partitions,
+/// each trailer covers the parts since the previous trailer.
+fn read_block_parts<R: Read + Seek + Clone>(
+    mut block: R,
+    block_len: u64,
+) -> Result<Vec<BlockPartReader<R>>> {
+    let mut parts = vec![];
+    let mut trailers = vec![];
+    let mut end = block_len;
+
+    while end > 0 {
+        if end < 8 {
+            return Err(shuffle_corrupted(format!("truncated at {}", end)));
+        }
+        let tail = read_i64_at(&mut block, end - 8)?;
+
+        // checksum trailer
+        if tail < 0 {
+            let algorithm = ShuffleChecksumAlgorithm::from_trailer_marker(tail)
+                .filter(|_| end >= CHECKSUM_TRAILER_LENGTH)
+                .ok_or_else(|| {
+                    shuffle_corrupted(format!(
+                        "invalid part length {} at {}",
+                        tail,
+                        end - 8,
+                    ))
+                })?;
+            let expected = read_i64_at(&mut block, end - 16)? as u64;
+            end -= CHECKSUM_TRAILER_LENGTH;
+            trailers.push((end, expected, algorithm));
+            continue;
+        }
+
+        let part_len = tail as u64;
+        if part_len > end - 8 {
+            return Err(shuffle_corrupted(format!(
+                "invalid part length {} at {}",
+                part_len,
+                end - 8,
+            )));
+        }
+        let part_start = end - 8 - part_len;
+        parts.push(BlockPartReader::new(block.clone(), part_start, part_len));
+        end = part_start;
+    }
+
+    // parts and trailers are found from the end of block
+    parts.reverse();
+    trailers.reverse();
+
+    let mut segment_start = 0;
+    for (trailer_start, expected, algorithm) in trailers {
+        let segment_len = trailer_start - segment_start;
+        let data = BlockPartReader::new(block.clone(), segment_start, segment_len);
+        let actual = compute_checksum(data, algorithm)?;
+        if actual != expected {
+            return Err(shuffle_corrupted(format!(
+                "{:?} checksum mismatch (expected {:#x}, actual {:#x}, range {}+{})",
+                algorithm, expected, actual, segment_start, segment_len,
+            )));
+        }
+        segment_start = trailer_start + CHECKSUM_TRAILER_LENGTH;
+    }
+    if segment_start > 0 && segment_start < block_len {
+        return Err(shuffle_corrupted(format!(
+            "missing checksum trailer after {}",
+            segment_start,
+        )));
+    }
+    Ok(parts)
+}
+
+/// Error of a corrupted shuffle block, which is reported to the JVM as a
+/// fetch failure so that the map output is recomputed
+#[derive(Debug)]
+pub struct ShuffleCorruptedError {
+    pub message: String,
+    /// native shuffle id and index of the corrupted block among the blocks
+    /// read by the reader, set once the error reaches the reader
+    pub block: Option<(String, usize)>,
+}
+
+impl std::fmt::Display for ShuffleCorruptedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.block {
+            Some((native_shuffle_id, block_id)) => write!(
+                f,
+                "shuffle block corrupted: {} (block #{} of shuffle {})",
+                self.message, block_id, native_shuffle_id,
+            ),
+            None => write!(f, "shuffle block corrupted: {}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ShuffleCorruptedError {}
+
+/// Corruption error of block `block_id` of the shuffle, if `e` is a corruption
+/// error
+fn block_corrupted(
+    e: &DataFusionError,
+    native_shuffle_id: &str,
+    block_id: usize,
+) -> Option<DataFusionError> {
+    match e {
+        DataFusionError::External(e) => {
+            e.downcast_ref::<ShuffleCorruptedError>().map(|err| {
+                DataFusionError::External(Box::new(ShuffleCorruptedError {
+                    message: err.message.clone(),
+                    block: Some((native_shuffle_id.to_owned(), block_id)),
+                }))
+            })
+        }
+        _ => None,
+    }
+}
+
+fn shuffle_corrupted(message: String) -> DataFusionError {
+    DataFusionError::External(Box::new(ShuffleCorruptedError {
+        message,
+        block: None,
+    }))
+}
+
+fn read_i64_at<R: Read + Seek>(input: &mut R, pos: u64) -> Result<i64> {
+    let mut buf = [0u8; 8];
+    input.seek(SeekFrom::Start(pos))?;
+    input.read_exact(&mut buf)?;
+    Ok(i64::from_le_bytes(buf))
+}
+
+/// Reader of range `[start, start + len)` in a shuffle block
+struct BlockPartReader<R: Read + Seek> {
+    inner: R,
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl<R: Read + Seek> BlockPartReader<R> {
+    fn new(inner: R, start: u64, len: u64) -> Self {
+        Self {
+            inner,
+            start,
+            len,
+            pos: 0,
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for BlockPartReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.len {
+            return Ok(0);
+        }
+        // parts of a block share the underlying channel, so always seek before reading
+        let max_len = buf.len().min((self.len - self.pos) as usize);
+        self.inner.seek(SeekFrom::Start(self.start + self.pos))?;
+        let n = self.inner.read(&mut buf[..max_len])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for BlockPartReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.pos = seek_position(pos, self.pos, self.len)?;
+        Ok(self.pos)
+    }
+}
+
+/// New position of a seek in a range of length `len`
+fn seek_position(pos: SeekFrom, current: u64, len: u64) -> std::io::Result<u64> {
+    let new_pos = match pos {
+        SeekFrom::Start(pos) => pos as i64,
+        SeekFrom::End(offset) => len as i64 + offset,
+        SeekFrom::Current(offset) => current as i64 + offset,
+    };
+    if new_pos < 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "invalid seek to a negative position",
+        ));
+    }
+    Ok(new_pos as u64)
+}
+
+/// Reader of a shuffle block, either from a local file or a JVM channel
+#[derive(Clone)]
+enum ShuffleBlockReader {
+    Local(LocalFileSegmentReader),
+    Channel(SeekableByteChannelReader),
+}
+
+impl Read for ShuffleBlockReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            ShuffleBlockReader::Local(r) => r.read(buf),
+            ShuffleBlockReader::Channel(r) => r.read(buf),
+        }
+    }
+}
+
+impl Seek for ShuffleBlockReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            ShuffleBlockReader::Local(r) => r.seek(pos),
+            ShuffleBlockReader::Channel(r) => r.seek(pos),
+        }
+    }
+}
+
+/// Reader of segment `[offset, offset + len)` in a local shuffle file. Positional
+/// reads are used so that clones can share the same file without seeking it.
+#[derive(Clone)]
+struct LocalFileSegmentReader {
+    file: Arc<File>,
+    offset: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl LocalFileSegmentReader {
+    fn try_new(path: &str, offset: u64, len: u64) -> Result<Self> {
+        let file = File::open(path).map_err(|e| {
+            DataFusionError::Execution(format!(
+                "error opening local shuffle file {}: {}",
+                path, e
+            ))
+        })?;
+        Ok(Self {
+            file: Arc::new(file),
+            offset,
+            len,
+            pos: 0,
+        })
+    }
+}
+
+impl Read for LocalFileSegmentReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.len {
+            return Ok(0);
+        }
+        let max_len = buf.len().min((self.len - self.pos) as usize);
+        let n = self
+            .file
+            .read_at(&mut buf[..max_len], self.offset + self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for LocalFileSegmentReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.pos = seek_position(pos, self.pos, self.len)?;
+        Ok(self.pos)
+    }
+}
+
+/// Reader of a JVM channel of `len` bytes, tracking its position for relative
+/// seeks. Clones share the channel, so their positions are only valid until
+/// another clone reads or seeks.
+#[derive(Clone)]
+struct SeekableByteChannelReader {
+    channel: GlobalRef,
+    len: u64,
+    pos: u64,
+}
+
+impl SeekableByteChannelReader {
+    fn new(channel: GlobalRef, len: u64) -> Self {
+        Self {
+            channel,
+            len,
+            pos: 0,
+        }
+    }
+}
 
 impl Read for SeekableByteChannelReader {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        Ok(jni_call!(
-            JavaNioSeekableByteChannel(self.0.as_obj()).read(
+        // channel returns -1 at end of stream
+        let n = jni_call!(
+            JavaNioSeekableByteChannel(self.channel.as_obj()).read(
                 jni_new_direct_byte_buffer!(buf).to_io_result()?
             ) -> jint
         )
-        .to_io_result()? as usize)
+        .to_io_result()?
+        .max(0) as usize;
+        self.pos += n as u64;
+        Ok(n)
     }
 }
 
 impl Seek for SeekableByteChannelReader {
     fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = seek_position(pos, self.pos, self.len)?;
         jni_call_static!(
-            JniBridge.seekByteChannel(
-                self.0.as_obj(),
-                match pos {
-                    SeekFrom::Start(position) =>
-                        position as i64,
-                    SeekFrom::End(position) =>
-                        position,
-                    SeekFrom::Current(_) =>
-                        unimplemented!(),
-                }
-            ) -> jlong
+            JniBridge.seekByteChannel(self.channel.as_obj(), pos as i64) -> jlong
         )
-        .map(|position| position as u64)
-        .to_io_result()
+        .to_io_result()?;
+        self.pos = pos;
+        Ok(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::io::Read;
+    use std::io::Write;
+    use std::sync::Arc;
+
+    use datafusion::arrow::array::Int32Array;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::error::ArrowError;
+    use datafusion::arrow::ipc::reader::FileReader;
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::execution::memory_manager::MemoryConsumer;
+    use datafusion::execution::runtime_env::{RuntimeConfig, RuntimeEnv};
+    use datafusion::physical_plan::common::collect;
+    use datafusion::physical_plan::memory::MemoryStream;
+    use datafusion::physical_plan::metrics::{
+        BaselineMetrics, ExecutionPlanMetricsSet, Time,
+    };
+    use datafusion::physical_plan::{ExecutionPlan, Partitioning};
+    use datafusion::prelude::SessionContext;
+    use futures::StreamExt;
+
+    use crate::native_error::{NativeError, NativeErrorKind};
+    use crate::shuffle_checksum::{
+        ChecksumWriter, ShuffleChecksumAlgorithm, CHECKSUM_TRAILER_LENGTH,
+    };
+    use crate::shuffle_codec::{
+        write_ipc_part, write_ipc_stream_part, IpcPartReader, ShuffleCompressionCodec,
+    };
+    use crate::shuffle_reader_exec::{
+        read_block_parts, register_local_shuffle_blocks, BlockParts, CoalesceStream,
+        LocalFileSegmentReader, LocalShuffleBlock, PartBatches, PrefetchMemoryConsumer,
+        PrefetchShuffleReaderStream, ShuffleReaderExec, StreamBlockReader,
+    };
+
+    fn write_block(
+        batches: &[RecordBatch],
+        algorithm: ShuffleChecksumAlgorithm,
+    ) -> Vec<u8> {
+        let schema = batches[0].schema();
+        let mut output = ChecksumWriter::new(vec![], algorithm);
+        for batch in batches {
+            let part_start = output.position();
+            let codec = ShuffleCompressionCodec::Lz4;
+            write_ipc_part(&[batch.clone()], &schema, codec, &mut output).unwrap();
+            let part_len = output.position() - part_start;
+            output.write_all(&part_len.to_le_bytes()).unwrap();
+        }
+        if algorithm != ShuffleChecksumAlgorithm::None {
+            output.write_checksum_trailer().unwrap();
+        }
+        output.into_inner()
+    }
+
+    fn write_stream_block(
+        batches: &[RecordBatch],
+        algorithm: ShuffleChecksumAlgorithm,
+    ) -> Vec<u8> {
+        let schema = batches[0].schema();
+        let mut output = ChecksumWriter::new(vec![], algorithm);
+        for (i, batch) in batches.iter().enumerate() {
+            let codec = [
+                ShuffleCompressionCodec::None,
+                ShuffleCompressionCodec::Lz4,
+                ShuffleCompressionCodec::Zstd,
+            ][i % 3];
+            write_ipc_stream_part(
+                &[batch.clone()],
+                &schema,
+                codec,
+                algorithm,
+                &mut output,
+            )
+            .unwrap();
+        }
+        if algorithm != ShuffleChecksumAlgorithm::None {
+            output.write_checksum_trailer().unwrap();
+        }
+        output.into_inner()
+    }
+
+    fn read_stream_block(block: &[u8]) -> datafusion::error::Result<Vec<RecordBatch>> {
+        // blocks are read from a source which cannot seek
+        let input = Cursor::new(block.to_vec()).take(block.len() as u64);
+        let mut batches = vec![];
+        let mut reader = StreamBlockReader::new(input, block.len() as u64);
+        while let Some(part) = reader.next_part()? {
+            for batch in part {
+                batches.push(batch?);
+            }
+        }
+        Ok(batches)
+    }
+
+    fn read_block(block: Vec<u8>) -> datafusion::error::Result<Vec<RecordBatch>> {
+        let block_len = block.len() as u64;
+        let mut block_parts = BlockParts::try_new(Cursor::new(block), block_len)?;
+        let mut batches = vec![];
+        while let Some((_, part)) = block_parts.next_part()? {
+            for batch in part {
+                batches.push(batch?);
+            }
+        }
+        Ok(batches)
+    }
+
+    fn test_batches() -> Vec<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, true)]));
+        (0..3)
+            .map(|i| {
+                let array = Int32Array::from(vec![Some(i), None, Some(i * 2)]);
+                RecordBatch::try_new(schema.clone(), vec![Arc::new(array)]).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_read_block_parts() {
+        let batches = test_batches();
+        for algorithm in [
+            ShuffleChecksumAlgorithm::None,
+            ShuffleChecksumAlgorithm::Adler32,
+            ShuffleChecksumAlgorithm::Crc32c,
+        ] {
+            let block = write_block(&batches, algorithm);
+            let block_len = block.len() as u64;
+            let parts = read_block_parts(Cursor::new(block), block_len).unwrap();
+            let read_batches = parts
+                .into_iter()
+                .flat_map(|part| {
+                    let reader = IpcPartReader::try_new(part).unwrap();
+                    FileReader::try_new(reader, None).unwrap()
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(read_batches, batches);
+        }
+    }
+
+    #[test]
+    fn test_read_local_file_segment() {
+        let batches = test_batches();
+        let block = write_block(&batches, ShuffleChecksumAlgorithm::Adler32);
+
+        // the block is located after some other data in the file
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&[0xff; 100]).unwrap();
+        file.write_all(&block).unwrap();
+        file.write_all(&[0xff; 100]).unwrap();
+        file.flush().unwrap();
+
+        let path = file.path().to_str().unwrap();
+        let block_len = block.len() as u64;
+        let segment = LocalFileSegmentReader::try_new(path, 100, block_len).unwrap();
+        let parts = read_block_parts(segment, block_len).unwrap();
+        let read_batches = parts
+            .into_iter()
+            .flat_map(|part| {
+                let reader = IpcPartReader::try_new(part).unwrap();
+                FileReader::try_new(reader, None).unwrap()
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(read_batches, batches);
+    }
+
+    #[test]
+    fn test_read_stream_block() {
+        let batches = test_batches();
+        for algorithm in [
+            ShuffleChecksumAlgorithm::None,
+            ShuffleChecksumAlgorithm::Adler32,
+            ShuffleChecksumAlgorithm::Crc32c,
+        ] {
+            let block = write_stream_block(&batches, algorithm);
+            assert_eq!(read_stream_block(&block).unwrap(), batches);
+            if algorithm == ShuffleChecksumAlgorithm::None {
+                continue;
+            }
+
+            let mut corrupted = block.clone();
+            let len = corrupted.len();
+            corrupted[len - 20] ^= 0xff;
+            assert!(read_stream_block(&corrupted).is_err());
+        }
+    }
+
+    #[test]
+    fn test_verify_checksum_before_last_batch() {
+        let batches = test_batches();
+        let mut block =
+            write_stream_block(&batches[..1], ShuffleChecksumAlgorithm::Crc32c);
+
+        // the part is still decodable, but its checksum does not match
+        let checksum_start = block.len() - CHECKSUM_TRAILER_LENGTH as usize;
+        block[checksum_start] ^= 0xff;
+        let input = Cursor::new(block.clone()).take(block.len() as u64);
+        let mut reader = StreamBlockReader::new(input, block.len() as u64);
+        let mut part = reader.next_part().unwrap().unwrap();
+        let err = part.next().unwrap().unwrap_err().to_string();
+        assert!(err.contains("Crc32c checksum mismatch"), "{}", err);
+        assert!(part.next().is_none());
+    }
+
+    #[test]
+    fn test_read_chunked_stream_parts() {
+        // parts larger than a chunk, in a block of two concatenated partitions
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, true)]));
+        let batches = (0..2)
+            .map(|i| {
+                let array = Int32Array::from_iter_values(i..i + 100000);
+                RecordBatch::try_new(schema.clone(), vec![Arc::new(array)]).unwrap()
+            })
+            .collect::<Vec<_>>();
+        let algorithm = ShuffleChecksumAlgorithm::Crc32c;
+        let block = [
+            write_stream_block(&batches[..1], algorithm),
+            write_stream_block(&batches[1..], algorithm),
+        ]
+        .concat();
+        assert!(block.len() > 2 * 65536);
+        assert_eq!(read_stream_block(&block).unwrap(), batches);
+
+        let mut truncated = block.clone();
+        truncated.truncate(block.len() - CHECKSUM_TRAILER_LENGTH as usize - 1);
+        assert!(read_stream_block(&truncated).is_err());
+    }
+
+    #[test]
+    fn test_detect_block_format() {
+        let batches = test_batches();
+        let algorithm = ShuffleChecksumAlgorithm::Adler32;
+        let legacy_block = write_block(&batches, algorithm);
+        let stream_block = write_stream_block(&batches, algorithm);
+
+        for (block, is_stream) in [(legacy_block, false), (stream_block, true)] {
+            let block_len = block.len() as u64;
+            let mut block_parts =
+                BlockParts::try_new(Cursor::new(block), block_len).unwrap();
+            assert_eq!(matches!(block_parts, BlockParts::Stream(_)), is_stream);
+
+            let mut read_batches = vec![];
+            while let Some((_, part)) = block_parts.next_part().unwrap() {
+                for batch in part {
+                    read_batches.push(batch.unwrap());
+                }
+            }
+            assert_eq!(read_batches, batches);
+        }
+    }
+
+    #[test]
+    fn test_read_corrupted_block() {
+        let batches = test_batches();
+        let mut block = write_block(&batches, ShuffleChecksumAlgorithm::Crc32c);
+        block[10] ^= 0xff;
+
+        let block_len = block.len() as u64;
+        let err = read_block_parts(Cursor::new(block), block_len)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("Crc32c checksum mismatch"), "{}", err);
+    }
+
+    #[test]
+    fn test_read_concatenated_blocks() {
+        // batch-fetched blocks are concatenated partitions, each with its trailer
+        let batches = test_batches();
+        let algorithm = ShuffleChecksumAlgorithm::Adler32;
+        let legacy_block = [
+            write_block(&batches[..1], algorithm),
+            write_block(&batches[1..], algorithm),
+        ]
+        .concat();
+        let stream_block = [
+            write_stream_block(&batches[..1], algorithm),
+            write_stream_block(&batches[1..], algorithm),
+        ]
+        .concat();
+
+        for block in [legacy_block, stream_block] {
+            assert_eq!(read_block(block.clone()).unwrap(), batches);
+
+            // checksum of the second partition is verified
+            let mut corrupted = block;
+            let trailer_start = corrupted.len() - CHECKSUM_TRAILER_LENGTH as usize;
+            corrupted[trailer_start] ^= 0xff;
+            let err = read_block(corrupted).unwrap_err();
+            let kind = NativeError::new(err, None).kind;
+            assert_eq!(kind, NativeErrorKind::ShuffleCorrupted);
+        }
+    }
+
+    #[test]
+    fn test_prefetch_segments() {
+        let batches = test_batches();
+        let segments = batches
+            .chunks(2)
+            .map(|chunk| {
+                let part_batches: PartBatches =
+                    Box::new(chunk.to_vec().into_iter().map(Ok::<_, ArrowError>));
+                Ok(part_batches)
+            })
+            .collect::<Vec<datafusion::error::Result<PartBatches>>>();
+
+        let runtime_env = Arc::new(RuntimeEnv::new(RuntimeConfig::new()).unwrap());
+        let metrics = ExecutionPlanMetricsSet::new();
+        let baseline_metrics = BaselineMetrics::new(&metrics, 0);
+        let consumer = Arc::new(PrefetchMemoryConsumer::new(
+            0,
+            runtime_env.clone(),
+            baseline_metrics.mem_used().clone(),
+        ));
+        runtime_env.register_requester(consumer.id());
+
+        let stream = PrefetchShuffleReaderStream::new(
+            batches[0].schema(),
+            Box::new(segments.into_iter()),
+            1,
+            consumer.clone(),
+            baseline_metrics,
+        );
+        let runtime = tokio::runtime::Builder::new_multi_thread().build().unwrap();
+        let read_batches = runtime.block_on(stream.collect::<Vec<_>>());
+        let read_batches = read_batches
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(read_batches, batches);
+        assert_eq!(consumer.mem_used(), 0);
+    }
+
+    #[test]
+    fn test_coalesce_stream() {
+        // batches of 3 rows
+        let batches = test_batches();
+        let schema = batches[0].schema();
+        for (batch_size, expected_num_rows) in [(5, vec![6, 3]), (3, vec![3, 3, 3])] {
+            let input = MemoryStream::try_new(batches.clone(), schema.clone(), None);
+            let stream =
+                CoalesceStream::new(Box::pin(input.unwrap()), batch_size, Time::new());
+            let coalesced = futures::executor::block_on(stream.collect::<Vec<_>>());
+            let num_rows = coalesced
+                .into_iter()
+                .map(|batch| batch.unwrap().num_rows())
+                .collect::<Vec<_>>();
+            assert_eq!(num_rows, expected_num_rows);
+        }
+    }
+
+    #[test]
+    fn test_read_registered_local_blocks() {
+        let batches = test_batches();
+        let block = write_stream_block(&batches, ShuffleChecksumAlgorithm::Crc32c);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&block).unwrap();
+        file.write_all(&block).unwrap();
+
+        let path = file.path().to_str().unwrap().to_owned();
+        let blocks = (0..2)
+            .map(|i| LocalShuffleBlock {
+                path: path.clone(),
+                offset: i * block.len() as u64,
+                length: block.len() as u64,
+            })
+            .collect();
+        register_local_shuffle_blocks("test_local_blocks", blocks);
+
+        let runtime = tokio::runtime::Builder::new_multi_thread().build().unwrap();
+        for prefetch_depth in [0, 1] {
+            let shuffle_reader = ShuffleReaderExec::new(
+                Partitioning::UnknownPartitioning(1),
+                "test_local_blocks".to_owned(),
+                batches[0].schema(),
+                prefetch_depth,
+            );
+            let task_ctx = SessionContext::new().task_ctx();
+            let read_batches = runtime
+                .block_on(async { collect(shuffle_reader.execute(0, task_ctx)?).await })
+                .unwrap();
+            let num_rows = read_batches.iter().map(|b| b.num_rows()).sum::<usize>();
+            assert_eq!(num_rows, 18);
+        }
+    }
+
+    #[test]
+    fn test_corrupted_block_index() {
+        let batches = test_batches();
+        let block = write_stream_block(&batches, ShuffleChecksumAlgorithm::Crc32c);
+        let mut corrupted = block.clone();
+        let trailer_start = corrupted.len() - CHECKSUM_TRAILER_LENGTH as usize;
+        corrupted[trailer_start] ^= 0xff;
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&block).unwrap();
+        file.write_all(&corrupted).unwrap();
+
+        let path = file.path().to_str().unwrap().to_owned();
+        let blocks = (0..2)
+            .map(|i| LocalShuffleBlock {
+                path: path.clone(),
+                offset: i * block.len() as u64,
+                length: block.len() as u64,
+            })
+            .collect();
+        register_local_shuffle_blocks("test_corrupted_blocks", blocks);
+
+        let runtime = tokio::runtime::Builder::new_multi_thread().build().unwrap();
+        let shuffle_reader = ShuffleReaderExec::new(
+            Partitioning::UnknownPartitioning(1),
+            "test_corrupted_blocks".to_owned(),
+            batches[0].schema(),
+            0,
+        );
+        let task_ctx = SessionContext::new().task_ctx();
+        let err = runtime
+            .block_on(async { collect(shuffle_reader.execute(0, task_ctx)?).await })
+            .unwrap_err();
+        let err = NativeError::new(err, None);
+        assert_eq!(err.kind, NativeErrorKind::ShuffleCorrupted);
+        assert_eq!(err.corrupted_block(), Some(("test_corrupted_blocks", 1)));
     }
 }
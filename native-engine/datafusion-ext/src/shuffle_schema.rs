@@ -0,0 +1,210 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Reconciliation of schemas of shuffle ipc parts.
+//!
+//! Parts written by the JVM and by native shuffle writer may have schemas which
+//! differ from the expected schema of shuffle reader in field names, nullability
+//! or offset sizes of string/binary types. Such parts are converted into the
+//! expected schema, while parts with incompatible schemas are rejected.
+
+use datafusion::arrow::array::ArrayRef;
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{DataType, Schema, SchemaRef};
+use datafusion::arrow::error::{ArrowError, Result as ArrowResult};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::{DataFusionError, Result};
+
+/// Converts batches of an ipc part into the expected schema
+#[derive(Debug)]
+pub struct SchemaAdapter {
+    schema: SchemaRef,
+    columns: Vec<ColumnAdapter>,
+    identity: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ColumnAdapter {
+    /// nullable column of a non-nullable field, which must contain no nulls
+    check_no_nulls: bool,
+    cast: Option<DataType>,
+}
+
+impl SchemaAdapter {
+    pub fn try_new(part_schema: &Schema, schema: &SchemaRef) -> Result<Self> {
+        if part_schema.fields().len() != schema.fields().len() {
+            return Err(DataFusionError::Execution(format!(
+                "schema mismatch: expected {} columns, found {}",
+                schema.fields().len(),
+                part_schema.fields().len(),
+            )));
+        }
+
+        let columns = part_schema
+            .fields()
+            .iter()
+            .zip(schema.fields())
+            .map(|(part_field, field)| {
+                let (from, to) = (part_field.data_type(), field.data_type());
+                if !can_reconcile(from, to) {
+                    return Err(DataFusionError::Execution(format!(
+                        "schema mismatch: column {} expected type {:?}, found {:?}",
+                        field.name(),
+                        to,
+                        from,
+                    )));
+                }
+                Ok(ColumnAdapter {
+                    check_no_nulls: part_field.is_nullable() && !field.is_nullable(),
+                    cast: Some(to.clone()).filter(|to| to != from),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // batches can be used without conversion only if all fields are identical
+        let identity = part_schema.fields() == schema.fields();
+        Ok(Self {
+            schema: schema.clone(),
+            columns,
+            identity,
+        })
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.identity
+    }
+
+    pub fn adapt(&self, batch: RecordBatch) -> ArrowResult<RecordBatch> {
+        let columns = batch
+            .columns()
+            .iter()
+            .zip(&self.columns)
+            .zip(self.schema.fields())
+            .map(|((column, adapter), field)| {
+                if adapter.check_no_nulls && column.null_count() > 0 {
+                    return Err(ArrowError::InvalidArgumentError(format!(
+                        "schema mismatch: column {} is not nullable but contains {} nulls",
+                        field.name(),
+                        column.null_count(),
+                    )));
+                }
+                match &adapter.cast {
+                    Some(data_type) => cast(column, data_type),
+                    None => Ok(column.clone()),
+                }
+            })
+            .collect::<ArrowResult<Vec<ArrayRef>>>()?;
+        RecordBatch::try_new(self.schema.clone(), columns)
+    }
+}
+
+/// whether a column can be converted without changing its values
+fn can_reconcile(from: &DataType, to: &DataType) -> bool {
+    use DataType::*;
+    match (from, to) {
+        (Utf8 | LargeUtf8, Utf8 | LargeUtf8) => true,
+        (Binary | LargeBinary, Binary | LargeBinary) => true,
+        (List(from_field), List(to_field))
+        | (LargeList(from_field), LargeList(to_field)) => {
+            can_reconcile(from_field.data_type(), to_field.data_type())
+        }
+        _ => from == to,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use datafusion::arrow::array::*;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
+
+    use crate::shuffle_schema::SchemaAdapter;
+
+    #[test]
+    fn test_adapt_part_schema() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("a", DataType::Int32, false),
+            Field::new("b", DataType::LargeUtf8, true),
+        ]));
+        let part_schema = Arc::new(Schema::new(vec![
+            Field::new("#1", DataType::Int32, true),
+            Field::new("#2", DataType::Utf8, false),
+        ]));
+        let adapter = SchemaAdapter::try_new(&part_schema, &schema).unwrap();
+        assert!(!adapter.is_identity());
+        assert!(SchemaAdapter::try_new(&schema, &schema)
+            .unwrap()
+            .is_identity());
+
+        let batch = RecordBatch::try_new(
+            part_schema.clone(),
+            vec![
+                Arc::new(Int32Array::from(vec![1, 2])),
+                Arc::new(StringArray::from(vec!["x", "y"])),
+            ],
+        )
+        .unwrap();
+        let adapted = adapter.adapt(batch).unwrap();
+        assert_eq!(adapted.schema(), schema);
+        assert_eq!(
+            as_largestring_array(adapted.column(1)),
+            &LargeStringArray::from(vec!["x", "y"])
+        );
+
+        // nulls in non-nullable column
+        let batch = RecordBatch::try_new(
+            part_schema.clone(),
+            vec![
+                Arc::new(Int32Array::from(vec![Some(1), None])),
+                Arc::new(StringArray::from(vec!["x", "y"])),
+            ],
+        )
+        .unwrap();
+        assert!(adapter.adapt(batch).is_err());
+
+        // nulls in non-nullable column of a different type
+        let batch = RecordBatch::try_new(
+            Arc::new(Schema::new(vec![
+                Field::new("#1", DataType::Int32, true),
+                Field::new("#2", DataType::Utf8, true),
+            ])),
+            vec![
+                Arc::new(Int32Array::from(vec![1, 2])),
+                Arc::new(StringArray::from(vec![Some("x"), None])),
+            ],
+        )
+        .unwrap();
+        let non_nullable_schema = Arc::new(Schema::new(vec![
+            Field::new("a", DataType::Int32, true),
+            Field::new("b", DataType::LargeUtf8, false),
+        ]));
+        let adapter =
+            SchemaAdapter::try_new(&batch.schema(), &non_nullable_schema).unwrap();
+        let err = adapter.adapt(batch).unwrap_err();
+        assert!(err.to_string().contains("column b is not nullable"));
+
+        // incompatible types
+        let part_schema = Schema::new(vec![
+            Field::new("a", DataType::Int64, false),
+            Field::new("b", DataType::LargeUtf8, true),
+        ]);
+        let err = SchemaAdapter::try_new(&part_schema, &schema).unwrap_err();
+        assert!(err.to_string().contains("column a expected type Int32"));
+    }
+}
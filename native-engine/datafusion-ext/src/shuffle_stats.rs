@@ -0,0 +1,184 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Statistics of shuffle output partitions.
+
+use std::cmp::Ordering;
+
+use datafusion::arrow::array::*;
+use datafusion::arrow::compute::{
+    max, max_boolean, max_string, min, min_boolean, min_string,
+};
+use datafusion::arrow::datatypes::DataType;
+use datafusion::physical_plan::ColumnStatistics;
+use datafusion::scalar::ScalarValue;
+
+/// Statistics of a single output partition written by shuffle writer
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShuffleWritePartitionStats {
+    pub partition_id: usize,
+    pub num_batches: usize,
+    pub num_rows: usize,
+    pub num_bytes: usize,
+    /// min/max values and null counts of each column, if enabled
+    pub column_stats: Option<Vec<ColumnStatistics>>,
+}
+
+impl ShuffleWritePartitionStats {
+    pub fn new(partition_id: usize, num_columns: usize, with_column_stats: bool) -> Self {
+        let column_stats = with_column_stats.then(|| {
+            (0..num_columns)
+                .map(|_| ColumnStatistics {
+                    null_count: Some(0),
+                    ..Default::default()
+                })
+                .collect()
+        });
+        Self {
+            partition_id,
+            column_stats,
+            ..Default::default()
+        }
+    }
+
+    /// update statistics with rows of `columns` written into this partition
+    pub fn update(&mut self, columns: &[ArrayRef], num_rows: usize) {
+        if num_rows == 0 {
+            return;
+        }
+        self.num_rows += num_rows;
+
+        if let Some(column_stats) = &mut self.column_stats {
+            for (stats, column) in column_stats.iter_mut().zip(columns) {
+                stats.null_count =
+                    Some(stats.null_count.unwrap_or(0) + column.null_count());
+                if let Some((min_value, max_value)) = compute_min_max(column) {
+                    merge_bound(&mut stats.min_value, min_value, Ordering::Less);
+                    merge_bound(&mut stats.max_value, max_value, Ordering::Greater);
+                }
+            }
+        }
+    }
+}
+
+/// replace `bound` with `value` if `value` compares to it as `ordering`
+fn merge_bound(bound: &mut Option<ScalarValue>, value: ScalarValue, ordering: Ordering) {
+    if value.is_null() {
+        return;
+    }
+    match bound {
+        Some(current) if value.partial_cmp(current) != Some(ordering) => {}
+        _ => *bound = Some(value),
+    }
+}
+
+macro_rules! min_max_primitive {
+    ($column:expr, $ARRAYTY:ident, $SCALAR:ident) => {{
+        let array = $column.as_any().downcast_ref::<$ARRAYTY>().unwrap();
+        Some((
+            ScalarValue::$SCALAR(min(array)),
+            ScalarValue::$SCALAR(max(array)),
+        ))
+    }};
+}
+
+/// min and max values of a column, only supported for primitive, string and
+/// boolean types which can be serialized into protobuf scalar values
+fn compute_min_max(column: &ArrayRef) -> Option<(ScalarValue, ScalarValue)> {
+    match column.data_type() {
+        DataType::Boolean => {
+            let array = as_boolean_array(column);
+            Some((
+                ScalarValue::Boolean(min_boolean(array)),
+                ScalarValue::Boolean(max_boolean(array)),
+            ))
+        }
+        DataType::Int8 => min_max_primitive!(column, Int8Array, Int8),
+        DataType::Int16 => min_max_primitive!(column, Int16Array, Int16),
+        DataType::Int32 => min_max_primitive!(column, Int32Array, Int32),
+        DataType::Int64 => min_max_primitive!(column, Int64Array, Int64),
+        DataType::UInt8 => min_max_primitive!(column, UInt8Array, UInt8),
+        DataType::UInt16 => min_max_primitive!(column, UInt16Array, UInt16),
+        DataType::UInt32 => min_max_primitive!(column, UInt32Array, UInt32),
+        DataType::UInt64 => min_max_primitive!(column, UInt64Array, UInt64),
+        DataType::Float32 => min_max_primitive!(column, Float32Array, Float32),
+        DataType::Float64 => min_max_primitive!(column, Float64Array, Float64),
+        DataType::Date32 => min_max_primitive!(column, Date32Array, Date32),
+        DataType::Utf8 => {
+            let array = as_string_array(column);
+            Some((
+                ScalarValue::Utf8(min_string(array).map(|s| s.to_owned())),
+                ScalarValue::Utf8(max_string(array).map(|s| s.to_owned())),
+            ))
+        }
+        DataType::LargeUtf8 => {
+            let array = as_largestring_array(column);
+            Some((
+                ScalarValue::LargeUtf8(min_string(array).map(|s| s.to_owned())),
+                ScalarValue::LargeUtf8(max_string(array).map(|s| s.to_owned())),
+            ))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use datafusion::arrow::array::*;
+    use datafusion::scalar::ScalarValue;
+
+    use crate::shuffle_stats::ShuffleWritePartitionStats;
+
+    #[test]
+    fn test_update_partition_stats() {
+        let mut stats = ShuffleWritePartitionStats::new(3, 3, true);
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int32Array::from(vec![Some(5), None, Some(-1)])),
+            Arc::new(StringArray::from(vec![Some("b"), Some("a"), None])),
+            Arc::new(NullArray::new(3)),
+        ];
+        stats.update(&columns, 3);
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int32Array::from(vec![Some(7)])),
+            Arc::new(StringArray::from(vec![Some("c")])),
+            Arc::new(NullArray::new(1)),
+        ];
+        stats.update(&columns, 1);
+
+        assert_eq!(stats.partition_id, 3);
+        assert_eq!(stats.num_rows, 4);
+        let column_stats = stats.column_stats.unwrap();
+        assert_eq!(column_stats[0].null_count, Some(1));
+        assert_eq!(
+            column_stats[0].min_value,
+            Some(ScalarValue::Int32(Some(-1)))
+        );
+        assert_eq!(column_stats[0].max_value, Some(ScalarValue::Int32(Some(7))));
+        assert_eq!(column_stats[1].null_count, Some(1));
+        assert_eq!(
+            column_stats[1].min_value,
+            Some(ScalarValue::Utf8(Some("a".to_owned())))
+        );
+        assert_eq!(
+            column_stats[1].max_value,
+            Some(ScalarValue::Utf8(Some("c".to_owned())))
+        );
+        assert_eq!(column_stats[2].min_value, None);
+    }
+}
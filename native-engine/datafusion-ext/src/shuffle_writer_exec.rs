@@ -18,6 +18,7 @@
 //! Defines the External shuffle repartition plan
 
 use std::any::Any;
+use std::cmp::Ordering;
 use std::fmt;
 use std::fmt::Debug;
 use std::fmt::Formatter;
@@ -28,16 +29,19 @@ use std::io::Seek;
 use std::io::SeekFrom;
 use std::io::Write;
 use std::path::Path;
+use std::sync::atomic::AtomicI32;
+use std::sync::atomic::Ordering::Relaxed;
 use std::sync::Arc;
 
 use async_trait::async_trait;
 use datafusion::arrow::array::*;
 use datafusion::arrow::compute::take;
+use datafusion::arrow::datatypes::ArrowNativeType;
 use datafusion::arrow::datatypes::DataType;
 use datafusion::arrow::datatypes::SchemaRef;
 use datafusion::arrow::datatypes::TimeUnit;
 use datafusion::arrow::error::ArrowError;
-use datafusion::arrow::ipc::writer::FileWriter;
+use datafusion::arrow::error::Result as ArrowResult;
 use datafusion::arrow::record_batch::RecordBatch;
 use datafusion::error::{DataFusionError, Result};
 use datafusion::execution::context::TaskContext;
@@ -52,11 +56,15 @@ use datafusion::physical_plan::expressions::PhysicalSortExpr;
 use datafusion::physical_plan::memory::MemoryStream;
 use datafusion::physical_plan::metrics::BaselineMetrics;
 use datafusion::physical_plan::metrics::CompositeMetricsSet;
+use datafusion::physical_plan::metrics::ExecutionPlanMetricsSet;
+use datafusion::physical_plan::metrics::Gauge;
+use datafusion::physical_plan::metrics::MetricBuilder;
 use datafusion::physical_plan::metrics::MetricsSet;
 use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
 use datafusion::physical_plan::DisplayFormatType;
 use datafusion::physical_plan::ExecutionPlan;
 use datafusion::physical_plan::Partitioning;
+use datafusion::physical_plan::PhysicalExpr;
 use datafusion::physical_plan::SendableRecordBatchStream;
 use datafusion::physical_plan::Statistics;
 use futures::lock::Mutex;
@@ -65,7 +73,13 @@ use log::debug;
 use tempfile::NamedTempFile;
 use tokio::task;
 
+use crate::batch_buffer::ConcatArrayBuilder;
 use crate::batch_buffer::MutableRecordBatch;
+use crate::shuffle_checksum::ChecksumWriter;
+use crate::shuffle_checksum::ShuffleChecksumAlgorithm;
+use crate::shuffle_codec::write_ipc_stream_part;
+use crate::shuffle_codec::ShuffleCompressionCodec;
+use crate::shuffle_stats::ShuffleWritePartitionStats;
 use crate::spark_hash::{create_hashes, pmod};
 
 #[derive(Default)]
@@ -75,31 +89,42 @@ struct PartitionBuffer {
 }
 
 impl PartitionBuffer {
+    /// output all buffered rows, the active builder is dropped to release its memory
+    /// and will be created again on next insertion
     fn output_all(&mut self) -> Result<Vec<RecordBatch>> {
         let mut output: Vec<RecordBatch> = vec![];
         output.append(&mut self.frozen);
         if let Some(mut mutable) = self.active.take() {
-            let result = mutable.output_and_reset()?;
+            let result = mutable.output()?;
             output.push(result);
-            self.active = Some(mutable);
         }
         Ok(output)
     }
 
-    fn output_clean(&mut self) -> Result<Vec<RecordBatch>> {
-        let mut output: Vec<RecordBatch> = vec![];
-        output.append(&mut self.frozen);
-        if let Some(mut mutable) = self.active.take() {
-            let result = mutable.output()?;
-            output.push(result);
-        }
-        Ok(output)
+    fn mem_size(&self) -> usize {
+        let frozen_size = self.frozen.iter().map(batch_byte_size).sum::<usize>();
+        let active_size = self.active.as_ref().map(|a| a.mem_size()).unwrap_or(0);
+        frozen_size + active_size
     }
 }
 
+/// An input batch of sort-based shuffle, with rows sorted by partition id
+struct PartitionSortedBatch {
+    batch: RecordBatch,
+    /// contiguous runs of rows as (partition_id, offset, len), ordered by partition id
+    runs: Vec<(usize, usize, usize)>,
+}
+
+/// Above this number of output partitions, the repartitioner buffers whole input
+/// batches sorted by partition id instead of keeping a buffer for each partition,
+/// in the same way as spark's UnsafeShuffleWriter.
+pub const DEFAULT_SORT_BASED_PARTITION_THRESHOLD: usize = 1000;
+
 struct SpillInfo {
     file: NamedTempFile,
     offsets: Vec<u64>,
+    /// number of batches of each partition
+    num_batches: Vec<usize>,
 }
 
 macro_rules! append {
@@ -112,14 +137,33 @@ macro_rules! append {
     }};
 }
 
+macro_rules! append_binary {
+    ($TO:ty, $FROM:ty, $to: ident, $from: ident) => {{
+        let to = $to.as_any_mut().downcast_mut::<$TO>().unwrap();
+        let from = $from.as_any().downcast_ref::<$FROM>().unwrap();
+        for i in 0..from.len() {
+            if from.is_valid(i) {
+                to.append_value(from.value(i))?;
+            } else {
+                to.append_null()?;
+            }
+        }
+    }};
+}
+
 fn append_column(
     to: &mut Box<dyn ArrayBuilder>,
     from: &Arc<dyn Array>,
     data_type: &DataType,
 ) -> Result<()> {
+    // types without typed builders are buffered as whole arrays
+    if let Some(to) = to.as_any_mut().downcast_mut::<ConcatArrayBuilder>() {
+        to.append_array(from.clone());
+        return Ok(());
+    }
+
     // output buffered start `buffered_idx`, len `rows_to_output`
     match data_type {
-        DataType::Null => unimplemented!(),
         DataType::Boolean => append!(BooleanBuilder, BooleanArray, to, from),
         DataType::Int8 => append!(Int8Builder, Int8Array, to, from),
         DataType::Int16 => append!(Int16Builder, Int16Array, to, from),
@@ -137,7 +181,7 @@ fn append_column(
             append!(Time32SecondBuilder, Time32SecondArray, to, from)
         }
         DataType::Time32(TimeUnit::Millisecond) => {
-            append!(Time32MillisecondBuilder, Time32SecondArray, to, from)
+            append!(Time32MillisecondBuilder, Time32MillisecondArray, to, from)
         }
         DataType::Time64(TimeUnit::Microsecond) => {
             append!(Time64MicrosecondBuilder, Time64MicrosecondArray, to, from)
@@ -145,39 +189,104 @@ fn append_column(
         DataType::Time64(TimeUnit::Nanosecond) => {
             append!(Time64NanosecondBuilder, Time64NanosecondArray, to, from)
         }
+        DataType::Timestamp(TimeUnit::Second, None) => {
+            append!(TimestampSecondBuilder, TimestampSecondArray, to, from)
+        }
+        DataType::Timestamp(TimeUnit::Millisecond, None) => {
+            append!(
+                TimestampMillisecondBuilder,
+                TimestampMillisecondArray,
+                to,
+                from
+            )
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, None) => {
+            append!(
+                TimestampMicrosecondBuilder,
+                TimestampMicrosecondArray,
+                to,
+                from
+            )
+        }
+        DataType::Timestamp(TimeUnit::Nanosecond, None) => {
+            append!(
+                TimestampNanosecondBuilder,
+                TimestampNanosecondArray,
+                to,
+                from
+            )
+        }
         DataType::Utf8 => append!(StringBuilder, StringArray, to, from),
         DataType::LargeUtf8 => append!(LargeStringBuilder, LargeStringArray, to, from),
+        DataType::Binary => append_binary!(BinaryBuilder, BinaryArray, to, from),
+        DataType::LargeBinary => {
+            append_binary!(LargeBinaryBuilder, LargeBinaryArray, to, from)
+        }
         DataType::Decimal(_precision, _scale) => {
-            let decimal_builder =
-                to.as_any_mut().downcast_mut::<DecimalBuilder>().unwrap();
-            let decimal_array = from.as_any().downcast_ref::<DecimalArray>().unwrap();
-            for i in 0..decimal_array.len() {
-                if decimal_array.is_valid(i) {
-                    decimal_builder.append_value(decimal_array.value(i))?;
-                } else {
-                    decimal_builder.append_null()?;
-                }
-            }
+            append_binary!(DecimalBuilder, DecimalArray, to, from)
+        }
+        other => {
+            return Err(DataFusionError::Internal(format!(
+                "no typed shuffle buffer for data type {:?}",
+                other
+            )));
         }
-        _ => todo!(),
     }
     Ok(())
 }
 
+/// Partitioning scheme of shuffle writer, covering spark partitionings which
+/// are not available in datafusion's [`Partitioning`]
+#[derive(Debug, Clone)]
+pub enum ShuffleRepartitioning {
+    /// All rows go to the only output partition
+    Single,
+    /// Spark-compatible murmur3 hash partitioning
+    Hash(Vec<Arc<dyn PhysicalExpr>>, usize),
+    /// Round-robin partitioning starting from a random partition, like spark does
+    RoundRobin(usize),
+    /// Range partitioning on sort keys, `bounds` contains one array of sorted
+    /// upper bounds for each sort expression
+    Range {
+        sort_exprs: Vec<PhysicalSortExpr>,
+        num_partitions: usize,
+        bounds: Vec<ArrayRef>,
+    },
+}
+
+impl ShuffleRepartitioning {
+    pub fn partition_count(&self) -> usize {
+        match self {
+            ShuffleRepartitioning::Single => 1,
+            ShuffleRepartitioning::Hash(_, n) => *n,
+            ShuffleRepartitioning::RoundRobin(n) => *n,
+            ShuffleRepartitioning::Range { num_partitions, .. } => *num_partitions,
+        }
+    }
+}
+
 struct ShuffleRepartitioner {
     id: MemoryConsumerId,
     output_data_file: String,
     output_index_file: String,
     schema: SchemaRef,
     buffered_partitions: Mutex<Vec<PartitionBuffer>>,
+    /// Buffered batches if sort-based shuffle is used
+    sorted_batches: Mutex<Vec<PartitionSortedBatch>>,
+    sort_based: bool,
     spills: Mutex<Vec<SpillInfo>>,
-    /// Sort expressions
     /// Partitioning scheme to use
-    partitioning: Partitioning,
+    partitioning: ShuffleRepartitioning,
+    /// Position of last row for round-robin partitioning
+    round_robin_position: AtomicI32,
     num_output_partitions: usize,
     runtime: Arc<RuntimeEnv>,
     metrics: BaselineMetrics,
+    peak_mem_used: Gauge,
     batch_size: usize,
+    codec: ShuffleCompressionCodec,
+    checksum_algorithm: ShuffleChecksumAlgorithm,
+    partition_stats: Mutex<Vec<ShuffleWritePartitionStats>>,
 }
 
 impl ShuffleRepartitioner {
@@ -187,129 +296,334 @@ impl ShuffleRepartitioner {
         output_data_file: String,
         output_index_file: String,
         schema: SchemaRef,
-        partitioning: Partitioning,
+        partitioning: ShuffleRepartitioning,
         metrics: BaselineMetrics,
+        peak_mem_used: Gauge,
         runtime: Arc<RuntimeEnv>,
         batch_size: usize,
+        codec: ShuffleCompressionCodec,
+        checksum_algorithm: ShuffleChecksumAlgorithm,
+        with_column_stats: bool,
+        sort_based_partition_threshold: usize,
     ) -> Self {
         let num_output_partitions = partitioning.partition_count();
+        let sort_based = num_output_partitions > sort_based_partition_threshold;
+
+        // spark starts round-robin partitioning from a random partition:
+        // `new java.util.Random(partitionId).nextInt(numPartitions)`
+        let round_robin_start = match &partitioning {
+            ShuffleRepartitioning::RoundRobin(n) => {
+                java_random_next_int(partition_id as i64, *n as i32)
+            }
+            _ => 0,
+        };
+        let num_columns = schema.fields().len();
         Self {
             id: MemoryConsumerId::new(partition_id),
             output_data_file,
             output_index_file,
             schema,
-            buffered_partitions: Mutex::new(
+            buffered_partitions: Mutex::new(if sort_based {
+                vec![]
+            } else {
                 (0..num_output_partitions)
                     .map(|_| Default::default())
-                    .collect::<Vec<_>>(),
-            ),
+                    .collect::<Vec<_>>()
+            }),
+            sorted_batches: Mutex::new(vec![]),
+            sort_based,
             spills: Mutex::new(vec![]),
             partitioning,
+            round_robin_position: AtomicI32::new(round_robin_start),
             num_output_partitions,
             runtime,
             metrics,
+            peak_mem_used,
             batch_size,
+            codec,
+            checksum_algorithm,
+            partition_stats: Mutex::new(
+                (0..num_output_partitions)
+                    .map(|i| {
+                        ShuffleWritePartitionStats::new(i, num_columns, with_column_stats)
+                    })
+                    .collect(),
+            ),
         }
     }
 
     async fn insert_batch(&self, input: RecordBatch) -> Result<()> {
         let _timer = self.metrics.elapsed_compute().timer();
 
-        // TODO: this is a rough estimation of memory consumed for a input batch
-        // for example, for first batch seen, we need to open as much output buffer
-        // as we encountered in this batch, thus the memory consumption is `rough`.
-        let size = batch_byte_size(&input);
-        self.try_grow(size).await?;
-        self.metrics.mem_used().add(size);
+        // reserve memory for the partitioned copy of input batch before buffering it,
+        // the reservation is then adjusted to the actual growth of buffers.
+        let reserved = batch_byte_size(&input);
+        self.try_grow(reserved).await?;
+        self.metrics.mem_used().add(reserved);
+
+        let partition_ids = self.evaluate_partition_ids(&input)?;
+        let mem_diff = if self.sort_based {
+            self.insert_sorted_batch(input, partition_ids).await?
+        } else {
+            self.insert_partitioned_batch(input, partition_ids).await?
+        };
+        self.update_mem_used(reserved, mem_diff);
+        Ok(())
+    }
 
+    /// Replace the memory reserved for an inserted batch with the actual size
+    /// difference of buffers
+    fn update_mem_used(&self, reserved: usize, mem_diff: isize) {
+        let mem_used = self.metrics.mem_used();
+        if mem_diff > reserved as isize {
+            // buffers are already allocated, so grow without spilling
+            let required = mem_diff as usize - reserved;
+            self.grow(required);
+            mem_used.add(required);
+        } else {
+            let freed = (reserved as isize - mem_diff) as usize;
+            self.shrink(freed);
+            mem_used.set(mem_used.value().saturating_sub(freed));
+        }
+
+        if mem_used.value() > self.peak_mem_used.value() {
+            self.peak_mem_used.set(mem_used.value());
+        }
+    }
+
+    /// Append rows of input batch into buffers of their output partitions, returns
+    /// the size difference of buffers
+    async fn insert_partitioned_batch(
+        &self,
+        input: RecordBatch,
+        partition_ids: Vec<usize>,
+    ) -> Result<isize> {
         let num_output_partitions = self.num_output_partitions;
+        let mut mem_diff = 0isize;
+        let mut indices = vec![vec![]; num_output_partitions];
+        for (index, partition_id) in partition_ids.into_iter().enumerate() {
+            indices[partition_id].push(index as u64)
+        }
+
+        let mut partition_stats = self.partition_stats.lock().await;
+        for (num_output_partition, partition_indices) in indices.into_iter().enumerate() {
+            let mut buffered_partitions = self.buffered_partitions.lock().await;
+            let output = &mut buffered_partitions[num_output_partition];
+            let mem_size_before = output.mem_size();
+            let indices = UInt64Array::from_slice(&partition_indices);
+            // Produce batches based on indices
+            let columns = input
+                .columns()
+                .iter()
+                .map(|c| {
+                    take(c.as_ref(), &indices, None)
+                        .map_err(|e| DataFusionError::Execution(e.to_string()))
+                })
+                .collect::<Result<Vec<Arc<dyn Array>>>>()?;
+            partition_stats[num_output_partition]
+                .update(&columns, partition_indices.len());
+
+            if partition_indices.len() > self.batch_size {
+                let output_batch = RecordBatch::try_new(input.schema().clone(), columns)?;
+                output.frozen.push(output_batch);
+            } else {
+                if output.active.is_none() {
+                    let buffer =
+                        MutableRecordBatch::new(self.batch_size, self.schema.clone());
+                    output.active = Some(buffer);
+                };
+
+                let mut batch = output.active.take().unwrap();
+                let mut data_size = 0;
+                for ((to, from), dt) in batch
+                    .arrays
+                    .iter_mut()
+                    .zip(columns.iter())
+                    .zip(self.schema.fields().iter().map(|f| f.data_type()))
+                {
+                    append_column(to, from, dt)?;
+                    data_size += appended_data_size(to.as_ref(), from);
+                }
+                batch.append(partition_indices.len());
+                batch.append_data_size(data_size);
+
+                if batch.is_full() {
+                    let result = batch.output_and_reset()?;
+                    output.frozen.push(result);
+                }
+                output.active = Some(batch);
+            }
+            mem_diff += output.mem_size() as isize - mem_size_before as isize;
+        }
+        Ok(mem_diff)
+    }
+
+    /// Buffer input batch with rows sorted by output partition, returns the size
+    /// of buffered batch
+    async fn insert_sorted_batch(
+        &self,
+        input: RecordBatch,
+        partition_ids: Vec<usize>,
+    ) -> Result<isize> {
+        // stable sort of rows by partition id
+        let mut sorted_indices = (0..input.num_rows() as u64).collect::<Vec<_>>();
+        sorted_indices.sort_by_key(|&i| partition_ids[i as usize]);
+
+        let mut runs = vec![];
+        let mut start = 0;
+        while start < sorted_indices.len() {
+            let partition_id = partition_ids[sorted_indices[start] as usize];
+            let mut end = start + 1;
+            while end < sorted_indices.len()
+                && partition_ids[sorted_indices[end] as usize] == partition_id
+            {
+                end += 1;
+            }
+            runs.push((partition_id, start, end - start));
+            start = end;
+        }
+
+        let indices = UInt64Array::from_slice(&sorted_indices);
+        let columns = input
+            .columns()
+            .iter()
+            .map(|c| {
+                take(c.as_ref(), &indices, None)
+                    .map_err(|e| DataFusionError::Execution(e.to_string()))
+            })
+            .collect::<Result<Vec<Arc<dyn Array>>>>()?;
+        let batch = RecordBatch::try_new(input.schema(), columns)?;
+        let mut partition_stats = self.partition_stats.lock().await;
+        for &(partition_id, offset, len) in &runs {
+            let columns = batch
+                .columns()
+                .iter()
+                .map(|c| c.slice(offset, len))
+                .collect::<Vec<_>>();
+            partition_stats[partition_id].update(&columns, len);
+        }
+
+        let mem_size = batch_byte_size(&batch)
+            + runs.capacity() * std::mem::size_of::<(usize, usize, usize)>();
+
+        let mut sorted_batches = self.sorted_batches.lock().await;
+        sorted_batches.push(PartitionSortedBatch { batch, runs });
+        Ok(mem_size as isize)
+    }
+
+    /// Take all buffered rows grouped by output partition
+    async fn take_buffered_batches(&self) -> Result<Vec<Vec<RecordBatch>>> {
+        let mut output_batches: Vec<Vec<RecordBatch>> =
+            vec![vec![]; self.num_output_partitions];
+
+        let mut buffered_partitions = self.buffered_partitions.lock().await;
+        for (i, buffered_partition) in buffered_partitions.iter_mut().enumerate() {
+            output_batches[i] = buffered_partition.output_all()?;
+        }
+
+        let mut sorted_batches = self.sorted_batches.lock().await;
+        for sorted_batch in sorted_batches.drain(..) {
+            for (partition_id, offset, len) in sorted_batch.runs {
+                output_batches[partition_id].push(sorted_batch.batch.slice(offset, len));
+            }
+        }
+        Ok(output_batches)
+    }
+
+    /// Compute output partition id of each row in `input`, following the partition
+    /// assignment of spark's partitioners.
+    fn evaluate_partition_ids(&self, input: &RecordBatch) -> Result<Vec<usize>> {
+        let num_rows = input.num_rows();
         match &self.partitioning {
-            Partitioning::Hash(exprs, _) => {
-                let hashes_buf = &mut vec![];
+            ShuffleRepartitioning::Single => Ok(vec![0; num_rows]),
+            ShuffleRepartitioning::Hash(exprs, num_output_partitions) => {
                 let arrays = exprs
                     .iter()
-                    .map(|expr| Ok(expr.evaluate(&input)?.into_array(input.num_rows())))
+                    .map(|expr| Ok(expr.evaluate(input)?.into_array(num_rows)))
                     .collect::<Result<Vec<_>>>()?;
                 // use identical seed as spark hash partition
-                hashes_buf.resize(arrays[0].len(), 42);
-                // Hash arrays and compute buckets based on number of partitions
+                let hashes_buf = &mut vec![42; num_rows];
                 let hashes = create_hashes(&arrays, hashes_buf)?;
-                let mut indices = vec![vec![]; num_output_partitions];
-                for (index, hash) in hashes.iter().enumerate() {
-                    indices[pmod(*hash, num_output_partitions)].push(index as u64)
-                }
-
-                for (num_output_partition, partition_indices) in
-                    indices.into_iter().enumerate()
-                {
-                    let mut buffered_partitions = self.buffered_partitions.lock().await;
-                    let output = &mut buffered_partitions[num_output_partition];
-                    let indices = UInt64Array::from_slice(&partition_indices);
-                    // Produce batches based on indices
-                    let columns = input
-                        .columns()
-                        .iter()
-                        .map(|c| {
-                            take(c.as_ref(), &indices, None)
-                                .map_err(|e| DataFusionError::Execution(e.to_string()))
-                        })
-                        .collect::<Result<Vec<Arc<dyn Array>>>>()?;
-
-                    if partition_indices.len() > self.batch_size {
-                        let output_batch =
-                            RecordBatch::try_new(input.schema().clone(), columns)?;
-                        output.frozen.push(output_batch);
-                    } else {
-                        if output.active.is_none() {
-                            let buffer = MutableRecordBatch::new(
-                                self.batch_size,
-                                self.schema.clone(),
-                            );
-                            output.active = Some(buffer);
+                Ok(hashes
+                    .iter()
+                    .map(|hash| pmod(*hash, *num_output_partitions))
+                    .collect())
+            }
+            ShuffleRepartitioning::RoundRobin(num_output_partitions) => {
+                // position is increased before assigning each row, and wraps
+                // around on overflow like spark's Int position
+                let start = self
+                    .round_robin_position
+                    .fetch_add(num_rows as i32, Relaxed);
+                Ok((0..num_rows as i32)
+                    .map(|i| {
+                        let position = start.wrapping_add(i).wrapping_add(1);
+                        pmod(position as u32, *num_output_partitions)
+                    })
+                    .collect())
+            }
+            ShuffleRepartitioning::Range {
+                sort_exprs, bounds, ..
+            } => {
+                let keys = sort_exprs
+                    .iter()
+                    .map(|expr| Ok(expr.expr.evaluate(input)?.into_array(num_rows)))
+                    .collect::<Result<Vec<_>>>()?;
+                let comparators = keys
+                    .iter()
+                    .zip(bounds.iter())
+                    .map(|(key, bound)| build_compare(key.as_ref(), bound.as_ref()))
+                    .collect::<ArrowResult<Vec<_>>>()?;
+                let num_bounds = bounds.first().map(|bound| bound.len()).unwrap_or(0);
+
+                let compare = |row: usize, bound_idx: usize| {
+                    for (i, sort_expr) in sort_exprs.iter().enumerate() {
+                        let nulls_first = sort_expr.options.nulls_first;
+                        let ordering = match (
+                            keys[i].is_valid(row),
+                            bounds[i].is_valid(bound_idx),
+                        ) {
+                            (false, false) => Ordering::Equal,
+                            (false, true) if nulls_first => Ordering::Less,
+                            (false, true) => Ordering::Greater,
+                            (true, false) if nulls_first => Ordering::Greater,
+                            (true, false) => Ordering::Less,
+                            (true, true) if sort_expr.options.descending => {
+                                comparators[i](row, bound_idx).reverse()
+                            }
+                            (true, true) => comparators[i](row, bound_idx),
                         };
-
-                        let mut batch = output.active.take().unwrap();
-                        batch
-                            .arrays
-                            .iter_mut()
-                            .zip(columns.iter())
-                            .zip(self.schema.fields().iter().map(|f| f.data_type()))
-                            .for_each(|((to, from), dt)| {
-                                append_column(to, from, dt).unwrap()
-                            });
-                        batch.append(partition_indices.len());
-
-                        if batch.is_full() {
-                            let result = batch.output_and_reset()?;
-                            output.frozen.push(result);
+                        if ordering != Ordering::Equal {
+                            return ordering;
                         }
-                        output.active = Some(batch);
                     }
-                }
-            }
-            other => {
-                // this should be unreachable as long as the validation logic
-                // in the constructor is kept up-to-date
-                return Err(DataFusionError::NotImplemented(format!(
-                    "Unsupported repartitioning scheme {:?}",
-                    other
-                )));
+                    Ordering::Equal
+                };
+
+                // partition id is the number of bounds less than the key, which is
+                // identical to spark's RangePartitioner with distinct sorted bounds
+                Ok((0..num_rows)
+                    .map(|row| {
+                        let (mut lo, mut hi) = (0, num_bounds);
+                        while lo < hi {
+                            let mid = (lo + hi) / 2;
+                            if compare(row, mid) == Ordering::Greater {
+                                lo = mid + 1;
+                            } else {
+                                hi = mid;
+                            }
+                        }
+                        lo
+                    })
+                    .collect())
             }
         }
-        Ok(())
     }
 
     async fn shuffle_write(&self) -> Result<SendableRecordBatchStream> {
         let _timer = self.metrics.elapsed_compute().timer();
         let num_output_partitions = self.num_output_partitions;
-        let mut buffered_partitions = self.buffered_partitions.lock().await;
-        let mut output_batches: Vec<Vec<RecordBatch>> =
-            vec![vec![]; num_output_partitions];
-
-        for i in 0..num_output_partitions {
-            let partition_batches = buffered_partitions[i].output_clean()?;
-            output_batches[i] = partition_batches;
-        }
+        let output_batches = self.take_buffered_batches().await?;
 
         let mut spills = self.spills.lock().await;
         let output_spills = spills.drain(..).collect::<Vec<_>>();
@@ -317,40 +631,42 @@ impl ShuffleRepartitioner {
         let data_file = self.output_data_file.clone();
         let index_file = self.output_index_file.clone();
         let input_schema = self.schema.clone();
+        let codec = self.codec;
+        let checksum_algorithm = self.checksum_algorithm;
+        let batch_size = self.batch_size;
 
         std::mem::drop(_timer);
         let elapsed_compute = self.metrics.elapsed_compute().clone();
 
-        task::spawn_blocking(move || {
+        let (offsets, num_batches) = task::spawn_blocking(move || {
             let _timer = elapsed_compute.timer();
-            let mut offset: u64 = 0;
             let mut offsets = vec![0; num_output_partitions + 1];
-            let mut output_data = File::create(data_file)?;
+            let mut num_batches = vec![0; num_output_partitions];
+            let mut output_data =
+                ChecksumWriter::new(File::create(data_file)?, checksum_algorithm);
 
             for i in 0..num_output_partitions {
-                offsets[i] = offset;
-                let partition_start = output_data.seek(SeekFrom::Current(0))?;
+                let partition_start = output_data.position();
+                offsets[i] = partition_start;
+                output_data.reset_checksum();
 
                 // write in-mem batches first if any
-                let in_mem_batches = &output_batches[i];
+                let in_mem_batches =
+                    coalesce_batches(&input_schema, &output_batches[i], batch_size)?;
+                num_batches[i] += in_mem_batches.len();
                 if !in_mem_batches.is_empty() {
-                    let mut file_writer =
-                        FileWriter::try_new(output_data, input_schema.as_ref())?;
-                    for batch in in_mem_batches {
-                        file_writer.write(batch)?;
-                    }
-                    file_writer.finish()?;
-
-                    output_data = file_writer.into_inner()?;
-                    let partition_end = output_data.seek(SeekFrom::Current(0))?;
-                    let ipc_length: u64 = partition_end - partition_start;
-                    output_data.write_all(&ipc_length.to_le_bytes()[..])?;
-                    output_data.flush()?;
-                    offset = partition_start + ipc_length + 8;
+                    write_ipc_stream_part(
+                        &in_mem_batches,
+                        &input_schema,
+                        codec,
+                        checksum_algorithm,
+                        &mut output_data,
+                    )?;
                 }
 
                 // append partition in each spills
                 for spill in &output_spills {
+                    num_batches[i] += spill.num_batches[i];
                     let length = spill.offsets[i + 1] - spill.offsets[i];
                     if length > 0 {
                         let spill_file = File::open(&spill.file.path())?;
@@ -358,25 +674,37 @@ impl ShuffleRepartitioner {
                         reader.seek(SeekFrom::Start(spill.offsets[i]))?;
                         let mut take = reader.take(length);
                         std::io::copy(&mut take, &mut output_data)?;
-                        output_data.flush()?;
                     }
-                    offset += length;
+                }
+
+                if checksum_algorithm != ShuffleChecksumAlgorithm::None
+                    && output_data.position() > partition_start
+                {
+                    output_data.write_checksum_trailer()?;
                 }
             }
+            output_data.flush()?;
+
             // add one extra offset at last to ease partition length computation
-            offsets[num_output_partitions] = offset;
+            offsets[num_output_partitions] = output_data.position();
             let mut output_index = File::create(index_file)?;
-            for offset in offsets {
+            for &offset in &offsets {
                 output_index.write_all(&(offset as i64).to_le_bytes()[..])?;
             }
             output_index.flush()?;
-            Ok::<(), DataFusionError>(())
+            Ok::<_, DataFusionError>((offsets, num_batches))
         })
         .await
         .map_err(|e| {
             DataFusionError::Execution(format!("shuffle write error: {:?}", e))
         })??;
 
+        let mut partition_stats = self.partition_stats.lock().await;
+        for (i, stats) in partition_stats.iter_mut().enumerate() {
+            stats.num_batches = num_batches[i];
+            stats.num_bytes = (offsets[i + 1] - offsets[i]) as usize;
+        }
+
         let used = self.metrics.mem_used().set(0);
         self.shrink(used);
 
@@ -388,6 +716,11 @@ impl ShuffleRepartitioner {
         )?))
     }
 
+    /// Take statistics of output partitions, available after shuffle_write()
+    async fn take_partition_stats(&self) -> Vec<ShuffleWritePartitionStats> {
+        std::mem::take(&mut *self.partition_stats.lock().await)
+    }
+
     fn used(&self) -> usize {
         self.metrics.mem_used().value()
     }
@@ -401,48 +734,97 @@ impl ShuffleRepartitioner {
     }
 }
 
-/// consume the `buffered_partitions` and do spill into a single temp shuffle output file
+/// Size of data appended into a builder beyond its preallocated slots
+fn appended_data_size(to: &dyn ArrayBuilder, from: &ArrayRef) -> usize {
+    if to.as_any().is::<ConcatArrayBuilder>() {
+        return from.get_array_memory_size();
+    }
+    match from.data_type() {
+        DataType::Utf8 => value_data_size(as_string_array(from).value_offsets()),
+        DataType::LargeUtf8 => {
+            value_data_size(as_largestring_array(from).value_offsets())
+        }
+        DataType::Binary => {
+            let from = from.as_any().downcast_ref::<BinaryArray>().unwrap();
+            value_data_size(from.value_offsets())
+        }
+        DataType::LargeBinary => {
+            let from = from.as_any().downcast_ref::<LargeBinaryArray>().unwrap();
+            value_data_size(from.value_offsets())
+        }
+        _ => 0,
+    }
+}
+
+fn value_data_size<T: OffsetSizeTrait>(offsets: &[T]) -> usize {
+    match (offsets.first(), offsets.last()) {
+        (Some(first), Some(last)) => last.to_usize().unwrap() - first.to_usize().unwrap(),
+        _ => 0,
+    }
+}
+
+/// Concatenate consecutive batches into batches of at most `batch_size` rows (or a
+/// single larger batch), avoiding tiny ipc messages for partition-sorted slices
+fn coalesce_batches(
+    schema: &SchemaRef,
+    batches: &[RecordBatch],
+    batch_size: usize,
+) -> Result<Vec<RecordBatch>> {
+    let mut coalesced = vec![];
+    let mut staging: Vec<RecordBatch> = vec![];
+    let mut staging_rows = 0;
+
+    for batch in batches {
+        if staging_rows > 0 && staging_rows + batch.num_rows() > batch_size {
+            coalesced.push(RecordBatch::concat(schema, &staging)?);
+            staging.clear();
+            staging_rows = 0;
+        }
+        staging_rows += batch.num_rows();
+        staging.push(batch.clone());
+    }
+    if !staging.is_empty() {
+        coalesced.push(RecordBatch::concat(schema, &staging)?);
+    }
+    Ok(coalesced)
+}
+
+/// consume the `output_batches` and do spill into a single temp shuffle output file
 async fn spill_into(
-    buffered_partitions: &mut [PartitionBuffer],
+    output_batches: Vec<Vec<RecordBatch>>,
     schema: SchemaRef,
     path: &Path,
     num_output_partitions: usize,
-) -> Result<Vec<u64>> {
-    let mut output_batches: Vec<Vec<RecordBatch>> = vec![vec![]; num_output_partitions];
-
-    for i in 0..num_output_partitions {
-        let partition_batches = buffered_partitions[i].output_all()?;
-        output_batches[i] = partition_batches;
-    }
+    batch_size: usize,
+    codec: ShuffleCompressionCodec,
+    checksum_algorithm: ShuffleChecksumAlgorithm,
+) -> Result<(Vec<u64>, Vec<usize>)> {
     let path = path.to_owned();
 
     let res = task::spawn_blocking(move || {
-        let mut offset: u64 = 0;
         let mut offsets = vec![0; num_output_partitions + 1];
+        let mut num_batches = vec![0; num_output_partitions];
         let mut file = OpenOptions::new().read(true).append(true).open(path)?;
 
         for i in 0..num_output_partitions {
-            let partition_start = file.seek(SeekFrom::Current(0))?;
-            let partition_batches = &output_batches[i];
-            offsets[i] = offset;
+            offsets[i] = file.seek(SeekFrom::Current(0))?;
+            let partition_batches =
+                coalesce_batches(&schema, &output_batches[i], batch_size)?;
+            num_batches[i] = partition_batches.len();
             if !partition_batches.is_empty() {
-                let mut file_writer = FileWriter::try_new(file, &schema)?;
-                for batch in partition_batches {
-                    file_writer.write(batch)?;
-                }
-                file_writer.finish()?;
-
-                file = file_writer.into_inner()?;
-                let partition_end = file.seek(SeekFrom::Current(0))?;
-                let ipc_length: u64 = partition_end - partition_start;
-                file.write_all(&ipc_length.to_le_bytes()[..])?;
+                write_ipc_stream_part(
+                    &partition_batches,
+                    &schema,
+                    codec,
+                    checksum_algorithm,
+                    &mut file,
+                )?;
                 file.flush()?;
-                offset = partition_start + ipc_length + 8;
             }
         }
         // add one extra offset at last to ease partition length computation
-        offsets[num_output_partitions] = offset;
-        Ok(offsets)
+        offsets[num_output_partitions] = file.seek(SeekFrom::Current(0))?;
+        Ok((offsets, num_batches))
     })
     .await
     .map_err(|e| {
@@ -452,6 +834,32 @@ async fn spill_into(
     res
 }
 
+/// `new java.util.Random(seed).nextInt(bound)`
+fn java_random_next_int(seed: i64, bound: i32) -> i32 {
+    const MULTIPLIER: i64 = 0x5DEECE66D;
+    const MASK: i64 = (1 << 48) - 1;
+
+    let mut seed = (seed ^ MULTIPLIER) & MASK;
+    let mut next31 = || {
+        seed = seed.wrapping_mul(MULTIPLIER).wrapping_add(0xB) & MASK;
+        (seed >> 17) as i32
+    };
+
+    let m = bound - 1;
+    let mut r = next31();
+    if bound & m == 0 {
+        return ((bound as i64 * r as i64) >> 31) as i32;
+    }
+    let mut u = r;
+    loop {
+        r = u % bound;
+        if u.wrapping_sub(r).wrapping_add(m) >= 0 {
+            return r;
+        }
+        u = next31();
+    }
+}
+
 impl Debug for ShuffleRepartitioner {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.debug_struct("ShuffleRepartitioner")
@@ -490,18 +898,21 @@ impl MemoryConsumer for ShuffleRepartitioner {
             self.spill_count()
         );
 
-        let mut buffered_partitions = self.buffered_partitions.lock().await;
         // we could always get a chance to free some memory as long as we are holding some
-        if buffered_partitions.len() == 0 {
+        if self.used() == 0 {
             return Ok(0);
         }
 
+        let output_batches = self.take_buffered_batches().await?;
         let spillfile = self.runtime.disk_manager.create_tmp_file()?;
-        let offsets = spill_into(
-            &mut *buffered_partitions,
+        let (offsets, num_batches) = spill_into(
+            output_batches,
             self.schema.clone(),
             spillfile.path(),
             self.num_output_partitions,
+            self.batch_size,
+            self.codec,
+            self.checksum_algorithm,
         )
         .await?;
 
@@ -511,6 +922,7 @@ impl MemoryConsumer for ShuffleRepartitioner {
         spills.push(SpillInfo {
             file: spillfile,
             offsets,
+            num_batches,
         });
         Ok(freed)
     }
@@ -533,13 +945,25 @@ pub struct ShuffleWriterExec {
     /// Input execution plan
     input: Arc<dyn ExecutionPlan>,
     /// Partitioning scheme to use
-    partitioning: Partitioning,
+    partitioning: ShuffleRepartitioning,
     /// Output data file path
     output_data_file: String,
     /// Output index file path
     output_index_file: String,
+    /// Compression codec of output ipc parts
+    codec: ShuffleCompressionCodec,
+    /// Checksum algorithm of output partitions
+    checksum_algorithm: ShuffleChecksumAlgorithm,
+    /// Whether to collect min/max values and null counts of output partitions
+    with_column_stats: bool,
+    /// Number of output partitions above which sort-based shuffle is used
+    sort_based_partition_threshold: usize,
+    /// Statistics of output partitions, set after the output stream is consumed
+    partition_stats: Arc<std::sync::Mutex<Option<Vec<ShuffleWritePartitionStats>>>>,
     /// Containing all metrics set created during sort
     all_metrics: CompositeMetricsSet,
+    /// Metrics not covered by baseline metrics
+    metrics: ExecutionPlanMetricsSet,
 }
 
 #[async_trait]
@@ -555,7 +979,13 @@ impl ExecutionPlan for ShuffleWriterExec {
     }
 
     fn output_partitioning(&self) -> Partitioning {
-        self.partitioning.clone()
+        match &self.partitioning {
+            ShuffleRepartitioning::Hash(exprs, n) => {
+                Partitioning::Hash(exprs.clone(), *n)
+            }
+            ShuffleRepartitioning::RoundRobin(n) => Partitioning::RoundRobinBatch(*n),
+            other => Partitioning::UnknownPartitioning(other.partition_count()),
+        }
     }
 
     fn output_ordering(&self) -> Option<&[PhysicalSortExpr]> {
@@ -576,6 +1006,10 @@ impl ExecutionPlan for ShuffleWriterExec {
                 self.partitioning.clone(),
                 self.output_data_file.clone(),
                 self.output_index_file.clone(),
+                self.codec,
+                self.checksum_algorithm,
+                self.with_column_stats,
+                self.sort_based_partition_threshold,
             )?)),
             _ => Err(DataFusionError::Internal(
                 "RepartitionExec wrong number of children".to_string(),
@@ -590,6 +1024,8 @@ impl ExecutionPlan for ShuffleWriterExec {
     ) -> Result<SendableRecordBatchStream> {
         let input = self.input.execute(partition, context.clone())?;
         let metrics = self.all_metrics.new_intermediate_baseline(partition);
+        let peak_mem_used =
+            MetricBuilder::new(&self.metrics).gauge("peak_mem_used", partition);
 
         Ok(Box::pin(RecordBatchStreamAdapter::new(
             self.schema(),
@@ -600,7 +1036,13 @@ impl ExecutionPlan for ShuffleWriterExec {
                     self.output_data_file.clone(),
                     self.output_index_file.clone(),
                     self.partitioning.clone(),
+                    self.codec,
+                    self.checksum_algorithm,
+                    self.with_column_stats,
+                    self.sort_based_partition_threshold,
+                    self.partition_stats.clone(),
                     metrics,
+                    peak_mem_used,
                     context,
                 )
                 .map_err(|e| ArrowError::ExternalError(Box::new(e))),
@@ -610,7 +1052,11 @@ impl ExecutionPlan for ShuffleWriterExec {
     }
 
     fn metrics(&self) -> Option<MetricsSet> {
-        Some(self.all_metrics.aggregate_all())
+        let mut metrics = self.all_metrics.aggregate_all();
+        for metric in self.metrics.clone_inner().iter() {
+            metrics.push(metric.clone());
+        }
+        Some(metrics)
     }
 
     fn fmt_as(
@@ -620,7 +1066,15 @@ impl ExecutionPlan for ShuffleWriterExec {
     ) -> std::fmt::Result {
         match t {
             DisplayFormatType::Default => {
-                write!(f, "ShuffleWriterExec: partitioning={:?}", self.partitioning)
+                write!(
+                    f,
+                    "ShuffleWriterExec: partitioning={:?}, codec={:?}, checksum={:?}, \
+                     column_stats={}",
+                    self.partitioning,
+                    self.codec,
+                    self.checksum_algorithm,
+                    self.with_column_stats
+                )
             }
         }
     }
@@ -632,30 +1086,87 @@ impl ExecutionPlan for ShuffleWriterExec {
 
 impl ShuffleWriterExec {
     /// Create a new ShuffleWriterExec
+    #[allow(clippy::too_many_arguments)]
     pub fn try_new(
         input: Arc<dyn ExecutionPlan>,
-        partitioning: Partitioning,
+        partitioning: ShuffleRepartitioning,
         output_data_file: String,
         output_index_file: String,
+        codec: ShuffleCompressionCodec,
+        checksum_algorithm: ShuffleChecksumAlgorithm,
+        with_column_stats: bool,
+        sort_based_partition_threshold: usize,
     ) -> Result<Self> {
         Ok(ShuffleWriterExec {
             input,
             partitioning,
             all_metrics: CompositeMetricsSet::new(),
+            metrics: ExecutionPlanMetricsSet::new(),
             output_data_file,
             output_index_file,
+            codec,
+            checksum_algorithm,
+            with_column_stats,
+            sort_based_partition_threshold,
+            partition_stats: Arc::default(),
         })
     }
+
+    /// Statistics of output partitions, available after the output stream of
+    /// `execute()` is fully consumed
+    pub fn partition_stats(&self) -> Option<Vec<ShuffleWritePartitionStats>> {
+        self.partition_stats.lock().unwrap().clone()
+    }
+
+    pub fn with_column_stats(&self) -> bool {
+        self.with_column_stats
+    }
+
+    pub fn sort_based_partition_threshold(&self) -> usize {
+        self.sort_based_partition_threshold
+    }
+
+    pub fn input(&self) -> &Arc<dyn ExecutionPlan> {
+        &self.input
+    }
+
+    pub fn partitioning(&self) -> &ShuffleRepartitioning {
+        &self.partitioning
+    }
+
+    pub fn output_data_file(&self) -> &str {
+        &self.output_data_file
+    }
+
+    pub fn output_index_file(&self) -> &str {
+        &self.output_index_file
+    }
+
+    pub fn codec(&self) -> ShuffleCompressionCodec {
+        self.codec
+    }
+
+    pub fn checksum_algorithm(&self) -> ShuffleChecksumAlgorithm {
+        self.checksum_algorithm
+    }
 }
 
-// TODO: reconsider memory consumption for shuffle buffers, unrevealed usage?
+#[allow(clippy::too_many_arguments)]
 pub async fn external_shuffle(
     mut input: SendableRecordBatchStream,
     partition_id: usize,
     output_data_file: String,
     output_index_file: String,
-    partitioning: Partitioning,
+    partitioning: ShuffleRepartitioning,
+    codec: ShuffleCompressionCodec,
+    checksum_algorithm: ShuffleChecksumAlgorithm,
+    with_column_stats: bool,
+    sort_based_partition_threshold: usize,
+    output_partition_stats: Arc<
+        std::sync::Mutex<Option<Vec<ShuffleWritePartitionStats>>>,
+    >,
     metrics: BaselineMetrics,
+    peak_mem_used: Gauge,
     context: Arc<TaskContext>,
 ) -> Result<SendableRecordBatchStream> {
     let schema = input.schema();
@@ -666,8 +1177,13 @@ pub async fn external_shuffle(
         schema.clone(),
         partitioning,
         metrics,
+        peak_mem_used,
         context.runtime_env(),
         context.session_config().batch_size,
+        codec,
+        checksum_algorithm,
+        with_column_stats,
+        sort_based_partition_threshold,
     );
     context.runtime_env().register_requester(repartitioner.id());
 
@@ -676,5 +1192,493 @@ pub async fn external_shuffle(
         repartitioner.insert_batch(batch).await?;
     }
 
-    repartitioner.shuffle_write().await
+    let output = repartitioner.shuffle_write().await?;
+    let partition_stats = repartitioner.take_partition_stats().await;
+    *output_partition_stats.lock().unwrap() = Some(partition_stats);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::sync::Arc;
+
+    use datafusion::arrow::array::*;
+    use datafusion::arrow::compute::{concat, SortOptions};
+    use datafusion::arrow::datatypes::{DataType, Field, Int32Type, Schema};
+
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::error::DataFusionError;
+    use datafusion::execution::memory_manager::MemoryConsumer;
+    use datafusion::execution::runtime_env::{RuntimeConfig, RuntimeEnv};
+    use datafusion::physical_plan::common::collect;
+    use datafusion::physical_plan::expressions::{col, PhysicalSortExpr};
+    use datafusion::physical_plan::metrics::{
+        BaselineMetrics, ExecutionPlanMetricsSet, MetricBuilder,
+    };
+    use datafusion::physical_plan::{ExecutionPlan, Partitioning};
+    use datafusion::prelude::SessionContext;
+
+    use crate::batch_buffer::MutableRecordBatch;
+    use crate::shuffle_checksum::ShuffleChecksumAlgorithm;
+    use crate::shuffle_codec::ShuffleCompressionCodec;
+    use crate::shuffle_reader_exec::{
+        register_local_shuffle_blocks, LocalShuffleBlock, ShuffleReaderExec,
+    };
+    use crate::shuffle_writer_exec::{
+        append_column, appended_data_size, coalesce_batches, java_random_next_int,
+        ShuffleRepartitioner, ShuffleRepartitioning,
+        DEFAULT_SORT_BASED_PARTITION_THRESHOLD,
+    };
+
+    /// buffer arrays with append_column and check output equals to the concatenated input
+    fn assert_buffered(arrays: Vec<ArrayRef>) {
+        let data_type = arrays[0].data_type().clone();
+        let schema = Arc::new(Schema::new(vec![Field::new("c", data_type, true)]));
+        let mut buffer = MutableRecordBatch::new(16, schema.clone());
+        for array in &arrays {
+            let data_type = schema.field(0).data_type();
+            append_column(&mut buffer.arrays[0], array, data_type).unwrap();
+            buffer.append(array.len());
+        }
+        let batch = buffer.output().unwrap();
+        let expected =
+            concat(&arrays.iter().map(|a| a.as_ref()).collect::<Vec<_>>()).unwrap();
+        assert_eq!(batch.column(0).data(), expected.data());
+    }
+
+    #[test]
+    fn test_append_null() {
+        assert_buffered(vec![
+            Arc::new(NullArray::new(2)),
+            Arc::new(NullArray::new(3)),
+        ]);
+    }
+
+    #[test]
+    fn test_append_primitive() {
+        assert_buffered(vec![
+            Arc::new(Int32Array::from(vec![Some(1), None])),
+            Arc::new(Int32Array::from(vec![None, Some(3)])),
+        ]);
+        assert_buffered(vec![
+            Arc::new(Time32MillisecondArray::from(vec![Some(1000), None])),
+            Arc::new(Time32MillisecondArray::from(vec![Some(2000)])),
+        ]);
+    }
+
+    #[test]
+    fn test_append_binary() {
+        assert_buffered(vec![
+            Arc::new(BinaryArray::from_opt_vec(vec![Some(&b"a"[..]), None])),
+            Arc::new(BinaryArray::from_opt_vec(vec![Some(&b"bc"[..])])),
+        ]);
+    }
+
+    #[test]
+    fn test_append_large_binary() {
+        assert_buffered(vec![
+            Arc::new(LargeBinaryArray::from_opt_vec(vec![Some(&b"a"[..]), None])),
+            Arc::new(LargeBinaryArray::from_opt_vec(vec![Some(&b"bc"[..])])),
+        ]);
+    }
+
+    #[test]
+    fn test_append_timestamp() {
+        for tz in [None, Some("UTC".to_owned()), Some("+08:00".to_owned())] {
+            assert_buffered(vec![
+                Arc::new(TimestampSecondArray::from_opt_vec(
+                    vec![Some(1), None],
+                    tz.clone(),
+                )),
+                Arc::new(TimestampSecondArray::from_opt_vec(
+                    vec![Some(2)],
+                    tz.clone(),
+                )),
+            ]);
+            assert_buffered(vec![
+                Arc::new(TimestampMillisecondArray::from_opt_vec(
+                    vec![Some(1), None],
+                    tz.clone(),
+                )),
+                Arc::new(TimestampMillisecondArray::from_opt_vec(
+                    vec![Some(2)],
+                    tz.clone(),
+                )),
+            ]);
+            assert_buffered(vec![
+                Arc::new(TimestampMicrosecondArray::from_opt_vec(
+                    vec![Some(1), None],
+                    tz.clone(),
+                )),
+                Arc::new(TimestampMicrosecondArray::from_opt_vec(
+                    vec![Some(2)],
+                    tz.clone(),
+                )),
+            ]);
+            assert_buffered(vec![
+                Arc::new(TimestampNanosecondArray::from_opt_vec(
+                    vec![Some(1), None],
+                    tz.clone(),
+                )),
+                Arc::new(TimestampNanosecondArray::from_opt_vec(
+                    vec![Some(2)],
+                    tz.clone(),
+                )),
+            ]);
+        }
+    }
+
+    #[test]
+    fn test_append_decimal() {
+        let mut builder = DecimalBuilder::new(2, 10, 2);
+        builder.append_value(12345).unwrap();
+        builder.append_null().unwrap();
+        assert_buffered(vec![Arc::new(builder.finish())]);
+    }
+
+    #[test]
+    fn test_append_dictionary() {
+        assert_buffered(vec![
+            Arc::new(
+                vec![Some("a"), None, Some("b")]
+                    .into_iter()
+                    .collect::<DictionaryArray<Int32Type>>(),
+            ),
+            Arc::new(
+                vec![Some("b"), Some("c")]
+                    .into_iter()
+                    .collect::<DictionaryArray<Int32Type>>(),
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_append_list() {
+        assert_buffered(vec![
+            Arc::new(ListArray::from_iter_primitive::<Int32Type, _, _>(vec![
+                Some(vec![Some(1), None]),
+                None,
+            ])),
+            Arc::new(ListArray::from_iter_primitive::<Int32Type, _, _>(vec![
+                Some(vec![]),
+            ])),
+        ]);
+    }
+
+    #[test]
+    fn test_append_large_list() {
+        assert_buffered(vec![
+            Arc::new(LargeListArray::from_iter_primitive::<Int32Type, _, _>(
+                vec![Some(vec![Some(1), None]), None],
+            )),
+            Arc::new(LargeListArray::from_iter_primitive::<Int32Type, _, _>(
+                vec![Some(vec![Some(2)])],
+            )),
+        ]);
+    }
+
+    #[test]
+    fn test_append_struct() {
+        let new_struct = |values: Vec<Option<i32>>| -> ArrayRef {
+            Arc::new(StructArray::from(vec![(
+                Field::new("a", DataType::Int32, true),
+                Arc::new(Int32Array::from(values)) as ArrayRef,
+            )]))
+        };
+        assert_buffered(vec![
+            new_struct(vec![Some(1), None]),
+            new_struct(vec![Some(3)]),
+        ]);
+    }
+
+    #[test]
+    fn test_append_map() {
+        let new_map = |entries: Vec<Option<(&str, i32)>>| -> ArrayRef {
+            let mut builder =
+                MapBuilder::new(None, StringBuilder::new(4), Int32Builder::new(4));
+            for entry in entries {
+                match entry {
+                    Some((key, value)) => {
+                        builder.keys().append_value(key).unwrap();
+                        builder.values().append_value(value).unwrap();
+                        builder.append(true).unwrap();
+                    }
+                    None => builder.append(false).unwrap(),
+                }
+            }
+            Arc::new(builder.finish())
+        };
+        assert_buffered(vec![
+            new_map(vec![Some(("a", 1)), None]),
+            new_map(vec![Some(("b", 2))]),
+        ]);
+    }
+
+    #[test]
+    fn test_java_random_next_int() {
+        assert_eq!(java_random_next_int(0, 100), 60);
+        assert_eq!(java_random_next_int(42, 10), 0);
+        let starts = (0..5)
+            .map(|partition_id| java_random_next_int(partition_id, 200))
+            .collect::<Vec<_>>();
+        assert_eq!(starts, vec![160, 185, 108, 134, 62]);
+    }
+
+    fn test_repartitioner(
+        partition_id: usize,
+        partitioning: ShuffleRepartitioning,
+    ) -> ShuffleRepartitioner {
+        test_repartitioner_with_output(
+            partition_id,
+            partitioning,
+            "data",
+            "index",
+            DEFAULT_SORT_BASED_PARTITION_THRESHOLD,
+        )
+    }
+
+    fn test_repartitioner_with_output(
+        partition_id: usize,
+        partitioning: ShuffleRepartitioning,
+        data_file: &str,
+        index_file: &str,
+        sort_based_partition_threshold: usize,
+    ) -> ShuffleRepartitioner {
+        let schema = key_batch(vec![]).schema();
+        let metrics = ExecutionPlanMetricsSet::new();
+        ShuffleRepartitioner::new(
+            partition_id,
+            data_file.to_owned(),
+            index_file.to_owned(),
+            schema,
+            partitioning,
+            BaselineMetrics::new(&metrics, partition_id),
+            MetricBuilder::new(&metrics).gauge("peak_mem_used", partition_id),
+            Arc::new(RuntimeEnv::new(RuntimeConfig::new()).unwrap()),
+            1024,
+            ShuffleCompressionCodec::None,
+            ShuffleChecksumAlgorithm::None,
+            false,
+            sort_based_partition_threshold,
+        )
+    }
+
+    fn key_batch(keys: Vec<Option<i32>>) -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![Field::new("k", DataType::Int32, true)]));
+        RecordBatch::try_new(schema, vec![Arc::new(Int32Array::from(keys))]).unwrap()
+    }
+
+    #[test]
+    fn test_range_partition_ids() {
+        // expected ids are computed by spark's RangePartitioner.getPartition with
+        // the same ordering and bounds
+        let range_partition_ids = |options, bounds: Vec<Option<i32>>, keys| {
+            let schema = key_batch(vec![]).schema();
+            let partitioning = ShuffleRepartitioning::Range {
+                sort_exprs: vec![PhysicalSortExpr {
+                    expr: col("k", &schema).unwrap(),
+                    options,
+                }],
+                num_partitions: bounds.len() + 1,
+                bounds: vec![Arc::new(Int32Array::from(bounds))],
+            };
+            test_repartitioner(0, partitioning)
+                .evaluate_partition_ids(&key_batch(keys))
+                .unwrap()
+        };
+        let asc_nulls_first = SortOptions {
+            descending: false,
+            nulls_first: true,
+        };
+        let asc_nulls_last = SortOptions {
+            descending: false,
+            nulls_first: false,
+        };
+        let desc_nulls_last = SortOptions {
+            descending: true,
+            nulls_first: false,
+        };
+        let keys = vec![None, Some(5), Some(10), Some(15), Some(20), Some(25)];
+
+        assert_eq!(
+            range_partition_ids(asc_nulls_first, vec![Some(10), Some(20)], keys.clone()),
+            vec![0, 0, 0, 1, 1, 2]
+        );
+        assert_eq!(
+            range_partition_ids(asc_nulls_last, vec![Some(10), Some(20)], keys.clone()),
+            vec![2, 0, 0, 1, 1, 2]
+        );
+        // null is the largest bound if nulls are last
+        assert_eq!(
+            range_partition_ids(asc_nulls_last, vec![Some(10), None], keys.clone()),
+            vec![1, 0, 0, 1, 1, 1]
+        );
+        assert_eq!(
+            range_partition_ids(desc_nulls_last, vec![Some(20), Some(10)], keys),
+            vec![2, 2, 1, 1, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_round_robin_partition_ids() {
+        // spark starts from `new Random(0).nextInt(100)` = 60 in partition 0, and
+        // increases the position before assigning each row
+        let repartitioner = test_repartitioner(0, ShuffleRepartitioning::RoundRobin(100));
+        let batch = key_batch(vec![None; 3]);
+        let ids = repartitioner.evaluate_partition_ids(&batch).unwrap();
+        assert_eq!(ids, vec![61, 62, 63]);
+        let ids = repartitioner.evaluate_partition_ids(&batch).unwrap();
+        assert_eq!(ids, vec![64, 65, 66]);
+
+        // the Int position of spark wraps around to negative values
+        let repartitioner = test_repartitioner(0, ShuffleRepartitioning::RoundRobin(7));
+        repartitioner
+            .round_robin_position
+            .store(i32::MAX - 1, Relaxed);
+        let ids = repartitioner.evaluate_partition_ids(&batch).unwrap();
+        assert_eq!(ids, vec![1, 5, 6]);
+    }
+
+    #[test]
+    fn test_sort_based_shuffle_write() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let batches = (0..4)
+            .map(|i| key_batch((i * 100..(i + 1) * 100).map(Some).collect()))
+            .collect::<Vec<_>>();
+        let num_partitions = 8;
+
+        // write with the given threshold and read rows of each output partition
+        let write_and_read = |sort_based_partition_threshold: usize| {
+            let data_file = tempfile::NamedTempFile::new().unwrap();
+            let index_file = tempfile::NamedTempFile::new().unwrap();
+            let data_path = data_file.path().to_str().unwrap();
+            let index_path = index_file.path().to_str().unwrap();
+            let schema = key_batch(vec![]).schema();
+            let partitioning = ShuffleRepartitioning::Hash(
+                vec![col("k", &schema).unwrap()],
+                num_partitions,
+            );
+            let repartitioner = test_repartitioner_with_output(
+                0,
+                partitioning,
+                data_path,
+                index_path,
+                sort_based_partition_threshold,
+            );
+            assert_eq!(
+                repartitioner.sort_based,
+                num_partitions > sort_based_partition_threshold
+            );
+            repartitioner.runtime.register_requester(repartitioner.id());
+            runtime
+                .block_on(async {
+                    for (i, batch) in batches.iter().enumerate() {
+                        repartitioner.insert_batch(batch.clone()).await?;
+                        if i == 1 {
+                            assert!(repartitioner.spill().await? > 0);
+                        }
+                    }
+                    repartitioner.shuffle_write().await?;
+                    Ok::<_, DataFusionError>(())
+                })
+                .unwrap();
+
+            let offsets = std::fs::read(index_path)
+                .unwrap()
+                .chunks(8)
+                .map(|offset| i64::from_le_bytes(offset.try_into().unwrap()) as u64)
+                .collect::<Vec<_>>();
+            assert_eq!(offsets.len(), num_partitions + 1);
+            (0..num_partitions)
+                .map(|i| {
+                    let native_shuffle_id = format!(
+                        "test_sort_based_shuffle_write:{}:{}",
+                        sort_based_partition_threshold, i,
+                    );
+                    let length = offsets[i + 1] - offsets[i];
+                    let blocks = if length > 0 {
+                        vec![LocalShuffleBlock {
+                            path: data_path.to_owned(),
+                            offset: offsets[i],
+                            length,
+                        }]
+                    } else {
+                        vec![]
+                    };
+                    register_local_shuffle_blocks(native_shuffle_id.clone(), blocks);
+                    let reader = ShuffleReaderExec::new(
+                        Partitioning::UnknownPartitioning(1),
+                        native_shuffle_id,
+                        schema.clone(),
+                        0,
+                    );
+                    let task_ctx = SessionContext::new().task_ctx();
+                    let batches = runtime
+                        .block_on(async { collect(reader.execute(0, task_ctx)?).await })
+                        .unwrap();
+                    batches
+                        .iter()
+                        .flat_map(|batch| {
+                            as_primitive_array::<Int32Type>(batch.column(0))
+                                .iter()
+                                .collect::<Vec<_>>()
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let partitions = write_and_read(DEFAULT_SORT_BASED_PARTITION_THRESHOLD);
+        let num_rows = partitions.iter().map(|rows| rows.len()).sum::<usize>();
+        assert_eq!(num_rows, 400);
+        assert_eq!(write_and_read(num_partitions - 1), partitions);
+    }
+
+    #[test]
+    fn test_coalesce_batches() {
+        let schema = Arc::new(Schema::new(vec![Field::new("c", DataType::Int32, true)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from(vec![1, 2, 3, 4, 5, 6]))],
+        )
+        .unwrap();
+        let slices = vec![batch.slice(0, 2), batch.slice(2, 3), batch.slice(5, 1)];
+
+        let coalesced = coalesce_batches(&schema, &slices, 5).unwrap();
+        let num_rows = coalesced.iter().map(|b| b.num_rows()).collect::<Vec<_>>();
+        assert_eq!(num_rows, vec![5, 1]);
+
+        let coalesced = coalesce_batches(&schema, &slices, 1024).unwrap();
+        assert_eq!(coalesced.len(), 1);
+        assert_eq!(coalesced[0], batch);
+        assert!(coalesce_batches(&schema, &[], 1024).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_buffer_mem_size() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("i", DataType::Int64, true),
+            Field::new("s", DataType::Utf8, true),
+        ]));
+        let mut buffer = MutableRecordBatch::new(16, schema.clone());
+        let preallocated = buffer.mem_size();
+        assert_eq!(preallocated, 16 * (8 + 5) + 2 * 2);
+
+        let strings: ArrayRef =
+            Arc::new(StringArray::from(vec![Some("hello"), None, Some("world")]));
+        let strings = strings.slice(1, 2);
+        let data_size = appended_data_size(buffer.arrays[1].as_ref(), &strings);
+        assert_eq!(data_size, 5);
+
+        append_column(&mut buffer.arrays[1], &strings, &DataType::Utf8).unwrap();
+        buffer.append_data_size(data_size);
+        assert_eq!(buffer.mem_size(), preallocated + 5);
+
+        buffer.output_and_reset().unwrap();
+        assert_eq!(buffer.mem_size(), preallocated);
+
+        buffer.output().unwrap();
+        assert_eq!(buffer.mem_size(), 0);
+    }
 }
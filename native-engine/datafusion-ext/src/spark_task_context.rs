@@ -0,0 +1,255 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Spark task context of native tasks.
+//!
+//! Worker threads of the shared runtime poll tasks of all native tasks, so the
+//! spark task context of a native task is set on the polling thread before each
+//! poll instead of once per thread. Tasks spawned with [`spawn`] and
+//! [`spawn_blocking`] inherit the task context of the native task spawning them,
+//! and are cancelled with the native task by its [`CancelHandle`].
+
+use std::cell::Cell;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use futures::future::{AbortHandle, Abortable, Aborted};
+use jni::objects::{GlobalRef, JObject};
+use tokio::task::JoinHandle;
+
+use crate::jni_call_static;
+
+#[derive(Clone)]
+struct SparkTask {
+    native_task_id: u64,
+    task_context: Option<GlobalRef>,
+    cancel_handle: CancelHandle,
+}
+
+tokio::task_local! {
+    static SPARK_TASK: SparkTask;
+}
+
+thread_local! {
+    // native task whose spark task context is currently set on this thread
+    static THREAD_NATIVE_TASK_ID: Cell<u64> = Cell::new(0);
+}
+
+/// Cancels a native task together with all tasks spawned by it
+#[derive(Clone, Default)]
+pub struct CancelHandle {
+    cancelled: Arc<AtomicBool>,
+    abort_handles: Arc<Mutex<Vec<AbortHandle>>>,
+}
+
+impl CancelHandle {
+    /// Registers a task to be aborted on cancellation, the task is aborted
+    /// immediately if already cancelled
+    pub fn register(&self, abort_handle: AbortHandle) {
+        let mut abort_handles = self.abort_handles.lock().unwrap();
+        if self.is_cancelled() {
+            abort_handle.abort();
+            return;
+        }
+        abort_handles.push(abort_handle);
+    }
+
+    /// Aborts all registered tasks. Tasks in blocking threads cannot be aborted
+    /// and stop when they see [`is_cancelled`].
+    pub fn cancel(&self) {
+        let abort_handles = {
+            let mut abort_handles = self.abort_handles.lock().unwrap();
+            self.cancelled.store(true, Ordering::SeqCst);
+            std::mem::take(&mut *abort_handles)
+        };
+        for abort_handle in abort_handles {
+            abort_handle.abort();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Executes `future` as native task `native_task_id` with the spark task context
+/// `task_context`, which is also inherited by tasks spawned by the future.
+/// `task_context` is None if the task is not executed by the JVM.
+pub fn with_task_context<F: Future>(
+    native_task_id: u64,
+    task_context: Option<GlobalRef>,
+    cancel_handle: CancelHandle,
+    future: F,
+) -> impl Future<Output = F::Output> {
+    scope(
+        SparkTask {
+            native_task_id,
+            task_context,
+            cancel_handle,
+        },
+        future,
+    )
+}
+
+fn scope<F: Future>(spark_task: SparkTask, future: F) -> impl Future<Output = F::Output> {
+    let native_task_id = spark_task.native_task_id;
+    let task_context = spark_task.task_context.clone();
+    let mut future = Box::pin(future);
+    SPARK_TASK.scope(
+        spark_task,
+        futures::future::poll_fn(move |cx| {
+            if let Some(task_context) = &task_context {
+                THREAD_NATIVE_TASK_ID.with(|thread_native_task_id| {
+                    if thread_native_task_id.get() != native_task_id {
+                        jni_call_static!(
+                            JniBridge.setTaskContext(task_context.as_obj()) -> ()
+                        )
+                        .unwrap();
+                        thread_native_task_id.set(native_task_id);
+                    }
+                });
+            }
+            future.as_mut().poll(cx)
+        }),
+    )
+}
+
+/// Returns the spark task context of the current native task, or None if not
+/// called by a native task executed by the JVM.
+pub fn current_task_context() -> Option<GlobalRef> {
+    SPARK_TASK
+        .try_with(|spark_task| spark_task.task_context.clone())
+        .ok()
+        .flatten()
+}
+
+/// Returns whether the current native task is cancelled, used by tasks in
+/// blocking threads which cannot be aborted.
+pub fn is_cancelled() -> bool {
+    SPARK_TASK
+        .try_with(|spark_task| spark_task.cancel_handle.is_cancelled())
+        .unwrap_or(false)
+}
+
+/// Spawns a task on the current runtime, which inherits the spark task context
+/// of the current native task and is aborted when the native task is cancelled.
+pub fn spawn<F>(future: F) -> JoinHandle<Result<F::Output, Aborted>>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (abort_handle, abort_registration) = AbortHandle::new_pair();
+    let future = Abortable::new(future, abort_registration);
+    match SPARK_TASK.try_with(SparkTask::clone) {
+        Ok(spark_task) => {
+            spark_task.cancel_handle.register(abort_handle);
+            tokio::spawn(scope(spark_task, future))
+        }
+        Err(_) => tokio::spawn(future),
+    }
+}
+
+/// Runs `f` in a blocking thread, with the spark task context of the current
+/// native task set on the thread while running.
+pub fn spawn_blocking<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let spark_task = SPARK_TASK.try_with(SparkTask::clone).ok();
+    tokio::task::spawn_blocking(move || {
+        let spark_task = match spark_task {
+            Some(spark_task) => spark_task,
+            None => return f(),
+        };
+        let task_context = match spark_task.task_context.clone() {
+            Some(task_context) => task_context,
+            None => return SPARK_TASK.sync_scope(spark_task, f),
+        };
+
+        // blocking threads are reused by other tasks, so the task context is
+        // cleared after running
+        let set_task_context =
+            jni_call_static!(JniBridge.setTaskContext(task_context.as_obj()) -> ());
+        if let Err(e) = set_task_context {
+            log::warn!("error setting task context of blocking thread: {}", e);
+        }
+        let result = SPARK_TASK.sync_scope(spark_task, f);
+        let clear_task_context =
+            jni_call_static!(JniBridge.setTaskContext(JObject::null()) -> ());
+        if let Err(e) = clear_task_context {
+            log::warn!("error clearing task context of blocking thread: {}", e);
+        }
+        result
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::spark_task_context::{
+        is_cancelled, spawn, spawn_blocking, with_task_context, CancelHandle,
+    };
+
+    struct DropFlag(Arc<AtomicBool>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_cancel_spawned_tasks() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let cancel_handle = CancelHandle::default();
+        let dropped = Arc::new(AtomicBool::new(false));
+
+        runtime.block_on(async {
+            // a long running sub-partition and a prefetching thread of the task
+            let flag = DropFlag(dropped.clone());
+            let (task, blocking) =
+                with_task_context(1, None, cancel_handle.clone(), async move {
+                    let task = spawn(async move {
+                        let _flag = flag;
+                        futures::future::pending::<()>().await
+                    });
+                    let blocking = spawn_blocking(|| {
+                        while !is_cancelled() {
+                            std::thread::sleep(Duration::from_millis(1));
+                        }
+                    });
+                    (task, blocking)
+                })
+                .await;
+
+            cancel_handle.cancel();
+            assert!(task.await.unwrap().is_err());
+            blocking.await.unwrap();
+        });
+        assert!(dropped.load(Ordering::SeqCst));
+
+        // tasks spawned after cancellation are aborted immediately
+        runtime.block_on(with_task_context(1, None, cancel_handle, async {
+            assert!(spawn(async {}).await.unwrap().is_err());
+        }));
+    }
+}
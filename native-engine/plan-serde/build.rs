@@ -21,6 +21,8 @@ fn main() -> Result<(), String> {
 
     println!("cargo:rerun-if-changed=proto/plan.proto");
     tonic_build::configure()
+        // allows dumping plans as json for debugging
+        .type_attribute(".", "#[derive(serde::Serialize)]")
         .compile(&["proto/plan.proto"], &["proto"])
         .map_err(|e| format!("protobuf compilation failed: {}", e))
 }
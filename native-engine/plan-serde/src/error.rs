@@ -38,7 +38,16 @@ pub enum PlanSerDeError {
     DataFusionError(DataFusionError),
     IoError(io::Error),
     MissingRequiredField(String),
-    UnknownEnumVariant { name: String, value: i32 },
+    UnknownEnumVariant {
+        name: String,
+        value: i32,
+    },
+    /// An error occurred while converting the node at `path` of a plan, like
+    /// `root.input.left.expr[2]`
+    AtPath {
+        path: String,
+        error: Box<PlanSerDeError>,
+    },
 }
 
 #[allow(clippy::from_over_into)]
@@ -97,11 +106,22 @@ impl Display for PlanSerDeError {
             Self::UnknownEnumVariant { name, value } => {
                 write!(f, "Unknown i32 value for {} enum: {}", name, value)
             }
+            Self::AtPath { path, error } => write!(f, "{} (at {})", error, path),
         }
     }
 }
 
-impl Error for PlanSerDeError {}
+impl Error for PlanSerDeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PlanSerDeError::ArrowError(e) => Some(e),
+            PlanSerDeError::DataFusionError(e) => Some(e),
+            PlanSerDeError::IoError(e) => Some(e),
+            PlanSerDeError::AtPath { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 impl PlanSerDeError {
     pub(crate) fn required(field: impl Into<String>) -> PlanSerDeError {
@@ -114,6 +134,44 @@ impl PlanSerDeError {
             value,
         }
     }
+
+    /// Prepends `segment` to the path of the node where the error occurred
+    pub fn at(self, segment: impl AsRef<str>) -> PlanSerDeError {
+        let segment = segment.as_ref();
+        match self {
+            PlanSerDeError::AtPath { path, error } => {
+                let separator = if path.starts_with('[') { "" } else { "." };
+                PlanSerDeError::AtPath {
+                    path: format!("{}{}{}", segment, separator, path),
+                    error,
+                }
+            }
+            error => PlanSerDeError::AtPath {
+                path: segment.to_owned(),
+                error: Box::new(error),
+            },
+        }
+    }
+
+    /// Path of the node where the error occurred, if known
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            PlanSerDeError::AtPath { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+}
+
+/// An extension trait that adds the method `at` to results of converting a
+/// child node, which prepends the child's position to the path of errors
+pub trait WithPath<T> {
+    fn at(self, segment: impl AsRef<str>) -> std::result::Result<T, PlanSerDeError>;
+}
+
+impl<T, E: Into<PlanSerDeError>> WithPath<T> for std::result::Result<T, E> {
+    fn at(self, segment: impl AsRef<str>) -> std::result::Result<T, PlanSerDeError> {
+        self.map_err(|e| e.into().at(segment))
+    }
 }
 
 /// An extension trait that adds the methods `optional` and `required` to any
@@ -17,11 +17,15 @@
 
 //! Serde code to convert from protocol buffers to Rust data structures.
 
+use std::any::Any;
 use std::convert::{TryFrom, TryInto};
 use std::sync::Arc;
 
 use chrono::{TimeZone, Utc};
-use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::array::{new_empty_array, ArrayRef};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{Field, SchemaRef};
+use datafusion::arrow::record_batch::RecordBatch;
 use datafusion::datafusion_data_access::{FileMeta, SizedFile};
 use datafusion::datasource::listing::{FileRange, PartitionedFile};
 use datafusion::error::DataFusionError;
@@ -32,7 +36,6 @@ use datafusion::logical_plan::window_frames::WindowFrame;
 use datafusion::logical_plan::*;
 use datafusion::physical_plan::aggregates::create_aggregate_expr;
 use datafusion::physical_plan::aggregates::{AggregateExec, AggregateMode};
-use datafusion::physical_plan::coalesce_partitions::CoalescePartitionsExec;
 use datafusion::physical_plan::file_format::{
     AvroExec, CsvExec, FileScanConfig, ParquetExec,
 };
@@ -65,13 +68,19 @@ use datafusion::scalar::ScalarValue;
 
 use datafusion_ext::empty_partitions_exec::EmptyPartitionsExec;
 use datafusion_ext::global_object_store_registry;
+use datafusion_ext::merge_exec::MergeExec;
 use datafusion_ext::rename_columns_exec::RenameColumnsExec;
+use datafusion_ext::shuffle_checksum::ShuffleChecksumAlgorithm;
+use datafusion_ext::shuffle_codec::ShuffleCompressionCodec;
 use datafusion_ext::shuffle_reader_exec::ShuffleReaderExec;
+use datafusion_ext::shuffle_writer_exec::ShuffleRepartitioning;
 use datafusion_ext::shuffle_writer_exec::ShuffleWriterExec;
+use datafusion_ext::shuffle_writer_exec::DEFAULT_SORT_BASED_PARTITION_THRESHOLD;
 
-use crate::error::{FromOptionalField, PlanSerDeError};
+use crate::error::{FromOptionalField, PlanSerDeError, WithPath};
 use crate::protobuf::physical_expr_node::ExprType;
 use crate::protobuf::physical_plan_node::PhysicalPlanType;
+use crate::protobuf::physical_repartition::RepartitionType;
 use crate::protobuf::repartition_exec_node::PartitionMethod;
 use crate::{convert_box_required, convert_required, into_required, protobuf, Schema};
 use crate::{from_proto_binary_op, proto_error, str_to_byte};
@@ -103,12 +112,12 @@ fn bind(
             expr.when_then_expr()
                 .iter()
                 .map(|(when_expr, then_expr)| {
-                    (
-                        bind(when_expr.clone(), input_schema).unwrap(),
-                        bind(then_expr.clone(), input_schema).unwrap(),
-                    )
+                    Ok((
+                        bind(when_expr.clone(), input_schema)?,
+                        bind(then_expr.clone(), input_schema)?,
+                    ))
                 })
-                .collect::<Vec<_>>()
+                .collect::<Result<Vec<_>, DataFusionError>>()?
                 .as_slice(),
             expr.else_expr()
                 .as_ref()
@@ -166,7 +175,10 @@ fn bind(
         ));
         Ok(sfe)
     } else {
-        unimplemented!("Expression binding not implemented yet")
+        Err(DataFusionError::NotImplemented(format!(
+            "Expression binding not implemented for {:?}",
+            expr_in
+        )))
     }
 }
 
@@ -183,37 +195,30 @@ impl TryInto<Arc<dyn ExecutionPlan>> for &protobuf::PhysicalPlanNode {
         match plan {
             PhysicalPlanType::Projection(projection) => {
                 let input: Arc<dyn ExecutionPlan> =
-                    convert_box_required!(projection.input)?;
-                let exprs = projection
-                    .expr
-                    .iter()
-                    .zip(projection.expr_name.iter())
-                    .map(|(expr, name)| {
-                        Ok((bind(expr.try_into()?, &input.schema())?, name.to_string()))
-                    })
-                    .collect::<Result<Vec<(Arc<dyn PhysicalExpr>, String)>, Self::Error>>(
-                    )?;
+                    convert_box_required!(projection.input).at("input")?;
+                let exprs =
+                    try_parse_bound_exprs(&projection.expr, "expr", &input.schema())?
+                        .into_iter()
+                        .zip(projection.expr_name.iter().cloned())
+                        .collect::<Vec<(Arc<dyn PhysicalExpr>, String)>>();
                 Ok(Arc::new(ProjectionExec::try_new(exprs, input)?))
             }
             PhysicalPlanType::Filter(filter) => {
-                let input: Arc<dyn ExecutionPlan> = convert_box_required!(filter.input)?;
-                let predicate = filter
-                    .expr
-                    .as_ref()
-                    .ok_or_else(|| {
-                        PlanSerDeError::General(
-                            "filter (FilterExecNode) in PhysicalPlanNode is missing."
-                                .to_owned(),
-                        )
-                    })?
-                    .try_into()?;
+                let input: Arc<dyn ExecutionPlan> =
+                    convert_box_required!(filter.input).at("input")?;
+                let predicate = filter.expr.as_ref().ok_or_else(|| {
+                    PlanSerDeError::General(
+                        "filter (FilterExecNode) in PhysicalPlanNode is missing."
+                            .to_owned(),
+                    )
+                })?;
                 Ok(Arc::new(FilterExec::try_new(
-                    bind(predicate, &input.schema())?,
+                    try_parse_bound_expr(predicate, &input.schema()).at("expr")?,
                     input,
                 )?))
             }
             PhysicalPlanType::CsvScan(scan) => Ok(Arc::new(CsvExec::new(
-                scan.base_conf.as_ref().unwrap().try_into()?,
+                convert_required!(scan.base_conf).at("base_conf")?,
                 scan.has_header,
                 str_to_byte(&scan.delimiter)?,
             ))),
@@ -222,60 +227,57 @@ impl TryInto<Arc<dyn ExecutionPlan>> for &protobuf::PhysicalPlanNode {
                     .pruning_predicate
                     .as_ref()
                     .map(|expr| expr.try_into())
-                    .transpose()?;
+                    .transpose()
+                    .at("pruning_predicate")?;
                 Ok(Arc::new(ParquetExec::new(
-                    scan.base_conf.as_ref().unwrap().try_into()?,
+                    convert_required!(scan.base_conf).at("base_conf")?,
                     predicate,
                 )))
             }
             PhysicalPlanType::AvroScan(scan) => Ok(Arc::new(AvroExec::new(
-                scan.base_conf.as_ref().unwrap().try_into()?,
+                convert_required!(scan.base_conf).at("base_conf")?,
             ))),
             PhysicalPlanType::CoalesceBatches(coalesce_batches) => {
                 let input: Arc<dyn ExecutionPlan> =
-                    convert_box_required!(coalesce_batches.input)?;
+                    convert_box_required!(coalesce_batches.input).at("input")?;
                 Ok(Arc::new(CoalesceBatchesExec::new(
                     input,
                     coalesce_batches.target_batch_size as usize,
                 )))
             }
             PhysicalPlanType::Merge(merge) => {
-                let input: Arc<dyn ExecutionPlan> = convert_box_required!(merge.input)?;
-                Ok(Arc::new(CoalescePartitionsExec::new(input)))
+                let input: Arc<dyn ExecutionPlan> =
+                    convert_box_required!(merge.input).at("input")?;
+                Ok(Arc::new(MergeExec::new(input)))
             }
             PhysicalPlanType::Repartition(repart) => {
-                let input: Arc<dyn ExecutionPlan> = convert_box_required!(repart.input)?;
+                let input: Arc<dyn ExecutionPlan> =
+                    convert_box_required!(repart.input).at("input")?;
                 let schema = input.schema();
                 match repart.partition_method {
                     Some(PartitionMethod::Hash(ref hash_part)) => {
-                        let expr = hash_part
-                            .hash_expr
-                            .iter()
-                            .map(|e| bind(e.try_into().unwrap(), &schema))
-                            .collect::<Result<Vec<Arc<dyn PhysicalExpr>>, _>>()?;
+                        let expr = try_parse_bound_exprs(
+                            &hash_part.hash_expr,
+                            "hash_expr",
+                            &schema,
+                        )
+                        .at("hash")?;
 
                         Ok(Arc::new(RepartitionExec::try_new(
                             input,
-                            Partitioning::Hash(
-                                expr,
-                                hash_part.partition_count.try_into().unwrap(),
-                            ),
+                            Partitioning::Hash(expr, hash_part.partition_count as usize),
                         )?))
                     }
                     Some(PartitionMethod::RoundRobin(partition_count)) => {
                         Ok(Arc::new(RepartitionExec::try_new(
                             input,
-                            Partitioning::RoundRobinBatch(
-                                partition_count.try_into().unwrap(),
-                            ),
+                            Partitioning::RoundRobinBatch(partition_count as usize),
                         )?))
                     }
                     Some(PartitionMethod::Unknown(partition_count)) => {
                         Ok(Arc::new(RepartitionExec::try_new(
                             input,
-                            Partitioning::UnknownPartitioning(
-                                partition_count.try_into().unwrap(),
-                            ),
+                            Partitioning::UnknownPartitioning(partition_count as usize),
                         )?))
                     }
                     _ => Err(PlanSerDeError::General(
@@ -284,16 +286,18 @@ impl TryInto<Arc<dyn ExecutionPlan>> for &protobuf::PhysicalPlanNode {
                 }
             }
             PhysicalPlanType::GlobalLimit(limit) => {
-                let input: Arc<dyn ExecutionPlan> = convert_box_required!(limit.input)?;
+                let input: Arc<dyn ExecutionPlan> =
+                    convert_box_required!(limit.input).at("input")?;
                 Ok(Arc::new(GlobalLimitExec::new(input, limit.limit as usize)))
             }
             PhysicalPlanType::LocalLimit(limit) => {
-                let input: Arc<dyn ExecutionPlan> = convert_box_required!(limit.input)?;
+                let input: Arc<dyn ExecutionPlan> =
+                    convert_box_required!(limit.input).at("input")?;
                 Ok(Arc::new(LocalLimitExec::new(input, limit.limit as usize)))
             }
             PhysicalPlanType::Window(window_agg) => {
                 let input: Arc<dyn ExecutionPlan> =
-                    convert_box_required!(window_agg.input)?;
+                    convert_box_required!(window_agg.input).at("input")?;
                 let input_schema = window_agg
                     .input_schema
                     .as_ref()
@@ -304,49 +308,33 @@ impl TryInto<Arc<dyn ExecutionPlan>> for &protobuf::PhysicalPlanNode {
                     })?
                     .clone();
                 let physical_schema: SchemaRef =
-                    SchemaRef::new((&input_schema).try_into()?);
+                    SchemaRef::new((&input_schema).try_into().at("input_schema")?);
 
                 let physical_window_expr: Vec<Arc<dyn WindowExpr>> = window_agg
                     .window_expr
                     .iter()
                     .zip(window_agg.window_expr_name.iter())
-                    .map(|(expr, name)| {
-                        let expr_type = expr.expr_type.as_ref().ok_or_else(|| {
-                            proto_error("Unexpected empty window physical expression")
-                        })?;
-
-                        match expr_type {
-                            ExprType::WindowExpr(window_node) => {
-                                let window_node_expr = bind(
-                                    convert_box_required!(window_node.expr)?,
-                                    &input.schema(),
-                                )?;
-                                Ok(create_window_expr(
-                                    &convert_required!(window_node.window_function)?,
-                                    name.to_owned(),
-                                    &[window_node_expr],
-                                    &[],
-                                    &[],
-                                    Some(WindowFrame::default()),
-                                    &physical_schema,
-                                )?)
-                            }
-                            _ => Err(PlanSerDeError::General(
-                                "Invalid expression for WindowAggrExec".to_string(),
-                            )),
-                        }
+                    .enumerate()
+                    .map(|(i, (expr, name))| {
+                        try_parse_window_expr(
+                            expr,
+                            name,
+                            &input.schema(),
+                            &physical_schema,
+                        )
+                        .at(format!("window_expr[{}]", i))
                     })
                     .collect::<Result<Vec<_>, _>>()?;
 
                 Ok(Arc::new(WindowAggExec::try_new(
                     physical_window_expr,
                     input,
-                    Arc::new((&input_schema).try_into()?),
+                    physical_schema,
                 )?))
             }
             PhysicalPlanType::HashAggregate(hash_agg) => {
                 let input: Arc<dyn ExecutionPlan> =
-                    convert_box_required!(hash_agg.input)?;
+                    convert_box_required!(hash_agg.input).at("input")?;
                 let mode = protobuf::AggregateMode::from_i32(hash_agg.mode).ok_or_else(|| {
                     proto_error(format!(
                         "Received a HashAggregateNode message with unknown AggregateMode {}",
@@ -360,18 +348,14 @@ impl TryInto<Arc<dyn ExecutionPlan>> for &protobuf::PhysicalPlanNode {
                         AggregateMode::FinalPartitioned
                     }
                 };
-                let group = hash_agg
-                    .group_expr
-                    .iter()
-                    .zip(hash_agg.group_expr_name.iter())
-                    .map(|(expr, name)| {
-                        expr.try_into().and_then(|expr: Arc<dyn PhysicalExpr>| {
-                            bind(expr, &input.schema())
-                                .map(|expr| (expr, name.to_string()))
-                                .map_err(PlanSerDeError::DataFusionError)
-                        })
-                    })
-                    .collect::<Result<Vec<_>, _>>()?;
+                let group = try_parse_bound_exprs(
+                    &hash_agg.group_expr,
+                    "group_expr",
+                    &input.schema(),
+                )?
+                .into_iter()
+                .zip(hash_agg.group_expr_name.iter().cloned())
+                .collect::<Vec<_>>();
 
                 let input_schema = hash_agg
                     .input_schema
@@ -383,48 +367,16 @@ impl TryInto<Arc<dyn ExecutionPlan>> for &protobuf::PhysicalPlanNode {
                     })?
                     .clone();
                 let physical_schema: SchemaRef =
-                    SchemaRef::new((&input_schema).try_into()?);
+                    SchemaRef::new((&input_schema).try_into().at("input_schema")?);
 
                 let physical_aggr_expr: Vec<Arc<dyn AggregateExpr>> = hash_agg
                     .aggr_expr
                     .iter()
                     .zip(hash_agg.aggr_expr_name.iter())
-                    .map(|(expr, name)| {
-                        let expr_type = expr.expr_type.as_ref().ok_or_else(|| {
-                            proto_error("Unexpected empty aggregate physical expression")
-                        })?;
-
-                        match expr_type {
-                            ExprType::AggregateExpr(agg_node) => {
-                                let aggr_function =
-                                    protobuf::AggregateFunction::from_i32(
-                                        agg_node.aggr_function,
-                                    )
-                                    .ok_or_else(
-                                        || {
-                                            proto_error(format!(
-                                            "Received an unknown aggregate function: {}",
-                                            agg_node.aggr_function
-                                        ))
-                                        },
-                                    )?;
-                                let agg_expr = bind(
-                                    convert_box_required!(agg_node.expr)?,
-                                    &input.schema(),
-                                )?;
-                                Ok(create_aggregate_expr(
-                                    &aggr_function.into(),
-                                    false,
-                                    &[agg_expr],
-                                    &physical_schema,
-                                    name.to_string(),
-                                )?)
-                            }
-                            _ => Err(PlanSerDeError::General(
-                                "Invalid aggregate  expression for AggregateExec"
-                                    .to_string(),
-                            )),
-                        }
+                    .enumerate()
+                    .map(|(i, (expr, name))| {
+                        try_parse_aggr_expr(expr, name, &input.schema(), &physical_schema)
+                            .at(format!("aggr_expr[{}]", i))
                     })
                     .collect::<Result<Vec<_>, _>>()?;
 
@@ -433,26 +385,16 @@ impl TryInto<Arc<dyn ExecutionPlan>> for &protobuf::PhysicalPlanNode {
                     group,
                     physical_aggr_expr,
                     input,
-                    Arc::new((&input_schema).try_into()?),
+                    physical_schema,
                 )?))
             }
             PhysicalPlanType::HashJoin(hashjoin) => {
-                let left: Arc<dyn ExecutionPlan> = convert_box_required!(hashjoin.left)?;
+                let left: Arc<dyn ExecutionPlan> =
+                    convert_box_required!(hashjoin.left).at("left")?;
                 let right: Arc<dyn ExecutionPlan> =
-                    convert_box_required!(hashjoin.right)?;
-                let on: Vec<(Column, Column)> = hashjoin
-                    .on
-                    .iter()
-                    .map(|col| {
-                        let left_col: Column = into_required!(col.left)?;
-                        let left_col_binded: Column =
-                            Column::new_with_schema(left_col.name(), &left.schema())?;
-                        let right_col: Column = into_required!(col.right)?;
-                        let right_col_binded: Column =
-                            Column::new_with_schema(right_col.name(), &right.schema())?;
-                        Ok((left_col_binded, right_col_binded))
-                    })
-                    .collect::<Result<_, Self::Error>>()?;
+                    convert_box_required!(hashjoin.right).at("right")?;
+                let on =
+                    try_parse_join_on(&hashjoin.on, &left.schema(), &right.schema())?;
                 let join_type = protobuf::JoinType::from_i32(hashjoin.join_type)
                     .ok_or_else(|| {
                         proto_error(format!(
@@ -484,22 +426,14 @@ impl TryInto<Arc<dyn ExecutionPlan>> for &protobuf::PhysicalPlanNode {
             }
             PhysicalPlanType::SortMergeJoin(sort_merge_join) => {
                 let left: Arc<dyn ExecutionPlan> =
-                    convert_box_required!(sort_merge_join.left)?;
+                    convert_box_required!(sort_merge_join.left).at("left")?;
                 let right: Arc<dyn ExecutionPlan> =
-                    convert_box_required!(sort_merge_join.right)?;
-                let on: Vec<(Column, Column)> = sort_merge_join
-                    .on
-                    .iter()
-                    .map(|col| {
-                        let left_col: Column = into_required!(col.left)?;
-                        let left_col_binded: Column =
-                            Column::new_with_schema(left_col.name(), &left.schema())?;
-                        let right_col: Column = into_required!(col.right)?;
-                        let right_col_binded: Column =
-                            Column::new_with_schema(right_col.name(), &right.schema())?;
-                        Ok((left_col_binded, right_col_binded))
-                    })
-                    .collect::<Result<_, Self::Error>>()?;
+                    convert_box_required!(sort_merge_join.right).at("right")?;
+                let on = try_parse_join_on(
+                    &sort_merge_join.on,
+                    &left.schema(),
+                    &right.schema(),
+                )?;
 
                 let sort_options = sort_merge_join
                     .sort_options
@@ -528,75 +462,123 @@ impl TryInto<Arc<dyn ExecutionPlan>> for &protobuf::PhysicalPlanNode {
                 )?))
             }
             PhysicalPlanType::CrossJoin(crossjoin) => {
-                let left: Arc<dyn ExecutionPlan> = convert_box_required!(crossjoin.left)?;
+                let left: Arc<dyn ExecutionPlan> =
+                    convert_box_required!(crossjoin.left).at("left")?;
                 let right: Arc<dyn ExecutionPlan> =
-                    convert_box_required!(crossjoin.right)?;
+                    convert_box_required!(crossjoin.right).at("right")?;
                 Ok(Arc::new(CrossJoinExec::try_new(left, right)?))
             }
             PhysicalPlanType::ShuffleWriter(shuffle_writer) => {
                 let input: Arc<dyn ExecutionPlan> =
-                    convert_box_required!(shuffle_writer.input)?;
+                    convert_box_required!(shuffle_writer.input).at("input")?;
 
-                let output_partitioning = parse_protobuf_hash_partitioning(
+                let output_partitioning = parse_protobuf_partitioning(
                     input.clone(),
                     shuffle_writer.output_partitioning.as_ref(),
-                )?;
+                )
+                .at("output_partitioning")?
+                .ok_or_else(|| PlanSerDeError::required("output_partitioning"))?;
+
+                let codec = protobuf::ShuffleCompressionCodec::from_i32(
+                    shuffle_writer.compression_codec,
+                )
+                .ok_or_else(|| {
+                    proto_error(format!(
+                        "Received a ShuffleWriterExecNode message with unknown codec {}",
+                        shuffle_writer.compression_codec
+                    ))
+                })?;
+                let codec = match codec {
+                    protobuf::ShuffleCompressionCodec::NoCompression => {
+                        ShuffleCompressionCodec::None
+                    }
+                    protobuf::ShuffleCompressionCodec::Lz4 => {
+                        ShuffleCompressionCodec::Lz4
+                    }
+                    protobuf::ShuffleCompressionCodec::Zstd => {
+                        ShuffleCompressionCodec::Zstd
+                    }
+                };
+
+                let checksum_algorithm = protobuf::ShuffleChecksumAlgorithm::from_i32(
+                    shuffle_writer.checksum_algorithm,
+                )
+                .ok_or_else(|| {
+                    proto_error(format!(
+                        "Received a ShuffleWriterExecNode message with unknown checksum algorithm {}",
+                        shuffle_writer.checksum_algorithm
+                    ))
+                })?;
+                let checksum_algorithm = match checksum_algorithm {
+                    protobuf::ShuffleChecksumAlgorithm::NoChecksum => {
+                        ShuffleChecksumAlgorithm::None
+                    }
+                    protobuf::ShuffleChecksumAlgorithm::Adler32 => {
+                        ShuffleChecksumAlgorithm::Adler32
+                    }
+                    protobuf::ShuffleChecksumAlgorithm::Crc32c => {
+                        ShuffleChecksumAlgorithm::Crc32c
+                    }
+                };
 
                 Ok(Arc::new(ShuffleWriterExec::try_new(
                     input,
-                    output_partitioning.unwrap(),
+                    output_partitioning,
                     shuffle_writer.output_data_file.clone(),
                     shuffle_writer.output_index_file.clone(),
+                    codec,
+                    checksum_algorithm,
+                    shuffle_writer.collect_column_stats,
+                    match shuffle_writer.sort_based_partition_threshold {
+                        0 => DEFAULT_SORT_BASED_PARTITION_THRESHOLD,
+                        threshold => threshold as usize,
+                    },
                 )?))
             }
             PhysicalPlanType::ShuffleReader(shuffle_reader) => {
-                let schema = Arc::new(convert_required!(shuffle_reader.schema)?);
+                let schema =
+                    Arc::new(convert_required!(shuffle_reader.schema).at("schema")?);
+                let num_partitions = shuffle_reader.num_partitions as usize;
+                let partitioning = match &shuffle_reader.output_partitioning {
+                    Some(hash_part) => {
+                        if hash_part.partition_count as usize != num_partitions {
+                            return Err(proto_error(format!(
+                                "Received a ShuffleReaderExecNode with {} partitions \
+                                    but hash partitioning of {} partitions",
+                                num_partitions, hash_part.partition_count,
+                            )));
+                        }
+                        let expr = try_parse_bound_exprs(
+                            &hash_part.hash_expr,
+                            "hash_expr",
+                            &schema,
+                        )
+                        .at("output_partitioning")?;
+                        Partitioning::Hash(expr, num_partitions)
+                    }
+                    None => Partitioning::UnknownPartitioning(num_partitions),
+                };
                 Ok(Arc::new(ShuffleReaderExec::new(
-                    shuffle_reader.num_partitions as usize,
+                    partitioning,
                     shuffle_reader.native_shuffle_id.clone(),
                     schema,
+                    shuffle_reader.prefetch_depth as usize,
                 )))
             }
             PhysicalPlanType::Empty(empty) => {
-                let schema = Arc::new(convert_required!(empty.schema)?);
+                let schema = Arc::new(convert_required!(empty.schema).at("schema")?);
                 Ok(Arc::new(EmptyExec::new(empty.produce_one_row, schema)))
             }
             PhysicalPlanType::Sort(sort) => {
-                let input: Arc<dyn ExecutionPlan> = convert_box_required!(sort.input)?;
+                let input: Arc<dyn ExecutionPlan> =
+                    convert_box_required!(sort.input).at("input")?;
                 let exprs = sort
                     .expr
                     .iter()
-                    .map(|expr| {
-                        let expr = expr.expr_type.as_ref().ok_or_else(|| {
-                            proto_error(format!(
-                                "physical_plan::from_proto() Unexpected expr {:?}",
-                                self
-                            ))
-                        })?;
-                        if let protobuf::physical_expr_node::ExprType::Sort(sort_expr) = expr {
-                            let expr = sort_expr
-                                .expr
-                                .as_ref()
-                                .ok_or_else(|| {
-                                    proto_error(format!(
-                                        "physical_plan::from_proto() Unexpected sort expr {:?}",
-                                        self
-                                    ))
-                                })?
-                                .as_ref();
-                            Ok(PhysicalSortExpr {
-                                expr: bind(expr.try_into()?, &input.schema()).unwrap(),
-                                options: SortOptions {
-                                    descending: !sort_expr.asc,
-                                    nulls_first: sort_expr.nulls_first,
-                                },
-                            })
-                        } else {
-                            Err(PlanSerDeError::General(format!(
-                                "physical_plan::from_proto() {:?}",
-                                self
-                            )))
-                        }
+                    .enumerate()
+                    .map(|(i, expr)| {
+                        try_parse_bound_sort_expr(expr, &input.schema())
+                            .at(format!("expr[{}]", i))
                     })
                     .collect::<Result<Vec<_>, _>>()?;
                 // always preserve partitioning
@@ -608,12 +590,14 @@ impl TryInto<Arc<dyn ExecutionPlan>> for &protobuf::PhysicalPlanNode {
                 let inputs: Vec<Arc<dyn ExecutionPlan>> = union
                     .children
                     .iter()
-                    .map(|i| i.try_into())
+                    .enumerate()
+                    .map(|(i, child)| child.try_into().at(format!("children[{}]", i)))
                     .collect::<Result<Vec<_>, _>>()?;
                 Ok(Arc::new(UnionExec::new(inputs)))
             }
             PhysicalPlanType::EmptyPartitions(empty_partitions) => {
-                let schema = Arc::new(convert_required!(empty_partitions.schema)?);
+                let schema =
+                    Arc::new(convert_required!(empty_partitions.schema).at("schema")?);
                 Ok(Arc::new(EmptyPartitionsExec::new(
                     schema,
                     empty_partitions.num_partitions as usize,
@@ -621,19 +605,218 @@ impl TryInto<Arc<dyn ExecutionPlan>> for &protobuf::PhysicalPlanNode {
             }
             PhysicalPlanType::RenameColumns(rename_columns) => {
                 let input: Arc<dyn ExecutionPlan> =
-                    convert_box_required!(rename_columns.input)?;
+                    convert_box_required!(rename_columns.input).at("input")?;
                 Ok(Arc::new(RenameColumnsExec::try_new(
                     input,
                     rename_columns.renamed_column_names.clone(),
                 )?))
             }
             PhysicalPlanType::Unresolved(_unresolved_shuffle) => {
-                unreachable!()
+                Err(PlanSerDeError::NotImplemented(
+                    "physical_plan::from_proto() UnresolvedShuffleExecNode cannot be \
+                     executed"
+                        .to_owned(),
+                ))
             }
         }
     }
 }
 
+/// Converts a plan received from the JVM, errors are located at the path of the
+/// offending node starting with `root`, like `root.input.left.expr[2]`
+pub fn try_parse_physical_plan(
+    plan: &protobuf::PhysicalPlanNode,
+) -> Result<Arc<dyn ExecutionPlan>, PlanSerDeError> {
+    plan.try_into().at("root")
+}
+
+fn try_parse_bound_expr(
+    expr: &protobuf::PhysicalExprNode,
+    input_schema: &SchemaRef,
+) -> Result<Arc<dyn PhysicalExpr>, PlanSerDeError> {
+    let expr: Arc<dyn PhysicalExpr> = expr.try_into()?;
+    Ok(bind(expr, input_schema)?)
+}
+
+/// converts expressions and binds them to `input_schema`, errors of the i-th
+/// expression are located at `name[i]`
+fn try_parse_bound_exprs(
+    exprs: &[protobuf::PhysicalExprNode],
+    name: &str,
+    input_schema: &SchemaRef,
+) -> Result<Vec<Arc<dyn PhysicalExpr>>, PlanSerDeError> {
+    exprs
+        .iter()
+        .enumerate()
+        .map(|(i, expr)| {
+            try_parse_bound_expr(expr, input_schema).at(format!("{}[{}]", name, i))
+        })
+        .collect()
+}
+
+fn try_parse_bound_sort_expr(
+    expr: &protobuf::PhysicalExprNode,
+    input_schema: &SchemaRef,
+) -> Result<PhysicalSortExpr, PlanSerDeError> {
+    match &expr.expr_type {
+        Some(ExprType::Sort(sort_expr)) => {
+            let expr: Arc<dyn PhysicalExpr> =
+                convert_box_required!(sort_expr.expr).at("expr")?;
+            Ok(PhysicalSortExpr {
+                expr: bind(expr, input_schema).at("expr")?,
+                options: SortOptions {
+                    descending: !sort_expr.asc,
+                    nulls_first: sort_expr.nulls_first,
+                },
+            })
+        }
+        _ => Err(proto_error(format!(
+            "physical_plan::from_proto() Unexpected non-sort expr {:?}",
+            expr
+        ))),
+    }
+}
+
+fn try_parse_window_expr(
+    expr: &protobuf::PhysicalExprNode,
+    name: &str,
+    input_schema: &SchemaRef,
+    physical_schema: &SchemaRef,
+) -> Result<Arc<dyn WindowExpr>, PlanSerDeError> {
+    let expr_type = expr
+        .expr_type
+        .as_ref()
+        .ok_or_else(|| proto_error("Unexpected empty window physical expression"))?;
+
+    match expr_type {
+        ExprType::WindowExpr(window_node) => {
+            let window_node_expr: Arc<dyn PhysicalExpr> =
+                convert_box_required!(window_node.expr).at("expr")?;
+            let window_node_expr = bind(window_node_expr, input_schema).at("expr")?;
+            let window_function = window_node
+                .window_function
+                .clone()
+                .ok_or_else(|| PlanSerDeError::required("window_function"))?;
+            let inner = create_window_expr(
+                &(&window_function).try_into()?,
+                name.to_owned(),
+                &[window_node_expr.clone()],
+                &[],
+                &[],
+                Some(WindowFrame::default()),
+                physical_schema,
+            )?;
+            Ok(Arc::new(ParsedWindowExpr {
+                window_function,
+                arg: window_node_expr,
+                inner,
+            }))
+        }
+        _ => Err(PlanSerDeError::General(
+            "Invalid expression for WindowAggrExec".to_string(),
+        )),
+    }
+}
+
+/// A window expression decoded from protobuf.
+///
+/// DataFusion's window expressions do not expose their window functions, so the
+/// decoded function and argument are kept here to encode the expression again.
+#[derive(Debug)]
+pub(crate) struct ParsedWindowExpr {
+    pub(crate) window_function: protobuf::physical_window_expr_node::WindowFunction,
+    pub(crate) arg: Arc<dyn PhysicalExpr>,
+    inner: Arc<dyn WindowExpr>,
+}
+
+impl WindowExpr for ParsedWindowExpr {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn field(&self) -> Result<Field, DataFusionError> {
+        self.inner.field()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn expressions(&self) -> Vec<Arc<dyn PhysicalExpr>> {
+        self.inner.expressions()
+    }
+
+    fn evaluate(&self, batch: &RecordBatch) -> Result<ArrayRef, DataFusionError> {
+        self.inner.evaluate(batch)
+    }
+
+    fn partition_by(&self) -> &[Arc<dyn PhysicalExpr>] {
+        self.inner.partition_by()
+    }
+
+    fn order_by(&self) -> &[PhysicalSortExpr] {
+        self.inner.order_by()
+    }
+}
+
+fn try_parse_aggr_expr(
+    expr: &protobuf::PhysicalExprNode,
+    name: &str,
+    input_schema: &SchemaRef,
+    physical_schema: &SchemaRef,
+) -> Result<Arc<dyn AggregateExpr>, PlanSerDeError> {
+    let expr_type = expr
+        .expr_type
+        .as_ref()
+        .ok_or_else(|| proto_error("Unexpected empty aggregate physical expression"))?;
+
+    match expr_type {
+        ExprType::AggregateExpr(agg_node) => {
+            let aggr_function =
+                protobuf::AggregateFunction::from_i32(agg_node.aggr_function)
+                    .ok_or_else(|| {
+                        proto_error(format!(
+                            "Received an unknown aggregate function: {}",
+                            agg_node.aggr_function
+                        ))
+                    })?;
+            let agg_expr: Arc<dyn PhysicalExpr> =
+                convert_box_required!(agg_node.expr).at("expr")?;
+            let agg_expr = bind(agg_expr, input_schema).at("expr")?;
+            Ok(create_aggregate_expr(
+                &aggr_function.into(),
+                false,
+                &[agg_expr],
+                physical_schema,
+                name.to_string(),
+            )?)
+        }
+        _ => Err(PlanSerDeError::General(
+            "Invalid aggregate  expression for AggregateExec".to_string(),
+        )),
+    }
+}
+
+fn try_parse_join_on(
+    on: &[protobuf::JoinOn],
+    left_schema: &SchemaRef,
+    right_schema: &SchemaRef,
+) -> Result<Vec<(Column, Column)>, PlanSerDeError> {
+    on.iter()
+        .enumerate()
+        .map(|(i, col)| {
+            let left_col: Column = into_required!(col.left).at(format!("on[{}]", i))?;
+            let left_col_binded = Column::new_with_schema(left_col.name(), left_schema)
+                .at(format!("on[{}].left", i))?;
+            let right_col: Column = into_required!(col.right).at(format!("on[{}]", i))?;
+            let right_col_binded =
+                Column::new_with_schema(right_col.name(), right_schema)
+                    .at(format!("on[{}].right", i))?;
+            Ok((left_col_binded, right_col_binded))
+        })
+        .collect()
+}
+
 impl From<&protobuf::PhysicalColumn> for Column {
     fn from(c: &protobuf::PhysicalColumn) -> Column {
         Column::new(&c.name, c.index as usize)
@@ -727,12 +910,12 @@ impl TryFrom<&protobuf::PhysicalExprNode> for Arc<dyn PhysicalExpr> {
                 Arc::new(pcol)
             }
             ExprType::Literal(scalar) => {
-                Arc::new(Literal::new(convert_required!(scalar.value)?))
+                Arc::new(Literal::new(convert_required!(scalar.value).at("value")?))
             }
             ExprType::BinaryExpr(binary_expr) => Arc::new(BinaryExpr::new(
-                convert_box_required!(&binary_expr.l)?,
+                convert_box_required!(&binary_expr.l).at("l")?,
                 from_proto_binary_op(&binary_expr.op)?,
-                convert_box_required!(&binary_expr.r)?,
+                convert_box_required!(&binary_expr.r).at("r")?,
             )),
             ExprType::AggregateExpr(_) => {
                 return Err(PlanSerDeError::General(
@@ -751,33 +934,41 @@ impl TryFrom<&protobuf::PhysicalExprNode> for Arc<dyn PhysicalExpr> {
                 ));
             }
             ExprType::IsNullExpr(e) => {
-                Arc::new(IsNullExpr::new(convert_box_required!(e.expr)?))
-            }
-            ExprType::IsNotNullExpr(e) => {
-                Arc::new(IsNotNullExpr::new(convert_box_required!(e.expr)?))
+                Arc::new(IsNullExpr::new(convert_box_required!(e.expr).at("expr")?))
             }
+            ExprType::IsNotNullExpr(e) => Arc::new(IsNotNullExpr::new(
+                convert_box_required!(e.expr).at("expr")?,
+            )),
             ExprType::NotExpr(e) => {
-                Arc::new(NotExpr::new(convert_box_required!(e.expr)?))
+                Arc::new(NotExpr::new(convert_box_required!(e.expr).at("expr")?))
             }
             ExprType::Negative(e) => {
-                Arc::new(NegativeExpr::new(convert_box_required!(e.expr)?))
+                Arc::new(NegativeExpr::new(convert_box_required!(e.expr).at("expr")?))
             }
             ExprType::InList(e) => Arc::new(InListExpr::new(
-                convert_box_required!(e.expr)?,
+                convert_box_required!(e.expr).at("expr")?,
                 e.list
                     .iter()
-                    .map(|x| x.try_into())
+                    .enumerate()
+                    .map(|(i, x)| x.try_into().at(format!("list[{}]", i)))
                     .collect::<Result<Vec<_>, _>>()?,
                 e.negated,
             )),
             ExprType::Case(e) => Arc::new(CaseExpr::try_new(
-                e.expr.as_ref().map(|e| e.as_ref().try_into()).transpose()?,
+                e.expr
+                    .as_ref()
+                    .map(|e| e.as_ref().try_into())
+                    .transpose()
+                    .at("expr")?,
                 e.when_then_expr
                     .iter()
-                    .map(|e| {
+                    .enumerate()
+                    .map(|(i, e)| {
                         Ok((
-                            convert_required!(e.when_expr)?,
-                            convert_required!(e.then_expr)?,
+                            convert_required!(e.when_expr)
+                                .at(format!("when_then_expr[{}].when_expr", i))?,
+                            convert_required!(e.then_expr)
+                                .at(format!("when_then_expr[{}].then_expr", i))?,
                         ))
                     })
                     .collect::<Result<Vec<_>, PlanSerDeError>>()?
@@ -785,16 +976,17 @@ impl TryFrom<&protobuf::PhysicalExprNode> for Arc<dyn PhysicalExpr> {
                 e.else_expr
                     .as_ref()
                     .map(|e| e.as_ref().try_into())
-                    .transpose()?,
+                    .transpose()
+                    .at("else_expr")?,
             )?),
             ExprType::Cast(e) => Arc::new(CastExpr::new(
-                convert_box_required!(e.expr)?,
-                convert_required!(e.arrow_type)?,
+                convert_box_required!(e.expr).at("expr")?,
+                convert_required!(e.arrow_type).at("arrow_type")?,
                 DEFAULT_DATAFUSION_CAST_OPTIONS,
             )),
             ExprType::TryCast(e) => Arc::new(TryCastExpr::new(
-                convert_box_required!(e.expr)?,
-                convert_required!(e.arrow_type)?,
+                convert_box_required!(e.expr).at("expr")?,
+                convert_required!(e.arrow_type).at("arrow_type")?,
             )),
             ExprType::ScalarFunction(e) => {
                 let scalar_function = protobuf::ScalarFunction::from_i32(e.fun)
@@ -808,7 +1000,8 @@ impl TryFrom<&protobuf::PhysicalExprNode> for Arc<dyn PhysicalExpr> {
                 let args = e
                     .args
                     .iter()
-                    .map(|x| x.try_into())
+                    .enumerate()
+                    .map(|(i, x)| x.try_into().at(format!("args[{}]", i)))
                     .collect::<Result<Vec<_>, _>>()?;
 
                 let execution_props = ExecutionProps::new();
@@ -821,7 +1014,7 @@ impl TryFrom<&protobuf::PhysicalExprNode> for Arc<dyn PhysicalExpr> {
                     &e.name,
                     fun_expr,
                     args,
-                    &convert_required!(e.return_type)?,
+                    &convert_required!(e.return_type).at("return_type")?,
                 ))
             }
         };
@@ -862,28 +1055,76 @@ impl TryFrom<&protobuf::physical_window_expr_node::WindowFunction> for WindowFun
     }
 }
 
-pub fn parse_protobuf_hash_partitioning(
+pub fn parse_protobuf_partitioning(
     input: Arc<dyn ExecutionPlan>,
-    partitioning: Option<&protobuf::PhysicalHashRepartition>,
-) -> Result<Option<Partitioning>, PlanSerDeError> {
-    match partitioning {
-        Some(hash_part) => {
-            let expr = hash_part
-                .hash_expr
-                .iter()
-                .map(|e| {
-                    e.try_into().and_then(|e| {
-                        bind(e, &input.schema()).map_err(PlanSerDeError::DataFusionError)
-                    })
-                })
-                .collect::<Result<Vec<Arc<dyn PhysicalExpr>>, _>>()?;
+    partitioning: Option<&protobuf::PhysicalRepartition>,
+) -> Result<Option<ShuffleRepartitioning>, PlanSerDeError> {
+    let repartition_type = match partitioning.and_then(|p| p.repartition_type.as_ref()) {
+        Some(repartition_type) => repartition_type,
+        None => return Ok(None),
+    };
+    let schema = input.schema();
+
+    match repartition_type {
+        RepartitionType::SingleRepartition(_) => Ok(Some(ShuffleRepartitioning::Single)),
+        RepartitionType::HashRepartition(hash_part) => {
+            let expr = try_parse_bound_exprs(&hash_part.hash_expr, "hash_expr", &schema)
+                .at("hash_repartition")?;
 
-            Ok(Some(Partitioning::Hash(
+            Ok(Some(ShuffleRepartitioning::Hash(
                 expr,
-                hash_part.partition_count.try_into().unwrap(),
+                hash_part.partition_count as usize,
             )))
         }
-        None => Ok(None),
+        RepartitionType::RoundRobinRepartition(round_robin_part) => Ok(Some(
+            ShuffleRepartitioning::RoundRobin(round_robin_part.partition_count as usize),
+        )),
+        RepartitionType::RangeRepartition(range_part) => {
+            let sort_exprs = range_part
+                .sort_expr
+                .iter()
+                .enumerate()
+                .map(|(i, expr)| {
+                    try_parse_bound_sort_expr(expr, &schema)
+                        .at(format!("range_repartition.sort_expr[{}]", i))
+                })
+                .collect::<Result<Vec<_>, PlanSerDeError>>()?;
+
+            // convert bounds from rows of literals into one array for each sort expr
+            let bounds = sort_exprs
+                .iter()
+                .enumerate()
+                .map(|(i, sort_expr)| {
+                    let values = range_part
+                        .bounds
+                        .iter()
+                        .enumerate()
+                        .map(|(j, bound)| {
+                            let value = bound.value.get(i).ok_or_else(|| {
+                                proto_error(
+                                    "Received a PhysicalRangeBound with missing values",
+                                )
+                            });
+                            value
+                                .and_then(|value| value.try_into())
+                                .at(format!("range_repartition.bounds[{}]", j))
+                        })
+                        .collect::<Result<Vec<ScalarValue>, PlanSerDeError>>()?;
+                    let data_type = sort_expr.expr.data_type(&schema)?;
+                    if values.is_empty() {
+                        return Ok(new_empty_array(&data_type));
+                    }
+                    let array = ScalarValue::iter_to_array(values)?;
+                    Ok(cast(&array, &data_type)?)
+                })
+                .collect::<Result<Vec<ArrayRef>, PlanSerDeError>>()?;
+
+            Ok(Some(ShuffleRepartitioning::Range {
+                sort_exprs,
+                num_partitions: range_part.partition_count as usize,
+                bounds,
+            }))
+        }
     }
 }
 
@@ -935,14 +1176,16 @@ impl TryFrom<&protobuf::FileGroup> for Vec<PartitionedFile> {
     }
 }
 
-impl From<&protobuf::ColumnStats> for ColumnStatistics {
-    fn from(cs: &protobuf::ColumnStats) -> ColumnStatistics {
-        ColumnStatistics {
+impl TryFrom<&protobuf::ColumnStats> for ColumnStatistics {
+    type Error = PlanSerDeError;
+
+    fn try_from(cs: &protobuf::ColumnStats) -> Result<Self, Self::Error> {
+        Ok(ColumnStatistics {
             null_count: Some(cs.null_count as usize),
-            max_value: cs.max_value.as_ref().map(|m| m.try_into().unwrap()),
-            min_value: cs.min_value.as_ref().map(|m| m.try_into().unwrap()),
+            max_value: cs.max_value.as_ref().map(|m| m.try_into()).transpose()?,
+            min_value: cs.min_value.as_ref().map(|m| m.try_into()).transpose()?,
             distinct_count: Some(cs.distinct_count as usize),
-        }
+        })
     }
 }
 
@@ -953,8 +1196,9 @@ impl TryInto<Statistics> for &protobuf::Statistics {
         let column_statistics = self
             .column_stats
             .iter()
-            .map(|s| s.into())
-            .collect::<Vec<_>>();
+            .enumerate()
+            .map(|(i, s)| s.try_into().at(format!("column_stats[{}]", i)))
+            .collect::<Result<Vec<_>, _>>()?;
         Ok(Statistics {
             num_rows: Some(self.num_rows as usize),
             total_byte_size: Some(self.total_byte_size as usize),
@@ -973,7 +1217,7 @@ impl TryInto<FileScanConfig> for &protobuf::FileScanExecConf {
     type Error = PlanSerDeError;
 
     fn try_into(self) -> Result<FileScanConfig, Self::Error> {
-        let schema = Arc::new(convert_required!(self.schema)?);
+        let schema = Arc::new(convert_required!(self.schema).at("schema")?);
         let projection = self
             .projection
             .iter()
@@ -984,7 +1228,7 @@ impl TryInto<FileScanConfig> for &protobuf::FileScanExecConf {
         } else {
             Some(projection)
         };
-        let statistics = convert_required!(self.statistics)?;
+        let statistics = convert_required!(self.statistics).at("statistics")?;
 
         Ok(FileScanConfig {
             // use datafusion_ext::global_object_store_registry to get object score
@@ -1002,7 +1246,8 @@ impl TryInto<FileScanConfig> for &protobuf::FileScanExecConf {
             file_groups: self
                 .file_groups
                 .iter()
-                .map(|f| f.try_into())
+                .enumerate()
+                .map(|(i, f)| f.try_into().at(format!("file_groups[{}]", i)))
                 .collect::<Result<Vec<_>, _>>()?,
             statistics,
             projection,
@@ -1097,77 +1342,68 @@ impl TryFrom<&protobuf::LogicalExprNode> for Expr {
                 let scalar_function = protobuf::ScalarFunction::from_i32(expr.fun)
                     .ok_or_else(|| PlanSerDeError::unknown("ScalarFunction", expr.fun))?;
                 let args = &expr.args;
+                let arg = |i: usize| -> Result<Expr, PlanSerDeError> {
+                    args.get(i)
+                        .ok_or_else(|| {
+                            proto_error(format!(
+                                "Received a ScalarFunctionNode with missing argument {}",
+                                i
+                            ))
+                        })?
+                        .try_into()
+                        .at(format!("args[{}]", i))
+                };
 
                 match scalar_function {
-                    ScalarFunction::Asin => Ok(asin((&args[0]).try_into()?)),
-                    ScalarFunction::Acos => Ok(acos((&args[0]).try_into()?)),
+                    ScalarFunction::Asin => Ok(asin(arg(0)?)),
+                    ScalarFunction::Acos => Ok(acos(arg(0)?)),
                     ScalarFunction::Array => Ok(array(
                         args.to_owned()
                             .iter()
                             .map(|e| e.try_into())
                             .collect::<Result<Vec<_>, _>>()?,
                     )),
-                    ScalarFunction::Sqrt => Ok(sqrt((&args[0]).try_into()?)),
-                    ScalarFunction::Sin => Ok(sin((&args[0]).try_into()?)),
-                    ScalarFunction::Cos => Ok(cos((&args[0]).try_into()?)),
-                    ScalarFunction::Tan => Ok(tan((&args[0]).try_into()?)),
-                    ScalarFunction::Atan => Ok(atan((&args[0]).try_into()?)),
-                    ScalarFunction::Exp => Ok(exp((&args[0]).try_into()?)),
-                    ScalarFunction::Log2 => Ok(log2((&args[0]).try_into()?)),
-                    ScalarFunction::Ln => Ok(ln((&args[0]).try_into()?)),
-                    ScalarFunction::Log10 => Ok(log10((&args[0]).try_into()?)),
-                    ScalarFunction::Floor => Ok(floor((&args[0]).try_into()?)),
-                    ScalarFunction::Ceil => Ok(ceil((&args[0]).try_into()?)),
-                    ScalarFunction::Round => Ok(round((&args[0]).try_into()?)),
-                    ScalarFunction::Trunc => Ok(trunc((&args[0]).try_into()?)),
-                    ScalarFunction::Abs => Ok(abs((&args[0]).try_into()?)),
-                    ScalarFunction::Signum => Ok(signum((&args[0]).try_into()?)),
-                    ScalarFunction::OctetLength => {
-                        Ok(octet_length((&args[0]).try_into()?))
-                    }
-                    ScalarFunction::Lower => Ok(lower((&args[0]).try_into()?)),
-                    ScalarFunction::Upper => Ok(upper((&args[0]).try_into()?)),
-                    ScalarFunction::Trim => Ok(trim((&args[0]).try_into()?)),
-                    ScalarFunction::Ltrim => Ok(ltrim((&args[0]).try_into()?)),
-                    ScalarFunction::Rtrim => Ok(rtrim((&args[0]).try_into()?)),
-                    ScalarFunction::DatePart => {
-                        Ok(date_part((&args[0]).try_into()?, (&args[1]).try_into()?))
-                    }
-                    ScalarFunction::DateTrunc => {
-                        Ok(date_trunc((&args[0]).try_into()?, (&args[1]).try_into()?))
-                    }
-                    ScalarFunction::Sha224 => Ok(sha224((&args[0]).try_into()?)),
-                    ScalarFunction::Sha256 => Ok(sha256((&args[0]).try_into()?)),
-                    ScalarFunction::Sha384 => Ok(sha384((&args[0]).try_into()?)),
-                    ScalarFunction::Sha512 => Ok(sha512((&args[0]).try_into()?)),
-                    ScalarFunction::Md5 => Ok(md5((&args[0]).try_into()?)),
-                    ScalarFunction::NullIf => Ok(nullif((&args[0]).try_into()?)),
-                    ScalarFunction::Digest => {
-                        Ok(digest((&args[0]).try_into()?, (&args[1]).try_into()?))
-                    }
-                    ScalarFunction::Ascii => Ok(ascii((&args[0]).try_into()?)),
-                    ScalarFunction::BitLength => Ok((&args[0]).try_into()?),
-                    ScalarFunction::CharacterLength => {
-                        Ok(character_length((&args[0]).try_into()?))
-                    }
-                    ScalarFunction::Chr => Ok(chr((&args[0]).try_into()?)),
-                    ScalarFunction::InitCap => Ok(ascii((&args[0]).try_into()?)),
-                    ScalarFunction::Left => {
-                        Ok(left((&args[0]).try_into()?, (&args[1]).try_into()?))
-                    }
+                    ScalarFunction::Sqrt => Ok(sqrt(arg(0)?)),
+                    ScalarFunction::Sin => Ok(sin(arg(0)?)),
+                    ScalarFunction::Cos => Ok(cos(arg(0)?)),
+                    ScalarFunction::Tan => Ok(tan(arg(0)?)),
+                    ScalarFunction::Atan => Ok(atan(arg(0)?)),
+                    ScalarFunction::Exp => Ok(exp(arg(0)?)),
+                    ScalarFunction::Log2 => Ok(log2(arg(0)?)),
+                    ScalarFunction::Ln => Ok(ln(arg(0)?)),
+                    ScalarFunction::Log10 => Ok(log10(arg(0)?)),
+                    ScalarFunction::Floor => Ok(floor(arg(0)?)),
+                    ScalarFunction::Ceil => Ok(ceil(arg(0)?)),
+                    ScalarFunction::Round => Ok(round(arg(0)?)),
+                    ScalarFunction::Trunc => Ok(trunc(arg(0)?)),
+                    ScalarFunction::Abs => Ok(abs(arg(0)?)),
+                    ScalarFunction::Signum => Ok(signum(arg(0)?)),
+                    ScalarFunction::OctetLength => Ok(octet_length(arg(0)?)),
+                    ScalarFunction::Lower => Ok(lower(arg(0)?)),
+                    ScalarFunction::Upper => Ok(upper(arg(0)?)),
+                    ScalarFunction::Trim => Ok(trim(arg(0)?)),
+                    ScalarFunction::Ltrim => Ok(ltrim(arg(0)?)),
+                    ScalarFunction::Rtrim => Ok(rtrim(arg(0)?)),
+                    ScalarFunction::DatePart => Ok(date_part(arg(0)?, arg(1)?)),
+                    ScalarFunction::DateTrunc => Ok(date_trunc(arg(0)?, arg(1)?)),
+                    ScalarFunction::Sha224 => Ok(sha224(arg(0)?)),
+                    ScalarFunction::Sha256 => Ok(sha256(arg(0)?)),
+                    ScalarFunction::Sha384 => Ok(sha384(arg(0)?)),
+                    ScalarFunction::Sha512 => Ok(sha512(arg(0)?)),
+                    ScalarFunction::Md5 => Ok(md5(arg(0)?)),
+                    ScalarFunction::NullIf => Ok(nullif(arg(0)?)),
+                    ScalarFunction::Digest => Ok(digest(arg(0)?, arg(1)?)),
+                    ScalarFunction::Ascii => Ok(ascii(arg(0)?)),
+                    ScalarFunction::BitLength => Ok(arg(0)?),
+                    ScalarFunction::CharacterLength => Ok(character_length(arg(0)?)),
+                    ScalarFunction::Chr => Ok(chr(arg(0)?)),
+                    ScalarFunction::InitCap => Ok(ascii(arg(0)?)),
+                    ScalarFunction::Left => Ok(left(arg(0)?, arg(1)?)),
                     ScalarFunction::Random => Ok(random()),
-                    ScalarFunction::Repeat => {
-                        Ok(repeat((&args[0]).try_into()?, (&args[1]).try_into()?))
-                    }
-                    ScalarFunction::Replace => Ok(replace(
-                        (&args[0]).try_into()?,
-                        (&args[1]).try_into()?,
-                        (&args[2]).try_into()?,
-                    )),
-                    ScalarFunction::Reverse => Ok(reverse((&args[0]).try_into()?)),
-                    ScalarFunction::Right => {
-                        Ok(right((&args[0]).try_into()?, (&args[1]).try_into()?))
-                    }
+                    ScalarFunction::Repeat => Ok(repeat(arg(0)?, arg(1)?)),
+                    ScalarFunction::Replace => Ok(replace(arg(0)?, arg(1)?, arg(2)?)),
+                    ScalarFunction::Reverse => Ok(reverse(arg(0)?)),
+                    ScalarFunction::Right => Ok(right(arg(0)?, arg(1)?)),
                     ScalarFunction::Concat => Ok(concat_expr(
                         args.to_owned()
                             .iter()
@@ -1210,29 +1446,17 @@ impl TryFrom<&protobuf::LogicalExprNode> for Expr {
                             .map(|e| e.try_into())
                             .collect::<Result<Vec<_>, _>>()?,
                     )),
-                    ScalarFunction::SplitPart => Ok(split_part(
-                        (&args[0]).try_into()?,
-                        (&args[1]).try_into()?,
-                        (&args[2]).try_into()?,
-                    )),
-                    ScalarFunction::StartsWith => {
-                        Ok(starts_with((&args[0]).try_into()?, (&args[1]).try_into()?))
-                    }
-                    ScalarFunction::Strpos => {
-                        Ok(strpos((&args[0]).try_into()?, (&args[1]).try_into()?))
-                    }
-                    ScalarFunction::Substr => {
-                        Ok(substr((&args[0]).try_into()?, (&args[1]).try_into()?))
-                    }
-                    ScalarFunction::ToHex => Ok(to_hex((&args[0]).try_into()?)),
-                    ScalarFunction::ToTimestampMillis => {
-                        Ok(to_timestamp_millis((&args[0]).try_into()?))
-                    }
-                    ScalarFunction::ToTimestampMicros => {
-                        Ok(to_timestamp_micros((&args[0]).try_into()?))
+                    ScalarFunction::SplitPart => {
+                        Ok(split_part(arg(0)?, arg(1)?, arg(2)?))
                     }
+                    ScalarFunction::StartsWith => Ok(starts_with(arg(0)?, arg(1)?)),
+                    ScalarFunction::Strpos => Ok(strpos(arg(0)?, arg(1)?)),
+                    ScalarFunction::Substr => Ok(substr(arg(0)?, arg(1)?)),
+                    ScalarFunction::ToHex => Ok(to_hex(arg(0)?)),
+                    ScalarFunction::ToTimestampMillis => Ok(to_timestamp_millis(arg(0)?)),
+                    ScalarFunction::ToTimestampMicros => Ok(to_timestamp_micros(arg(0)?)),
                     ScalarFunction::ToTimestampSeconds => {
-                        Ok(to_timestamp_seconds((&args[0]).try_into()?))
+                        Ok(to_timestamp_seconds(arg(0)?))
                     }
                     ScalarFunction::Now => Ok(now_expr(
                         args.to_owned()
@@ -1240,11 +1464,7 @@ impl TryFrom<&protobuf::LogicalExprNode> for Expr {
                             .map(|e| e.try_into())
                             .collect::<Result<Vec<_>, _>>()?,
                     )),
-                    ScalarFunction::Translate => Ok(translate(
-                        (&args[0]).try_into()?,
-                        (&args[1]).try_into()?,
-                        (&args[2]).try_into()?,
-                    )),
+                    ScalarFunction::Translate => Ok(translate(arg(0)?, arg(1)?, arg(2)?)),
                     ScalarFunction::Coalesce => Ok(coalesce(
                         args.to_owned()
                             .iter()
@@ -1285,3 +1505,62 @@ impl From<&protobuf::Column> for logical_plan::Column {
         c.clone().into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+
+    use crate::from_proto::try_parse_physical_plan;
+    use crate::protobuf;
+    use crate::protobuf::physical_expr_node::ExprType;
+    use crate::protobuf::physical_plan_node::PhysicalPlanType;
+
+    fn column(name: &str) -> protobuf::PhysicalExprNode {
+        protobuf::PhysicalExprNode {
+            expr_type: Some(ExprType::Column(protobuf::PhysicalColumn {
+                name: name.to_owned(),
+                index: 0,
+            })),
+        }
+    }
+
+    #[test]
+    fn test_error_path() {
+        let schema = Schema::new(vec![Field::new("a", DataType::Int64, false)]);
+        let empty = protobuf::PhysicalPlanNode {
+            physical_plan_type: Some(PhysicalPlanType::EmptyPartitions(
+                protobuf::EmptyPartitionsExecNode {
+                    schema: Some((&schema).try_into().unwrap()),
+                    num_partitions: 1,
+                },
+            )),
+        };
+        let projection = protobuf::PhysicalPlanNode {
+            physical_plan_type: Some(PhysicalPlanType::Projection(Box::new(
+                protobuf::ProjectionExecNode {
+                    input: Some(Box::new(empty)),
+                    expr: vec![column("a"), column("b")],
+                    expr_name: vec!["a".to_owned(), "b".to_owned()],
+                },
+            ))),
+        };
+        let plan = protobuf::PhysicalPlanNode {
+            physical_plan_type: Some(PhysicalPlanType::Merge(Box::new(
+                protobuf::CoalescePartitionsExecNode {
+                    input: Some(Box::new(projection)),
+                },
+            ))),
+        };
+
+        let err = try_parse_physical_plan(&plan).unwrap_err();
+        assert_eq!(err.path(), Some("root.input.expr[1]"));
+
+        let unresolved = protobuf::PhysicalPlanNode {
+            physical_plan_type: Some(PhysicalPlanType::Unresolved(
+                protobuf::UnresolvedShuffleExecNode::default(),
+            )),
+        };
+        let err = try_parse_physical_plan(&unresolved).unwrap_err();
+        assert_eq!(err.path(), Some("root"));
+    }
+}
@@ -35,6 +35,9 @@ pub mod protobuf {
 
 pub mod error;
 pub mod from_proto;
+pub mod parallelize;
+pub mod to_proto;
+pub mod validate;
 
 pub(crate) fn proto_error<S: Into<String>>(message: S) -> PlanSerDeError {
     PlanSerDeError::General(message.into())
@@ -97,6 +100,30 @@ pub(crate) fn from_proto_binary_op(op: &str) -> Result<Operator, PlanSerDeError>
     }
 }
 
+pub(crate) fn to_proto_binary_op(op: &Operator) -> Result<String, PlanSerDeError> {
+    match op {
+        Operator::And
+        | Operator::Or
+        | Operator::Eq
+        | Operator::NotEq
+        | Operator::LtEq
+        | Operator::Lt
+        | Operator::Gt
+        | Operator::GtEq
+        | Operator::Plus
+        | Operator::Minus
+        | Operator::Multiply
+        | Operator::Divide
+        | Operator::Modulo
+        | Operator::Like
+        | Operator::NotLike => Ok(format!("{:?}", op)),
+        other => Err(PlanSerDeError::NotImplemented(format!(
+            "Unsupported binary operator '{:?}'",
+            other
+        ))),
+    }
+}
+
 impl From<protobuf::AggregateFunction> for AggregateFunction {
     fn from(agg_fun: protobuf::AggregateFunction) -> AggregateFunction {
         match agg_fun {
@@ -711,30 +738,36 @@ impl TryFrom<&datafusion::scalar::ScalarValue> for protobuf::ScalarValue {
     }
 }
 
-impl From<&Field> for protobuf::Field {
-    fn from(field: &Field) -> Self {
-        protobuf::Field {
+impl TryFrom<&Field> for protobuf::Field {
+    type Error = PlanSerDeError;
+
+    fn try_from(field: &Field) -> Result<Self, Self::Error> {
+        Ok(protobuf::Field {
             name: field.name().to_owned(),
-            arrow_type: Some(Box::new(field.data_type().into())),
+            arrow_type: Some(Box::new(field.data_type().try_into()?)),
             nullable: field.is_nullable(),
             children: Vec::new(),
-        }
+        })
     }
 }
 
-impl From<&DataType> for protobuf::ArrowType {
-    fn from(val: &DataType) -> protobuf::ArrowType {
-        protobuf::ArrowType {
-            arrow_type_enum: Some(val.into()),
-        }
+impl TryFrom<&DataType> for protobuf::ArrowType {
+    type Error = PlanSerDeError;
+
+    fn try_from(val: &DataType) -> Result<Self, Self::Error> {
+        Ok(protobuf::ArrowType {
+            arrow_type_enum: Some(val.try_into()?),
+        })
     }
 }
 
-impl From<&DataType> for protobuf::arrow_type::ArrowTypeEnum {
-    fn from(val: &DataType) -> protobuf::arrow_type::ArrowTypeEnum {
+impl TryFrom<&DataType> for protobuf::arrow_type::ArrowTypeEnum {
+    type Error = PlanSerDeError;
+
+    fn try_from(val: &DataType) -> Result<Self, Self::Error> {
         use protobuf::arrow_type::ArrowTypeEnum;
         use protobuf::EmptyMessage;
-        match val {
+        Ok(match val {
             DataType::Null => ArrowTypeEnum::None(EmptyMessage {}),
             DataType::Boolean => ArrowTypeEnum::Bool(EmptyMessage {}),
             DataType::Int8 => ArrowTypeEnum::Int8(EmptyMessage {}),
@@ -774,24 +807,24 @@ impl From<&DataType> for protobuf::arrow_type::ArrowTypeEnum {
             DataType::Utf8 => ArrowTypeEnum::Utf8(EmptyMessage {}),
             DataType::LargeUtf8 => ArrowTypeEnum::LargeUtf8(EmptyMessage {}),
             DataType::List(item_type) => ArrowTypeEnum::List(Box::new(protobuf::List {
-                field_type: Some(Box::new(item_type.as_ref().into())),
+                field_type: Some(Box::new(item_type.as_ref().try_into()?)),
             })),
             DataType::FixedSizeList(item_type, size) => {
                 ArrowTypeEnum::FixedSizeList(Box::new(protobuf::FixedSizeList {
-                    field_type: Some(Box::new(item_type.as_ref().into())),
+                    field_type: Some(Box::new(item_type.as_ref().try_into()?)),
                     list_size: *size,
                 }))
             }
             DataType::LargeList(item_type) => {
                 ArrowTypeEnum::LargeList(Box::new(protobuf::List {
-                    field_type: Some(Box::new(item_type.as_ref().into())),
+                    field_type: Some(Box::new(item_type.as_ref().try_into()?)),
                 }))
             }
             DataType::Struct(struct_fields) => ArrowTypeEnum::Struct(protobuf::Struct {
                 sub_field_types: struct_fields
                     .iter()
-                    .map(|field| field.into())
-                    .collect::<Vec<_>>(),
+                    .map(|field| field.try_into())
+                    .collect::<Result<Vec<_>, _>>()?,
             }),
             DataType::Union(union_types, union_mode) => {
                 let union_mode = match union_mode {
@@ -808,8 +841,8 @@ impl From<&DataType> for protobuf::arrow_type::ArrowTypeEnum {
             }
             DataType::Dictionary(key_type, value_type) => {
                 ArrowTypeEnum::Dictionary(Box::new(protobuf::Dictionary {
-                    key: Some(Box::new(key_type.as_ref().into())),
-                    value: Some(Box::new(value_type.as_ref().into())),
+                    key: Some(Box::new(key_type.as_ref().try_into()?)),
+                    value: Some(Box::new(value_type.as_ref().try_into()?)),
                 }))
             }
             DataType::Decimal(whole, fractional) => {
@@ -819,9 +852,11 @@ impl From<&DataType> for protobuf::arrow_type::ArrowTypeEnum {
                 })
             }
             DataType::Map(_, _) => {
-                unimplemented!("Ballista does not yet support Map data type")
+                return Err(PlanSerDeError::NotImplemented(
+                    "Map data type is not supported".to_owned(),
+                ));
             }
-        }
+        })
     }
 }
 
@@ -866,29 +901,25 @@ fn create_proto_scalar<I, T: FnOnce(&I) -> protobuf::scalar_value::Value>(
     }
 }
 
-#[allow(clippy::from_over_into)]
-impl Into<protobuf::Schema> for &Schema {
-    fn into(self) -> protobuf::Schema {
-        protobuf::Schema {
-            columns: self
+impl TryFrom<&Schema> for protobuf::Schema {
+    type Error = PlanSerDeError;
+
+    fn try_from(schema: &Schema) -> Result<Self, Self::Error> {
+        Ok(protobuf::Schema {
+            columns: schema
                 .fields()
                 .iter()
-                .map(protobuf::Field::from)
-                .collect::<Vec<_>>(),
-        }
+                .map(protobuf::Field::try_from)
+                .collect::<Result<Vec<_>, _>>()?,
+        })
     }
 }
 
-#[allow(clippy::from_over_into)]
-impl Into<protobuf::Schema> for SchemaRef {
-    fn into(self) -> protobuf::Schema {
-        protobuf::Schema {
-            columns: self
-                .fields()
-                .iter()
-                .map(protobuf::Field::from)
-                .collect::<Vec<_>>(),
-        }
+impl TryFrom<SchemaRef> for protobuf::Schema {
+    type Error = PlanSerDeError;
+
+    fn try_from(schema: SchemaRef) -> Result<Self, Self::Error> {
+        schema.as_ref().try_into()
     }
 }
 
@@ -1058,7 +1089,10 @@ impl TryInto<datafusion::scalar::ScalarValue> for &protobuf::ScalarValue {
                 ScalarValue::TimestampNanosecond(Some(*v), None)
             }
             protobuf::scalar_value::Value::DecimalValue(v) => {
-                let decimal = v.decimal.as_ref().unwrap();
+                let decimal = v
+                    .decimal
+                    .as_ref()
+                    .ok_or_else(|| PlanSerDeError::required("decimal"))?;
                 ScalarValue::Decimal128(
                     Some(v.long_value as i128),
                     decimal.whole as usize,
@@ -1218,7 +1252,10 @@ impl TryInto<datafusion::scalar::ScalarValue> for &protobuf::scalar_value::Value
                     .try_into()?
             }
             protobuf::scalar_value::Value::DecimalValue(v) => {
-                let decimal = v.decimal.as_ref().unwrap();
+                let decimal = v
+                    .decimal
+                    .as_ref()
+                    .ok_or_else(|| PlanSerDeError::required("decimal"))?;
                 ScalarValue::Decimal128(
                     Some(v.long_value as i128),
                     decimal.whole as usize,
@@ -0,0 +1,417 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Intra-task parallelism of plans scanning files.
+//!
+//! A task executes one partition of the plan, that is one file group of its
+//! scan. For a linear plan, the file group is split into sub-partitions which
+//! are executed in parallel by the partition-wise operators above the scan, and
+//! merged before the first operator that needs the whole partition.
+
+use crate::protobuf;
+use crate::protobuf::physical_plan_node::PhysicalPlanType;
+use crate::protobuf::physical_repartition::RepartitionType;
+
+/// Rewrites the plan of a task executing `partition` into a plan executing
+/// the partition with up to `parallelism` sub-partitions. The rewritten plan
+/// has a single output partition `0`. Returns `None` if the plan cannot be
+/// split.
+pub fn parallelize_task_plan(
+    plan: &protobuf::PhysicalPlanNode,
+    partition: usize,
+    parallelism: usize,
+) -> Option<protobuf::PhysicalPlanNode> {
+    if parallelism < 2 {
+        return None;
+    }
+    let mut plan = plan.clone();
+    match split_plan_node(&mut plan, partition, parallelism) {
+        Split::PartitionWise => Some(merge(plan)),
+        Split::Merged => Some(plan),
+        Split::Unsupported => None,
+    }
+}
+
+enum Split {
+    /// the node has all sub-partitions as output partitions
+    PartitionWise,
+    /// the sub-partitions are merged below or at the node
+    Merged,
+    Unsupported,
+}
+
+fn split_plan_node(
+    plan: &mut protobuf::PhysicalPlanNode,
+    partition: usize,
+    parallelism: usize,
+) -> Split {
+    let (input, partition_wise) = match &mut plan.physical_plan_type {
+        Some(PhysicalPlanType::ParquetScan(node)) => {
+            return split_scan(node.base_conf.as_mut(), partition, parallelism, true);
+        }
+        Some(PhysicalPlanType::CsvScan(node)) => {
+            return split_scan(node.base_conf.as_mut(), partition, parallelism, false);
+        }
+        Some(PhysicalPlanType::AvroScan(node)) => {
+            return split_scan(node.base_conf.as_mut(), partition, parallelism, false);
+        }
+
+        // operators computing each sub-partition independently
+        Some(PhysicalPlanType::Projection(node)) => (&mut node.input, true),
+        Some(PhysicalPlanType::Filter(node)) => (&mut node.input, true),
+        Some(PhysicalPlanType::CoalesceBatches(node)) => (&mut node.input, true),
+        Some(PhysicalPlanType::RenameColumns(node)) => (&mut node.input, true),
+        Some(PhysicalPlanType::HashAggregate(node))
+            if node.mode == protobuf::AggregateMode::Partial as i32 =>
+        {
+            (&mut node.input, true)
+        }
+
+        // operators which need the whole partition, but not in the original order
+        Some(PhysicalPlanType::HashAggregate(node)) => (&mut node.input, false),
+        Some(PhysicalPlanType::Sort(node)) => (&mut node.input, false),
+        Some(PhysicalPlanType::GlobalLimit(node)) => (&mut node.input, false),
+        Some(PhysicalPlanType::LocalLimit(node)) => (&mut node.input, false),
+        Some(PhysicalPlanType::ShuffleWriter(node)) if !is_round_robin(node) => {
+            (&mut node.input, false)
+        }
+        _ => return Split::Unsupported,
+    };
+    let input = match input {
+        Some(input) => input,
+        None => return Split::Unsupported,
+    };
+    match split_plan_node(input, partition, parallelism) {
+        Split::PartitionWise if partition_wise => Split::PartitionWise,
+        Split::PartitionWise => {
+            let split_input = std::mem::take(input.as_mut());
+            **input = merge(split_input);
+            Split::Merged
+        }
+        split => split,
+    }
+}
+
+/// round robin partitioning depends on the order of rows, which is not
+/// deterministic after merging sub-partitions
+fn is_round_robin(shuffle_writer: &protobuf::ShuffleWriterExecNode) -> bool {
+    matches!(
+        shuffle_writer
+            .output_partitioning
+            .as_ref()
+            .and_then(|partitioning| partitioning.repartition_type.as_ref()),
+        Some(RepartitionType::RoundRobinRepartition(_))
+    )
+}
+
+fn merge(plan: protobuf::PhysicalPlanNode) -> protobuf::PhysicalPlanNode {
+    protobuf::PhysicalPlanNode {
+        physical_plan_type: Some(PhysicalPlanType::Merge(Box::new(
+            protobuf::CoalescePartitionsExecNode {
+                input: Some(Box::new(plan)),
+            },
+        ))),
+    }
+}
+
+/// Replaces the file groups of the scan with sub-groups of similar sizes split
+/// from the file group of `partition`. Ranges of splittable files are split
+/// if there are not enough files.
+fn split_scan(
+    conf: Option<&mut protobuf::FileScanExecConf>,
+    partition: usize,
+    parallelism: usize,
+    splittable: bool,
+) -> Split {
+    let conf = match conf {
+        Some(conf) if partition < conf.file_groups.len() => conf,
+        _ => return Split::Unsupported,
+    };
+    let ranges = |file: &protobuf::PartitionedFile| match &file.range {
+        Some(range) => (range.start, range.end),
+        None => (0, file.size as i64),
+    };
+    let files = &conf.file_groups[partition].files;
+    let total_size: i64 = files
+        .iter()
+        .map(ranges)
+        .map(|(start, end)| end - start)
+        .sum();
+    let split_size = (total_size / parallelism as i64).max(1);
+
+    let mut splits = vec![];
+    for file in files {
+        let (start, end) = ranges(file);
+        let num_splits = if splittable {
+            ((end - start + split_size - 1) / split_size).max(1)
+        } else {
+            1
+        };
+        if num_splits == 1 {
+            splits.push((file.clone(), end - start));
+            continue;
+        }
+        let len = (end - start + num_splits - 1) / num_splits;
+        for split_start in (start..end).step_by(len as usize) {
+            let split_end = (split_start + len).min(end);
+            let mut split = file.clone();
+            split.range = Some(protobuf::FileRange {
+                start: split_start,
+                end: split_end,
+            });
+            splits.push((split, split_end - split_start));
+        }
+    }
+    if splits.len() < 2 {
+        return Split::Unsupported;
+    }
+
+    // assign the largest splits first to the smallest group
+    splits.sort_by_key(|(_, len)| -len);
+    let mut groups = vec![(0, protobuf::FileGroup::default()); parallelism];
+    for (split, len) in splits {
+        let group = groups.iter_mut().min_by_key(|(size, _)| *size).unwrap();
+        group.0 += len;
+        group.1.files.push(split);
+    }
+    conf.file_groups = groups
+        .into_iter()
+        .map(|(_, group)| group)
+        .filter(|group| !group.files.is_empty())
+        .collect();
+    Split::PartitionWise
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+    use std::sync::Arc;
+
+    use datafusion::arrow::array::{Array, Int32Array};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::datafusion_data_access::object_store::local::LocalFileSystem;
+    use datafusion::datafusion_data_access::{FileMeta, SizedFile};
+    use datafusion::datasource::listing::PartitionedFile;
+    use datafusion::parquet::arrow::ArrowWriter;
+    use datafusion::parquet::file::properties::WriterProperties;
+    use datafusion::physical_plan::common::collect;
+    use datafusion::physical_plan::file_format::{FileScanConfig, ParquetExec};
+    use datafusion::physical_plan::{ExecutionPlan, Statistics};
+    use datafusion::prelude::SessionContext;
+
+    use crate::parallelize::parallelize_task_plan;
+    use crate::protobuf;
+    use crate::protobuf::physical_plan_node::PhysicalPlanType;
+
+    fn parquet_scan(file_sizes: &[u64]) -> protobuf::PhysicalPlanNode {
+        let files = file_sizes
+            .iter()
+            .enumerate()
+            .map(|(i, &size)| protobuf::PartitionedFile {
+                path: format!("/data/part-{}.parquet", i),
+                size,
+                ..Default::default()
+            })
+            .collect();
+        let other_group = protobuf::FileGroup {
+            files: vec![protobuf::PartitionedFile {
+                path: "/data/other.parquet".to_owned(),
+                size: 1000,
+                ..Default::default()
+            }],
+        };
+        protobuf::PhysicalPlanNode {
+            physical_plan_type: Some(PhysicalPlanType::ParquetScan(
+                protobuf::ParquetScanExecNode {
+                    base_conf: Some(protobuf::FileScanExecConf {
+                        file_groups: vec![other_group, protobuf::FileGroup { files }],
+                        ..Default::default()
+                    }),
+                    pruning_predicate: None,
+                },
+            )),
+        }
+    }
+
+    fn with_input(
+        plan_type: fn(Box<protobuf::PhysicalPlanNode>) -> PhysicalPlanType,
+        input: protobuf::PhysicalPlanNode,
+    ) -> protobuf::PhysicalPlanNode {
+        protobuf::PhysicalPlanNode {
+            physical_plan_type: Some(plan_type(Box::new(input))),
+        }
+    }
+
+    fn filter(input: Box<protobuf::PhysicalPlanNode>) -> PhysicalPlanType {
+        PhysicalPlanType::Filter(Box::new(protobuf::FilterExecNode {
+            input: Some(input),
+            ..Default::default()
+        }))
+    }
+
+    fn sort(input: Box<protobuf::PhysicalPlanNode>) -> PhysicalPlanType {
+        PhysicalPlanType::Sort(Box::new(protobuf::SortExecNode {
+            input: Some(input),
+            ..Default::default()
+        }))
+    }
+
+    fn window(input: Box<protobuf::PhysicalPlanNode>) -> PhysicalPlanType {
+        PhysicalPlanType::Window(Box::new(protobuf::WindowAggExecNode {
+            input: Some(input),
+            ..Default::default()
+        }))
+    }
+
+    fn file_groups(plan: &protobuf::PhysicalPlanNode) -> Vec<Vec<(String, i64)>> {
+        let conf = match &plan.physical_plan_type {
+            Some(PhysicalPlanType::ParquetScan(node)) => node.base_conf.as_ref().unwrap(),
+            _ => panic!("not a parquet scan"),
+        };
+        conf.file_groups
+            .iter()
+            .map(|group| {
+                group
+                    .files
+                    .iter()
+                    .map(|file| {
+                        let start = file.range.as_ref().map(|r| r.start).unwrap_or(0);
+                        (file.path.clone(), start)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parallelize_task_plan() {
+        let plan = with_input(sort, with_input(filter, parquet_scan(&[300, 100, 200])));
+        let parallelized = parallelize_task_plan(&plan, 1, 2).unwrap();
+
+        // sort(merge(filter(scan)))
+        let merge = match parallelized.physical_plan_type {
+            Some(PhysicalPlanType::Sort(sort)) => *sort.input.unwrap(),
+            _ => panic!("expect sort"),
+        };
+        let filter = match merge.physical_plan_type {
+            Some(PhysicalPlanType::Merge(merge)) => *merge.input.unwrap(),
+            _ => panic!("expect merge"),
+        };
+        let scan = match filter.physical_plan_type {
+            Some(PhysicalPlanType::Filter(filter)) => *filter.input.unwrap(),
+            _ => panic!("expect filter"),
+        };
+        assert_eq!(
+            file_groups(&scan),
+            vec![
+                vec![("/data/part-0.parquet".to_owned(), 0)],
+                vec![
+                    ("/data/part-2.parquet".to_owned(), 0),
+                    ("/data/part-1.parquet".to_owned(), 0),
+                ],
+            ]
+        );
+
+        // a single file is split into ranges
+        let plan = with_input(filter, parquet_scan(&[300]));
+        let parallelized = parallelize_task_plan(&plan, 1, 3).unwrap();
+        let scan = match parallelized.physical_plan_type {
+            Some(PhysicalPlanType::Merge(merge)) => {
+                match merge.input.unwrap().physical_plan_type {
+                    Some(PhysicalPlanType::Filter(filter)) => *filter.input.unwrap(),
+                    _ => panic!("expect filter"),
+                }
+            }
+            _ => panic!("expect merge"),
+        };
+        let starts = file_groups(&scan)
+            .into_iter()
+            .map(|group| group[0].1)
+            .collect::<Vec<_>>();
+        assert_eq!(starts, vec![0, 100, 200]);
+
+        // window relies on the order of its input
+        let plan = with_input(window, parquet_scan(&[300, 100, 200]));
+        assert!(parallelize_task_plan(&plan, 1, 2).is_none());
+    }
+
+    #[test]
+    fn test_execute_split_parquet_scan() {
+        // a single file of 10 row groups
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let props = WriterProperties::builder()
+            .set_max_row_group_size(100)
+            .build();
+        let mut writer = ArrowWriter::try_new(
+            file.as_file().try_clone().unwrap(),
+            schema.clone(),
+            Some(props),
+        )
+        .unwrap();
+        for i in 0..10 {
+            let values = Int32Array::from_iter_values(i * 100..(i + 1) * 100);
+            let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(values)]);
+            writer.write(&batch.unwrap()).unwrap();
+        }
+        writer.close().unwrap();
+
+        let scan: Arc<dyn ExecutionPlan> = Arc::new(ParquetExec::new(
+            FileScanConfig {
+                object_store: Arc::new(LocalFileSystem),
+                file_schema: schema,
+                file_groups: vec![vec![PartitionedFile {
+                    file_meta: FileMeta {
+                        sized_file: SizedFile {
+                            path: file.path().to_str().unwrap().to_owned(),
+                            size: file.as_file().metadata().unwrap().len(),
+                        },
+                        last_modified: None,
+                    },
+                    partition_values: vec![],
+                    range: None,
+                }]],
+                statistics: Statistics::default(),
+                projection: None,
+                limit: None,
+                table_partition_cols: vec![],
+            },
+            None,
+        ));
+        let plan = protobuf::PhysicalPlanNode::try_from(&scan).unwrap();
+        let parallelized = parallelize_task_plan(&plan, 0, 3).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let sum_values = |plan: &protobuf::PhysicalPlanNode| {
+            let plan: Arc<dyn ExecutionPlan> = plan.try_into().unwrap();
+            let task_ctx = SessionContext::new().task_ctx();
+            let batches = runtime
+                .block_on(async { collect(plan.execute(0, task_ctx)?).await })
+                .unwrap();
+            batches.iter().fold((0, 0), |(num_rows, sum), batch| {
+                let values = batch.column(0).as_any().downcast_ref::<Int32Array>();
+                let batch_sum = values.unwrap().values().iter().sum::<i32>();
+                (num_rows + batch.num_rows(), sum + batch_sum)
+            })
+        };
+
+        // every row group is read by exactly one of the split ranges
+        assert_eq!(sum_values(&plan), (1000, 499500));
+        assert_eq!(sum_values(&parallelized), (1000, 499500));
+    }
+}
@@ -0,0 +1,1312 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Serde code to convert Arrow schemas and DataFusion physical plans and results
+//! to Blaze protobuf format.
+//!
+//! Converting a plan and converting the resulting protobuf message back with
+//! [`crate::from_proto`] yields an equivalent plan. Plans containing nodes or
+//! expressions which cannot be represented in `plan.proto` are rejected with
+//! [`PlanSerDeError::NotImplemented`].
+
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use datafusion::datasource::listing::{FileRange, PartitionedFile};
+use datafusion::logical_plan::{self, Expr};
+use datafusion::physical_plan::aggregates::{AggregateExec, AggregateMode};
+use datafusion::physical_plan::coalesce_partitions::CoalescePartitionsExec;
+use datafusion::physical_plan::file_format::{
+    AvroExec, CsvExec, FileScanConfig, ParquetExec,
+};
+use datafusion::physical_plan::hash_join::PartitionMode;
+use datafusion::physical_plan::sorts::sort::SortExec;
+use datafusion::physical_plan::union::UnionExec;
+use datafusion::physical_plan::windows::WindowAggExec;
+use datafusion::physical_plan::{
+    coalesce_batches::CoalesceBatchesExec,
+    cross_join::CrossJoinExec,
+    empty::EmptyExec,
+    expressions::{
+        ApproxDistinct, ArrayAgg, Avg, BinaryExpr, CaseExpr, CastExpr, Column, Count,
+        InListExpr, IsNotNullExpr, IsNullExpr, Literal, Max, Min, NegativeExpr, NotExpr,
+        PhysicalSortExpr, Stddev, StddevPop, Sum, TryCastExpr, Variance, VariancePop,
+    },
+    filter::FilterExec,
+    functions::ScalarFunctionExpr,
+    hash_join::HashJoinExec,
+    limit::{GlobalLimitExec, LocalLimitExec},
+    projection::ProjectionExec,
+    repartition::RepartitionExec,
+    sort_merge_join::SortMergeJoinExec,
+    Partitioning,
+};
+use datafusion::physical_plan::{
+    AggregateExpr, ColumnStatistics, ExecutionPlan, PhysicalExpr, Statistics, WindowExpr,
+};
+use datafusion::scalar::ScalarValue;
+
+use datafusion_ext::empty_partitions_exec::EmptyPartitionsExec;
+use datafusion_ext::merge_exec::MergeExec;
+use datafusion_ext::native_error::{NativeError, NativeErrorKind};
+use datafusion_ext::rename_columns_exec::RenameColumnsExec;
+use datafusion_ext::shuffle_checksum::ShuffleChecksumAlgorithm;
+use datafusion_ext::shuffle_codec::ShuffleCompressionCodec;
+use datafusion_ext::shuffle_reader_exec::ShuffleReaderExec;
+use datafusion_ext::shuffle_stats::ShuffleWritePartitionStats;
+use datafusion_ext::shuffle_writer_exec::{ShuffleRepartitioning, ShuffleWriterExec};
+
+use crate::error::PlanSerDeError;
+use crate::from_proto::ParsedWindowExpr;
+use crate::protobuf;
+use crate::protobuf::physical_expr_node::ExprType;
+use crate::protobuf::physical_plan_node::PhysicalPlanType;
+use crate::protobuf::physical_repartition::RepartitionType;
+use crate::protobuf::repartition_exec_node::PartitionMethod;
+use crate::to_proto_binary_op;
+
+impl TryFrom<&ColumnStatistics> for protobuf::ColumnStats {
+    type Error = PlanSerDeError;
+
+    fn try_from(stats: &ColumnStatistics) -> Result<Self, Self::Error> {
+        Ok(protobuf::ColumnStats {
+            min_value: stats.min_value.as_ref().map(|v| v.try_into()).transpose()?,
+            max_value: stats.max_value.as_ref().map(|v| v.try_into()).transpose()?,
+            null_count: stats.null_count.unwrap_or(0) as u32,
+            distinct_count: stats.distinct_count.unwrap_or(0) as u32,
+        })
+    }
+}
+
+impl TryFrom<&ShuffleWritePartitionStats> for protobuf::ShuffleWritePartition {
+    type Error = PlanSerDeError;
+
+    fn try_from(stats: &ShuffleWritePartitionStats) -> Result<Self, Self::Error> {
+        let column_stats = match &stats.column_stats {
+            Some(column_stats) => column_stats
+                .iter()
+                .map(|s| s.try_into())
+                .collect::<Result<Vec<_>, _>>()?,
+            None => vec![],
+        };
+        Ok(protobuf::ShuffleWritePartition {
+            partition_id: stats.partition_id as u64,
+            path: String::new(),
+            num_batches: stats.num_batches as u64,
+            num_rows: stats.num_rows as u64,
+            num_bytes: stats.num_bytes as u64,
+            column_stats,
+        })
+    }
+}
+
+impl TryFrom<&ShuffleWriterExec> for protobuf::ShuffleWriteResult {
+    type Error = PlanSerDeError;
+
+    fn try_from(exec: &ShuffleWriterExec) -> Result<Self, Self::Error> {
+        let partition_stats = exec.partition_stats().ok_or_else(|| {
+            PlanSerDeError::Internal(
+                "ShuffleWriterExec has no partition statistics before finishing"
+                    .to_owned(),
+            )
+        })?;
+        let partitions = partition_stats
+            .iter()
+            .map(|stats| stats.try_into())
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(protobuf::ShuffleWriteResult { partitions })
+    }
+}
+
+impl From<&NativeError> for protobuf::NativeError {
+    fn from(err: &NativeError) -> Self {
+        let kind = match err.kind {
+            NativeErrorKind::Execution => protobuf::NativeErrorKind::Execution,
+            NativeErrorKind::Io => protobuf::NativeErrorKind::Io,
+            NativeErrorKind::ResourcesExhausted => {
+                protobuf::NativeErrorKind::ResourcesExhausted
+            }
+            NativeErrorKind::Plan => protobuf::NativeErrorKind::Plan,
+            NativeErrorKind::ArithmeticOverflow => {
+                protobuf::NativeErrorKind::ArithmeticOverflow
+            }
+            NativeErrorKind::Cancelled => protobuf::NativeErrorKind::Cancelled,
+            NativeErrorKind::ShuffleCorrupted => {
+                protobuf::NativeErrorKind::ShuffleCorrupted
+            }
+        };
+        let (corrupted_shuffle_id, corrupted_block_index) = err
+            .corrupted_block()
+            .map(|(native_shuffle_id, block_id)| {
+                (native_shuffle_id.to_owned(), block_id as u64)
+            })
+            .unwrap_or_default();
+        protobuf::NativeError {
+            kind: kind as i32,
+            operator: err.operator.clone().unwrap_or_default(),
+            causes: err.causes(),
+            corrupted_shuffle_id,
+            corrupted_block_index,
+        }
+    }
+}
+
+impl TryFrom<&Arc<dyn ExecutionPlan>> for protobuf::PhysicalPlanNode {
+    type Error = PlanSerDeError;
+
+    fn try_from(plan: &Arc<dyn ExecutionPlan>) -> Result<Self, Self::Error> {
+        let any = plan.as_any();
+
+        let physical_plan_type = if let Some(exec) = any.downcast_ref::<ProjectionExec>()
+        {
+            PhysicalPlanType::Projection(Box::new(protobuf::ProjectionExecNode {
+                input: convert_box_plan(exec.input())?,
+                expr: exec
+                    .expr()
+                    .iter()
+                    .map(|(expr, _)| expr.try_into())
+                    .collect::<Result<Vec<_>, _>>()?,
+                expr_name: exec.expr().iter().map(|(_, name)| name.clone()).collect(),
+            }))
+        } else if let Some(exec) = any.downcast_ref::<FilterExec>() {
+            PhysicalPlanType::Filter(Box::new(protobuf::FilterExecNode {
+                input: convert_box_plan(exec.input())?,
+                expr: Some(exec.predicate().try_into()?),
+            }))
+        } else if let Some(exec) = any.downcast_ref::<CsvExec>() {
+            PhysicalPlanType::CsvScan(protobuf::CsvScanExecNode {
+                base_conf: Some(exec.base_config().try_into()?),
+                has_header: exec.has_header(),
+                delimiter: char::from(exec.delimiter()).to_string(),
+            })
+        } else if let Some(exec) = any.downcast_ref::<ParquetExec>() {
+            PhysicalPlanType::ParquetScan(protobuf::ParquetScanExecNode {
+                base_conf: Some(exec.base_config().try_into()?),
+                pruning_predicate: exec
+                    .pruning_predicate()
+                    .map(|predicate| predicate.logical_expr().try_into())
+                    .transpose()?,
+            })
+        } else if let Some(exec) = any.downcast_ref::<AvroExec>() {
+            PhysicalPlanType::AvroScan(protobuf::AvroScanExecNode {
+                base_conf: Some(exec.base_config().try_into()?),
+            })
+        } else if let Some(exec) = any.downcast_ref::<CoalesceBatchesExec>() {
+            PhysicalPlanType::CoalesceBatches(Box::new(
+                protobuf::CoalesceBatchesExecNode {
+                    input: convert_box_plan(exec.input())?,
+                    target_batch_size: exec.target_batch_size() as u32,
+                },
+            ))
+        } else if let Some(exec) = any.downcast_ref::<CoalescePartitionsExec>() {
+            PhysicalPlanType::Merge(Box::new(protobuf::CoalescePartitionsExecNode {
+                input: convert_box_plan(exec.input())?,
+            }))
+        } else if let Some(exec) = any.downcast_ref::<MergeExec>() {
+            PhysicalPlanType::Merge(Box::new(protobuf::CoalescePartitionsExecNode {
+                input: convert_box_plan(exec.input())?,
+            }))
+        } else if let Some(exec) = any.downcast_ref::<RepartitionExec>() {
+            let partition_method = match exec.partitioning() {
+                Partitioning::RoundRobinBatch(n) => {
+                    PartitionMethod::RoundRobin(*n as u64)
+                }
+                Partitioning::Hash(exprs, n) => {
+                    PartitionMethod::Hash(convert_hash_partitioning(exprs, *n)?)
+                }
+                Partitioning::UnknownPartitioning(n) => {
+                    PartitionMethod::Unknown(*n as u64)
+                }
+            };
+            PhysicalPlanType::Repartition(Box::new(protobuf::RepartitionExecNode {
+                input: convert_box_plan(exec.input())?,
+                partition_method: Some(partition_method),
+            }))
+        } else if let Some(exec) = any.downcast_ref::<GlobalLimitExec>() {
+            PhysicalPlanType::GlobalLimit(Box::new(protobuf::GlobalLimitExecNode {
+                input: convert_box_plan(exec.input())?,
+                limit: exec.limit() as u32,
+            }))
+        } else if let Some(exec) = any.downcast_ref::<LocalLimitExec>() {
+            PhysicalPlanType::LocalLimit(Box::new(protobuf::LocalLimitExecNode {
+                input: convert_box_plan(exec.input())?,
+                limit: exec.limit() as u32,
+            }))
+        } else if let Some(exec) = any.downcast_ref::<WindowAggExec>() {
+            PhysicalPlanType::Window(Box::new(protobuf::WindowAggExecNode {
+                input: convert_box_plan(exec.input())?,
+                window_expr: exec
+                    .window_expr()
+                    .iter()
+                    .map(|expr| expr.try_into())
+                    .collect::<Result<Vec<_>, _>>()?,
+                window_expr_name: exec
+                    .window_expr()
+                    .iter()
+                    .map(|expr| expr.name().to_owned())
+                    .collect(),
+                input_schema: Some(exec.input_schema().try_into()?),
+            }))
+        } else if let Some(exec) = any.downcast_ref::<AggregateExec>() {
+            let mode = match exec.mode() {
+                AggregateMode::Partial => protobuf::AggregateMode::Partial,
+                AggregateMode::Final => protobuf::AggregateMode::Final,
+                AggregateMode::FinalPartitioned => {
+                    protobuf::AggregateMode::FinalPartitioned
+                }
+            };
+            let aggr_expr_name = exec
+                .aggr_expr()
+                .iter()
+                .map(|expr| Ok(expr.field()?.name().clone()))
+                .collect::<Result<Vec<_>, PlanSerDeError>>()?;
+            PhysicalPlanType::HashAggregate(Box::new(protobuf::HashAggregateExecNode {
+                group_expr: exec
+                    .group_expr()
+                    .iter()
+                    .map(|(expr, _)| expr.try_into())
+                    .collect::<Result<Vec<_>, _>>()?,
+                aggr_expr: exec
+                    .aggr_expr()
+                    .iter()
+                    .map(|expr| expr.try_into())
+                    .collect::<Result<Vec<_>, _>>()?,
+                mode: mode.into(),
+                input: convert_box_plan(exec.input())?,
+                group_expr_name: exec
+                    .group_expr()
+                    .iter()
+                    .map(|(_, name)| name.clone())
+                    .collect(),
+                aggr_expr_name,
+                input_schema: Some(exec.input_schema().try_into()?),
+            }))
+        } else if let Some(exec) = any.downcast_ref::<HashJoinExec>() {
+            let partition_mode = match exec.partition_mode() {
+                PartitionMode::CollectLeft => protobuf::PartitionMode::CollectLeft,
+                PartitionMode::Partitioned => protobuf::PartitionMode::Partitioned,
+            };
+            PhysicalPlanType::HashJoin(Box::new(protobuf::HashJoinExecNode {
+                left: convert_box_plan(exec.left())?,
+                right: convert_box_plan(exec.right())?,
+                on: convert_join_on(exec.on()),
+                join_type: protobuf::JoinType::from(*exec.join_type()).into(),
+                partition_mode: partition_mode.into(),
+                null_equals_null: *exec.null_equals_null(),
+            }))
+        } else if let Some(exec) = any.downcast_ref::<SortMergeJoinExec>() {
+            PhysicalPlanType::SortMergeJoin(Box::new(protobuf::SortMergeJoinExecNode {
+                left: convert_box_plan(&exec.left)?,
+                right: convert_box_plan(&exec.right)?,
+                on: convert_join_on(&exec.on),
+                sort_options: exec
+                    .sort_options
+                    .iter()
+                    .map(|options| protobuf::SortOptions {
+                        asc: !options.descending,
+                        nulls_first: options.nulls_first,
+                    })
+                    .collect(),
+                join_type: protobuf::JoinType::from(exec.join_type).into(),
+                null_equals_null: exec.null_equals_null,
+            }))
+        } else if let Some(exec) = any.downcast_ref::<CrossJoinExec>() {
+            PhysicalPlanType::CrossJoin(Box::new(protobuf::CrossJoinExecNode {
+                left: convert_box_plan(exec.left())?,
+                right: convert_box_plan(exec.right())?,
+            }))
+        } else if let Some(exec) = any.downcast_ref::<ShuffleWriterExec>() {
+            PhysicalPlanType::ShuffleWriter(Box::new(protobuf::ShuffleWriterExecNode {
+                input: convert_box_plan(exec.input())?,
+                output_partitioning: Some(exec.partitioning().try_into()?),
+                output_data_file: exec.output_data_file().to_owned(),
+                output_index_file: exec.output_index_file().to_owned(),
+                compression_codec: protobuf::ShuffleCompressionCodec::from(exec.codec())
+                    .into(),
+                checksum_algorithm: protobuf::ShuffleChecksumAlgorithm::from(
+                    exec.checksum_algorithm(),
+                )
+                .into(),
+                collect_column_stats: exec.with_column_stats(),
+                sort_based_partition_threshold: exec.sort_based_partition_threshold()
+                    as u32,
+            }))
+        } else if let Some(exec) = any.downcast_ref::<ShuffleReaderExec>() {
+            let output_partitioning = match &exec.partitioning {
+                Partitioning::Hash(exprs, n) => {
+                    Some(convert_hash_partitioning(exprs, *n)?)
+                }
+                _ => None,
+            };
+            PhysicalPlanType::ShuffleReader(protobuf::ShuffleReaderExecNode {
+                num_partitions: exec.partitioning.partition_count() as u32,
+                schema: Some(exec.schema.as_ref().try_into()?),
+                native_shuffle_id: exec.native_shuffle_id.clone(),
+                prefetch_depth: exec.prefetch_depth as u32,
+                output_partitioning,
+            })
+        } else if let Some(exec) = any.downcast_ref::<EmptyExec>() {
+            PhysicalPlanType::Empty(protobuf::EmptyExecNode {
+                produce_one_row: exec.produce_one_row(),
+                schema: Some(exec.schema().try_into()?),
+            })
+        } else if let Some(exec) = any.downcast_ref::<SortExec>() {
+            PhysicalPlanType::Sort(Box::new(protobuf::SortExecNode {
+                input: convert_box_plan(exec.input())?,
+                expr: exec
+                    .expr()
+                    .iter()
+                    .map(|expr| expr.try_into())
+                    .collect::<Result<Vec<_>, _>>()?,
+            }))
+        } else if let Some(exec) = any.downcast_ref::<UnionExec>() {
+            PhysicalPlanType::Union(protobuf::UnionExecNode {
+                children: exec
+                    .children()
+                    .iter()
+                    .map(|child| child.try_into())
+                    .collect::<Result<Vec<_>, _>>()?,
+            })
+        } else if let Some(exec) = any.downcast_ref::<EmptyPartitionsExec>() {
+            PhysicalPlanType::EmptyPartitions(protobuf::EmptyPartitionsExecNode {
+                schema: Some(exec.schema().try_into()?),
+                num_partitions: exec.output_partitioning().partition_count() as u32,
+            })
+        } else if let Some(exec) = any.downcast_ref::<RenameColumnsExec>() {
+            PhysicalPlanType::RenameColumns(Box::new(protobuf::RenameColumnsExecNode {
+                input: convert_box_plan(exec.input())?,
+                renamed_column_names: exec.renamed_column_names().to_vec(),
+            }))
+        } else {
+            return Err(PlanSerDeError::NotImplemented(format!(
+                "physical_plan::to_proto() Unsupported physical plan '{:?}'",
+                plan
+            )));
+        };
+
+        Ok(protobuf::PhysicalPlanNode {
+            physical_plan_type: Some(physical_plan_type),
+        })
+    }
+}
+
+fn convert_box_plan(
+    plan: &Arc<dyn ExecutionPlan>,
+) -> Result<Option<Box<protobuf::PhysicalPlanNode>>, PlanSerDeError> {
+    Ok(Some(Box::new(plan.try_into()?)))
+}
+
+fn convert_box_expr(
+    expr: &Arc<dyn PhysicalExpr>,
+) -> Result<Option<Box<protobuf::PhysicalExprNode>>, PlanSerDeError> {
+    Ok(Some(Box::new(expr.try_into()?)))
+}
+
+fn convert_join_on(on: &[(Column, Column)]) -> Vec<protobuf::JoinOn> {
+    on.iter()
+        .map(|(left, right)| protobuf::JoinOn {
+            left: Some(left.into()),
+            right: Some(right.into()),
+        })
+        .collect()
+}
+
+fn convert_hash_partitioning(
+    exprs: &[Arc<dyn PhysicalExpr>],
+    partition_count: usize,
+) -> Result<protobuf::PhysicalHashRepartition, PlanSerDeError> {
+    Ok(protobuf::PhysicalHashRepartition {
+        hash_expr: exprs
+            .iter()
+            .map(|expr| expr.try_into())
+            .collect::<Result<Vec<_>, _>>()?,
+        partition_count: partition_count as u64,
+    })
+}
+
+impl From<&Column> for protobuf::PhysicalColumn {
+    fn from(column: &Column) -> Self {
+        protobuf::PhysicalColumn {
+            name: column.name().to_owned(),
+            index: column.index() as u32,
+        }
+    }
+}
+
+impl TryFrom<&Arc<dyn PhysicalExpr>> for protobuf::PhysicalExprNode {
+    type Error = PlanSerDeError;
+
+    fn try_from(expr: &Arc<dyn PhysicalExpr>) -> Result<Self, Self::Error> {
+        let any = expr.as_any();
+
+        let expr_type = if let Some(expr) = any.downcast_ref::<Column>() {
+            ExprType::Column(expr.into())
+        } else if let Some(expr) = any.downcast_ref::<Literal>() {
+            ExprType::Literal(expr.value().try_into()?)
+        } else if let Some(expr) = any.downcast_ref::<BinaryExpr>() {
+            ExprType::BinaryExpr(Box::new(protobuf::PhysicalBinaryExprNode {
+                l: convert_box_expr(expr.left())?,
+                r: convert_box_expr(expr.right())?,
+                op: to_proto_binary_op(expr.op())?,
+            }))
+        } else if let Some(expr) = any.downcast_ref::<IsNullExpr>() {
+            ExprType::IsNullExpr(Box::new(protobuf::PhysicalIsNull {
+                expr: convert_box_expr(expr.arg())?,
+            }))
+        } else if let Some(expr) = any.downcast_ref::<IsNotNullExpr>() {
+            ExprType::IsNotNullExpr(Box::new(protobuf::PhysicalIsNotNull {
+                expr: convert_box_expr(expr.arg())?,
+            }))
+        } else if let Some(expr) = any.downcast_ref::<NotExpr>() {
+            ExprType::NotExpr(Box::new(protobuf::PhysicalNot {
+                expr: convert_box_expr(expr.arg())?,
+            }))
+        } else if let Some(expr) = any.downcast_ref::<NegativeExpr>() {
+            ExprType::Negative(Box::new(protobuf::PhysicalNegativeNode {
+                expr: convert_box_expr(expr.arg())?,
+            }))
+        } else if let Some(expr) = any.downcast_ref::<InListExpr>() {
+            ExprType::InList(Box::new(protobuf::PhysicalInListNode {
+                expr: convert_box_expr(expr.expr())?,
+                list: expr
+                    .list()
+                    .iter()
+                    .map(|e| e.try_into())
+                    .collect::<Result<Vec<_>, _>>()?,
+                negated: expr.negated(),
+            }))
+        } else if let Some(expr) = any.downcast_ref::<CaseExpr>() {
+            ExprType::Case(Box::new(protobuf::PhysicalCaseNode {
+                expr: match expr.expr() {
+                    Some(e) => convert_box_expr(e)?,
+                    None => None,
+                },
+                when_then_expr: expr
+                    .when_then_expr()
+                    .iter()
+                    .map(|(when_expr, then_expr)| {
+                        Ok(protobuf::PhysicalWhenThen {
+                            when_expr: Some(when_expr.try_into()?),
+                            then_expr: Some(then_expr.try_into()?),
+                        })
+                    })
+                    .collect::<Result<Vec<_>, PlanSerDeError>>()?,
+                else_expr: match expr.else_expr() {
+                    Some(e) => convert_box_expr(e)?,
+                    None => None,
+                },
+            }))
+        } else if let Some(expr) = any.downcast_ref::<CastExpr>() {
+            ExprType::Cast(Box::new(protobuf::PhysicalCastNode {
+                expr: convert_box_expr(expr.expr())?,
+                arrow_type: Some(expr.cast_type().try_into()?),
+            }))
+        } else if let Some(expr) = any.downcast_ref::<TryCastExpr>() {
+            ExprType::TryCast(Box::new(protobuf::PhysicalTryCastNode {
+                expr: convert_box_expr(expr.expr())?,
+                arrow_type: Some(expr.cast_type().try_into()?),
+            }))
+        } else if let Some(expr) = any.downcast_ref::<ScalarFunctionExpr>() {
+            ExprType::ScalarFunction(protobuf::PhysicalScalarFunctionNode {
+                name: expr.name().to_owned(),
+                fun: scalar_function_from_name(expr.name())?.into(),
+                args: expr
+                    .args()
+                    .iter()
+                    .map(|e| e.try_into())
+                    .collect::<Result<Vec<_>, _>>()?,
+                return_type: Some(expr.return_type().try_into()?),
+            })
+        } else {
+            return Err(PlanSerDeError::NotImplemented(format!(
+                "physical_plan::to_proto() Unsupported physical expression '{:?}'",
+                expr
+            )));
+        };
+
+        Ok(protobuf::PhysicalExprNode {
+            expr_type: Some(expr_type),
+        })
+    }
+}
+
+/// scalar functions are created from `PhysicalScalarFunctionNode` with names of
+/// `protobuf::ScalarFunction` variants, so the function can be found by its name
+fn scalar_function_from_name(
+    name: &str,
+) -> Result<protobuf::ScalarFunction, PlanSerDeError> {
+    let fun = match name {
+        "Abs" => protobuf::ScalarFunction::Abs,
+        "Acos" => protobuf::ScalarFunction::Acos,
+        "Asin" => protobuf::ScalarFunction::Asin,
+        "Atan" => protobuf::ScalarFunction::Atan,
+        "Ascii" => protobuf::ScalarFunction::Ascii,
+        "Ceil" => protobuf::ScalarFunction::Ceil,
+        "Cos" => protobuf::ScalarFunction::Cos,
+        "Digest" => protobuf::ScalarFunction::Digest,
+        "Exp" => protobuf::ScalarFunction::Exp,
+        "Floor" => protobuf::ScalarFunction::Floor,
+        "Ln" => protobuf::ScalarFunction::Ln,
+        "Log" => protobuf::ScalarFunction::Log,
+        "Log10" => protobuf::ScalarFunction::Log10,
+        "Log2" => protobuf::ScalarFunction::Log2,
+        "Round" => protobuf::ScalarFunction::Round,
+        "Signum" => protobuf::ScalarFunction::Signum,
+        "Sin" => protobuf::ScalarFunction::Sin,
+        "Sqrt" => protobuf::ScalarFunction::Sqrt,
+        "Tan" => protobuf::ScalarFunction::Tan,
+        "Trunc" => protobuf::ScalarFunction::Trunc,
+        "Array" => protobuf::ScalarFunction::Array,
+        "RegexpMatch" => protobuf::ScalarFunction::RegexpMatch,
+        "BitLength" => protobuf::ScalarFunction::BitLength,
+        "Btrim" => protobuf::ScalarFunction::Btrim,
+        "CharacterLength" => protobuf::ScalarFunction::CharacterLength,
+        "Chr" => protobuf::ScalarFunction::Chr,
+        "Concat" => protobuf::ScalarFunction::Concat,
+        "ConcatWithSeparator" => protobuf::ScalarFunction::ConcatWithSeparator,
+        "DatePart" => protobuf::ScalarFunction::DatePart,
+        "DateTrunc" => protobuf::ScalarFunction::DateTrunc,
+        "InitCap" => protobuf::ScalarFunction::InitCap,
+        "Left" => protobuf::ScalarFunction::Left,
+        "Lpad" => protobuf::ScalarFunction::Lpad,
+        "Lower" => protobuf::ScalarFunction::Lower,
+        "Ltrim" => protobuf::ScalarFunction::Ltrim,
+        "MD5" => protobuf::ScalarFunction::Md5,
+        "NullIf" => protobuf::ScalarFunction::NullIf,
+        "OctetLength" => protobuf::ScalarFunction::OctetLength,
+        "Random" => protobuf::ScalarFunction::Random,
+        "RegexpReplace" => protobuf::ScalarFunction::RegexpReplace,
+        "Repeat" => protobuf::ScalarFunction::Repeat,
+        "Replace" => protobuf::ScalarFunction::Replace,
+        "Reverse" => protobuf::ScalarFunction::Reverse,
+        "Right" => protobuf::ScalarFunction::Right,
+        "Rpad" => protobuf::ScalarFunction::Rpad,
+        "Rtrim" => protobuf::ScalarFunction::Rtrim,
+        "SHA224" => protobuf::ScalarFunction::Sha224,
+        "SHA256" => protobuf::ScalarFunction::Sha256,
+        "SHA384" => protobuf::ScalarFunction::Sha384,
+        "SHA512" => protobuf::ScalarFunction::Sha512,
+        "SplitPart" => protobuf::ScalarFunction::SplitPart,
+        "StartsWith" => protobuf::ScalarFunction::StartsWith,
+        "Strpos" => protobuf::ScalarFunction::Strpos,
+        "Substr" => protobuf::ScalarFunction::Substr,
+        "ToHex" => protobuf::ScalarFunction::ToHex,
+        "ToTimestamp" => protobuf::ScalarFunction::ToTimestamp,
+        "ToTimestampMillis" => protobuf::ScalarFunction::ToTimestampMillis,
+        "ToTimestampMicros" => protobuf::ScalarFunction::ToTimestampMicros,
+        "ToTimestampSeconds" => protobuf::ScalarFunction::ToTimestampSeconds,
+        "Now" => protobuf::ScalarFunction::Now,
+        "Translate" => protobuf::ScalarFunction::Translate,
+        "Trim" => protobuf::ScalarFunction::Trim,
+        "Upper" => protobuf::ScalarFunction::Upper,
+        "Coalesce" => protobuf::ScalarFunction::Coalesce,
+        _ => {
+            return Err(PlanSerDeError::NotImplemented(format!(
+                "physical_plan::to_proto() Unsupported scalar function '{}'",
+                name
+            )))
+        }
+    };
+    Ok(fun)
+}
+
+impl TryFrom<&Arc<dyn WindowExpr>> for protobuf::PhysicalExprNode {
+    type Error = PlanSerDeError;
+
+    fn try_from(expr: &Arc<dyn WindowExpr>) -> Result<Self, Self::Error> {
+        // only window expressions decoded from protobuf keep their window functions
+        let expr = expr
+            .as_any()
+            .downcast_ref::<ParsedWindowExpr>()
+            .ok_or_else(|| {
+                PlanSerDeError::NotImplemented(format!(
+                    "physical_plan::to_proto() Unsupported window expression '{:?}'",
+                    expr
+                ))
+            })?;
+
+        Ok(protobuf::PhysicalExprNode {
+            expr_type: Some(ExprType::WindowExpr(Box::new(
+                protobuf::PhysicalWindowExprNode {
+                    window_function: Some(expr.window_function.clone()),
+                    expr: convert_box_expr(&expr.arg)?,
+                },
+            ))),
+        })
+    }
+}
+
+impl TryFrom<&Arc<dyn AggregateExpr>> for protobuf::PhysicalExprNode {
+    type Error = PlanSerDeError;
+
+    fn try_from(expr: &Arc<dyn AggregateExpr>) -> Result<Self, Self::Error> {
+        let any = expr.as_any();
+        let aggr_function = if any.is::<Min>() {
+            protobuf::AggregateFunction::Min
+        } else if any.is::<Max>() {
+            protobuf::AggregateFunction::Max
+        } else if any.is::<Sum>() {
+            protobuf::AggregateFunction::Sum
+        } else if any.is::<Avg>() {
+            protobuf::AggregateFunction::Avg
+        } else if any.is::<Count>() {
+            protobuf::AggregateFunction::Count
+        } else if any.is::<ApproxDistinct>() {
+            protobuf::AggregateFunction::ApproxDistinct
+        } else if any.is::<ArrayAgg>() {
+            protobuf::AggregateFunction::ArrayAgg
+        } else if any.is::<Variance>() {
+            protobuf::AggregateFunction::Variance
+        } else if any.is::<VariancePop>() {
+            protobuf::AggregateFunction::VariancePop
+        } else if any.is::<Stddev>() {
+            protobuf::AggregateFunction::Stddev
+        } else if any.is::<StddevPop>() {
+            protobuf::AggregateFunction::StddevPop
+        } else {
+            return Err(PlanSerDeError::NotImplemented(format!(
+                "physical_plan::to_proto() Unsupported aggregate expression '{:?}'",
+                expr
+            )));
+        };
+
+        // PhysicalAggregateExprNode only has a single argument
+        let args = expr.expressions();
+        if args.len() != 1 {
+            return Err(PlanSerDeError::NotImplemented(format!(
+                "physical_plan::to_proto() Unsupported aggregate expression with {} \
+                 arguments '{:?}'",
+                args.len(),
+                expr
+            )));
+        }
+
+        Ok(protobuf::PhysicalExprNode {
+            expr_type: Some(ExprType::AggregateExpr(Box::new(
+                protobuf::PhysicalAggregateExprNode {
+                    aggr_function: aggr_function.into(),
+                    expr: convert_box_expr(&args[0])?,
+                },
+            ))),
+        })
+    }
+}
+
+impl TryFrom<&PhysicalSortExpr> for protobuf::PhysicalExprNode {
+    type Error = PlanSerDeError;
+
+    fn try_from(expr: &PhysicalSortExpr) -> Result<Self, Self::Error> {
+        Ok(protobuf::PhysicalExprNode {
+            expr_type: Some(ExprType::Sort(Box::new(protobuf::PhysicalSortExprNode {
+                expr: convert_box_expr(&expr.expr)?,
+                asc: !expr.options.descending,
+                nulls_first: expr.options.nulls_first,
+            }))),
+        })
+    }
+}
+
+impl TryFrom<&ShuffleRepartitioning> for protobuf::PhysicalRepartition {
+    type Error = PlanSerDeError;
+
+    fn try_from(partitioning: &ShuffleRepartitioning) -> Result<Self, Self::Error> {
+        let repartition_type = match partitioning {
+            ShuffleRepartitioning::Single => {
+                RepartitionType::SingleRepartition(protobuf::PhysicalSingleRepartition {})
+            }
+            ShuffleRepartitioning::Hash(exprs, n) => {
+                RepartitionType::HashRepartition(convert_hash_partitioning(exprs, *n)?)
+            }
+            ShuffleRepartitioning::RoundRobin(n) => {
+                RepartitionType::RoundRobinRepartition(
+                    protobuf::PhysicalRoundRobinRepartition {
+                        partition_count: *n as u64,
+                    },
+                )
+            }
+            ShuffleRepartitioning::Range {
+                sort_exprs,
+                num_partitions,
+                bounds,
+            } => {
+                // convert bounds from one array for each sort expr into rows of literals
+                let num_bounds = bounds.first().map(|array| array.len()).unwrap_or(0);
+                let bounds = (0..num_bounds)
+                    .map(|row| {
+                        let value = bounds
+                            .iter()
+                            .map(|array| {
+                                (&ScalarValue::try_from_array(array, row)?).try_into()
+                            })
+                            .collect::<Result<Vec<_>, PlanSerDeError>>()?;
+                        Ok(protobuf::PhysicalRangeBound { value })
+                    })
+                    .collect::<Result<Vec<_>, PlanSerDeError>>()?;
+
+                RepartitionType::RangeRepartition(protobuf::PhysicalRangeRepartition {
+                    sort_expr: sort_exprs
+                        .iter()
+                        .map(|expr| expr.try_into())
+                        .collect::<Result<Vec<_>, _>>()?,
+                    partition_count: *num_partitions as u64,
+                    bounds,
+                })
+            }
+        };
+        Ok(protobuf::PhysicalRepartition {
+            repartition_type: Some(repartition_type),
+        })
+    }
+}
+
+impl From<ShuffleCompressionCodec> for protobuf::ShuffleCompressionCodec {
+    fn from(codec: ShuffleCompressionCodec) -> Self {
+        match codec {
+            ShuffleCompressionCodec::None => Self::NoCompression,
+            ShuffleCompressionCodec::Lz4 => Self::Lz4,
+            ShuffleCompressionCodec::Zstd => Self::Zstd,
+        }
+    }
+}
+
+impl From<ShuffleChecksumAlgorithm> for protobuf::ShuffleChecksumAlgorithm {
+    fn from(algorithm: ShuffleChecksumAlgorithm) -> Self {
+        match algorithm {
+            ShuffleChecksumAlgorithm::None => Self::NoChecksum,
+            ShuffleChecksumAlgorithm::Adler32 => Self::Adler32,
+            ShuffleChecksumAlgorithm::Crc32c => Self::Crc32c,
+        }
+    }
+}
+
+impl TryFrom<&FileScanConfig> for protobuf::FileScanExecConf {
+    type Error = PlanSerDeError;
+
+    fn try_from(conf: &FileScanConfig) -> Result<Self, Self::Error> {
+        Ok(protobuf::FileScanExecConf {
+            file_groups: conf
+                .file_groups
+                .iter()
+                .map(|files| files.as_slice().try_into())
+                .collect::<Result<Vec<_>, _>>()?,
+            schema: Some(conf.file_schema.as_ref().try_into()?),
+            // no projection is encoded with empty array
+            projection: conf
+                .projection
+                .iter()
+                .flatten()
+                .map(|i| *i as u32)
+                .collect(),
+            limit: conf.limit.map(|limit| protobuf::ScanLimit {
+                limit: limit as u32,
+            }),
+            statistics: Some((&conf.statistics).try_into()?),
+            table_partition_cols: conf.table_partition_cols.clone(),
+        })
+    }
+}
+
+impl TryFrom<&[PartitionedFile]> for protobuf::FileGroup {
+    type Error = PlanSerDeError;
+
+    fn try_from(files: &[PartitionedFile]) -> Result<Self, Self::Error> {
+        Ok(protobuf::FileGroup {
+            files: files
+                .iter()
+                .map(|file| file.try_into())
+                .collect::<Result<Vec<_>, _>>()?,
+        })
+    }
+}
+
+impl TryFrom<&PartitionedFile> for protobuf::PartitionedFile {
+    type Error = PlanSerDeError;
+
+    fn try_from(file: &PartitionedFile) -> Result<Self, Self::Error> {
+        Ok(protobuf::PartitionedFile {
+            path: file.file_meta.path().to_owned(),
+            size: file.file_meta.size(),
+            last_modified_ns: file
+                .file_meta
+                .last_modified
+                .map(|ts| ts.timestamp_nanos() as u64)
+                .unwrap_or(0),
+            partition_values: file
+                .partition_values
+                .iter()
+                .map(|v| v.try_into())
+                .collect::<Result<Vec<_>, _>>()?,
+            range: file.range.as_ref().map(|range| range.into()),
+        })
+    }
+}
+
+impl From<&FileRange> for protobuf::FileRange {
+    fn from(range: &FileRange) -> Self {
+        protobuf::FileRange {
+            start: range.start,
+            end: range.end,
+        }
+    }
+}
+
+impl TryFrom<&Statistics> for protobuf::Statistics {
+    type Error = PlanSerDeError;
+
+    fn try_from(stats: &Statistics) -> Result<Self, Self::Error> {
+        Ok(protobuf::Statistics {
+            num_rows: stats.num_rows.unwrap_or(0) as i64,
+            total_byte_size: stats.total_byte_size.unwrap_or(0) as i64,
+            // No column statistic (None) is encoded with empty array
+            column_stats: stats
+                .column_statistics
+                .iter()
+                .flatten()
+                .map(|s| s.try_into())
+                .collect::<Result<Vec<_>, _>>()?,
+            is_exact: stats.is_exact,
+        })
+    }
+}
+
+impl TryFrom<&Expr> for protobuf::LogicalExprNode {
+    type Error = PlanSerDeError;
+
+    fn try_from(expr: &Expr) -> Result<Self, Self::Error> {
+        use crate::protobuf::logical_expr_node::ExprType;
+
+        let expr_type = match expr {
+            Expr::Column(column) => ExprType::Column(column.into()),
+            Expr::Alias(expr, alias) => ExprType::Alias(Box::new(protobuf::AliasNode {
+                expr: convert_box_logical_expr(expr)?,
+                alias: alias.clone(),
+            })),
+            Expr::Literal(value) => ExprType::Literal(value.try_into()?),
+            Expr::BinaryExpr { left, op, right } => {
+                ExprType::BinaryExpr(Box::new(protobuf::BinaryExprNode {
+                    l: convert_box_logical_expr(left)?,
+                    r: convert_box_logical_expr(right)?,
+                    op: to_proto_binary_op(op)?,
+                }))
+            }
+            Expr::IsNull(expr) => ExprType::IsNullExpr(Box::new(protobuf::IsNull {
+                expr: convert_box_logical_expr(expr)?,
+            })),
+            Expr::IsNotNull(expr) => {
+                ExprType::IsNotNullExpr(Box::new(protobuf::IsNotNull {
+                    expr: convert_box_logical_expr(expr)?,
+                }))
+            }
+            Expr::Not(expr) => ExprType::NotExpr(Box::new(protobuf::Not {
+                expr: convert_box_logical_expr(expr)?,
+            })),
+            Expr::Between {
+                expr,
+                negated,
+                low,
+                high,
+            } => ExprType::Between(Box::new(protobuf::BetweenNode {
+                expr: convert_box_logical_expr(expr)?,
+                negated: *negated,
+                low: convert_box_logical_expr(low)?,
+                high: convert_box_logical_expr(high)?,
+            })),
+            Expr::Case {
+                expr,
+                when_then_expr,
+                else_expr,
+            } => ExprType::Case(Box::new(protobuf::CaseNode {
+                expr: match expr {
+                    Some(e) => convert_box_logical_expr(e)?,
+                    None => None,
+                },
+                when_then_expr: when_then_expr
+                    .iter()
+                    .map(|(when_expr, then_expr)| {
+                        Ok(protobuf::WhenThen {
+                            when_expr: Some(when_expr.as_ref().try_into()?),
+                            then_expr: Some(then_expr.as_ref().try_into()?),
+                        })
+                    })
+                    .collect::<Result<Vec<_>, PlanSerDeError>>()?,
+                else_expr: match else_expr {
+                    Some(e) => convert_box_logical_expr(e)?,
+                    None => None,
+                },
+            })),
+            Expr::Cast { expr, data_type } => {
+                ExprType::Cast(Box::new(protobuf::CastNode {
+                    expr: convert_box_logical_expr(expr)?,
+                    arrow_type: Some(data_type.try_into()?),
+                }))
+            }
+            Expr::TryCast { expr, data_type } => {
+                ExprType::TryCast(Box::new(protobuf::TryCastNode {
+                    expr: convert_box_logical_expr(expr)?,
+                    arrow_type: Some(data_type.try_into()?),
+                }))
+            }
+            Expr::Negative(expr) => {
+                ExprType::Negative(Box::new(protobuf::NegativeNode {
+                    expr: convert_box_logical_expr(expr)?,
+                }))
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => ExprType::InList(Box::new(protobuf::InListNode {
+                expr: convert_box_logical_expr(expr)?,
+                list: list
+                    .iter()
+                    .map(|e| e.try_into())
+                    .collect::<Result<Vec<_>, _>>()?,
+                negated: *negated,
+            })),
+            Expr::Wildcard => ExprType::Wildcard(true),
+            Expr::ScalarFunction { fun, args } => {
+                ExprType::ScalarFunction(protobuf::ScalarFunctionNode {
+                    fun: protobuf::ScalarFunction::try_from(fun)?.into(),
+                    args: args
+                        .iter()
+                        .map(|e| e.try_into())
+                        .collect::<Result<Vec<_>, _>>()?,
+                })
+            }
+            other => {
+                return Err(PlanSerDeError::NotImplemented(format!(
+                    "logical_expr::to_proto() Unsupported logical expression '{:?}'",
+                    other
+                )));
+            }
+        };
+
+        Ok(protobuf::LogicalExprNode {
+            expr_type: Some(expr_type),
+        })
+    }
+}
+
+fn convert_box_logical_expr(
+    expr: &Expr,
+) -> Result<Option<Box<protobuf::LogicalExprNode>>, PlanSerDeError> {
+    Ok(Some(Box::new(expr.try_into()?)))
+}
+
+impl From<&logical_plan::Column> for protobuf::Column {
+    fn from(column: &logical_plan::Column) -> Self {
+        protobuf::Column {
+            name: column.name.clone(),
+            relation: column
+                .relation
+                .as_ref()
+                .map(|relation| protobuf::ColumnRelation {
+                    relation: relation.clone(),
+                }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+    use std::sync::Arc;
+
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::datafusion_data_access::object_store::local::LocalFileSystem;
+    use datafusion::datafusion_data_access::{FileMeta, SizedFile};
+    use datafusion::datasource::listing::{FileRange, PartitionedFile};
+    use datafusion::logical_plan::Operator;
+    use datafusion::physical_plan::aggregates::{AggregateExec, AggregateMode};
+    use datafusion::physical_plan::cross_join::CrossJoinExec;
+    use datafusion::physical_plan::expressions::{
+        col, BinaryExpr, CaseExpr, CastExpr, Column, Literal, PhysicalSortExpr, Sum,
+        DEFAULT_DATAFUSION_CAST_OPTIONS,
+    };
+    use datafusion::physical_plan::file_format::{CsvExec, FileScanConfig, ParquetExec};
+    use datafusion::physical_plan::filter::FilterExec;
+    use datafusion::physical_plan::hash_join::{HashJoinExec, PartitionMode};
+    use datafusion::physical_plan::projection::ProjectionExec;
+    use datafusion::physical_plan::sort_merge_join::SortMergeJoinExec;
+    use datafusion::physical_plan::sorts::sort::{SortExec, SortOptions};
+    use datafusion::physical_plan::{
+        ExecutionPlan, Partitioning, PhysicalExpr, Statistics,
+    };
+    use datafusion::prelude::JoinType;
+    use datafusion::scalar::ScalarValue;
+
+    use datafusion_ext::empty_partitions_exec::EmptyPartitionsExec;
+    use datafusion_ext::rename_columns_exec::RenameColumnsExec;
+    use datafusion_ext::shuffle_checksum::ShuffleChecksumAlgorithm;
+    use datafusion_ext::shuffle_codec::ShuffleCompressionCodec;
+    use datafusion_ext::shuffle_reader_exec::ShuffleReaderExec;
+    use datafusion_ext::shuffle_writer_exec::{ShuffleRepartitioning, ShuffleWriterExec};
+
+    use crate::protobuf;
+    use crate::protobuf::physical_expr_node::ExprType;
+    use crate::protobuf::physical_plan_node::PhysicalPlanType;
+    use crate::protobuf::physical_window_expr_node::WindowFunction;
+
+    fn assert_roundtrip(plan: Arc<dyn ExecutionPlan>) {
+        let node = protobuf::PhysicalPlanNode::try_from(&plan).unwrap();
+        let decoded: Arc<dyn ExecutionPlan> = (&node).try_into().unwrap();
+        let reencoded = protobuf::PhysicalPlanNode::try_from(&decoded).unwrap();
+        assert_eq!(node, reencoded);
+    }
+
+    #[test]
+    fn test_roundtrip_physical_plans() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("a", DataType::Int64, false),
+            Field::new("b", DataType::Utf8, true),
+        ]));
+        let a = col("a", &schema).unwrap();
+        let b = col("b", &schema).unwrap();
+
+        let input: Arc<dyn ExecutionPlan> =
+            Arc::new(EmptyPartitionsExec::new(schema.clone(), 4));
+        let filter = Arc::new(
+            FilterExec::try_new(
+                Arc::new(BinaryExpr::new(
+                    a.clone(),
+                    Operator::Gt,
+                    Arc::new(Literal::new(ScalarValue::Int64(Some(1)))),
+                )),
+                input.clone(),
+            )
+            .unwrap(),
+        );
+        let projection = Arc::new(
+            ProjectionExec::try_new(
+                vec![(b.clone(), "x".to_owned()), (a.clone(), "y".to_owned())],
+                filter,
+            )
+            .unwrap(),
+        );
+        let renamed = Arc::new(
+            RenameColumnsExec::try_new(projection, vec!["c".to_owned(), "d".to_owned()])
+                .unwrap(),
+        );
+        let sort = Arc::new(SortExec::new_with_partitioning(
+            vec![PhysicalSortExpr {
+                expr: col("d", &renamed.schema()).unwrap(),
+                options: SortOptions {
+                    descending: true,
+                    nulls_first: false,
+                },
+            }],
+            renamed,
+            true,
+        ));
+        let shuffle_writer = Arc::new(
+            ShuffleWriterExec::try_new(
+                sort.clone(),
+                ShuffleRepartitioning::Hash(vec![col("c", &sort.schema()).unwrap()], 8),
+                "data".to_owned(),
+                "index".to_owned(),
+                ShuffleCompressionCodec::Lz4,
+                ShuffleChecksumAlgorithm::Crc32c,
+                true,
+                2000,
+            )
+            .unwrap(),
+        );
+        assert_roundtrip(shuffle_writer);
+
+        let aggregate = Arc::new(
+            AggregateExec::try_new(
+                AggregateMode::Partial,
+                vec![(b.clone(), "b".to_owned())],
+                vec![Arc::new(Sum::new(a.clone(), "sum", DataType::Int64))],
+                input,
+                schema.clone(),
+            )
+            .unwrap(),
+        );
+        assert_roundtrip(aggregate);
+
+        let shuffle_reader = Arc::new(ShuffleReaderExec::new(
+            Partitioning::Hash(vec![a.clone()], 4),
+            "shuffle_1".to_owned(),
+            schema.clone(),
+            2,
+        ));
+        assert_roundtrip(shuffle_reader);
+
+        // case and cast expressions
+        let literal = |value| -> Arc<dyn PhysicalExpr> { Arc::new(Literal::new(value)) };
+        let a_gt_1: Arc<dyn PhysicalExpr> = Arc::new(BinaryExpr::new(
+            a.clone(),
+            Operator::Gt,
+            literal(ScalarValue::Int64(Some(1))),
+        ));
+        let a_as_utf8: Arc<dyn PhysicalExpr> = Arc::new(CastExpr::new(
+            a.clone(),
+            DataType::Utf8,
+            DEFAULT_DATAFUSION_CAST_OPTIONS,
+        ));
+        let searched_case: Arc<dyn PhysicalExpr> = Arc::new(
+            CaseExpr::try_new(None, &[(a_gt_1, b.clone())], Some(a_as_utf8)).unwrap(),
+        );
+        let simple_case: Arc<dyn PhysicalExpr> = Arc::new(
+            CaseExpr::try_new(
+                Some(a.clone()),
+                &[(
+                    literal(ScalarValue::Int64(Some(1))),
+                    literal(ScalarValue::Utf8(Some("one".to_owned()))),
+                )],
+                None,
+            )
+            .unwrap(),
+        );
+        let projection = Arc::new(
+            ProjectionExec::try_new(
+                vec![
+                    (searched_case, "x".to_owned()),
+                    (simple_case, "y".to_owned()),
+                ],
+                input.clone(),
+            )
+            .unwrap(),
+        );
+        assert_roundtrip(projection);
+
+        // joins
+        let on = vec![(Column::new("a", 0), Column::new("a", 0))];
+        let hash_join = Arc::new(
+            HashJoinExec::try_new(
+                input.clone(),
+                input.clone(),
+                on.clone(),
+                &JoinType::Left,
+                PartitionMode::Partitioned,
+                &false,
+            )
+            .unwrap(),
+        );
+        assert_roundtrip(hash_join);
+
+        let sort_merge_join = Arc::new(
+            SortMergeJoinExec::try_new(
+                input.clone(),
+                input.clone(),
+                on,
+                JoinType::Inner,
+                vec![SortOptions {
+                    descending: false,
+                    nulls_first: true,
+                }],
+                true,
+            )
+            .unwrap(),
+        );
+        assert_roundtrip(sort_merge_join);
+
+        let cross_join =
+            Arc::new(CrossJoinExec::try_new(input.clone(), input.clone()).unwrap());
+        assert_roundtrip(cross_join);
+
+        // file scans
+        let scan_conf = || FileScanConfig {
+            object_store: Arc::new(LocalFileSystem),
+            file_schema: schema.clone(),
+            file_groups: vec![vec![PartitionedFile {
+                file_meta: FileMeta {
+                    sized_file: SizedFile {
+                        path: "/data/part-0".to_owned(),
+                        size: 1000,
+                    },
+                    last_modified: None,
+                },
+                partition_values: vec![],
+                range: Some(FileRange {
+                    start: 100,
+                    end: 500,
+                }),
+            }]],
+            statistics: Statistics::default(),
+            projection: Some(vec![1, 0]),
+            limit: None,
+            table_partition_cols: vec![],
+        };
+        assert_roundtrip(Arc::new(ParquetExec::new(scan_conf(), None)));
+        assert_roundtrip(Arc::new(CsvExec::new(scan_conf(), true, b',')));
+
+        // map types cannot be represented in plan.proto
+        let map_type = DataType::Map(
+            Box::new(Field::new(
+                "entries",
+                DataType::Struct(vec![
+                    Field::new("key", DataType::Utf8, false),
+                    Field::new("value", DataType::Int64, true),
+                ]),
+                false,
+            )),
+            false,
+        );
+        let map_schema = Arc::new(Schema::new(vec![Field::new("m", map_type, true)]));
+        let plan: Arc<dyn ExecutionPlan> =
+            Arc::new(EmptyPartitionsExec::new(map_schema, 1));
+        assert!(protobuf::PhysicalPlanNode::try_from(&plan).is_err());
+    }
+
+    #[test]
+    fn test_roundtrip_window_plan() {
+        let schema = Schema::new(vec![Field::new("a", DataType::Int64, false)]);
+        let column = protobuf::PhysicalExprNode {
+            expr_type: Some(ExprType::Column(protobuf::PhysicalColumn {
+                name: "a".to_owned(),
+                index: 0,
+            })),
+        };
+        let window_expr = |window_function| protobuf::PhysicalExprNode {
+            expr_type: Some(ExprType::WindowExpr(Box::new(
+                protobuf::PhysicalWindowExprNode {
+                    window_function: Some(window_function),
+                    expr: Some(Box::new(column.clone())),
+                },
+            ))),
+        };
+        let node = protobuf::PhysicalPlanNode {
+            physical_plan_type: Some(PhysicalPlanType::Window(Box::new(
+                protobuf::WindowAggExecNode {
+                    input: Some(Box::new(protobuf::PhysicalPlanNode {
+                        physical_plan_type: Some(PhysicalPlanType::EmptyPartitions(
+                            protobuf::EmptyPartitionsExecNode {
+                                schema: Some((&schema).try_into().unwrap()),
+                                num_partitions: 1,
+                            },
+                        )),
+                    })),
+                    window_expr: vec![
+                        window_expr(WindowFunction::AggrFunction(
+                            protobuf::AggregateFunction::Sum.into(),
+                        )),
+                        window_expr(WindowFunction::BuiltInFunction(
+                            protobuf::BuiltInWindowFunction::RowNumber.into(),
+                        )),
+                    ],
+                    window_expr_name: vec!["sum".to_owned(), "row_number".to_owned()],
+                    input_schema: Some((&schema).try_into().unwrap()),
+                },
+            ))),
+        };
+        let plan: Arc<dyn ExecutionPlan> = (&node).try_into().unwrap();
+        assert_eq!(protobuf::PhysicalPlanNode::try_from(&plan).unwrap(), node);
+        assert_roundtrip(plan);
+    }
+}
@@ -0,0 +1,271 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Validation of plans without executing them.
+//!
+//! Every node of the plan is converted separately, so that all unsupported
+//! nodes are reported instead of only the first one. A node is only converted
+//! when all of its children are supported, since its expressions cannot be
+//! bound otherwise.
+
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use datafusion::arrow::datatypes::Schema;
+use datafusion::physical_plan::expressions::Column;
+use datafusion::physical_plan::hash_join::HashJoinExec;
+use datafusion::physical_plan::repartition::RepartitionExec;
+use datafusion::physical_plan::sort_merge_join::SortMergeJoinExec;
+use datafusion::physical_plan::sorts::sort::SortExec;
+use datafusion::physical_plan::{ExecutionPlan, Partitioning, PhysicalExpr};
+use datafusion_ext::shuffle_writer_exec::{ShuffleRepartitioning, ShuffleWriterExec};
+
+use crate::error::{PlanSerDeError, WithPath};
+use crate::protobuf;
+use crate::protobuf::physical_plan_node::PhysicalPlanType;
+
+/// Converts a plan received from the JVM and checks types of its expressions,
+/// errors are located at the path of the offending node starting with `root`
+pub fn validate_physical_plan(
+    plan: &protobuf::PhysicalPlanNode,
+) -> protobuf::PlanValidationResult {
+    let errors = validate_plan_node(plan)
+        .into_iter()
+        .map(|err| match err.at("root") {
+            PlanSerDeError::AtPath { path, error } => protobuf::PlanValidationError {
+                path,
+                message: error.to_string(),
+            },
+            error => protobuf::PlanValidationError {
+                path: String::new(),
+                message: error.to_string(),
+            },
+        })
+        .collect();
+    protobuf::PlanValidationResult { errors }
+}
+
+fn validate_plan_node(plan: &protobuf::PhysicalPlanNode) -> Vec<PlanSerDeError> {
+    let mut errors = vec![];
+    for (segment, child) in plan_children(plan) {
+        errors.extend(
+            validate_plan_node(child)
+                .into_iter()
+                .map(|err| err.at(&segment)),
+        );
+    }
+    if errors.is_empty() {
+        let converted: Result<Arc<dyn ExecutionPlan>, PlanSerDeError> = plan.try_into();
+        if let Err(err) = converted.and_then(|plan| check_types(plan.as_ref())) {
+            errors.push(err);
+        }
+    }
+    errors
+}
+
+/// input plans of a node with their paths relative to the node, missing inputs
+/// are reported when converting the node itself
+fn plan_children(
+    plan: &protobuf::PhysicalPlanNode,
+) -> Vec<(String, &protobuf::PhysicalPlanNode)> {
+    match &plan.physical_plan_type {
+        Some(PhysicalPlanType::Projection(node)) => input_child(&node.input),
+        Some(PhysicalPlanType::Filter(node)) => input_child(&node.input),
+        Some(PhysicalPlanType::CoalesceBatches(node)) => input_child(&node.input),
+        Some(PhysicalPlanType::Merge(node)) => input_child(&node.input),
+        Some(PhysicalPlanType::Repartition(node)) => input_child(&node.input),
+        Some(PhysicalPlanType::GlobalLimit(node)) => input_child(&node.input),
+        Some(PhysicalPlanType::LocalLimit(node)) => input_child(&node.input),
+        Some(PhysicalPlanType::Window(node)) => input_child(&node.input),
+        Some(PhysicalPlanType::HashAggregate(node)) => input_child(&node.input),
+        Some(PhysicalPlanType::ShuffleWriter(node)) => input_child(&node.input),
+        Some(PhysicalPlanType::Sort(node)) => input_child(&node.input),
+        Some(PhysicalPlanType::RenameColumns(node)) => input_child(&node.input),
+        Some(PhysicalPlanType::HashJoin(node)) => join_children(&node.left, &node.right),
+        Some(PhysicalPlanType::SortMergeJoin(node)) => {
+            join_children(&node.left, &node.right)
+        }
+        Some(PhysicalPlanType::CrossJoin(node)) => join_children(&node.left, &node.right),
+        Some(PhysicalPlanType::Union(node)) => node
+            .children
+            .iter()
+            .enumerate()
+            .map(|(i, child)| (format!("children[{}]", i), child))
+            .collect(),
+        Some(PhysicalPlanType::ParquetScan(_))
+        | Some(PhysicalPlanType::CsvScan(_))
+        | Some(PhysicalPlanType::AvroScan(_))
+        | Some(PhysicalPlanType::Empty(_))
+        | Some(PhysicalPlanType::EmptyPartitions(_))
+        | Some(PhysicalPlanType::ShuffleReader(_))
+        | Some(PhysicalPlanType::Unresolved(_))
+        | None => vec![],
+    }
+}
+
+fn input_child(
+    input: &Option<Box<protobuf::PhysicalPlanNode>>,
+) -> Vec<(String, &protobuf::PhysicalPlanNode)> {
+    input
+        .as_deref()
+        .map(|input| ("input".to_owned(), input))
+        .into_iter()
+        .collect()
+}
+
+fn join_children<'a>(
+    left: &'a Option<Box<protobuf::PhysicalPlanNode>>,
+    right: &'a Option<Box<protobuf::PhysicalPlanNode>>,
+) -> Vec<(String, &'a protobuf::PhysicalPlanNode)> {
+    let left = left.as_deref().map(|left| ("left".to_owned(), left));
+    let right = right.as_deref().map(|right| ("right".to_owned(), right));
+    left.into_iter().chain(right).collect()
+}
+
+/// type checks of expressions which are not done when creating the node, the
+/// other nodes compute their output schema from the types of their expressions
+fn check_types(plan: &dyn ExecutionPlan) -> Result<(), PlanSerDeError> {
+    let any = plan.as_any();
+    if let Some(exec) = any.downcast_ref::<SortExec>() {
+        let exprs = exec.expr().iter().map(|sort_expr| &sort_expr.expr);
+        check_expr_types(exprs, "expr", &exec.input().schema())?;
+    } else if let Some(exec) = any.downcast_ref::<RepartitionExec>() {
+        if let Partitioning::Hash(exprs, _) = exec.partitioning() {
+            check_expr_types(exprs.iter(), "hash_expr", &exec.input().schema())
+                .at("hash")?;
+        }
+    } else if let Some(exec) = any.downcast_ref::<ShuffleWriterExec>() {
+        if let ShuffleRepartitioning::Hash(exprs, _) = exec.partitioning() {
+            check_expr_types(exprs.iter(), "hash_expr", &exec.input().schema())
+                .at("output_partitioning.hash_repartition")?;
+        }
+    } else if let Some(exec) = any.downcast_ref::<HashJoinExec>() {
+        check_join_on(exec.on(), &exec.left().schema(), &exec.right().schema())?;
+    } else if let Some(exec) = any.downcast_ref::<SortMergeJoinExec>() {
+        check_join_on(&exec.on, &exec.left.schema(), &exec.right.schema())?;
+    }
+    Ok(())
+}
+
+fn check_expr_types<'a>(
+    exprs: impl Iterator<Item = &'a Arc<dyn PhysicalExpr>>,
+    name: &str,
+    input_schema: &Schema,
+) -> Result<(), PlanSerDeError> {
+    for (i, expr) in exprs.enumerate() {
+        expr.data_type(input_schema)
+            .and_then(|_| expr.nullable(input_schema))
+            .at(format!("{}[{}]", name, i))?;
+    }
+    Ok(())
+}
+
+fn check_join_on(
+    on: &[(Column, Column)],
+    left_schema: &Schema,
+    right_schema: &Schema,
+) -> Result<(), PlanSerDeError> {
+    for (i, (left, right)) in on.iter().enumerate() {
+        let left_type = left.data_type(left_schema).at(format!("on[{}].left", i))?;
+        let right_type = right
+            .data_type(right_schema)
+            .at(format!("on[{}].right", i))?;
+        if left_type != right_type {
+            return Err(PlanSerDeError::NotImplemented(format!(
+                "join on columns of different types {:?} and {:?}",
+                left_type, right_type,
+            ))
+            .at(format!("on[{}]", i)));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+
+    use crate::protobuf;
+    use crate::protobuf::physical_expr_node::ExprType;
+    use crate::protobuf::physical_plan_node::PhysicalPlanType;
+    use crate::validate::validate_physical_plan;
+
+    fn column(name: &str) -> protobuf::PhysicalExprNode {
+        protobuf::PhysicalExprNode {
+            expr_type: Some(ExprType::Column(protobuf::PhysicalColumn {
+                name: name.to_owned(),
+                index: 0,
+            })),
+        }
+    }
+
+    fn projection(
+        input: protobuf::PhysicalPlanNode,
+        column_name: &str,
+    ) -> protobuf::PhysicalPlanNode {
+        protobuf::PhysicalPlanNode {
+            physical_plan_type: Some(PhysicalPlanType::Projection(Box::new(
+                protobuf::ProjectionExecNode {
+                    input: Some(Box::new(input)),
+                    expr: vec![column(column_name)],
+                    expr_name: vec![column_name.to_owned()],
+                },
+            ))),
+        }
+    }
+
+    #[test]
+    fn test_validate_physical_plan() {
+        let schema = Schema::new(vec![Field::new("a", DataType::Int64, false)]);
+        let empty = protobuf::PhysicalPlanNode {
+            physical_plan_type: Some(PhysicalPlanType::EmptyPartitions(
+                protobuf::EmptyPartitionsExecNode {
+                    schema: Some((&schema).try_into().unwrap()),
+                    num_partitions: 1,
+                },
+            )),
+        };
+        let unresolved = protobuf::PhysicalPlanNode {
+            physical_plan_type: Some(PhysicalPlanType::Unresolved(
+                protobuf::UnresolvedShuffleExecNode::default(),
+            )),
+        };
+
+        let plan = protobuf::PhysicalPlanNode {
+            physical_plan_type: Some(PhysicalPlanType::Union(protobuf::UnionExecNode {
+                children: vec![projection(empty.clone(), "a")],
+            })),
+        };
+        assert!(validate_physical_plan(&plan).errors.is_empty());
+
+        // both unsupported children are reported
+        let plan = protobuf::PhysicalPlanNode {
+            physical_plan_type: Some(PhysicalPlanType::Union(protobuf::UnionExecNode {
+                children: vec![projection(empty, "b"), projection(unresolved, "a")],
+            })),
+        };
+        let paths = validate_physical_plan(&plan)
+            .errors
+            .into_iter()
+            .map(|err| err.path)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec!["root.children[0].expr[0]", "root.children[1].input"]
+        );
+    }
+}
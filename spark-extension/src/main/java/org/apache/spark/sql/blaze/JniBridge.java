@@ -22,20 +22,54 @@ import java.nio.ByteBuffer;
 import java.nio.channels.Channels;
 import java.nio.channels.ReadableByteChannel;
 import java.nio.channels.SeekableByteChannel;
+import java.util.Enumeration;
 import java.util.concurrent.ConcurrentHashMap;
 import org.apache.hadoop.fs.FSDataInputStream;
 import org.apache.hadoop.fs.FileSystem;
+import org.apache.log4j.LogManager;
 import org.apache.spark.TaskContext;
 import org.apache.spark.TaskContext$;
 import org.apache.spark.deploy.SparkHadoopUtil;
+import org.slf4j.Logger;
+import org.slf4j.LoggerFactory;
 
 public class JniBridge {
   public static final ConcurrentHashMap<String, Object> resourcesMap = new ConcurrentHashMap<>();
 
+  private static final String NATIVE_LOGGER_NAME = "blaze.native";
+  private static final ConcurrentHashMap<String, Logger> nativeLoggers =
+      new ConcurrentHashMap<>();
+
   public static native void initNative(
-      long batchSize, long nativeMemory, double memoryFraction, String tmpDirs);
+      long batchSize,
+      long nativeMemory,
+      double memoryFraction,
+      String tmpDirs,
+      String taskDumpDir,
+      long numWorkerThreads,
+      long taskParallelism,
+      long metricsUpdateIntervalMs);
 
-  public static native void callNative(BlazeCallNativeWrapper wrapper);
+  /** shuts down the native runtime shared by all tasks, called when the executor exits */
+  public static native void finalizeNative();
+
+  /**
+   * starts executing the native plan of the wrapper, exporting its output into the given
+   * ArrowArrayStream
+   *
+   * @return id of the native task, used for cancelling it
+   */
+  public static native long callNative(BlazeCallNativeWrapper wrapper, long streamPtr);
+
+  /** cancels a running native task, ignored if the task is already finished */
+  public static native void cancelNative(long nativeTaskId);
+
+  /**
+   * validates a serialized PhysicalPlanNode without executing it
+   *
+   * @return serialized PlanValidationResult
+   */
+  public static native byte[] validatePlan(byte[] rawPlan);
 
   public static ClassLoader getContextClassLoader() {
     return Thread.currentThread().getContextClassLoader();
@@ -61,6 +95,69 @@ public class JniBridge {
     TaskContext$.MODULE$.setTaskContext(tc);
   }
 
+  /**
+   * level filter of native logs, the most verbose level of the "blaze.native" logger and its
+   * child loggers with a configured level, records are then filtered by their own loggers
+   *
+   * @return 0: off, 1: error, 2: warn, 3: info, 4: debug, 5: trace
+   */
+  public static int getNativeLogLevel() {
+    int level = getLogLevel(LoggerFactory.getLogger(NATIVE_LOGGER_NAME));
+    Enumeration<?> loggers = LogManager.getCurrentLoggers();
+    while (loggers.hasMoreElements()) {
+      org.apache.log4j.Logger logger = (org.apache.log4j.Logger) loggers.nextElement();
+      if (logger.getName().startsWith(NATIVE_LOGGER_NAME + ".") && logger.getLevel() != null) {
+        level = Math.max(level, getLogLevel(LoggerFactory.getLogger(logger.getName())));
+      }
+    }
+    return level;
+  }
+
+  private static int getLogLevel(Logger logger) {
+    if (logger.isTraceEnabled()) {
+      return 5;
+    } else if (logger.isDebugEnabled()) {
+      return 4;
+    } else if (logger.isInfoEnabled()) {
+      return 3;
+    } else if (logger.isWarnEnabled()) {
+      return 2;
+    } else if (logger.isErrorEnabled()) {
+      return 1;
+    }
+    return 0;
+  }
+
+  /**
+   * logs a native log record, the rust module path of the record is mapped to logger
+   * "blaze.native.{module}", e.g. "blaze.native.datafusion_ext.shuffle_writer_exec"
+   *
+   * @param level level of the record, same as getNativeLogLevel()
+   */
+  public static void nativeLog(int level, String target, String message) {
+    Logger logger =
+        nativeLoggers.computeIfAbsent(
+            target,
+            t -> LoggerFactory.getLogger(NATIVE_LOGGER_NAME + "." + t.replace("::", ".")));
+    switch (level) {
+      case 1:
+        logger.error(message);
+        break;
+      case 2:
+        logger.warn(message);
+        break;
+      case 3:
+        logger.info(message);
+        break;
+      case 4:
+        logger.debug(message);
+        break;
+      default:
+        logger.trace(message);
+        break;
+    }
+  }
+
   /**
    * shim method to FSDataInputStream.read()
    *
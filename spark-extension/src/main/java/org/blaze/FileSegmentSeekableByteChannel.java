@@ -37,6 +37,18 @@ public class FileSegmentSeekableByteChannel implements SeekableByteChannel {
     this.length = length;
   }
 
+  public File getFile() {
+    return this.file;
+  }
+
+  public long getOffset() {
+    return this.offset;
+  }
+
+  public long getLength() {
+    return this.length;
+  }
+
   @Override
   public int read(ByteBuffer dst) throws IOException {
     FileChannel channel = null;
@@ -0,0 +1,48 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+package org.apache.spark.sql.blaze
+
+import scala.collection.JavaConverters._
+
+import org.blaze.protobuf.NativeError
+import org.blaze.protobuf.NativeErrorKind
+
+/**
+ * Failure of a native execution reported by the native side, with the kind of the error and
+ * the name of the failing operator if known. Nested causes of the native error are chained as
+ * causes of the exception, ending with the JVM-side cause.
+ */
+class BlazeNativeException(
+    val kind: NativeErrorKind,
+    val operator: Option[String],
+    message: String,
+    cause: Throwable)
+    extends RuntimeException(message, cause)
+
+object BlazeNativeException {
+  def apply(nativeError: NativeError, cause: Throwable): BlazeNativeException = {
+    val operator = Some(nativeError.getOperator).filter(_.nonEmpty)
+    val causes = nativeError.getCausesList.asScala
+    val nestedCause = causes.drop(1).foldRight(cause) { (message, cause) =>
+      new RuntimeException(message, cause)
+    }
+    val message = s"[${nativeError.getKind}] ${operator.getOrElse("native execution")} " +
+      s"failed: ${causes.headOption.getOrElse("unknown error")}"
+    new BlazeNativeException(nativeError.getKind, operator, message, nestedCause)
+  }
+}
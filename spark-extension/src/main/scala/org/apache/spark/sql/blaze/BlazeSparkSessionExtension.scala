@@ -183,19 +183,38 @@ private object Util extends Logging {
     SparkEnv.get.conf.getBoolean(ENABLE_OPERATION + "union", defaultValue = true)
   val enableSmj: Boolean =
     SparkEnv.get.conf.getBoolean(ENABLE_OPERATION + "sortmergejoin", defaultValue = true)
+  val enableValidation: Boolean =
+    SparkEnv.get.conf.getBoolean(ENABLE_OPERATION + "validation", defaultValue = true)
 
   val skewJoinSortChildrenTag: TreeNodeTag[Boolean] = TreeNodeTag("skewJoinSortChildren")
 
   def tryConvert[T <: SparkPlan](exec: T, convert: T => SparkPlan): SparkPlan =
     try {
       val convertedExec = convert(exec)
+      if (enableValidation) {
+        validateNativeExec(convertedExec)
+      }
       convertedExec
     } catch {
-      case e @ (_: NotImplementedError | _: Exception) =>
+      // LinkageError is thrown by validation if the native library cannot be loaded
+      case e @ (_: NotImplementedError | _: LinkageError | _: Exception) =>
         logWarning(s"Error converting exec: ${exec.getClass.getSimpleName}: ${e.getMessage}")
         exec
     }
 
+  def validateNativeExec(exec: SparkPlan): Unit =
+    exec match {
+      case exec: NativeSupports =>
+        exec.nativePlanForValidation.foreach { nativePlan =>
+          val errors = NativeSupports.validateNativePlan(nativePlan)
+          if (errors.nonEmpty) {
+            val messages = errors.map(e => s"${e.getMessage} (at ${e.getPath})")
+            throw new NotImplementedError(s"unsupported native plan: ${messages.mkString("; ")}")
+          }
+        }
+      case _ =>
+    }
+
   def convertShuffleExchangeExec(exec: ShuffleExchangeExec): SparkPlan = {
     val ShuffleExchangeExec(outputPartitioning, child, noUserSpecifiedNumPartition) = exec
     logDebug(s"Converting ShuffleExchangeExec: ${exec.simpleStringWithNodeId}")
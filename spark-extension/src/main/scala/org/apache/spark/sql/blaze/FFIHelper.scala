@@ -17,20 +17,12 @@
 
 package org.apache.spark.sql.blaze
 
-import java.util.concurrent.SynchronousQueue
-import java.util.concurrent.TimeUnit
-
 import scala.collection.JavaConverters._
 
-import org.apache.arrow.c.ArrowArray
-import org.apache.arrow.c.ArrowSchema
-import org.apache.arrow.c.CDataDictionaryProvider
-import org.apache.arrow.c.Data
 import org.apache.arrow.vector.VectorSchemaRoot
 import org.apache.spark.TaskContext
 import org.apache.spark.sql.catalyst.InternalRow
 import org.apache.spark.sql.util2.ArrowColumnVector
-import org.apache.spark.sql.util2.ArrowUtils2
 import org.apache.spark.sql.vectorized.ColumnarBatch
 import org.apache.spark.sql.vectorized.ColumnVector
 import org.apache.spark.util.CompletionIterator
@@ -66,67 +58,44 @@ object FFIHelper {
   def fromBlazeCallNativeColumnar(
       wrapper: BlazeCallNativeWrapper,
       context: TaskContext): Iterator[ColumnarBatch] = {
-    val allocator =
-      ArrowUtils2.rootAllocator.newChildAllocator("fromBlazeCallNativeColumnar", 0, Long.MaxValue)
-    val provider = new CDataDictionaryProvider()
-
-    val root = tryWithResource(ArrowSchema.allocateNew(allocator)) { consumerSchema =>
-      tryWithResource(ArrowArray.allocateNew(allocator)) { consumerArray =>
-        val schemaPtr: Long = consumerSchema.memoryAddress
-        val arrayPtr: Long = consumerArray.memoryAddress
-        val hasNext = wrapper.nextBatch(schemaPtr, arrayPtr)
-        if (!hasNext) {
-          return CompletionIterator[ColumnarBatch, Iterator[ColumnarBatch]](
-            Iterator.empty, {
-              wrapper.finish()
-              allocator.close()
-            })
-        }
-        val root: VectorSchemaRoot =
-          Data.importVectorSchemaRoot(allocator, consumerArray, consumerSchema, provider)
-        root
-      }
-    }
+    val reader = wrapper.reader
+    val root = reader.getVectorSchemaRoot
 
     new Iterator[ColumnarBatch] {
-      private var batch: ColumnarBatch = _
+      private var batchLoaded = false
       private var finished = false
 
       context.addTaskCompletionListener[Unit] { _ =>
         finish()
-        allocator.close()
       }
 
       override def hasNext: Boolean =
-        !finished && {
-          if (batch == null) { // first call
-            batch = rootAsBatch(root)
-            return true
-          }
-          tryWithResource(ArrowSchema.allocateNew(allocator)) { consumerSchema =>
-            tryWithResource(ArrowArray.allocateNew(allocator)) { consumerArray =>
-              val schemaPtr: Long = consumerSchema.memoryAddress
-              val arrayPtr: Long = consumerArray.memoryAddress
-              val hasNext = wrapper.nextBatch(schemaPtr, arrayPtr)
-              if (!hasNext) {
-                finish()
-                return false
-              }
-
-              Data.importIntoVectorSchemaRoot(allocator, consumerArray, root, provider)
-              batch = rootAsBatch(root)
-              true
+        !finished && (batchLoaded || {
+          // blocks until the native side produces the next batch
+          batchLoaded =
+            try {
+              reader.loadNextBatch()
+            } catch {
+              case e: Throwable => throw wrapper.wrapError(e)
             }
+          if (!batchLoaded) {
+            finish()
           }
-        }
+          batchLoaded
+        })
 
-      override def next(): ColumnarBatch = batch
+      override def next(): ColumnarBatch = {
+        if (!hasNext) {
+          throw new NoSuchElementException()
+        }
+        batchLoaded = false
+        rootAsBatch(root)
+      }
 
       private def finish(): Unit = {
         if (!finished) {
           finished = true
-          wrapper.finish()
-          root.close()
+          wrapper.close()
         }
       }
     }
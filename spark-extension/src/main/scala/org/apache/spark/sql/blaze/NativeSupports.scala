@@ -19,15 +19,19 @@ package org.apache.spark.sql.blaze
 
 import java.io.{File, FileNotFoundException, IOException}
 import java.nio.file.{Files, StandardCopyOption}
-import java.util.concurrent.SynchronousQueue
+import java.util.concurrent.ConcurrentHashMap
 import java.util.concurrent.TimeUnit
-import java.util.concurrent.atomic.AtomicBoolean
 
 import scala.annotation.tailrec
 import scala.collection.immutable.TreeMap
+import scala.collection.JavaConverters._
 
+import org.apache.arrow.c.ArrowArrayStream
+import org.apache.arrow.c.Data
+import org.apache.arrow.vector.ipc.ArrowReader
 import org.apache.spark.sql.execution.SparkPlan
 import org.apache.spark.SparkException
+import org.apache.spark.TaskKilledException
 import org.apache.spark.sql.catalyst.InternalRow
 import org.apache.spark.sql.execution.adaptive.CustomShuffleReaderExec
 import org.apache.spark.sql.execution.adaptive.QueryStageExec
@@ -40,14 +44,36 @@ import org.apache.spark.Partition
 import org.apache.spark.sql.vectorized.ColumnarBatch
 import org.apache.spark.SparkContext
 import org.apache.spark.rdd.RDD
+import org.apache.spark.shuffle.FetchFailedException
+import org.apache.spark.sql.blaze.execution.ArrowBlockStoreShuffleReader301
 import org.apache.spark.sql.execution.metric.SQLMetrics
+import org.apache.spark.sql.types.StructField
+import org.apache.spark.sql.types.StructType
+import org.apache.spark.sql.util2.ArrowUtils2
+import org.apache.spark.util.ShutdownHookManager
+import org.apache.spark.util.ThreadUtils
+import org.blaze.protobuf.EmptyPartitionsExecNode
+import org.blaze.protobuf.NativeError
+import org.blaze.protobuf.NativeErrorKind
 import org.blaze.protobuf.PartitionId
 import org.blaze.protobuf.PhysicalPlanNode
+import org.blaze.protobuf.PlanValidationError
+import org.blaze.protobuf.PlanValidationResult
+import org.blaze.protobuf.ShuffleWriteResult
 import org.blaze.protobuf.TaskDefinition
 
 trait NativeSupports extends SparkPlan {
   def doExecuteNative(): NativeRDD
 
+  /**
+   * Native plan of this exec with its input replaced by an empty input of the same schema, used
+   * to validate the plan on the driver. None if the plan cannot be built without executing.
+   *
+   * Only filter, project, sort and sort merge join execs are validated, other native execs are
+   * converted without validation.
+   */
+  def nativePlanForValidation: Option[PhysicalPlanNode] = None
+
   protected override def doExecute(): RDD[InternalRow] = doExecuteNative()
   protected override def doExecuteColumnar(): RDD[ColumnarBatch] = doExecuteNative().toColumnar
 }
@@ -103,6 +129,35 @@ object NativeSupports extends Logging {
     FFIHelper.fromBlazeCallNativeColumnar(wrapper, context)
   }
 
+  /**
+   * Validates a native plan without executing it, returns all unsupported nodes and
+   * expressions of the plan.
+   */
+  def validateNativePlan(nativePlan: PhysicalPlanNode): Seq[PlanValidationError] = {
+    BlazeCallNativeWrapper.loadNativeLibrary()
+    val rawResult = JniBridge.validatePlan(nativePlan.toByteArray)
+    PlanValidationResult.parseFrom(rawResult).getErrorsList.asScala
+  }
+
+  /**
+   * An input producing no rows with the output schema of a native plan, so that plans on top of
+   * it can be validated.
+   */
+  def validationInput(plan: SparkPlan): PhysicalPlanNode = {
+    val nativeSchema = NativeConverters.convertSchema(StructType(plan.output.map { a =>
+      StructField(a.toString(), a.dataType, a.nullable, a.metadata)
+    }))
+    PhysicalPlanNode
+      .newBuilder()
+      .setEmptyPartitions(
+        EmptyPartitionsExecNode
+          .newBuilder()
+          .setSchema(nativeSchema)
+          .setNumPartitions(1)
+          .build())
+      .build()
+  }
+
   def getDefaultNativeMetrics(sc: SparkContext): Map[String, SQLMetric] =
     TreeMap(
       "output_rows" -> SQLMetrics.createMetric(sc, "Native.output_rows"),
@@ -110,20 +165,33 @@ object NativeSupports extends Logging {
       "input_rows" -> SQLMetrics.createMetric(sc, "Native.input_rows"),
       "input_batches" -> SQLMetrics.createMetric(sc, "Native.input_batches"),
       "elapsed_compute" -> SQLMetrics.createNanoTimingMetric(sc, "Native.elapsed_compute"),
-      "join_time" -> SQLMetrics.createNanoTimingMetric(sc, "Native.join_time"))
+      "join_time" -> SQLMetrics.createNanoTimingMetric(sc, "Native.join_time"),
+      "spill_count" -> SQLMetrics.createMetric(sc, "Native.spill_count"),
+      "spilled_bytes" -> SQLMetrics.createSizeMetric(sc, "Native.spilled_bytes"))
 
 }
 
+/**
+ * Metrics of a native plan node, updated by the native side. All native metrics are reported,
+ * those not declared by the node are ignored.
+ */
 case class MetricNode(metrics: Map[String, SQLMetric], children: Seq[MetricNode])
     extends Logging {
+
+  /** metric node of the i-th input, or null if the input has no metrics */
   def getChild(i: Int): MetricNode =
-    children(i)
+    children.lift(i).orNull
 
   def add(metricName: String, v: Long): Unit = {
     metrics.get(metricName) match {
-      case Some(metric) => metric.add(v)
+      case Some(metric) =>
+        // size and timing metrics start at -1 until set
+        if (metric.value < 0) {
+          metric.set(0)
+        }
+        metric.add(v)
       case None =>
-        logWarning(s"Ignore non-exist metric: ${metricName}")
+        logDebug(s"Ignore non-exist metric: ${metricName}")
     }
   }
 }
@@ -135,9 +203,8 @@ case class BlazeCallNativeWrapper(
     metrics: MetricNode)
     extends Logging {
 
-  private val valueQueue: SynchronousQueue[Object] = new SynchronousQueue()
-  private val errorQueue: SynchronousQueue[Object] = new SynchronousQueue()
-  private val finished: AtomicBoolean = new AtomicBoolean(false)
+  @volatile private var shuffleWriteResult: Option[ShuffleWriteResult] = None
+  @volatile private var nativeError: Option[NativeError] = None
 
   BlazeCallNativeWrapper.synchronized {
     val conf = SparkEnv.get.conf
@@ -145,27 +212,136 @@ case class BlazeCallNativeWrapper(
     val nativeMemory = conf.getLong("spark.executor.memoryOverhead", Long.MaxValue) * 1024 * 1024;
     val memoryFraction = conf.getDouble("spark.blaze.memoryFraction", 0.75);
     val tmpDirs = SparkEnv.get.blockManager.diskBlockManager.localDirsString.mkString(",")
+    val taskDumpDir = conf.get("spark.blaze.dumpFailedTasksDir", "")
+
+    // one worker thread for each core of the concurrently running tasks
+    val executorCores =
+      conf.getInt("spark.executor.cores", Runtime.getRuntime.availableProcessors())
+    val taskCpus = conf.getInt("spark.task.cpus", 1)
+    val numWorkerThreads = conf.getLong(
+      "spark.blaze.numWorkerThreads",
+      math.max(executorCores / taskCpus, 1) * taskCpus)
+
+    // split scans of a task into sub-partitions for each of its cores
+    val taskParallelism =
+      if (conf.getBoolean("spark.blaze.intraTaskParallelism", false)) taskCpus else 1
+
+    // report metrics of running tasks periodically, 0 to report only when finished
+    val metricsUpdateIntervalMs = conf.getLong("spark.blaze.metricsUpdateIntervalMs", 10000)
 
     if (!BlazeCallNativeWrapper.nativeInitialized) {
-      logInfo(s"Initializing native environment ...")
-      BlazeCallNativeWrapper.load("blaze")
-      JniBridge.initNative(batchSize, nativeMemory, memoryFraction, tmpDirs)
+      logInfo(s"Initializing native environment with $numWorkerThreads worker threads ...")
+      BlazeCallNativeWrapper.loadNativeLibrary()
+      JniBridge.initNative(
+        batchSize,
+        nativeMemory,
+        memoryFraction,
+        tmpDirs,
+        taskDumpDir,
+        numWorkerThreads,
+        taskParallelism,
+        metricsUpdateIntervalMs)
+      ShutdownHookManager.addShutdownHook(() => JniBridge.finalizeNative())
       BlazeCallNativeWrapper.nativeInitialized = true
     }
   }
 
-  logInfo(s"Start executing native plan")
-  JniBridge.callNative(this)
+  private val allocator =
+    ArrowUtils2.rootAllocator.newChildAllocator("BlazeCallNativeWrapper", 0, Long.MaxValue)
+  private var closed = false
+  private var cancelled = false
+  private var nativeTaskId: Long = _
+
+  /**
+   * Output batches of the native plan, pulled from an arrow stream exported by the native side.
+   * Errors of the native execution are thrown when loading the next batch.
+   */
+  val reader: ArrowReader = {
+    logInfo(s"Start executing native plan")
+    FFIHelper.tryWithResource(ArrowArrayStream.allocateNew(allocator)) { stream =>
+      try {
+        nativeTaskId = JniBridge.callNative(this, stream.memoryAddress)
+      } catch {
+        case e: Throwable => throw wrapError(e)
+      }
+      Data.importArrayStream(allocator, stream)
+    }
+  }
+  BlazeCallNativeWrapper.startCheckingKilled(this)
+
+  /** Cancels the native execution, pending and later batches fail with an error. */
+  def cancel(): Unit = synchronized {
+    if (!closed && !cancelled) {
+      cancelled = true
+      logInfo(s"Cancelling native execution of killed task ${context.taskAttemptId()}")
+      JniBridge.cancelNative(nativeTaskId)
+    }
+  }
 
-  def isFinished: Boolean = finished.get()
-  def finish(): Unit = {
-    if (!isFinished) {
-      finished.set(true)
+  /** Releases the stream and stops the native execution if not finished. */
+  def close(): Unit = synchronized {
+    if (!closed) {
+      closed = true
+      BlazeCallNativeWrapper.stopCheckingKilled(this)
+      JniBridge.cancelNative(nativeTaskId)
+      reader.close()
+      allocator.close()
     }
   }
 
   protected def getMetrics: MetricNode = metrics
 
+  /**
+   * Statistics of output partitions if the native plan is a shuffle writer, available
+   * after all output is consumed.
+   */
+  def getShuffleWriteResult: Option[ShuffleWriteResult] = shuffleWriteResult
+
+  protected def setRawShuffleWriteResult(rawShuffleWriteResult: Array[Byte]): Unit = {
+    shuffleWriteResult = Some(ShuffleWriteResult.parseFrom(rawShuffleWriteResult))
+  }
+
+  /**
+   * Wraps an error of the native execution reported by the native side into the exception
+   * expected by Spark: cancellations are reported as killed tasks and corrupted or unreadable
+   * shuffle blocks as fetch failures, so that the map outputs are recomputed. Other errors are
+   * wrapped into a [[BlazeNativeException]].
+   */
+  def wrapError(e: Throwable): Throwable = (e, nativeError) match {
+    case (_: BlazeNativeException | _: FetchFailedException | _: TaskKilledException, _) => e
+    case (_, Some(nativeError)) =>
+      val nativeException = BlazeNativeException(nativeError, e)
+      nativeError.getKind match {
+        case NativeErrorKind.CANCELLED =>
+          new TaskKilledException(context.getKillReason().getOrElse(nativeException.getMessage))
+        case NativeErrorKind.SHUFFLE_CORRUPTED | NativeErrorKind.IO =>
+          fetchFailure(nativeError, nativeException).getOrElse(nativeException)
+        case _ => nativeException
+      }
+    case (_, None) => e
+  }
+
+  private def fetchFailure(
+      nativeError: NativeError,
+      cause: Throwable): Option[FetchFailedException] = {
+    // a failed fetch of the JVM block iterator has already been recorded in the task context
+    context.fetchFailed.orElse {
+      Some(nativeError.getCorruptedShuffleId).filter(_.nonEmpty).flatMap { nativeShuffleId =>
+        ArrowBlockStoreShuffleReader301.fetchFailedException(
+          nativeShuffleId,
+          nativeError.getCorruptedBlockIndex.toInt,
+          cause)
+      }
+    }
+  }
+
+  protected def setRawNativeError(rawNativeError: Array[Byte]): Unit = synchronized {
+    // keep the first error, later errors are caused by it
+    if (nativeError.isEmpty) {
+      nativeError = Some(NativeError.parseFrom(rawNativeError))
+    }
+  }
+
   protected def getRawTaskDefinition: Array[Byte] = {
     // do not use context.partitionId since it is not correct in Union plans.
     val partitionId: PartitionId = PartitionId
@@ -182,50 +358,41 @@ case class BlazeCallNativeWrapper(
       .build()
     taskDefinition.toByteArray
   }
+}
 
-  def nextBatch(schemaPtr: Long, arrayPtr: Long): Boolean = {
-    while (!isFinished && { checkError(); true } && !enqueueWithTimeout((schemaPtr, arrayPtr))) {}
-    while (!isFinished && { checkError(); true }) {
-      dequeueWithTimeout() match {
-        case java.lang.Boolean.TRUE =>
-          return true
-
-        case java.lang.Boolean.FALSE =>
-          finish()
-          return false
-
-        case null =>
-        // do nothing
-      }
-    }
-    !isFinished
-  }
+object BlazeCallNativeWrapper {
+  private var nativeLoaded: Boolean = false
+  private var nativeInitialized: Boolean = false
 
-  protected def enqueueWithTimeout(value: Object): Boolean = {
-    valueQueue.offer(value, 100, TimeUnit.MILLISECONDS)
+  // wrappers of running tasks, cancelled when their spark tasks are killed since the
+  // task threads are blocked in native code
+  private val runningWrappers = new ConcurrentHashMap[Long, BlazeCallNativeWrapper]()
+  private lazy val killedTaskChecker = {
+    val intervalMs = SparkEnv.get.conf.getLong("spark.blaze.killedTaskCheckIntervalMs", 100)
+    val executor = ThreadUtils.newDaemonSingleThreadScheduledExecutor("blaze-killed-task-checker")
+    executor.scheduleWithFixedDelay(
+      () => runningWrappers.values.asScala.filter(_.context.isInterrupted()).foreach(_.cancel()),
+      intervalMs,
+      intervalMs,
+      TimeUnit.MILLISECONDS)
+    executor
   }
 
-  protected def dequeueWithTimeout(): Object = {
-    valueQueue.poll(100, TimeUnit.MILLISECONDS)
+  private def startCheckingKilled(wrapper: BlazeCallNativeWrapper): Unit = {
+    killedTaskChecker
+    runningWrappers.put(wrapper.nativeTaskId, wrapper)
   }
 
-  protected def enqueueError(value: Object): Boolean = {
-    errorQueue.offer(value, 100, TimeUnit.MILLISECONDS)
+  private def stopCheckingKilled(wrapper: BlazeCallNativeWrapper): Unit = {
+    runningWrappers.remove(wrapper.nativeTaskId)
   }
 
-  protected def checkError(): Unit = {
-    errorQueue.poll() match {
-      case e: Throwable =>
-        finish()
-        throw e
-      case null =>
-      // do nothing
+  def loadNativeLibrary(): Unit = synchronized {
+    if (!nativeLoaded) {
+      load("blaze")
+      nativeLoaded = true
     }
   }
-}
-
-object BlazeCallNativeWrapper {
-  private var nativeInitialized: Boolean = false
 
   private def load(name: String): Unit = {
     val libraryToLoad = System.mapLibraryName(name)
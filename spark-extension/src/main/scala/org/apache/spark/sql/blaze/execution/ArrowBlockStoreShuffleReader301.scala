@@ -17,6 +17,8 @@
 
 package org.apache.spark.sql.blaze.execution
 
+import java.util.concurrent.ConcurrentHashMap
+
 import org.apache.spark.InterruptibleIterator
 import org.apache.spark.MapOutputTracker
 import org.apache.spark.SparkEnv
@@ -26,6 +28,7 @@ import org.apache.spark.internal.config
 import org.apache.spark.io.CompressionCodec
 import org.apache.spark.serializer.SerializerManager
 import org.apache.spark.shuffle.BaseShuffleHandle
+import org.apache.spark.shuffle.FetchFailedException
 import org.apache.spark.shuffle.ShuffleReader
 import org.apache.spark.shuffle.ShuffleReadMetricsReporter
 import org.apache.spark.sql.blaze.JniBridge
@@ -52,7 +55,7 @@ class ArrowBlockStoreShuffleReader301[K, C](
 
   /** Read the combined key-values for this reduce task */
   override def read(): Iterator[Product2[K, C]] = {
-    val provideBuffers = () =>
+    val createFetcher = () =>
       new ShuffleBlockFetcherIterator301(
         context,
         SparkEnv.get.blockManager.blockStoreClient,
@@ -66,18 +69,25 @@ class ArrowBlockStoreShuffleReader301[K, C](
         SparkEnv.get.conf.get(config.SHUFFLE_DETECT_CORRUPT),
         SparkEnv.get.conf.get(config.SHUFFLE_DETECT_CORRUPT_MEMORY),
         readMetrics,
-        fetchContinuousBlocksInBatch).toCompletionIterator
+        fetchContinuousBlocksInBatch)
+    val provideBuffers = () => createFetcher().toCompletionIterator
 
     // Store buffers in JniBridge
     val resourceId = ArrowShuffleExchangeExec301.getNativeShuffleId(context, handle.shuffleId)
     val provideIpcReader = () => {
-      val ipcIterator = provideBuffers().flatMap {
+      // keep the fetcher to report corrupted blocks found by the native reader
+      val fetcher = createFetcher()
+      ArrowBlockStoreShuffleReader301.nativeFetchers.put(resourceId, fetcher)
+      val ipcIterator = fetcher.toCompletionIterator.map {
         case (_, managedBuffer) =>
-          Converters.readManagedBufferToSegmentByteChannels(managedBuffer).toIterator
+          Converters.readManagedBufferToBlockByteChannel(managedBuffer)
       }
       new InterruptibleIterator(context, ipcIterator)
     }
     JniBridge.resourcesMap.put(resourceId, () => provideIpcReader())
+    context.addTaskCompletionListener[Unit] { _ =>
+      ArrowBlockStoreShuffleReader301.nativeFetchers.remove(resourceId)
+    }
 
     // Create a key/value iterator for each stream
     val recordIter = provideBuffers().flatMap {
@@ -149,3 +159,21 @@ class ArrowBlockStoreShuffleReader301[K, C](
     doBatchFetch
   }
 }
+
+object ArrowBlockStoreShuffleReader301 {
+
+  /** Block fetchers of running native shuffle readers, keyed by native shuffle id. */
+  private val nativeFetchers = new ConcurrentHashMap[String, ShuffleBlockFetcherIterator301]()
+
+  /**
+   * Creates a FetchFailedException of the `blockIndex`-th block read by the native shuffle
+   * reader of `nativeShuffleId`, if the block was fetched by a running task.
+   */
+  def fetchFailedException(
+      nativeShuffleId: String,
+      blockIndex: Int,
+      cause: Throwable): Option[FetchFailedException] = {
+    Option(nativeFetchers.get(nativeShuffleId))
+      .flatMap(_.fetchFailedException(blockIndex, cause))
+  }
+}
@@ -17,18 +17,31 @@
 
 package org.apache.spark.sql.blaze.execution
 
+import java.nio.channels.ReadableByteChannel
 import java.nio.channels.SeekableByteChannel
 
 import org.apache.arrow.vector.ipc.ArrowFileReader
+import org.apache.arrow.vector.ipc.ArrowReader
+import org.apache.arrow.vector.ipc.ArrowStreamReader
 import org.apache.spark.sql.catalyst.InternalRow
 import org.apache.spark.TaskContext
 import org.apache.spark.sql.blaze.FFIHelper
 import org.apache.spark.sql.util2.ArrowUtils2
 
-class ArrowReaderIterator(channel: SeekableByteChannel, taskContext: TaskContext) {
+/**
+ * Iterator of rows in an IPC stream, or in an IPC file which requires a seekable channel.
+ */
+class ArrowReaderIterator(
+    channel: ReadableByteChannel,
+    taskContext: TaskContext,
+    streamFormat: Boolean = false) {
   private val allocator =
     ArrowUtils2.rootAllocator.newChildAllocator("arrowReaderIterator", 0, Long.MaxValue)
-  private val arrowReader = new ArrowFileReader(channel, allocator)
+  private val arrowReader: ArrowReader = channel match {
+    case _ if streamFormat => new ArrowStreamReader(channel, allocator)
+    case seekable: SeekableByteChannel => new ArrowFileReader(seekable, allocator)
+    case _ => throw new IllegalArgumentException("IPC file requires a seekable channel")
+  }
   private val root = arrowReader.getVectorSchemaRoot
   private var closed = false
 
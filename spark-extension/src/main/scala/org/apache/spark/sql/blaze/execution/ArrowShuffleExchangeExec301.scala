@@ -17,6 +17,7 @@
 
 package org.apache.spark.sql.blaze.execution
 
+import java.lang.reflect.Field
 import java.nio.file.Paths
 import java.nio.ByteBuffer
 import java.nio.ByteOrder
@@ -27,9 +28,12 @@ import java.util.function.Supplier
 import scala.collection.JavaConverters._
 import scala.concurrent.Future
 import scala.reflect.ClassTag
+import scala.util.Try
 
 import org.apache.spark._
+import org.apache.spark.internal.Logging
 import org.apache.spark.internal.config
+import org.apache.spark.io.CompressionCodec
 import org.apache.spark.rdd.MapPartitionsRDD
 import org.apache.spark.rdd.RDD
 import org.apache.spark.scheduler.MapStatus
@@ -38,6 +42,8 @@ import org.apache.spark.shuffle.ShuffleWriteMetricsReporter
 import org.apache.spark.shuffle.ShuffleWriteProcessor
 import org.apache.spark.shuffle.sort.SortShuffleManager
 import org.apache.spark.shuffle.IndexShuffleBlockResolver
+import org.apache.spark.sql.blaze.BlazeCallNativeWrapper
+import org.apache.spark.sql.blaze.FFIHelper
 import org.apache.spark.sql.blaze.MetricNode
 import org.apache.spark.sql.blaze.NativeConverters
 import org.apache.spark.sql.blaze.NativeRDD
@@ -45,8 +51,12 @@ import org.apache.spark.sql.blaze.NativeSupports
 import org.apache.spark.sql.blaze.execution.ArrowShuffleExchangeExec301.canUseNativeShuffleWrite
 import org.apache.spark.sql.catalyst.InternalRow
 import org.apache.spark.sql.catalyst.errors.attachTree
+import org.apache.spark.sql.catalyst.expressions.Ascending
 import org.apache.spark.sql.catalyst.expressions.Attribute
 import org.apache.spark.sql.catalyst.expressions.BoundReference
+import org.apache.spark.sql.catalyst.expressions.Literal
+import org.apache.spark.sql.catalyst.expressions.NullsFirst
+import org.apache.spark.sql.catalyst.expressions.SortOrder
 import org.apache.spark.sql.catalyst.expressions.UnsafeProjection
 import org.apache.spark.sql.catalyst.expressions.UnsafeRow
 import org.apache.spark.sql.catalyst.expressions.codegen.LazilyGeneratedOrdering
@@ -65,9 +75,19 @@ import org.apache.spark.sql.types.StructType
 import org.apache.spark.util.MutablePair
 import org.apache.spark.util.collection.unsafe.sort.PrefixComparators
 import org.apache.spark.util.collection.unsafe.sort.RecordComparator
+import org.blaze.protobuf.PhysicalExprNode
 import org.blaze.protobuf.PhysicalHashRepartition
 import org.blaze.protobuf.PhysicalPlanNode
+import org.blaze.protobuf.PhysicalRangeBound
+import org.blaze.protobuf.PhysicalRangeRepartition
+import org.blaze.protobuf.PhysicalRepartition
+import org.blaze.protobuf.PhysicalRepartition.RepartitionTypeCase
+import org.blaze.protobuf.PhysicalRoundRobinRepartition
+import org.blaze.protobuf.PhysicalSingleRepartition
+import org.blaze.protobuf.PhysicalSortExprNode
 import org.blaze.protobuf.Schema
+import org.blaze.protobuf.ShuffleChecksumAlgorithm
+import org.blaze.protobuf.ShuffleCompressionCodec
 import org.blaze.protobuf.ShuffleReaderExecNode
 import org.blaze.protobuf.ShuffleWriterExecNode
 
@@ -88,6 +108,9 @@ case class ArrowShuffleExchangeExec301(
         SQLMetrics.createMetric(sparkContext, "Native.shuffle_read_rows"),
       "shuffle_read_elapsed_compute" ->
         SQLMetrics.createNanoTimingMetric(sparkContext, "Native.shuffle_read_elapsed_compute"),
+      "spill_count" -> SQLMetrics.createMetric(sparkContext, "Native.spill_count"),
+      "spilled_bytes" -> SQLMetrics.createSizeMetric(sparkContext, "Native.spilled_bytes"),
+      "peak_mem_used" -> SQLMetrics.createSizeMetric(sparkContext, "Native.peak_mem_used"),
       "dataSize" ->
         SQLMetrics.createSizeMetric(sparkContext, "data size")) ++ readMetrics ++ writeMetrics
 
@@ -181,6 +204,21 @@ case class ArrowShuffleExchangeExec301(
           .updated("elapsed_compute", metrics("shuffle_read_elapsed_compute")),
         Nil)
 
+    // expose hash partitioning to native plan, so that redundant repartitions
+    // can be avoided. the partitioning is not exposed if any of its expressions
+    // is not supported by native engine.
+    val nativeHashPartitioning = outputPartitioning match {
+      case HashPartitioning(expressions, _) =>
+        Try(expressions.map(NativeConverters.convertExpr)).toOption.map { hashExprs =>
+          PhysicalHashRepartition
+            .newBuilder()
+            .setPartitionCount(rdd.getNumPartitions)
+            .addAllHashExpr(hashExprs.asJava)
+            .build()
+        }
+      case _ => None
+    }
+
     new NativeRDD(
       sparkContext,
       nativeMetrics,
@@ -190,16 +228,18 @@ case class ArrowShuffleExchangeExec301(
         // store fetch iterator in jni resource before native compute
         rdd.compute(rdd.partitions(partition.index), taskContext)
 
+        val shuffleReader = ShuffleReaderExecNode
+          .newBuilder()
+          .setSchema(nativeSchema)
+          .setNumPartitions(rdd.getNumPartitions)
+          .setNativeShuffleId(
+            ArrowShuffleExchangeExec301.getNativeShuffleId(taskContext, shuffleId))
+          .setPrefetchDepth(SparkEnv.get.conf.getInt("spark.blaze.shuffle.prefetchDepth", 0))
+        nativeHashPartitioning.foreach(shuffleReader.setOutputPartitioning)
+
         PhysicalPlanNode
           .newBuilder()
-          .setShuffleReader(
-            ShuffleReaderExecNode
-              .newBuilder()
-              .setSchema(nativeSchema)
-              .setNumPartitions(rdd.getNumPartitions)
-              .setNativeShuffleId(
-                ArrowShuffleExchangeExec301.getNativeShuffleId(taskContext, shuffleId))
-              .build())
+          .setShuffleReader(shuffleReader.build())
           .build()
       })
   }
@@ -208,11 +248,18 @@ case class ArrowShuffleExchangeExec301(
     ShuffleExchangeExec(outputPartitioning, child, noUserSpecifiedNumPartition).canonicalized
 }
 
-object ArrowShuffleExchangeExec301 {
+object ArrowShuffleExchangeExec301 extends Logging {
   def canUseNativeShuffleWrite(
       rdd: RDD[InternalRow],
       outputPartitioning: Partitioning): Boolean = {
-    rdd.isInstanceOf[NativeRDD] && outputPartitioning.isInstanceOf[HashPartitioning]
+    rdd.isInstanceOf[NativeRDD] && (outputPartitioning match {
+      case _: HashPartitioning | SinglePartition => true
+      case _: RangePartitioning => rangeBoundsField.isDefined
+      // native shuffle writer does not sort rows before round-robin partitioning,
+      // so it can only be used when spark does not sort either
+      case RoundRobinPartitioning(n) => n == 1 || !SQLConf.get.sortBeforeRepartition
+      case _ => false
+    })
   }
 
   def getNativeShuffleId(context: TaskContext, shuffleId: Int): String =
@@ -226,8 +273,14 @@ object ArrowShuffleExchangeExec301 {
       metrics: Map[String, SQLMetric]): ShuffleDependency[Int, InternalRow, InternalRow] = {
 
     val nativeInputRDD = rdd.asInstanceOf[NativeRDD]
-    val HashPartitioning(expressions, numPartitions) =
-      outputPartitioning.asInstanceOf[HashPartitioning]
+    val nativePartitioning =
+      convertNativePartitioning(nativeInputRDD, outputAttributes, outputPartitioning)
+    val numNativePartitions = nativePartitioning.getRepartitionTypeCase match {
+      case RepartitionTypeCase.SINGLE_REPARTITION => 1
+      case RepartitionTypeCase.RANGE_REPARTITION =>
+        nativePartitioning.getRangeRepartition.getPartitionCount.toInt
+      case _ => outputPartitioning.numPartitions
+    }
 
     val nativeMetrics = MetricNode(
       metrics
@@ -249,12 +302,7 @@ object ArrowShuffleExchangeExec301 {
             ShuffleWriterExecNode
               .newBuilder()
               .setInput(nativeInputRDD.nativePlan(nativeInputPartition, taskContext))
-              .setOutputPartitioning(
-                PhysicalHashRepartition
-                  .newBuilder()
-                  .setPartitionCount(numPartitions)
-                  .addAllHashExpr(expressions.map(NativeConverters.convertExpr).asJava)
-                  .build())
+              .setOutputPartitioning(nativePartitioning)
               .buildPartial()
           ) // shuffleId is not set at the moment, will be set in ShuffleWriteProcessor
           .build()
@@ -265,13 +313,113 @@ object ArrowShuffleExchangeExec301 {
       serializer = serializer,
       shuffleWriterProcessor = createNativeShuffleWriteProcessor(metrics),
       partitioner = new Partitioner {
-        override def numPartitions: Int = outputPartitioning.numPartitions
+        override def numPartitions: Int = numNativePartitions
         override def getPartition(key: Any): Int = key.asInstanceOf[Int]
       },
       schema = StructType.fromAttributes(outputAttributes))
     dependency
   }
 
+  private def convertNativePartitioning(
+      rdd: RDD[InternalRow],
+      outputAttributes: Seq[Attribute],
+      outputPartitioning: Partitioning): PhysicalRepartition = {
+    val builder = PhysicalRepartition.newBuilder()
+    outputPartitioning match {
+      case SinglePartition =>
+        builder.setSingleRepartition(PhysicalSingleRepartition.getDefaultInstance)
+
+      case HashPartitioning(expressions, numPartitions) =>
+        builder.setHashRepartition(
+          PhysicalHashRepartition
+            .newBuilder()
+            .setPartitionCount(numPartitions)
+            .addAllHashExpr(expressions.map(NativeConverters.convertExpr).asJava))
+
+      case RoundRobinPartitioning(numPartitions) =>
+        builder.setRoundRobinRepartition(
+          PhysicalRoundRobinRepartition
+            .newBuilder()
+            .setPartitionCount(numPartitions))
+
+      case RangePartitioning(sortingExpressions, numPartitions) =>
+        val rangeBounds =
+          computeRangeBounds(rdd, outputAttributes, sortingExpressions, numPartitions)
+        val nativeSortExprs = sortingExpressions.map { sortOrder =>
+          PhysicalExprNode
+            .newBuilder()
+            .setSort(
+              PhysicalSortExprNode
+                .newBuilder()
+                .setExpr(NativeConverters.convertExpr(sortOrder.child))
+                .setAsc(sortOrder.direction == Ascending)
+                .setNullsFirst(sortOrder.nullOrdering == NullsFirst)
+                .build())
+            .build()
+        }
+        val nativeBounds = rangeBounds.map { bound =>
+          val values = sortingExpressions.zipWithIndex.map {
+            case (sortOrder, i) =>
+              val value = bound.get(i, sortOrder.dataType)
+              NativeConverters.convertExpr(Literal(value, sortOrder.dataType)).getLiteral
+          }
+          PhysicalRangeBound.newBuilder().addAllValue(values.asJava).build()
+        }
+        builder.setRangeRepartition(
+          PhysicalRangeRepartition
+            .newBuilder()
+            .setPartitionCount(rangeBounds.length + 1) // same as RangePartitioner
+            .addAllSortExpr(nativeSortExprs.asJava)
+            .addAllBounds(nativeBounds.toSeq.asJava))
+    }
+    builder.build()
+  }
+
+  /**
+   * Computes range bounds of native range partitioning by sampling the input, just like
+   * [[RangePartitioner]] in the non-native shuffle.
+   */
+  private def computeRangeBounds(
+      rdd: RDD[InternalRow],
+      outputAttributes: Seq[Attribute],
+      sortingExpressions: Seq[SortOrder],
+      numPartitions: Int): Array[InternalRow] = {
+    val rddForSampling = rdd.mapPartitionsInternal { iter =>
+      val projection =
+        UnsafeProjection.create(sortingExpressions.map(_.child), outputAttributes)
+      val mutablePair = new MutablePair[InternalRow, Null]()
+      iter.map(row => mutablePair.update(projection(row).copy(), null))
+    }
+    val orderingAttributes = sortingExpressions.zipWithIndex.map {
+      case (ord, i) =>
+        ord.copy(child = BoundReference(i, ord.dataType, ord.nullable))
+    }
+    implicit val ordering = new LazilyGeneratedOrdering(orderingAttributes)
+    val partitioner = new RangePartitioner(
+      numPartitions,
+      rddForSampling,
+      ascending = true,
+      samplePointsPerPartitionHint = SQLConf.get.rangeExchangeSampleSizePerPartition)
+
+    rangeBoundsField.get.get(partitioner).asInstanceOf[Array[InternalRow]]
+  }
+
+  /**
+   * The private `rangeBounds: Array[K]` field of [[RangePartitioner]], which is read through
+   * reflection. The field layout is checked against spark 3.0.x, native range partitioning
+   * is disabled and the JVM shuffle is used if the field is missing.
+   */
+  private lazy val rangeBoundsField: Option[Field] = {
+    val field = classOf[RangePartitioner[_, _]].getDeclaredFields
+      .find(_.getName.endsWith("rangeBounds"))
+    field match {
+      case Some(field) => field.setAccessible(true)
+      case None =>
+        logWarning("RangePartitioner.rangeBounds not found, native range shuffle disabled")
+    }
+    field
+  }
+
   /**
    * Returns a [[ShuffleDependency]] that will partition rows of its child based on
    * the partitioning scheme defined in `newPartitioning`. Those partitions of
@@ -497,6 +645,42 @@ object ArrowShuffleExchangeExec301 {
     }
   }
 
+  /**
+   * Maps spark.shuffle.compress / spark.io.compression.codec onto the codec used by the
+   * native shuffle writer. Codecs not supported natively fall back to lz4.
+   */
+  def getNativeShuffleCompressionCodec(conf: SparkConf): ShuffleCompressionCodec = {
+    if (!conf.get(config.SHUFFLE_COMPRESS)) {
+      return ShuffleCompressionCodec.NO_COMPRESSION
+    }
+    CompressionCodec.getShortName(conf.get(config.IO_COMPRESSION_CODEC)) match {
+      case "lz4" => ShuffleCompressionCodec.LZ4
+      case "zstd" => ShuffleCompressionCodec.ZSTD
+      case other =>
+        logWarning(s"compression codec $other is not supported in native shuffle, using lz4")
+        ShuffleCompressionCodec.LZ4
+    }
+  }
+
+  /**
+   * Maps spark.shuffle.checksum.enabled / spark.shuffle.checksum.algorithm (introduced in
+   * spark 3.2) onto the checksum algorithm used by the native shuffle writer. Checksums are
+   * disabled unless explicitly enabled, since spark 3.0 does not use them. They are only
+   * written as trailers of partitions in the data file, no spark `.checksum` file is produced.
+   */
+  def getNativeShuffleChecksumAlgorithm(conf: SparkConf): ShuffleChecksumAlgorithm = {
+    if (!conf.getBoolean("spark.shuffle.checksum.enabled", defaultValue = false)) {
+      return ShuffleChecksumAlgorithm.NO_CHECKSUM
+    }
+    conf.get("spark.shuffle.checksum.algorithm", "ADLER32").toUpperCase match {
+      case "ADLER32" => ShuffleChecksumAlgorithm.ADLER32
+      case "CRC32C" => ShuffleChecksumAlgorithm.CRC32C
+      case other =>
+        logWarning(s"checksum algorithm $other is not supported in native shuffle, using ADLER32")
+        ShuffleChecksumAlgorithm.ADLER32
+    }
+  }
+
   def createNativeShuffleWriteProcessor(
       metrics: Map[String, SQLMetric]): ShuffleWriteProcessor = {
     new ShuffleWriteProcessor {
@@ -527,21 +711,40 @@ object ArrowShuffleExchangeExec301 {
               .newBuilder(nativeShuffleRDD.nativePlan(partition, context).getShuffleWriter)
               .setOutputDataFile(tempDataFilePath)
               .setOutputIndexFile(tempIndexFilePath)
+              .setCompressionCodec(getNativeShuffleCompressionCodec(SparkEnv.get.conf))
+              .setChecksumAlgorithm(getNativeShuffleChecksumAlgorithm(SparkEnv.get.conf))
+              .setCollectColumnStats(
+                SparkEnv.get.conf.getBoolean("spark.blaze.shuffle.columnStats", false))
+              .setSortBasedPartitionThreshold(SparkEnv.get.conf
+                .getInt("spark.blaze.shuffle.sortBasedPartitionThreshold", 1000))
               .build())
           .build()
-        val iterator = NativeSupports.executeNativePlan(
+        val wrapper = BlazeCallNativeWrapper(
           nativeShuffleWriterExec,
-          nativeShuffleRDD.metrics,
           partition,
-          context)
-        assert(iterator.toArray.isEmpty)
+          context,
+          nativeShuffleRDD.metrics)
+        assert(FFIHelper.fromBlazeCallNative(wrapper, context).toArray.isEmpty)
+
+        // report written records and bytes from statistics of output partitions
+        val writeMetrics = createMetricsReporter(context)
+        wrapper.getShuffleWriteResult.foreach { result =>
+          result.getPartitionsList.asScala.foreach { p =>
+            writeMetrics.incRecordsWritten(p.getNumRows)
+            writeMetrics.incBytesWritten(p.getNumBytes)
+            logDebug(
+              s"native shuffle write: shuffleId=${dep.shuffleId}, mapId=$mapId, " +
+                s"partition=${p.getPartitionId}, rows=${p.getNumRows}, " +
+                s"batches=${p.getNumBatches}, bytes=${p.getNumBytes}")
+          }
+        }
 
         // get partition lengths from shuffle write output index file
         var offset = 0L
         val partitionLengths = Files
           .readAllBytes(Paths.get(tempIndexFilePath))
           .grouped(8)
-          .drop(1) // first partition offset is always 0
+          .slice(1, dep.partitioner.numPartitions + 1) // first partition offset is always 0
           .map(indexBytes => {
             val partitionOffset =
               ByteBuffer.wrap(indexBytes).order(ByteOrder.LITTLE_ENDIAN).getLong
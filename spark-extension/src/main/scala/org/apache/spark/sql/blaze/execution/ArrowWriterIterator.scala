@@ -17,6 +17,8 @@
 
 package org.apache.spark.sql.blaze.execution
 
+import java.nio.ByteBuffer
+import java.nio.ByteOrder
 import java.nio.channels.Channels
 
 import org.apache.arrow.vector.VectorSchemaRoot
@@ -64,6 +66,11 @@ class ArrowWriterIterator(
         writer.start()
         writer.writeBatch()
         writer.end()
+
+        // followed by IPC length, in the same format of native shuffle blocks
+        val ipcLength = outputStream.size()
+        outputStream.write(
+          ByteBuffer.allocate(8).order(ByteOrder.LITTLE_ENDIAN).putLong(ipcLength).array())
         writer.close()
       }
     }
@@ -17,13 +17,25 @@
 
 package org.apache.spark.sql.blaze.execution
 
+import java.io.BufferedInputStream
+import java.io.EOFException
+import java.io.IOException
+import java.io.InputStream
 import java.io.RandomAccessFile
 import java.nio.ByteBuffer
+import java.nio.channels.Channels
 import java.nio.channels.SeekableByteChannel
 import java.nio.ByteOrder
+import java.nio.charset.StandardCharsets
+import java.util.zip.Adler32
+import java.util.zip.Checksum
 
 import scala.collection.mutable.ArrayBuffer
+import scala.util.Try
 
+import com.github.luben.zstd.ZstdInputStream
+import com.google.common.io.ByteStreams
+import net.jpountz.lz4.LZ4FrameInputStream
 import org.apache.spark.TaskContext
 import org.apache.spark.internal.Logging
 import org.apache.spark.network.buffer.FileSegmentManagedBuffer
@@ -38,12 +50,208 @@ object Converters extends Logging {
 
   /**
    * Parse ManagedBuffer from shuffle reader into record iterator.
-   * Each ManagedBuffer may be composed of one or more IPC entities.
+   * Each ManagedBuffer may be composed of one or more IPC entities, either in
+   * stream parts written by native shuffle writer, or in legacy IPC file parts.
    */
   def readManagedBuffer(data: ManagedBuffer, context: TaskContext): Iterator[InternalRow] = {
+    if (isStreamBlock(data)) {
+      val partInputs = readManagedBufferToStreamPartInputs(data)
+      return partInputs.toIterator.flatMap { input =>
+        val channel = Channels.newChannel(decompressStream(input))
+        new ArrowReaderIterator(channel, context, streamFormat = true).result
+      }
+    }
     val segmentSeekableByteChannels = readManagedBufferToSegmentByteChannels(data)
     segmentSeekableByteChannels.toIterator.flatMap(channel =>
-      new ArrowReaderIterator(channel, context).result)
+      new ArrowReaderIterator(decompressSegment(channel), context).result)
+  }
+
+  /**
+   * Segments written by native shuffle writer may be wrapped into a lz4/zstd frame,
+   * decompress them into memory so that they can be read with ArrowFileReader.
+   */
+  def decompressSegment(channel: SeekableByteChannel): SeekableByteChannel = {
+    val header = ByteBuffer.allocate(4)
+    while (header.hasRemaining && channel.read(header) > 0) {}
+    channel.position(0)
+    header.flip()
+
+    val magic = if (header.remaining() == 4) {
+      header.order(ByteOrder.LITTLE_ENDIAN).getInt(0)
+    } else {
+      0
+    }
+    val decompressedInput: Option[InputStream] = magic match {
+      case LZ4_FRAME_MAGIC => Some(new LZ4FrameInputStream(Channels.newInputStream(channel)))
+      case ZSTD_FRAME_MAGIC => Some(new ZstdInputStream(Channels.newInputStream(channel)))
+      case _ => None
+    }
+    decompressedInput match {
+      case Some(input) =>
+        val decompressed = ByteStreams.toByteArray(input)
+        input.close()
+        new NioSeekableByteChannel(ByteBuffer.wrap(decompressed), 0, decompressed.length)
+      case None =>
+        channel
+    }
+  }
+
+  /**
+   * Decompress a stream part while reading if it is wrapped into a lz4/zstd frame.
+   */
+  def decompressStream(input: InputStream): InputStream = {
+    val buffered = new BufferedInputStream(input)
+    val header = new Array[Byte](4)
+    buffered.mark(header.length)
+    val headerLength = ByteStreams.read(buffered, header, 0, header.length)
+    buffered.reset()
+
+    val magic = if (headerLength == 4) {
+      ByteBuffer.wrap(header).order(ByteOrder.LITTLE_ENDIAN).getInt(0)
+    } else {
+      0
+    }
+    magic match {
+      case LZ4_FRAME_MAGIC => new LZ4FrameInputStream(buffered)
+      case ZSTD_FRAME_MAGIC => new ZstdInputStream(buffered)
+      case _ => buffered
+    }
+  }
+
+  private val LZ4_FRAME_MAGIC = 0x184d2204
+  private val ZSTD_FRAME_MAGIC = 0xfd2fb528
+  private val CHECKSUM_TRAILER_LENGTH = 16
+  private val STREAM_PART_MAGIC = "BLZIPC".getBytes(StandardCharsets.US_ASCII)
+  private val STREAM_PART_HEADER_LENGTH = 8
+
+  private def readBytes(channel: SeekableByteChannel, position: Long, length: Int): ByteBuffer = {
+    val buf = ByteBuffer.allocate(length)
+    channel.position(position)
+    while (buf.hasRemaining && channel.read(buf) > 0) {}
+    buf.flip()
+    buf.order(ByteOrder.LITTLE_ENDIAN)
+  }
+
+  private def readHeader(channel: SeekableByteChannel, position: Long): ByteBuffer =
+    readBytes(channel, position, STREAM_PART_HEADER_LENGTH)
+
+  private def isStreamPartHeader(header: ByteBuffer): Boolean =
+    header.remaining() >= STREAM_PART_MAGIC.length &&
+      STREAM_PART_MAGIC.indices.forall(i => header.get(i) == STREAM_PART_MAGIC(i))
+
+  def isStreamBlock(data: ManagedBuffer): Boolean =
+    isStreamPartHeader(readHeader(readManagedBufferToBlockByteChannel(data), 0))
+
+  /**
+   * Split a block of stream parts into input streams of their IPC streams. Each stream part
+   * is composed of an 8-byte header (magic, version, checksum algorithm) and the (possibly
+   * compressed) IPC stream split into length-prefixed chunks, ending with an empty chunk.
+   * A fetched block may be composed of several partitions, each followed by a checksum trailer
+   * of its parts, so the whole block is read and every trailer is verified.
+   */
+  def readManagedBufferToStreamPartInputs(data: ManagedBuffer): Seq[InputStream] = {
+    val result: ArrayBuffer[InputStream] = ArrayBuffer()
+    val block = readManagedBufferToBlockByteChannel(data)
+    val blockLength = block.size()
+    var segmentStart = 0L
+    var curStart = 0L
+
+    while (curStart < blockLength) {
+      if (isStreamPartHeader(readHeader(block, curStart))) {
+        val payloadStart = curStart + STREAM_PART_HEADER_LENGTH
+        var payloadEnd = payloadStart
+        var chunkLength = -1L
+        while (chunkLength != 0) {
+          val chunkLengthBuf = readBytes(block, payloadEnd, 4)
+          if (chunkLengthBuf.remaining() < 4) {
+            throw new IOException("shuffle block corrupted: truncated stream part")
+          }
+          chunkLength = chunkLengthBuf.getInt(0) & 0xffffffffL
+          payloadEnd += 4 + chunkLength
+        }
+        val payload = readManagedBufferRange(data, payloadStart, payloadEnd - payloadStart)
+        result += new ChunkedInputStream(Channels.newInputStream(payload))
+        curStart = payloadEnd
+      } else {
+        verifyChecksumTrailer(block, segmentStart, curStart)
+        curStart += CHECKSUM_TRAILER_LENGTH
+        segmentStart = curStart
+      }
+    }
+    result
+  }
+
+  private def readManagedBufferRange(
+      data: ManagedBuffer,
+      start: Long,
+      length: Long): SeekableByteChannel = {
+    data match {
+      case f: FileSegmentManagedBuffer =>
+        new FileSegmentSeekableByteChannel(f.getFile, f.getOffset + start, length)
+      case _ =>
+        new NioSeekableByteChannel(data.nioByteBuffer(), start, length)
+    }
+  }
+
+  /**
+   * Verify the checksum trailer at `trailerStart` covering the parts since `segmentStart`.
+   * CRC32C checksums are verified only on Java 9+, where java.util.zip.CRC32C is available.
+   */
+  private def verifyChecksumTrailer(
+      block: SeekableByteChannel,
+      segmentStart: Long,
+      trailerStart: Long): Unit = {
+    val trailer = readBytes(block, trailerStart, CHECKSUM_TRAILER_LENGTH)
+    if (trailer.remaining() < CHECKSUM_TRAILER_LENGTH) {
+      throw new IOException("shuffle block corrupted: truncated checksum trailer")
+    }
+    val expected = trailer.getLong(0)
+    val checksum: Option[Checksum] = -trailer.getLong(8) match {
+      case 1 => Some(new Adler32())
+      case 2 =>
+        Try(Class.forName("java.util.zip.CRC32C").newInstance().asInstanceOf[Checksum]).toOption
+      case _ =>
+        throw new IOException(
+          s"shuffle block corrupted: invalid part header at $trailerStart")
+    }
+
+    checksum.foreach { checksum =>
+      val buf = ByteBuffer.allocate(65536)
+      block.position(segmentStart)
+      var remaining = trailerStart - segmentStart
+      while (remaining > 0) {
+        buf.clear()
+        buf.limit(math.min(buf.capacity(), remaining).toInt)
+        val n = block.read(buf)
+        if (n <= 0) {
+          throw new IOException("shuffle block corrupted: truncated stream part")
+        }
+        checksum.update(buf.array(), 0, n)
+        remaining -= n
+      }
+      if (checksum.getValue != expected) {
+        throw new IOException(
+          s"shuffle block corrupted: checksum mismatch of range $segmentStart-$trailerStart")
+      }
+    }
+  }
+
+  /**
+   * Read the whole shuffle block as a single channel, which is split into IPC entities
+   * (and verified with checksum trailer if any) by the native shuffle reader.
+   * Local blocks are provided as [[FileSegmentSeekableByteChannel]], whose file path,
+   * offset and length are used by the native shuffle reader to read the file directly.
+   */
+  def readManagedBufferToBlockByteChannel(data: ManagedBuffer): SeekableByteChannel = {
+    data match {
+      case f: FileSegmentManagedBuffer =>
+        new FileSegmentSeekableByteChannel(f.getFile, f.getOffset, f.getLength)
+      case _: NettyManagedBuffer | _: NioManagedBuffer =>
+        val all = data.nioByteBuffer()
+        new NioSeekableByteChannel(all, 0, all.limit())
+      case mb =>
+        throw new UnsupportedOperationException(s"ManagedBuffer of $mb not supported")
+    }
   }
 
   def readManagedBufferToSegmentByteChannels(data: ManagedBuffer): Seq[SeekableByteChannel] = {
@@ -59,10 +267,15 @@ object Converters extends Logging {
           lengthReader.seek(curEnd - 8)
           lengthReader.read(lenBuf.array())
           val len = lenBuf.order(ByteOrder.LITTLE_ENDIAN).getLong(0).toInt
-          val curStart = curEnd - 8 - len
-          val fsc = new FileSegmentSeekableByteChannel(file, curStart, len)
-          result += fsc
-          curEnd = curStart
+          if (len < 0) {
+            // skip checksum trailer, which is verified only in native shuffle reader
+            curEnd -= CHECKSUM_TRAILER_LENGTH
+          } else {
+            val curStart = curEnd - 8 - len
+            val fsc = new FileSegmentSeekableByteChannel(file, curStart, len)
+            result += fsc
+            curEnd = curStart
+          }
         }
 
       case _: NettyManagedBuffer | _: NioManagedBuffer =>
@@ -74,10 +287,15 @@ object Converters extends Logging {
           all.position(curEnd - 8)
           lenBuf.putLong(all.getLong)
           val len = lenBuf.order(ByteOrder.LITTLE_ENDIAN).getLong(0).toInt
-          val curStart = curEnd - 8 - len
-          val sc = new NioSeekableByteChannel(all, curStart, len)
-          result += sc
-          curEnd = curStart
+          if (len < 0) {
+            // skip checksum trailer, which is verified only in native shuffle reader
+            curEnd -= CHECKSUM_TRAILER_LENGTH
+          } else {
+            val curStart = curEnd - 8 - len
+            val sc = new NioSeekableByteChannel(all, curStart, len)
+            result += sc
+            curEnd = curStart
+          }
         }
       case mb =>
         throw new UnsupportedOperationException(s"ManagedBuffer of $mb not supported")
@@ -85,3 +303,40 @@ object Converters extends Logging {
     result
   }
 }
+
+/**
+ * Input stream of the IPC stream of a stream part, joining its length-prefixed chunks until the
+ * terminating empty chunk.
+ */
+private class ChunkedInputStream(input: InputStream) extends InputStream {
+  private val chunkLengthBuf = ByteBuffer.allocate(4).order(ByteOrder.LITTLE_ENDIAN)
+  private var chunkRemaining = 0L
+  private var finished = false
+
+  override def read(): Int = {
+    val b = new Array[Byte](1)
+    if (read(b, 0, 1) <= 0) -1 else b(0) & 0xff
+  }
+
+  override def read(b: Array[Byte], off: Int, len: Int): Int = {
+    if (len == 0) {
+      return 0
+    }
+    if (chunkRemaining == 0 && !finished) {
+      ByteStreams.readFully(input, chunkLengthBuf.array())
+      chunkRemaining = chunkLengthBuf.getInt(0) & 0xffffffffL
+      finished = chunkRemaining == 0
+    }
+    if (finished) {
+      return -1
+    }
+    val n = input.read(b, off, math.min(len, chunkRemaining).toInt)
+    if (n < 0) {
+      throw new EOFException("shuffle block corrupted: truncated stream part")
+    }
+    chunkRemaining -= n
+    n
+  }
+
+  override def close(): Unit = input.close()
+}
@@ -56,15 +56,22 @@ case class NativeFilterExec(condition: Expression, override val child: SparkPlan
       inputRDD.dependencies,
       (partition, taskContext) => {
         val inputPartition = inputRDD.partitions(partition.index)
-        val nativeFilterExec = FilterExecNode
-          .newBuilder()
-          .setInput(inputRDD.nativePlan(inputPartition, taskContext))
-          .setExpr(nativeFilterExpr)
-          .build()
-        PhysicalPlanNode.newBuilder().setFilter(nativeFilterExec).build()
+        buildNativePlan(inputRDD.nativePlan(inputPartition, taskContext))
       })
   }
 
+  override def nativePlanForValidation: Option[PhysicalPlanNode] =
+    Some(buildNativePlan(NativeSupports.validationInput(child)))
+
+  private def buildNativePlan(nativeInput: PhysicalPlanNode): PhysicalPlanNode = {
+    val nativeFilterExec = FilterExecNode
+      .newBuilder()
+      .setInput(nativeInput)
+      .setExpr(nativeFilterExpr)
+      .build()
+    PhysicalPlanNode.newBuilder().setFilter(nativeFilterExec).build()
+  }
+
   override def doCanonicalize(): SparkPlan =
     FilterExec(condition, child).canonicalized
 }
@@ -31,6 +31,7 @@ import org.apache.spark.sql.execution.datasources.FileScanRDD
 import org.apache.spark.Partition
 import org.apache.spark.sql.catalyst.plans.physical.Partitioning
 import org.apache.spark.sql.execution.metric.SQLMetric
+import org.apache.spark.sql.execution.metric.SQLMetrics
 import org.apache.spark.sql.execution.SparkPlan
 import org.blaze.protobuf.FileGroup
 import org.blaze.protobuf.FileRange
@@ -45,7 +46,11 @@ case class NativeParquetScanExec(basedFileScan: FileSourceScanExec)
     with NativeSupports {
 
   override lazy val metrics: Map[String, SQLMetric] =
-    NativeSupports.getDefaultNativeMetrics(sparkContext)
+    NativeSupports.getDefaultNativeMetrics(sparkContext) ++ Map(
+      "bytes_scanned" -> SQLMetrics.createSizeMetric(sparkContext, "Native.bytes_scanned"),
+      "row_groups_pruned" -> SQLMetrics.createMetric(sparkContext, "Native.row_groups_pruned"),
+      "predicate_evaluation_errors" ->
+        SQLMetrics.createMetric(sparkContext, "Native.predicate_evaluation_errors"))
 
   override def output: Seq[Attribute] = basedFileScan.output
   override def outputPartitioning: Partitioning = basedFileScan.outputPartitioning
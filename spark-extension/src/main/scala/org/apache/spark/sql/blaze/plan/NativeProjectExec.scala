@@ -63,16 +63,23 @@ case class NativeProjectExec(projectList: Seq[NamedExpression], override val chi
       inputRDD.dependencies,
       (partition, taskContext) => {
         val inputPartition = inputRDD.partitions(partition.index)
-        val nativeProjectExec = ProjectionExecNode
-          .newBuilder()
-          .addAllExprName(nativeNamedExprs.map(_._1).asJava)
-          .addAllExpr(nativeNamedExprs.map(_._2).asJava)
-          .setInput(inputRDD.nativePlan(inputPartition, taskContext))
-          .build()
-        PhysicalPlanNode.newBuilder().setProjection(nativeProjectExec).build()
+        buildNativePlan(inputRDD.nativePlan(inputPartition, taskContext))
       })
   }
 
+  override def nativePlanForValidation: Option[PhysicalPlanNode] =
+    Some(buildNativePlan(NativeSupports.validationInput(child)))
+
+  private def buildNativePlan(nativeInput: PhysicalPlanNode): PhysicalPlanNode = {
+    val nativeProjectExec = ProjectionExecNode
+      .newBuilder()
+      .addAllExprName(nativeNamedExprs.map(_._1).asJava)
+      .addAllExpr(nativeNamedExprs.map(_._2).asJava)
+      .setInput(nativeInput)
+      .build()
+    PhysicalPlanNode.newBuilder().setProjection(nativeProjectExec).build()
+  }
+
   private val nativeNamedExprs: Seq[(String, PhysicalExprNode)] = {
     val namedExprs = ArrayBuffer[(String, PhysicalExprNode)]()
     var numAddedColumns = 0
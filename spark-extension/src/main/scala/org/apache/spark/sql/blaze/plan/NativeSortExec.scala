@@ -87,15 +87,22 @@ case class NativeSortExec(
       inputRDD.dependencies,
       (partition, taskContext) => {
         val inputPartition = inputRDD.partitions(partition.index)
-        val nativeSortExec = SortExecNode
-          .newBuilder()
-          .setInput(inputRDD.nativePlan(inputPartition, taskContext))
-          .addAllExpr(nativeSortExprs.asJava)
-          .build()
-        PhysicalPlanNode.newBuilder().setSort(nativeSortExec).build()
+        buildNativePlan(inputRDD.nativePlan(inputPartition, taskContext))
       })
   }
 
+  override def nativePlanForValidation: Option[PhysicalPlanNode] =
+    Some(buildNativePlan(NativeSupports.validationInput(child)))
+
+  private def buildNativePlan(nativeInput: PhysicalPlanNode): PhysicalPlanNode = {
+    val nativeSortExec = SortExecNode
+      .newBuilder()
+      .setInput(nativeInput)
+      .addAllExpr(nativeSortExprs.asJava)
+      .build()
+    PhysicalPlanNode.newBuilder().setSort(nativeSortExec).build()
+  }
+
   override def doCanonicalize(): SparkPlan =
     SortExec(sortOrder, global, child, testSpillFrequency = 0).canonicalized
 }
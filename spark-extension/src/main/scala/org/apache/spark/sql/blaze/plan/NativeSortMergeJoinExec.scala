@@ -110,19 +110,30 @@ case class NativeSortMergeJoinExec(
 
         val rightPartition = rightRDD.partitions(partition.index)
         val rightChild = rightRDD.nativePlan(rightPartition, taskContext)
-
-        val sortMergeJoinExec = SortMergeJoinExecNode
-          .newBuilder()
-          .setLeft(leftChild)
-          .setRight(rightChild)
-          .setJoinType(nativeJoinType)
-          .addAllOn(nativeJoinOn.asJava)
-          .addAllSortOptions(nativeSortOptions.asJava)
-          .setNullEqualsNull(false)
-        PhysicalPlanNode.newBuilder().setSortMergeJoin(sortMergeJoinExec).build()
+        buildNativePlan(leftChild, rightChild)
       })
   }
 
+  override def nativePlanForValidation: Option[PhysicalPlanNode] =
+    Some(
+      buildNativePlan(
+        NativeSupports.validationInput(left),
+        NativeSupports.validationInput(right)))
+
+  private def buildNativePlan(
+      leftChild: PhysicalPlanNode,
+      rightChild: PhysicalPlanNode): PhysicalPlanNode = {
+    val sortMergeJoinExec = SortMergeJoinExecNode
+      .newBuilder()
+      .setLeft(leftChild)
+      .setRight(rightChild)
+      .setJoinType(nativeJoinType)
+      .addAllOn(nativeJoinOn.asJava)
+      .addAllSortOptions(nativeSortOptions.asJava)
+      .setNullEqualsNull(false)
+    PhysicalPlanNode.newBuilder().setSortMergeJoin(sortMergeJoinExec).build()
+  }
+
   override def doCanonicalize(): SparkPlan =
     SortMergeJoinExec(
       leftKeys,
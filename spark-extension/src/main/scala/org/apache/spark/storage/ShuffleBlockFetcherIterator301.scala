@@ -162,6 +162,13 @@ private[spark] final class ShuffleBlockFetcherIterator301(
    */
   @volatile private[this] var currentResult: SuccessFetchResult = null
 
+  /**
+   * Block ids, map indices and addresses of the blocks returned by [[next]], in order, to report
+   * a fetch failure of a block found corrupted by its reader.
+   */
+  @GuardedBy("this")
+  private[this] val processedBlocks = new ArrayBuffer[(BlockId, Int, BlockManagerId)]
+
   /** Current bytes in flight from our requests */
   private[this] var bytesInFlight = 0L
 
@@ -275,6 +282,9 @@ private[spark] final class ShuffleBlockFetcherIterator301(
     }
 
     currentResult = result.asInstanceOf[SuccessFetchResult]
+    synchronized {
+      processedBlocks += ((currentResult.blockId, currentResult.mapIndex, currentResult.address))
+    }
     (currentResult.blockId, input)
   }
 
@@ -421,6 +431,20 @@ private[spark] final class ShuffleBlockFetcherIterator301(
     }
   }
 
+  /**
+   * Creates a FetchFailedException of the `blockIndex`-th block returned by [[next]], for a
+   * corruption found by the reader of the block after it was fetched.
+   */
+  def fetchFailedException(blockIndex: Int, e: Throwable): Option[FetchFailedException] = {
+    val block = synchronized(processedBlocks.lift(blockIndex))
+    block.collect {
+      case (ShuffleBlockId(shufId, mapId, reduceId), mapIndex, address) =>
+        new FetchFailedException(address, shufId, mapId, mapIndex, reduceId, e)
+      case (ShuffleBlockBatchId(shuffleId, mapId, startReduceId, _), mapIndex, address) =>
+        new FetchFailedException(address, shuffleId, mapId, mapIndex, startReduceId, e)
+    }
+  }
+
   def toCompletionIterator: Iterator[(BlockId, ManagedBuffer)] = {
     val inner = this
 